@@ -0,0 +1,41 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate nvme_ata_security_fuzz;
+
+use nvme_ata_security_fuzz::nvme::security::AtaSecurityIdentify;
+use nvme_ata_security_fuzz::nvme::Payload;
+
+fuzz_target!(|data: &[u8]| {
+	if data.len() < AtaSecurityIdentify::SIZE {
+		return;
+	}
+	// Responses with another length byte are rejected
+	let s = match AtaSecurityIdentify::from_wire(&data[..AtaSecurityIdentify::SIZE]) {
+		Ok(s) => s,
+		Err(_) => return,
+	};
+	let _ = (s.erase_time(false), s.erase_time(true));
+	let _ = (
+		s.master_password_identifier(),
+		s.master_password_identifier_supported(),
+	);
+	let _ = (
+		s.maxset(),
+		s.s_suprt(),
+		s.s_enabld(),
+		s.locked(),
+		s.frozen(),
+	);
+	let _ = (s.pwncntex(), s.en_er_sup());
+});
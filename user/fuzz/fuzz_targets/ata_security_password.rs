@@ -0,0 +1,73 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Round trip of the SECURITY SEND payload for password commands: every
+//! valid combination of password, user or master password, flag and master
+//! password identifier must land in its own field, and the others must be
+//! rejected. What is built must parse back.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate byteorder;
+extern crate nvme_ata_security_fuzz;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use nvme_ata_security_fuzz::nvme::security::{AtaSecurityPassword, PasswordError};
+use nvme_ata_security_fuzz::nvme::Payload;
+
+fuzz_target!(|data: &[u8]| {
+	if data.len() < 35 {
+		return;
+	}
+	let master = data[0] & 0x01 != 0;
+	let maximum_security = data[0] & 0x02 != 0;
+	let enhanced = data[0] & 0x04 != 0;
+	let id = if data[0] & 0x08 != 0 {
+		Some(BigEndian::read_u16(&data[1..3]))
+	} else {
+		None
+	};
+	let mut password = [0u8; 32];
+	password.copy_from_slice(&data[3..35]);
+
+	let mut builder = AtaSecurityPassword::builder(password);
+	if master {
+		builder = builder.master();
+	}
+	if maximum_security {
+		builder = builder.maximum_security();
+	}
+	if enhanced {
+		builder = builder.enhanced();
+	}
+	if let Some(id) = id {
+		builder = builder.identifier(id);
+	}
+	let p = match builder.build() {
+		Err(e) => {
+			match e {
+				PasswordError::IdentifierOnUserPassword => assert!(id.is_some() && !master),
+				PasswordError::SecurityLevelOnMasterPassword => assert!(maximum_security && master),
+				PasswordError::MaximumSecurityAndEnhanced => assert!(maximum_security && enhanced),
+			}
+			return;
+		}
+		Ok(p) => p,
+	};
+	let buf = p.to_wire();
+	assert!(master || id.is_none());
+	assert!(!(maximum_security && (master || enhanced)));
+	assert_eq!(buf[0], (maximum_security || enhanced) as u8);
+	assert_eq!(buf[1], master as u8);
+	assert_eq!(&buf[2..34], &password[..]);
+	assert_eq!(BigEndian::read_u16(&buf[34..36]), id.unwrap_or(0));
+	assert_eq!(AtaSecurityPassword::from_wire(buf).unwrap().to_wire(), buf);
+});
@@ -0,0 +1,27 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate nvme_ata_security_fuzz;
+
+use nvme_ata_security_fuzz::nvme::identify::IdentifyController;
+use nvme_ata_security_fuzz::nvme::Payload;
+
+fuzz_target!(|data: &[u8]| {
+	if data.len() < IdentifyController::SIZE {
+		return;
+	}
+	let i = IdentifyController::from_wire(&data[..IdentifyController::SIZE]).unwrap();
+	let _ = (i.vid(), i.ssvid(), i.sn(), i.mn(), i.fr(), i.ver());
+	let _ = (i.oacs(), i.frmw(), i.npss(), i.sanicap(), i.oncs(), i.fna());
+	let _ = (i.is_fabrics(), i.subnqn());
+});
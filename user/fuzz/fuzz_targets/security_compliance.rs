@@ -0,0 +1,20 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate nvme_ata_security_fuzz;
+
+use nvme_ata_security_fuzz::nvme::security::parse_security_compliance;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = parse_security_compliance(data);
+});
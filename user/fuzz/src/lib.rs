@@ -0,0 +1,38 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! The response parsers of `nvme-ata-security`, for the fuzz targets.
+//!
+//! The tool is a binary crate, so the modules with the parsers are included
+//! from its source tree. Run a target with e.g.
+//! `cargo fuzz run identify_controller`.
+
+#[macro_use]
+extern crate nix;
+#[macro_use]
+extern crate bitflags;
+extern crate byteorder;
+extern crate libc;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+#[path = "../../src/ieee1667.rs"]
+pub mod ieee1667;
+#[path = "../../src/nvme.rs"]
+pub mod nvme;
+#[path = "../../src/ops.rs"]
+pub mod ops;
+#[path = "../../src/tcg.rs"]
+pub mod tcg;
+#[path = "../../src/uring.rs"]
+mod uring;
+#[path = "../../src/x509.rs"]
+pub mod x509;
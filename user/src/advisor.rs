@@ -0,0 +1,137 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Pick the best available erase mechanism for `erase-advisor`.
+//!
+//! Which mechanisms work depends on the controller capabilities (OACS,
+//! SANICAP, FNA) and, for ATA security erase, on the security state.
+
+use nvme::identify::{Fna, IdentifyController, Oacs, Sanicap};
+use nvme::security::AtaSecurityIdentify;
+use state;
+
+/// Erase mechanisms, in order of preference.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Method {
+	EnhancedAtaErase,
+	/// Not recommended by the advisor, only recorded for `erase` without
+	/// `--enhanced`
+	AtaErase,
+	SanitizeCryptoErase,
+	SanitizeBlockErase,
+	FormatCryptoErase,
+	FormatUserDataErase,
+}
+
+const METHODS: &'static [Method] = &[
+	Method::EnhancedAtaErase,
+	Method::SanitizeCryptoErase,
+	Method::SanitizeBlockErase,
+	Method::FormatCryptoErase,
+	Method::FormatUserDataErase,
+];
+
+impl Method {
+	pub fn name(self) -> &'static str {
+		match self {
+			Method::EnhancedAtaErase => "enhanced ATA security erase",
+			Method::AtaErase => "ATA security erase",
+			Method::SanitizeCryptoErase => "sanitize crypto erase",
+			Method::SanitizeBlockErase => "sanitize block erase",
+			Method::FormatCryptoErase => "Format NVM with cryptographic erase",
+			Method::FormatUserDataErase => "Format NVM with user data erase",
+		}
+	}
+}
+
+#[derive(Serialize, Debug)]
+pub struct Candidate {
+	pub method: Method,
+	/// Why the method can't be used, `None` if it is available
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub unavailable: Option<&'static str>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub notes: Vec<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Advice {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub recommended: Option<Method>,
+	pub candidates: Vec<Candidate>,
+}
+
+fn candidate(
+	method: Method,
+	identity: &IdentifyController,
+	security: Option<&AtaSecurityIdentify>,
+) -> Candidate {
+	let oacs = identity.oacs();
+	let sanicap = identity.sanicap();
+	let fna = identity.fna();
+	let mut notes = vec![];
+	let unavailable = match method {
+		Method::EnhancedAtaErase | Method::AtaErase => {
+			notes.push("requires the user or master password");
+			match security {
+				None => Some("the drive does not support ATA security"),
+				Some(s) if method == Method::EnhancedAtaErase && !s.en_er_sup() => {
+					Some("the drive does not support enhanced erase")
+				}
+				Some(s) => state::blocker(s, "erase"),
+			}
+		}
+		Method::SanitizeCryptoErase | Method::SanitizeBlockErase => {
+			notes.push("applies to the whole NVM subsystem and continues in the background");
+			let flag = if method == Method::SanitizeCryptoErase {
+				Sanicap::CRYPTO_ERASE
+			} else {
+				Sanicap::BLOCK_ERASE
+			};
+			if sanicap.contains(flag) {
+				None
+			} else {
+				Some("not supported by the controller")
+			}
+		}
+		Method::FormatCryptoErase | Method::FormatUserDataErase => {
+			if fna.contains(Fna::ERASE_ALL) {
+				notes.push("erases all namespaces of the controller");
+			}
+			if !oacs.contains(Oacs::FORMAT) {
+				Some("the controller does not support Format NVM")
+			} else if method == Method::FormatCryptoErase && !fna.contains(Fna::CRYPTO_ERASE) {
+				Some("the controller does not support cryptographic erase")
+			} else {
+				None
+			}
+		}
+	};
+	Candidate {
+		method,
+		unavailable,
+		notes,
+	}
+}
+
+pub fn advise(identity: &IdentifyController, security: Option<&AtaSecurityIdentify>) -> Advice {
+	let candidates: Vec<Candidate> = METHODS
+		.iter()
+		.map(|&m| candidate(m, identity, security))
+		.collect();
+	Advice {
+		recommended: candidates
+			.iter()
+			.find(|c| c.unavailable.is_none())
+			.map(|c| c.method),
+		candidates,
+	}
+}
@@ -0,0 +1,112 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! The systemd password agent protocol
+//! (<https://systemd.io/PASSWORD_AGENTS/>), so that Plymouth,
+//! systemd-tty-ask-password-agent or a remote agent can answer the password
+//! prompt during boot.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use libc;
+
+const DIR: &'static str = "/run/systemd/ask-password";
+
+/// The files of a pending request, removed on drop.
+struct Request {
+	ask: PathBuf,
+	socket: PathBuf,
+}
+
+impl Drop for Request {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.ask);
+		let _ = fs::remove_file(&self.socket);
+	}
+}
+
+/// CLOCK_MONOTONIC in microseconds, the clock of `NotAfter`.
+fn monotonic_usec() -> u64 {
+	let mut ts = libc::timespec {
+		tv_sec: 0,
+		tv_nsec: 0,
+	};
+	unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+	ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1000
+}
+
+/// Ask the password agents for a password, showing `message`. `id` lets an
+/// agent recognize the request. Gives up after `timeout` seconds, unless 0.
+pub fn ask(message: &str, id: &str, timeout: u64) -> io::Result<Vec<u8>> {
+	let name = format!("nvme-ata-security.{}", process::id());
+	let request = Request {
+		ask: PathBuf::from(format!("{}/ask.{}", DIR, name)),
+		socket: PathBuf::from(format!("{}/sck.{}", DIR, name)),
+	};
+	let _ = fs::remove_file(&request.socket);
+	let socket = try!(UnixDatagram::bind(&request.socket));
+	// Only root may answer
+	try!(fs::set_permissions(
+		&request.socket,
+		fs::Permissions::from_mode(0o600)
+	));
+	let not_after = if timeout == 0 {
+		0
+	} else {
+		try!(socket.set_read_timeout(Some(Duration::from_secs(timeout))));
+		monotonic_usec() + timeout * 1_000_000
+	};
+
+	// Agents watch for ask.* files, write the request completely first
+	let tmp = format!("{}/tmp.{}", DIR, name);
+	{
+		let mut f = try!(File::create(&tmp));
+		try!(write!(
+			f,
+			"[Ask]\nPID={}\nSocket={}\nAcceptCached=0\nEcho=0\nNotAfter={}\nMessage={}\nId={}\n",
+			process::id(),
+			request.socket.display(),
+			not_after,
+			message,
+			id
+		));
+	}
+	try!(fs::rename(&tmp, &request.ask));
+
+	let mut buf = [0u8; 4096];
+	let len = match socket.recv(&mut buf) {
+		Err(ref e)
+			if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+		{
+			return Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				format!("no password was entered within {} seconds", timeout),
+			));
+		}
+		len => try!(len),
+	};
+	match buf[..len].split_first() {
+		Some((&b'+', password)) => Ok(password.split(|&b| b == 0).next().unwrap_or(&[]).to_vec()),
+		Some((&b'-', _)) => Err(io::Error::new(
+			io::ErrorKind::Other,
+			"the password request was cancelled",
+		)),
+		_ => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"invalid reply from password agent",
+		)),
+	}
+}
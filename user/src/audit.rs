@@ -0,0 +1,138 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Append-only audit log of security-altering commands.
+//!
+//! Each command appends one JSON object per line with a UTC timestamp, the
+//! invoking user and the command's `CommandResult`. Passwords are never
+//! logged.
+//!
+//! The daemon, its broker and `tui` outlive any single command: they open the
+//! log once for the process, before dropping privileges, and append a record
+//! for each unlock, freeze and reset they perform.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libc;
+use serde_json;
+
+use config;
+use ops;
+
+use report::CommandResult;
+
+#[derive(Debug)]
+pub struct AuditLog(File);
+
+static PROCESS_LOG: Mutex<Option<AuditLog>> = Mutex::new(None);
+
+#[derive(Serialize)]
+struct Entry<'a> {
+	time: String,
+	uid: u32,
+	#[serde(flatten)]
+	record: &'a CommandResult,
+}
+
+/// Format seconds since the epoch as RFC 3339 UTC, e.g.
+/// `2016-06-01T12:00:00Z`.
+fn rfc3339(secs: u64) -> String {
+	let days = (secs / 86400) as i64;
+	let rem = secs % 86400;
+	// Civil date from day count, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719468;
+	let era = z / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!(
+		"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+		year,
+		month,
+		day,
+		rem / 3600,
+		rem / 60 % 60,
+		rem % 60
+	)
+}
+
+/// The current time in RFC 3339 format.
+pub fn now() -> String {
+	let secs = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	rfc3339(secs)
+}
+
+impl AuditLog {
+	/// Open the audit log for appending, creating it readable only by the
+	/// owner if it doesn't exist.
+	pub fn open(path: &str) -> io::Result<AuditLog> {
+		OpenOptions::new()
+			.append(true)
+			.create(true)
+			.mode(0o600)
+			.open(path)
+			.map(AuditLog)
+	}
+
+	pub fn append(&mut self, record: &CommandResult) -> io::Result<()> {
+		let entry = Entry {
+			time: now(),
+			uid: unsafe { libc::getuid() },
+			record,
+		};
+		let mut line = try!(serde_json::to_string(&entry)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+		line.push('\n');
+		// A single write so concurrent invocations don't interleave lines
+		self.0.write_all(line.as_bytes())
+	}
+}
+
+/// Open the audit log for the rest of the process, for `record`.
+pub fn open_for_process(path: &str) -> io::Result<()> {
+	let log = try!(AuditLog::open(path));
+	*PROCESS_LOG.lock().unwrap() = Some(log);
+	Ok(())
+}
+
+/// Append the outcome of `command` on `dev` to the audit log opened with
+/// `open_for_process`, if any.
+pub fn record<T>(command: &'static str, dev: &Path, f: &File, result: &ops::Result<T>) {
+	let mut log = PROCESS_LOG.lock().unwrap();
+	let log = match *log {
+		None => return,
+		Some(ref mut log) => log,
+	};
+	let mut record = CommandResult::new(command);
+	record.device = Some(dev.display().to_string());
+	record.serial = ops::identify_controller(f.as_raw_fd())
+		.ok()
+		.map(|identity| config::serial(&identity));
+	if command == "unlock" {
+		record.slot = Some("user");
+	}
+	record.result = if result.is_ok() { "success" } else { "error" };
+	if let Err(e) = log.append(&record) {
+		eprintln!("Unable to write audit log: {}", e);
+	}
+}
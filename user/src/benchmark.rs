@@ -0,0 +1,76 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Erase durations for `erase --benchmark-report`.
+//!
+//! Each erase appends one JSON object per line, so records from many drives
+//! can be collected and compared. An erase that finishes in a fraction of the
+//! time the drive advertises may not have erased anything.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use serde_json;
+
+use audit;
+use config;
+use nvme::identify::IdentifyController;
+use nvme::security::AtaSecurityIdentify;
+
+/// An erase faster than this fraction of the advertised time is suspicious
+const SUSPICIOUS_FRACTION: u64 = 10;
+
+#[derive(Serialize, Debug)]
+pub struct Record {
+	pub time: String,
+	pub serial: String,
+	pub model: String,
+	pub firmware: String,
+	pub enhanced: bool,
+	/// Wall-clock duration of the erase in seconds
+	pub duration: u64,
+	/// Erase time the drive advertises in seconds, a lower bound if it
+	/// reports the maximum
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub advertised: Option<u64>,
+	pub suspicious: bool,
+}
+
+impl Record {
+	pub fn new(
+		identity: &IdentifyController,
+		security: &AtaSecurityIdentify,
+		enhanced: bool,
+		duration: Duration,
+	) -> Record {
+		let duration = duration.as_secs();
+		let advertised = security.erase_time(enhanced).map(|t| t.minutes as u64 * 60);
+		Record {
+			time: audit::now(),
+			serial: config::serial(identity),
+			model: String::from_utf8_lossy(identity.mn()).trim().to_owned(),
+			firmware: String::from_utf8_lossy(identity.fr()).trim().to_owned(),
+			enhanced,
+			duration,
+			advertised,
+			suspicious: advertised.map_or(false, |a| duration * SUSPICIOUS_FRACTION < a),
+		}
+	}
+
+	pub fn append(&self, path: &str) -> io::Result<()> {
+		let mut line =
+			try!(serde_json::to_string(self)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+		line.push('\n');
+		let mut f = try!(OpenOptions::new().append(true).create(true).open(path));
+		f.write_all(line.as_bytes())
+	}
+}
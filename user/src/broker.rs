@@ -0,0 +1,463 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Privilege separation of the daemon.
+//!
+//! With `daemon_user` in the configuration file, the daemon forks a broker
+//! that keeps the privileges of root and only sends the commands the daemon
+//! needs to NVMe namespaces: identify and query the security state, SECURITY
+//! UNLOCK, SECURITY FREEZE LOCK and the reset after unlocking. The rest of
+//! the daemon, which reads password files, runs Clevis and answers requests
+//! on the control socket, switches to `daemon_user` and asks the broker over
+//! a socket pair, one line of JSON per request and response.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::sync::Mutex;
+
+use libc;
+use nix::errno::Errno;
+use nix::Error as NixError;
+use serde_json;
+
+use audit;
+use config;
+use device;
+use nvme;
+use nvme::identify::IdentifyController;
+use nvme::security::{AtaSecurityIdentify, Protocol};
+use nvme::Payload;
+use ops;
+use password_file::decode_hex;
+use sandbox;
+use tcg;
+use throttle;
+use {after_unlock, open_device, security_freeze, security_unlock, DriveInfo, EXIT_FAILURE};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+	Query {
+		device: String,
+	},
+	Unlock {
+		device: String,
+		password: String,
+		automated: bool,
+	},
+	Freeze {
+		device: String,
+	},
+	AfterUnlock {
+		device: String,
+		freeze: bool,
+		reset: bool,
+	},
+}
+
+impl Request {
+	fn device(&self) -> &str {
+		match *self {
+			Request::Query { ref device }
+			| Request::Unlock { ref device, .. }
+			| Request::Freeze { ref device }
+			| Request::AfterUnlock { ref device, .. } => device,
+		}
+	}
+}
+
+/// `ops::Error` on the wire.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum WireError {
+	Io(i32),
+	Nvme(nvme::StatusCode),
+	Os(i32),
+	Device(String),
+	Tcg(tcg::Status),
+}
+
+impl From<io::Error> for WireError {
+	fn from(e: io::Error) -> WireError {
+		match e.raw_os_error() {
+			Some(errno) => WireError::Os(errno),
+			None => WireError::Device(e.to_string()),
+		}
+	}
+}
+
+impl From<ops::Error> for WireError {
+	fn from(e: ops::Error) -> WireError {
+		match e {
+			ops::Error::Io(NixError::Sys(errno)) => WireError::Io(errno as i32),
+			ops::Error::Io(e) => WireError::Device(e.to_string()),
+			ops::Error::Nvme(status) => WireError::Nvme(status),
+			ops::Error::Device(e) => e.into(),
+			ops::Error::Tcg(status) => WireError::Tcg(status),
+		}
+	}
+}
+
+impl From<WireError> for ops::Error {
+	fn from(e: WireError) -> ops::Error {
+		match e {
+			WireError::Io(errno) => ops::Error::Io(NixError::Sys(Errno::from_i32(errno))),
+			WireError::Nvme(status) => ops::Error::Nvme(status),
+			WireError::Os(errno) => ops::Error::Device(io::Error::from_raw_os_error(errno)),
+			WireError::Device(message) => {
+				ops::Error::Device(io::Error::new(io::ErrorKind::Other, message))
+			}
+			WireError::Tcg(status) => ops::Error::Tcg(status),
+		}
+	}
+}
+
+type WireResult<T> = Result<T, WireError>;
+
+/// `DriveInfo` on the wire, with the identify data and ATA security state in
+/// hex.
+#[derive(Serialize, Deserialize)]
+struct WireInfo(
+	WireResult<(
+		String,
+		WireResult<Option<(Vec<u8>, WireResult<Option<String>>)>>,
+	)>,
+);
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn malformed() -> io::Error {
+	io::Error::new(
+		io::ErrorKind::InvalidData,
+		"malformed response from the broker",
+	)
+}
+
+fn identify(hex: &str) -> io::Result<IdentifyController> {
+	let bytes = try!(decode_hex(hex).ok_or_else(malformed));
+	IdentifyController::from_wire(&bytes).map_err(|_| malformed())
+}
+
+fn security(hex: &str) -> io::Result<AtaSecurityIdentify> {
+	let bytes = try!(decode_hex(hex).ok_or_else(malformed));
+	AtaSecurityIdentify::from_wire(&bytes).map_err(|_| malformed())
+}
+
+impl From<DriveInfo> for WireInfo {
+	fn from(info: DriveInfo) -> WireInfo {
+		WireInfo(info.0.map_err(WireError::from).map(|(i, p)| {
+			let p = p.map_err(WireError::from).map(|p| {
+				p.map(|(p, s)| {
+					let s = s
+						.map_err(WireError::from)
+						.map(|s| s.map(|s| hex(s.as_ref())));
+					(p.into_iter().map(Into::<u8>::into).collect(), s)
+				})
+			});
+			(hex(i.as_ref()), p)
+		}))
+	}
+}
+
+impl WireInfo {
+	fn into_info(self) -> io::Result<DriveInfo> {
+		let (i, p) = match self.0 {
+			Err(e) => return Ok(DriveInfo(Err(e.into()))),
+			Ok(v) => v,
+		};
+		let p = match p {
+			Err(e) => Err(e.into()),
+			Ok(None) => Ok(None),
+			Ok(Some((p, s))) => {
+				let s = match s {
+					Err(e) => Err(e.into()),
+					Ok(None) => Ok(None),
+					Ok(Some(s)) => Ok(Some(try!(security(&s)))),
+				};
+				Ok(Some((p.into_iter().map(Protocol::from).collect(), s)))
+			}
+		};
+		Ok(DriveInfo(Ok((try!(identify(&i)), p))))
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Response {
+	Info(WireInfo),
+	Done,
+	Error(WireError),
+}
+
+/// The daemon's end of the socket pair.
+pub struct Client(Mutex<BufReader<UnixStream>>);
+
+impl Client {
+	fn call(&self, request: &Request) -> io::Result<Response> {
+		let mut stream = self.0.lock().unwrap();
+		try!(writeln!(
+			stream.get_mut(),
+			"{}",
+			serde_json::to_string(request).unwrap()
+		));
+		let mut line = String::new();
+		if try!(stream.read_line(&mut line)) == 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::UnexpectedEof,
+				"the broker exited",
+			));
+		}
+		serde_json::from_str(&line).map_err(|_| malformed())
+	}
+
+	fn command(&self, request: &Request) -> ops::Result<()> {
+		match try!(self.call(request)) {
+			Response::Done => Ok(()),
+			Response::Error(e) => Err(e.into()),
+			Response::Info(_) => Err(malformed().into()),
+		}
+	}
+}
+
+/// A drive the daemon sends commands to, itself or through the broker.
+pub enum Drive<'a> {
+	Local(&'a Path, File),
+	Brokered(&'a Path, &'a Client),
+}
+
+impl<'a> Drive<'a> {
+	/// Open `dev`, for writing if `write`. Through the broker, the device is
+	/// opened for each command.
+	pub fn open(dev: &'a Path, write: bool, broker: Option<&'a Client>) -> io::Result<Drive<'a>> {
+		match broker {
+			Some(client) => Ok(Drive::Brokered(dev, client)),
+			None => open_device(dev, write).map(|f| Drive::Local(dev, f)),
+		}
+	}
+
+	pub fn query(&self) -> DriveInfo {
+		match *self {
+			Drive::Local(_, ref f) => DriveInfo::query(f),
+			Drive::Brokered(dev, client) => {
+				let request = Request::Query {
+					device: dev.display().to_string(),
+				};
+				let info = client.call(&request).and_then(|response| match response {
+					Response::Info(info) => info.into_info(),
+					Response::Error(e) => Ok(DriveInfo(Err(e.into()))),
+					Response::Done => Err(malformed()),
+				});
+				info.unwrap_or_else(|e| DriveInfo(Err(e.into())))
+			}
+		}
+	}
+
+	/// SECURITY UNLOCK with the user password. Passwords for `automated`
+	/// attempts go through `throttle`.
+	pub fn unlock(&self, password: [u8; 32], automated: bool) -> ops::Result<()> {
+		match *self {
+			Drive::Local(dev, ref f) => unlock(f, dev, password, automated),
+			Drive::Brokered(dev, client) => client.command(&Request::Unlock {
+				device: dev.display().to_string(),
+				password: hex(&password),
+				automated,
+			}),
+		}
+	}
+
+	pub fn freeze(&self) -> ops::Result<()> {
+		match *self {
+			Drive::Local(dev, ref f) => freeze(f, dev),
+			Drive::Brokered(dev, client) => client.command(&Request::Freeze {
+				device: dev.display().to_string(),
+			}),
+		}
+	}
+
+	pub fn after_unlock(&self, freeze: bool, reset: bool) -> ops::Result<()> {
+		match *self {
+			Drive::Local(dev, ref f) => freeze_reset(f, dev, freeze, reset),
+			Drive::Brokered(dev, client) => client.command(&Request::AfterUnlock {
+				device: dev.display().to_string(),
+				freeze,
+				reset,
+			}),
+		}
+	}
+}
+
+// The commands changing the drive, recorded in the audit log where they are
+// executed: by the daemon itself, or by the broker on its behalf.
+
+// The records of `throttle` are kept in the same place, so that the daemon
+// can not read or clear them once it has given up root. They are keyed on
+// the serial number the drive reports here.
+fn unlock(f: &File, dev: &Path, password: [u8; 32], automated: bool) -> ops::Result<()> {
+	let serial = config::serial(&try!(ops::identify_controller(f.as_raw_fd())));
+	if automated {
+		if let Err(message) = throttle::check(&serial, &password) {
+			return Err(ops::Error::Device(io::Error::new(
+				io::ErrorKind::Other,
+				message,
+			)));
+		}
+	}
+	let result = security_unlock(f, password, false);
+	audit::record("unlock", dev, f, &result);
+	match result {
+		Ok(()) => throttle::accepted(&serial),
+		Err(ref e) if automated => {
+			if let Err(e) = throttle::rejected(&serial, &password, e) {
+				eprintln!("Unable to record the rejected password: {}", e);
+			}
+		}
+		Err(_) => {}
+	}
+	result
+}
+
+fn freeze(f: &File, dev: &Path) -> ops::Result<()> {
+	let result = security_freeze(f);
+	audit::record("freeze", dev, f, &result);
+	result
+}
+
+fn freeze_reset(f: &File, dev: &Path, freeze: bool, reset: bool) -> ops::Result<()> {
+	let result = after_unlock(f, dev, freeze, reset, false);
+	let action = match (freeze, reset) {
+		(false, false) => return result,
+		(true, false) => "freeze",
+		(false, true) => "reset",
+		(true, true) => "reset,freeze",
+	};
+	audit::record(action, dev, f, &result);
+	result
+}
+
+fn respond(request: Request) -> Response {
+	let dev = PathBuf::from(request.device());
+	// Only NVMe namespaces, whatever the daemon asks for
+	let namespace = dev.parent() == Some(Path::new("/dev"))
+		&& dev
+			.file_name()
+			.and_then(|name| name.to_str())
+			.map_or(false, device::is_namespace_name);
+	if !namespace {
+		return Response::Error(WireError::Device(format!(
+			"{} is not an NVMe namespace",
+			dev.display()
+		)));
+	}
+	let f = match open_device(&dev, true) {
+		Err(e) => return Response::Error(e.into()),
+		Ok(f) => f,
+	};
+	let result = match request {
+		Request::Query { .. } => return Response::Info(DriveInfo::query(&f).into()),
+		Request::Unlock {
+			ref password,
+			automated,
+			..
+		} => match decode_hex(password) {
+			Some(ref bytes) if bytes.len() == 32 => {
+				let mut password = [0u8; 32];
+				password.copy_from_slice(bytes);
+				unlock(&f, &dev, password, automated)
+			}
+			_ => Err(ops::Error::Device(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"the password must be 32 bytes",
+			))),
+		},
+		Request::Freeze { .. } => freeze(&f, &dev),
+		Request::AfterUnlock { freeze, reset, .. } => freeze_reset(&f, &dev, freeze, reset),
+	};
+	match result {
+		Err(e) => Response::Error(e.into()),
+		Ok(()) => Response::Done,
+	}
+}
+
+fn serve(stream: UnixStream) -> io::Result<()> {
+	let mut writer = try!(stream.try_clone());
+	for line in BufReader::new(stream).lines() {
+		let response = match serde_json::from_str::<Request>(&try!(line)) {
+			Err(e) => Response::Error(WireError::Device(format!("Invalid request: {}", e))),
+			Ok(request) => respond(request),
+		};
+		try!(writeln!(
+			writer,
+			"{}",
+			serde_json::to_string(&response).unwrap()
+		));
+	}
+	Ok(())
+}
+
+/// Fork the broker, which keeps the privileges of this process and exits
+/// when the daemon closes its end. Must be called before starting threads
+/// other than the idle launcher thread, which the broker does without.
+pub fn spawn(sandbox: bool) -> io::Result<Client> {
+	let (daemon, broker) = try!(UnixStream::pair());
+	match unsafe { libc::fork() } {
+		-1 => Err(io::Error::last_os_error()),
+		0 => {
+			drop(daemon);
+			if sandbox {
+				let profile = sandbox::Profile {
+					exec: false,
+					accept: false,
+				};
+				if let Err(e) = sandbox::restrict(profile) {
+					eprintln!("Unable to install the seccomp sandbox of the broker: {}", e);
+					process::exit(EXIT_FAILURE);
+				}
+			}
+			match serve(broker) {
+				Err(e) => {
+					eprintln!("The broker failed: {}", e);
+					process::exit(EXIT_FAILURE);
+				}
+				Ok(()) => process::exit(0),
+			}
+		}
+		_ => Ok(Client(Mutex::new(BufReader::new(daemon)))),
+	}
+}
+
+/// Switch to `user` and its primary group, giving up root. The records of
+/// rejected passwords stay with root, for the broker.
+pub fn drop_privileges(user: &str) -> io::Result<()> {
+	let name = try!(CString::new(user).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+	let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+	if passwd.is_null() {
+		return Err(io::Error::new(
+			io::ErrorKind::NotFound,
+			format!("there is no user {}", user),
+		));
+	}
+	let (uid, gid) = unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) };
+	// glibc applies these to all threads of the process
+	unsafe {
+		if libc::setgroups(0, ptr::null()) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0
+		{
+			return Err(io::Error::last_os_error());
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,132 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Nagios/Icinga plugin for `check`.
+//!
+//! Compares the state of the configured drives with their `expect_frozen`
+//! and `expect_unlocked` settings. The first line of output is the plugin
+//! status, followed by one line per problem, and the exit status follows the
+//! plugin conventions.
+
+use std::fs::File;
+use std::path::Path;
+
+use config;
+use device;
+use DriveInfo;
+
+const OK: i32 = 0;
+const WARNING: i32 = 1;
+const CRITICAL: i32 = 2;
+const UNKNOWN: i32 = 3;
+
+#[derive(Default)]
+struct Problems {
+	critical: Vec<String>,
+	warning: Vec<String>,
+}
+
+fn check_drive(
+	config: &config::Config,
+	dev: &Path,
+	checked: &mut Vec<String>,
+	problems: &mut Problems,
+) {
+	let f = match File::open(dev) {
+		Err(e) => {
+			problems
+				.warning
+				.push(format!("{}: unable to open: {}", dev.display(), e));
+			return;
+		}
+		Ok(f) => f,
+	};
+	let (identity, security) = match DriveInfo::query(&f).0 {
+		Err(_) => return,
+		Ok((identity, security)) => (identity, security),
+	};
+	let device_config = match config.device(&identity) {
+		Some(c) if c.expect_frozen || c.expect_unlocked => c,
+		_ => return,
+	};
+	let serial = config::serial(&identity);
+	let name = format!("{} ({})", serial, dev.display());
+	checked.push(serial);
+	let security = match security {
+		Ok(Some((_, Ok(Some(security))))) => security,
+		_ => {
+			problems
+				.warning
+				.push(format!("{}: unable to query ATA security", name));
+			return;
+		}
+	};
+	if device_config.expect_unlocked && security.locked() {
+		problems.critical.push(format!("{}: locked", name));
+	}
+	if device_config.expect_frozen && !security.frozen() {
+		problems.critical.push(format!("{}: not frozen", name));
+	}
+	if security.pwncntex() {
+		problems
+			.warning
+			.push(format!("{}: password attempt counter exceeded", name));
+	}
+}
+
+pub fn run(config: &config::Config) -> i32 {
+	let devices = match device::namespaces() {
+		Err(e) => {
+			println!(
+				"ATA SECURITY UNKNOWN - unable to enumerate NVMe devices: {}",
+				e
+			);
+			return UNKNOWN;
+		}
+		Ok(devices) => devices,
+	};
+	let mut checked = vec![];
+	let mut problems = Problems::default();
+	for dev in devices {
+		check_drive(config, &dev, &mut checked, &mut problems);
+	}
+	for (serial, c) in &config.device {
+		if (c.expect_frozen || c.expect_unlocked) && !checked.contains(serial) {
+			problems.warning.push(format!("{}: not present", serial));
+		}
+	}
+	let (status, label) = if !problems.critical.is_empty() {
+		(CRITICAL, "CRITICAL")
+	} else if !problems.warning.is_empty() {
+		(WARNING, "WARNING")
+	} else {
+		(OK, "OK")
+	};
+	let count = problems.critical.len() + problems.warning.len();
+	if count == 0 {
+		println!(
+			"ATA SECURITY OK - {} drives as expected | drives={}",
+			checked.len(),
+			checked.len()
+		);
+	} else {
+		println!(
+			"ATA SECURITY {} - {} problems | drives={} problems={}",
+			label,
+			count,
+			checked.len(),
+			count
+		);
+	}
+	for problem in problems.critical.iter().chain(&problems.warning) {
+		println!("{}", problem);
+	}
+	status
+}
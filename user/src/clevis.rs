@@ -0,0 +1,45 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Network-bound passwords through Clevis.
+//!
+//! A password encrypted with a Clevis pin, typically `tang`, can only be
+//! decrypted while the Tang server is reachable, so drives unlock
+//! automatically on the corporate network and nowhere else:
+//!
+//! ```sh
+//! nvme-ata-security gen-password | clevis encrypt tang '{"url":"http://tang"}' > drive.jwe
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::process::{Command, Stdio};
+
+use launcher;
+
+/// Decrypt the Clevis JWE in `path` with `clevis decrypt`.
+pub fn decrypt(path: &str) -> io::Result<Vec<u8>> {
+	let mut command = Command::new("clevis");
+	command
+		.arg("decrypt")
+		.stdin(try!(File::open(path)))
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped());
+	let output = try!(launcher::spawn(command)
+		.and_then(|child| child.wait_with_output())
+		.map_err(|e| io::Error::new(e.kind(), format!("Unable to run clevis: {}", e))));
+	if !output.status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("clevis decrypt failed with {}", output.status),
+		));
+	}
+	Ok(output.stdout)
+}
@@ -0,0 +1,206 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Per-device defaults from `/etc/nvme-ata-security.conf`.
+//!
+//! The file is TOML with one table per drive, keyed by serial number, and
+//! optionally the path of the audit log and checks on new passwords:
+//!
+//! ```toml
+//! audit_log = "/var/log/nvme-ata-security.log"
+//! control_uids = [1000]
+//! daemon_user = "nvme-ata-security"
+//!
+//! [password_policy]
+//! min_length = 12
+//! min_entropy = 50
+//! enforce = true
+//!
+//! [device.S3EWNX0K123456]
+//! password_file = "/etc/nvme-ata-security/S3EWNX0K123456.key"
+//! # or clevis = "/etc/nvme-ata-security/S3EWNX0K123456.jwe"
+//! security = "high"
+//! freeze = true
+//! reset = true
+//! normalize = "nfc"
+//! expect_frozen = true
+//! expect_unlocked = true
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+
+use toml;
+
+use normalize::Normalization;
+use nvme::identify::IdentifyController;
+
+pub const DEFAULT_PATH: &'static str = "/etc/nvme-ata-security.conf";
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// Append a record of security-altering commands to this file
+	pub audit_log: Option<String>,
+	/// Users besides root who may query drives through the control socket of
+	/// the daemon
+	#[serde(default)]
+	pub control_uids: Vec<u32>,
+	/// Run the daemon as this user, with a privileged broker for the drive
+	/// commands
+	pub daemon_user: Option<String>,
+	#[serde(default)]
+	pub password_policy: PasswordPolicy,
+	#[serde(default)]
+	pub device: HashMap<String, DeviceConfig>,
+}
+
+/// Checks on new passwords typed on the terminal.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicy {
+	/// Minimum length in characters
+	pub min_length: usize,
+	/// Minimum estimated entropy in bits
+	pub min_entropy: u32,
+	/// Refuse passwords that fail the checks instead of warning
+	pub enforce: bool,
+}
+
+impl Default for PasswordPolicy {
+	fn default() -> PasswordPolicy {
+		PasswordPolicy {
+			min_length: 12,
+			min_entropy: 50,
+			enforce: false,
+		}
+	}
+}
+
+impl PasswordPolicy {
+	/// The reasons `password` doesn't meet the policy.
+	///
+	/// The entropy estimate assumes characters are picked at random from the
+	/// character classes used, which overestimates it for words.
+	pub fn check(&self, password: &str) -> Vec<String> {
+		let mut problems = vec![];
+		let length = password.chars().count();
+		if length < self.min_length {
+			problems.push(format!("shorter than {} characters", self.min_length));
+		}
+		let classes: [(fn(char) -> bool, u32); 5] = [
+			(|c| c.is_ascii_lowercase(), 26),
+			(|c| c.is_ascii_uppercase(), 26),
+			(|c| c.is_ascii_digit(), 10),
+			(|c| c.is_ascii_punctuation() || c == ' ', 33),
+			(|c| !c.is_ascii(), 100),
+		];
+		let pool: u32 = classes
+			.iter()
+			.filter(|&&(class, _)| password.chars().any(class))
+			.map(|&(_, size)| size)
+			.sum();
+		let entropy = (length as f64 * (pool.max(1) as f64).log2()) as u32;
+		if entropy < self.min_entropy {
+			problems.push(format!(
+				"about {} bits of entropy, less than {}",
+				entropy, self.min_entropy
+			));
+		}
+		let distinct = {
+			let mut chars: Vec<char> = password.chars().collect();
+			chars.sort();
+			chars.dedup();
+			chars.len()
+		};
+		if length > 0 && distinct * 2 < length {
+			problems.push("mostly repeated characters".to_owned());
+		}
+		problems
+	}
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+	/// Read the password from this file instead of prompting
+	pub password_file: Option<String>,
+	/// Decrypt the password from this Clevis JWE, e.g. bound to a Tang server
+	pub clevis: Option<String>,
+	/// Security level used by `set-password -u`
+	pub security: Option<SecurityLevel>,
+	/// Issue SECURITY FREEZE LOCK after unlocking
+	#[serde(default)]
+	pub freeze: bool,
+	/// Reset the controller after unlocking
+	#[serde(default)]
+	pub reset: bool,
+	/// Unicode normalization applied to the password
+	pub normalize: Option<Normalization>,
+	/// `check` reports the drive as critical unless it is frozen
+	#[serde(default)]
+	pub expect_frozen: bool,
+	/// `check` reports the drive as critical if it is locked
+	#[serde(default)]
+	pub expect_unlocked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityLevel {
+	High,
+	Max,
+}
+
+#[derive(Debug)]
+pub enum Error {
+	Io(String, io::Error),
+	Parse(String, toml::de::Error),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Error::Io(ref path, ref e) => write!(fmt, "Unable to read {}: {}", path, e),
+			Error::Parse(ref path, ref e) => write!(fmt, "Unable to parse {}: {}", path, e),
+		}
+	}
+}
+
+impl Config {
+	/// Load the configuration from `path`, or from the default location if
+	/// `path` is `None`. A missing default configuration file is not an error.
+	pub fn load(path: Option<&str>) -> Result<Config, Error> {
+		let (path, required) = match path {
+			Some(path) => (path, true),
+			None => (DEFAULT_PATH, false),
+		};
+		let mut buf = String::new();
+		match File::open(path).and_then(|mut f| f.read_to_string(&mut buf)) {
+			Err(ref e) if !required && e.kind() == io::ErrorKind::NotFound => {
+				return Ok(Config::default())
+			}
+			Err(e) => return Err(Error::Io(path.to_owned(), e)),
+			Ok(_) => {}
+		}
+		toml::from_str(&buf).map_err(|e| Error::Parse(path.to_owned(), e))
+	}
+
+	/// The configuration for the drive with the given identity.
+	pub fn device(&self, identity: &IdentifyController) -> Option<&DeviceConfig> {
+		self.device.get(&serial(identity))
+	}
+}
+
+pub fn serial(identity: &IdentifyController) -> String {
+	String::from_utf8_lossy(identity.sn()).trim().to_owned()
+}
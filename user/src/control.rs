@@ -0,0 +1,497 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! The control socket of the daemon.
+//!
+//! With `daemon --control`, the daemon listens on a Unix socket for requests
+//! from other services and from `remote-unlock`, which connects to it,
+//! possibly through an SSH-forwarded socket, and supplies passwords for the
+//! drives that are still locked, e.g. data drives without a password file on
+//! a headless server.
+//!
+//! Requests and responses are single lines of JSON, such as
+//! `{"command":"query","serial":"S3EWNX0K123456"}`. The commands are `list`,
+//! `locked` and `query` (drive state), `unlock` with a `password`, and
+//! `freeze`. Clients are identified by their peer credentials: root may use
+//! all commands, the users in `control_uids` of the configuration file only
+//! those that do not change a drive, and others are disconnected. Commands
+//! that change a drive are serialized per drive, including those of the
+//! D-Bus service.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use libc;
+use rpassword;
+use serde_json;
+
+use broker;
+use config;
+use device;
+use journal;
+use report;
+use {access, derive_password, inventory, EXIT_FAILURE};
+
+pub const SOCKET: &'static str = "/run/nvme-ata-security.sock";
+
+/// Clients served at the same time, more are turned away
+const MAX_CLIENTS: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+	List,
+	Locked,
+	Query { serial: String },
+	Unlock { serial: String, password: String },
+	Freeze { serial: String },
+}
+
+impl Request {
+	/// Whether the request changes the state of a drive.
+	fn alters_state(&self) -> bool {
+		match *self {
+			Request::List | Request::Locked | Request::Query { .. } => false,
+			Request::Unlock { .. } | Request::Freeze { .. } => true,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Response {
+	Drives(Vec<Drive>),
+	/// The `list` inventory or `query` report, as printed with
+	/// `--format=json`
+	Report(serde_json::Value),
+	Unlocked,
+	Frozen,
+	Error(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Drive {
+	device: String,
+	model: String,
+	serial: String,
+}
+
+/// The user ID of the process at the other end of `stream`.
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+	let mut cred: libc::ucred = unsafe { mem::zeroed() };
+	let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+	let ret = unsafe {
+		libc::getsockopt(
+			stream.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_PEERCRED,
+			&mut cred as *mut _ as *mut libc::c_void,
+			&mut len,
+		)
+	};
+	if ret < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(cred.uid)
+}
+
+/// The locked drives.
+fn locked(broker: Option<&broker::Client>) -> io::Result<Vec<Drive>> {
+	let mut drives = vec![];
+	for dev in try!(device::namespaces()) {
+		let drive = match broker::Drive::open(&dev, false, broker) {
+			Err(_) => continue,
+			Ok(drive) => drive,
+		};
+		// Drives without ATA security are not worth an error message here
+		if let Ok((ref identity, Ok(Some((_, Ok(Some(ref security))))))) = drive.query().0 {
+			if security.locked() {
+				drives.push(Drive {
+					device: dev.to_string_lossy().into_owned(),
+					model: String::from_utf8_lossy(identity.mn()).trim().to_owned(),
+					serial: config::serial(identity),
+				});
+			}
+		}
+	}
+	Ok(drives)
+}
+
+/// Devices with a command that changes their state in progress.
+static BUSY: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+static IDLE: Condvar = Condvar::new();
+
+/// Exclusive use of a device for changing its state, so that an unlock and
+/// the freeze or reset after it are not interleaved with another client's.
+struct DeviceLock(PathBuf);
+
+impl DeviceLock {
+	fn acquire(dev: &Path) -> DeviceLock {
+		let mut busy = BUSY.lock().unwrap();
+		while busy.iter().any(|d| d == dev) {
+			busy = IDLE.wait(busy).unwrap();
+		}
+		busy.push(dev.to_owned());
+		DeviceLock(dev.to_owned())
+	}
+}
+
+impl Drop for DeviceLock {
+	fn drop(&mut self) {
+		BUSY.lock().unwrap().retain(|d| *d != self.0);
+		IDLE.notify_all();
+	}
+}
+
+/// The device of the drive with serial number `serial`.
+fn find(serial: &str) -> Result<PathBuf, String> {
+	match device::find_by_serial(serial) {
+		Err(e) => Err(format!("Unable to enumerate NVMe devices: {}", e)),
+		Ok(None) => Err(format!("No drive with serial number {}", serial)),
+		Ok(Some(dev)) => Ok(dev),
+	}
+}
+
+/// Open `dev`, for writing if `write`.
+fn open<'a>(
+	dev: &'a Path,
+	write: bool,
+	broker: Option<&'a broker::Client>,
+) -> Result<broker::Drive<'a>, String> {
+	broker::Drive::open(dev, write, broker).map_err(|e| {
+		format!(
+			"Unable to open {} for {}: {}",
+			dev.display(),
+			access(write),
+			e
+		)
+	})
+}
+
+fn log_result(
+	log: &journal::Log,
+	dev: &Path,
+	serial: &str,
+	action: &str,
+	result: &Result<(), String>,
+	success: &str,
+) {
+	log.event(journal::Event {
+		priority: if result.is_ok() {
+			journal::Priority::Notice
+		} else {
+			journal::Priority::Err
+		},
+		dev,
+		serial: Some(serial),
+		action,
+		result: if result.is_ok() { "success" } else { "error" },
+		message: match *result {
+			Ok(()) => success.to_owned(),
+			Err(ref e) => e.clone(),
+		},
+	});
+}
+
+/// The report of the drive with serial number `serial`, as printed by
+/// `query --format=json`.
+pub fn query(broker: Option<&broker::Client>, serial: &str) -> Result<serde_json::Value, String> {
+	let dev = try!(find(serial));
+	let drive = try!(open(&dev, false, broker));
+	let report = report::DriveReport::new(&dev.display().to_string(), &drive.query());
+	serde_json::to_value(&report).map_err(|e| e.to_string())
+}
+
+pub fn freeze(
+	broker: Option<&broker::Client>,
+	log: &journal::Log,
+	serial: &str,
+) -> Result<(), String> {
+	let dev = try!(find(serial));
+	let _lock = DeviceLock::acquire(&dev);
+	let drive = try!(open(&dev, true, broker));
+	let result = drive
+		.freeze()
+		.map_err(|e| format!("There was an error executing the command: {:?}", e));
+	log_result(log, &dev, serial, "freeze", &result, "Frozen");
+	result
+}
+
+/// Unlock the drive with serial number `serial`, then freeze or reset it as
+/// configured.
+pub fn unlock(
+	config: &config::Config,
+	broker: Option<&broker::Client>,
+	log: &journal::Log,
+	serial: &str,
+	password: &str,
+) -> Result<(), String> {
+	let dev = try!(find(serial));
+	// Checked again under the lock, another client may have just unlocked
+	let _lock = DeviceLock::acquire(&dev);
+	let drive = try!(open(&dev, true, broker));
+	let (identity, security) = try!(drive
+		.query()
+		.check_support()
+		.map_err(|_| "The drive does not support ATA security".to_owned()));
+	if !security.locked() {
+		return Ok(());
+	}
+	if security.pwncntex() {
+		return Err(
+			"The password attempt counter is exceeded, power cycle the drive before trying again"
+				.to_owned(),
+		);
+	}
+	let device_config = config.device(&identity).cloned().unwrap_or_default();
+	let password = try!(derive_password(
+		password.as_bytes().to_vec(),
+		&identity,
+		device_config.normalize
+	)
+	.map_err(|e| e.to_string()));
+	let result = drive
+		.unlock(password, false)
+		.and_then(|()| drive.after_unlock(device_config.freeze, device_config.reset))
+		.map_err(|e| format!("There was an error executing the command: {:?}", e));
+	log_result(log, &dev, serial, "remote-unlock", &result, "Unlocked");
+	result
+}
+
+fn respond(
+	config: &config::Config,
+	broker: Option<&broker::Client>,
+	log: &journal::Log,
+	request: Request,
+) -> Response {
+	match request {
+		Request::List => match inventory(broker) {
+			Err(e) => Response::Error(format!("Unable to enumerate NVMe devices: {}", e)),
+			Ok(inventory) => Response::Report(serde_json::to_value(&inventory).unwrap()),
+		},
+		Request::Locked => match locked(broker) {
+			Err(e) => Response::Error(format!("Unable to enumerate NVMe devices: {}", e)),
+			Ok(drives) => Response::Drives(drives),
+		},
+		Request::Query { serial } => match query(broker, &serial) {
+			Err(e) => Response::Error(e),
+			Ok(v) => Response::Report(v),
+		},
+		Request::Unlock { serial, password } => {
+			match unlock(config, broker, log, &serial, &password) {
+				Err(e) => Response::Error(e),
+				Ok(()) => Response::Unlocked,
+			}
+		}
+		Request::Freeze { serial } => match freeze(broker, log, &serial) {
+			Err(e) => Response::Error(e),
+			Ok(()) => Response::Frozen,
+		},
+	}
+}
+
+/// Whether the user `uid` may use the socket at all.
+fn allowed(config: &config::Config, uid: libc::uid_t) -> bool {
+	uid == 0 || config.control_uids.contains(&uid)
+}
+
+fn handle(
+	config: &config::Config,
+	broker: Option<&broker::Client>,
+	log: &journal::Log,
+	stream: UnixStream,
+	uid: libc::uid_t,
+) -> io::Result<()> {
+	let mut writer = try!(stream.try_clone());
+	for line in BufReader::new(stream).lines() {
+		let response = match serde_json::from_str::<Request>(&try!(line)) {
+			Err(e) => Response::Error(format!("Invalid request: {}", e)),
+			Ok(ref request) if request.alters_state() && uid != 0 => {
+				Response::Error("Permission denied".to_owned())
+			}
+			Ok(request) => respond(config, broker, log, request),
+		};
+		try!(writeln!(
+			writer,
+			"{}",
+			serde_json::to_string(&response).unwrap()
+		));
+	}
+	Ok(())
+}
+
+/// Refuse the client on `stream` with `message`.
+fn refuse(mut stream: UnixStream, message: &str) {
+	let response = Response::Error(message.to_owned());
+	let _ = writeln!(stream, "{}", serde_json::to_string(&response).unwrap());
+}
+
+/// Decrements the number of clients when a client thread ends.
+struct Client(Arc<AtomicUsize>);
+
+impl Drop for Client {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Create `SOCKET`, before the daemon drops its privileges and enters the
+/// sandbox. Connections wait until `listen` accepts them.
+pub fn bind() -> io::Result<UnixListener> {
+	let _ = fs::remove_file(SOCKET);
+	let listener = try!(UnixListener::bind(SOCKET));
+	// Access is checked with the peer credentials
+	try!(fs::set_permissions(
+		SOCKET,
+		fs::Permissions::from_mode(0o666)
+	));
+	Ok(listener)
+}
+
+/// Accept connections on `listener` in the background, handling each client
+/// on its own thread, up to `MAX_CLIENTS` at once. Users that may not use the
+/// socket are turned away before a thread is started. With `broker`, drive
+/// commands are sent through it.
+pub fn listen(
+	listener: UnixListener,
+	config: Arc<config::Config>,
+	broker: Option<Arc<broker::Client>>,
+) {
+	let clients = Arc::new(AtomicUsize::new(0));
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let stream = match stream {
+				Err(e) => {
+					eprintln!("Unable to accept control connection: {}", e);
+					continue;
+				}
+				Ok(stream) => stream,
+			};
+			let uid = match peer_uid(&stream) {
+				Err(e) => {
+					eprintln!("Unable to identify control client: {}", e);
+					continue;
+				}
+				Ok(uid) => uid,
+			};
+			if !allowed(&config, uid) {
+				refuse(stream, "Permission denied");
+				continue;
+			}
+			if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+				clients.fetch_sub(1, Ordering::SeqCst);
+				refuse(stream, "Too many clients, try again later");
+				continue;
+			}
+			let client = Client(clients.clone());
+			let config = config.clone();
+			let broker = broker.clone();
+			thread::spawn(move || {
+				let _client = client;
+				let broker = broker.as_ref().map(|client| &**client);
+				if let Err(e) = handle(&config, broker, &journal::Log::daemon(), stream, uid) {
+					eprintln!("Unable to handle control connection: {}", e);
+				}
+			});
+		}
+	});
+}
+
+fn call(stream: &mut BufReader<UnixStream>, request: &Request) -> io::Result<Response> {
+	try!(writeln!(
+		stream.get_mut(),
+		"{}",
+		serde_json::to_string(request).unwrap()
+	));
+	let mut line = String::new();
+	if try!(stream.read_line(&mut line)) == 0 {
+		return Err(io::Error::new(
+			io::ErrorKind::UnexpectedEof,
+			"the daemon closed the connection",
+		));
+	}
+	serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// `remote-unlock`: prompt for the password of each drive the daemon
+/// reports locked. An empty password skips the drive.
+pub fn remote_unlock(socket: &Path) -> i32 {
+	let mut stream = match UnixStream::connect(socket) {
+		Err(e) => {
+			eprintln!("Unable to connect to {}: {}", socket.display(), e);
+			return EXIT_FAILURE;
+		}
+		Ok(stream) => BufReader::new(stream),
+	};
+	let drives = match call(&mut stream, &Request::Locked) {
+		Err(e) => {
+			eprintln!("Unable to talk to the daemon: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(Response::Drives(drives)) => drives,
+		Ok(Response::Error(e)) => {
+			eprintln!("{}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(_) => {
+			eprintln!("Unexpected response from the daemon");
+			return EXIT_FAILURE;
+		}
+	};
+	if drives.is_empty() {
+		eprintln!("No drives are locked");
+		return 0;
+	}
+	let mut status = 0;
+	for drive in drives {
+		eprint!(
+			"Please enter password for {} {} ({}), or nothing to skip:",
+			drive.model, drive.serial, drive.device
+		);
+		let password = match rpassword::read_password() {
+			Err(e) => {
+				eprintln!("Error trying to read password: {}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(password) => password,
+		};
+		if password.is_empty() {
+			continue;
+		}
+		let request = Request::Unlock {
+			serial: drive.serial.clone(),
+			password,
+		};
+		match call(&mut stream, &request) {
+			Err(e) => {
+				eprintln!("Unable to talk to the daemon: {}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(Response::Unlocked) => eprintln!("Unlocked {}", drive.device),
+			Ok(Response::Error(e)) => {
+				eprintln!("Unable to unlock {}: {}", drive.device, e);
+				status = EXIT_FAILURE;
+			}
+			Ok(_) => {
+				eprintln!("Unexpected response from the daemon");
+				return EXIT_FAILURE;
+			}
+		}
+	}
+	status
+}
@@ -0,0 +1,709 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! D-Bus service of the daemon.
+//!
+//! With `daemon --dbus`, the daemon owns `org.nvme.AtaSecurity` on the system
+//! bus, so desktop environments can let users unlock a secondary drive. The
+//! object `/org/nvme/AtaSecurity` has the methods `Query(s serial) -> s`,
+//! which returns the report of `query --format=json`, `Unlock(s serial, s
+//! password)` and `Freeze(s serial)`. Each call is authorized by polkit for
+//! the caller, with the actions of `polkit/org.nvme.AtaSecurity.policy`, so
+//! the desktop prompts for authentication where the policy asks for it.
+//! `dbus/org.nvme.AtaSecurity.conf` lets root own the name.
+//!
+//! Only what this needs of the D-Bus protocol is implemented: EXTERNAL
+//! authentication on a Unix socket and the marshalling of strings, integers
+//! and the polkit subject. Calls are handled one at a time, including the
+//! authentication prompt.
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use libc;
+
+use broker;
+use config;
+use control;
+use journal;
+
+pub const NAME: &'static str = "org.nvme.AtaSecurity";
+const PATH: &'static str = "/org/nvme/AtaSecurity";
+const INTERFACE: &'static str = "org.nvme.AtaSecurity";
+const INTROSPECTABLE: &'static str = "org.freedesktop.DBus.Introspectable";
+const FAILED: &'static str = "org.nvme.AtaSecurity.Error.Failed";
+const SYSTEM_BUS: &'static str = "/run/dbus/system_bus_socket";
+// Calls to this service are tiny, larger messages are dropped
+const MAX_MESSAGE: u64 = 0x1_0000;
+
+const INTROSPECTION: &'static str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.nvme.AtaSecurity">
+    <method name="Query">
+      <arg name="serial" type="s" direction="in"/>
+      <arg name="report" type="s" direction="out"/>
+    </method>
+    <method name="Unlock">
+      <arg name="serial" type="s" direction="in"/>
+      <arg name="password" type="s" direction="in"/>
+    </method>
+    <method name="Freeze">
+      <arg name="serial" type="s" direction="in"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+// Message types
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+// Message flags
+const NO_REPLY_EXPECTED: u8 = 1;
+
+// Header fields
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+fn malformed() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "malformed D-Bus message")
+}
+
+/// Marshalling in little endian. Alignment is relative to the start of the
+/// buffer, which must be the start of a message or of its body.
+struct Writer(Vec<u8>);
+
+impl Writer {
+	fn align(&mut self, n: usize) {
+		while self.0.len() % n != 0 {
+			self.0.push(0);
+		}
+	}
+
+	fn byte(&mut self, b: u8) {
+		self.0.push(b);
+	}
+
+	fn u32(&mut self, v: u32) {
+		self.align(4);
+		let mut buf = [0u8; 4];
+		LittleEndian::write_u32(&mut buf, v);
+		self.0.extend_from_slice(&buf);
+	}
+
+	fn string(&mut self, s: &str) {
+		self.u32(s.len() as u32);
+		self.0.extend_from_slice(s.as_bytes());
+		self.0.push(0);
+	}
+
+	fn signature(&mut self, s: &str) {
+		self.byte(s.len() as u8);
+		self.0.extend_from_slice(s.as_bytes());
+		self.0.push(0);
+	}
+
+	/// Start an array with elements aligned to `align`. Returns what
+	/// `end_array` needs to fill in the length.
+	fn begin_array(&mut self, align: usize) -> (usize, usize) {
+		self.u32(0);
+		let length = self.0.len() - 4;
+		self.align(align);
+		(length, self.0.len())
+	}
+
+	fn end_array(&mut self, (length, start): (usize, usize)) {
+		let len = (self.0.len() - start) as u32;
+		LittleEndian::write_u32(&mut self.0[length..length + 4], len);
+	}
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+	pos: usize,
+	big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+		if self.data.len() - self.pos < n {
+			return Err(malformed());
+		}
+		self.pos += n;
+		Ok(&self.data[self.pos - n..self.pos])
+	}
+
+	fn align(&mut self, n: usize) -> io::Result<()> {
+		let padding = (n - self.pos % n) % n;
+		self.take(padding).map(|_| ())
+	}
+
+	fn byte(&mut self) -> io::Result<u8> {
+		self.take(1).map(|b| b[0])
+	}
+
+	fn u32(&mut self) -> io::Result<u32> {
+		try!(self.align(4));
+		let buf = try!(self.take(4));
+		Ok(if self.big_endian {
+			BigEndian::read_u32(buf)
+		} else {
+			LittleEndian::read_u32(buf)
+		})
+	}
+
+	/// The bytes of a string or signature and its terminating nul.
+	fn text(&mut self, len: usize) -> io::Result<String> {
+		let buf = try!(self.take(len + 1));
+		if buf[len] != 0 {
+			return Err(malformed());
+		}
+		String::from_utf8(buf[..len].to_vec()).map_err(|_| malformed())
+	}
+
+	fn string(&mut self) -> io::Result<String> {
+		let len = try!(self.u32()) as usize;
+		self.text(len)
+	}
+
+	fn signature(&mut self) -> io::Result<String> {
+		let len = try!(self.byte()) as usize;
+		self.text(len)
+	}
+}
+
+#[derive(Debug, Default)]
+struct Message {
+	kind: u8,
+	flags: u8,
+	serial: u32,
+	path: Option<String>,
+	interface: Option<String>,
+	member: Option<String>,
+	error_name: Option<String>,
+	reply_serial: Option<u32>,
+	destination: Option<String>,
+	sender: Option<String>,
+	signature: String,
+	big_endian: bool,
+	body: Vec<u8>,
+}
+
+impl Message {
+	fn method_call(
+		destination: &str,
+		path: &str,
+		interface: &str,
+		member: &str,
+		signature: &str,
+		body: Vec<u8>,
+	) -> Message {
+		Message {
+			kind: METHOD_CALL,
+			path: Some(path.to_owned()),
+			interface: Some(interface.to_owned()),
+			member: Some(member.to_owned()),
+			destination: Some(destination.to_owned()),
+			signature: signature.to_owned(),
+			body,
+			..Message::default()
+		}
+	}
+
+	/// The reply to this method call, with a string if `value`.
+	fn reply(&self, value: Option<&str>) -> Message {
+		let mut body = Writer(vec![]);
+		if let Some(value) = value {
+			body.string(value);
+		}
+		Message {
+			kind: METHOD_RETURN,
+			reply_serial: Some(self.serial),
+			destination: self.sender.clone(),
+			signature: if value.is_some() { "s" } else { "" }.to_owned(),
+			body: body.0,
+			..Message::default()
+		}
+	}
+
+	fn error(&self, name: &str, message: &str) -> Message {
+		let mut reply = self.reply(Some(message));
+		reply.kind = ERROR;
+		reply.error_name = Some(name.to_owned());
+		reply
+	}
+
+	fn body<'a>(&'a self) -> Reader<'a> {
+		Reader {
+			data: &self.body,
+			pos: 0,
+			big_endian: self.big_endian,
+		}
+	}
+
+	/// The arguments of a body of only strings.
+	fn strings(&self) -> io::Result<Vec<String>> {
+		let mut body = self.body();
+		self.signature
+			.chars()
+			.map(|c| match c {
+				's' => body.string(),
+				_ => Err(malformed()),
+			})
+			.collect()
+	}
+
+	fn encode(&self) -> Vec<u8> {
+		let mut w = Writer(vec![]);
+		w.byte(b'l');
+		w.byte(self.kind);
+		w.byte(self.flags);
+		w.byte(1);
+		w.u32(self.body.len() as u32);
+		w.u32(self.serial);
+		let fields = w.begin_array(8);
+		let strings = [
+			(FIELD_PATH, "o", &self.path),
+			(FIELD_INTERFACE, "s", &self.interface),
+			(FIELD_MEMBER, "s", &self.member),
+			(FIELD_ERROR_NAME, "s", &self.error_name),
+			(FIELD_DESTINATION, "s", &self.destination),
+		];
+		for &(code, signature, value) in strings.iter() {
+			if let Some(ref value) = *value {
+				w.align(8);
+				w.byte(code);
+				w.signature(signature);
+				w.string(value);
+			}
+		}
+		if let Some(serial) = self.reply_serial {
+			w.align(8);
+			w.byte(FIELD_REPLY_SERIAL);
+			w.signature("u");
+			w.u32(serial);
+		}
+		if !self.signature.is_empty() {
+			w.align(8);
+			w.byte(FIELD_SIGNATURE);
+			w.signature("g");
+			w.signature(&self.signature);
+		}
+		w.end_array(fields);
+		w.align(8);
+		w.0.extend_from_slice(&self.body);
+		w.0
+	}
+
+	fn decode(data: &[u8]) -> io::Result<Message> {
+		let mut r = Reader {
+			data,
+			pos: 0,
+			big_endian: false,
+		};
+		r.big_endian = match try!(r.byte()) {
+			b'l' => false,
+			b'B' => true,
+			_ => return Err(malformed()),
+		};
+		let mut message = Message {
+			kind: try!(r.byte()),
+			flags: try!(r.byte()),
+			big_endian: r.big_endian,
+			..Message::default()
+		};
+		if try!(r.byte()) != 1 {
+			return Err(malformed());
+		}
+		let body_len = try!(r.u32()) as usize;
+		message.serial = try!(r.u32());
+		let fields_len = try!(r.u32()) as usize;
+		try!(r.align(8));
+		let end = r.pos + fields_len;
+		while r.pos < end {
+			try!(r.align(8));
+			let code = try!(r.byte());
+			let value = match &try!(r.signature())[..] {
+				"s" | "o" => Some(try!(r.string())),
+				"g" => Some(try!(r.signature())),
+				"u" => {
+					let value = try!(r.u32());
+					if code == FIELD_REPLY_SERIAL {
+						message.reply_serial = Some(value);
+					}
+					None
+				}
+				_ => return Err(malformed()),
+			};
+			match code {
+				FIELD_PATH => message.path = value,
+				FIELD_INTERFACE => message.interface = value,
+				FIELD_MEMBER => message.member = value,
+				FIELD_ERROR_NAME => message.error_name = value,
+				FIELD_DESTINATION => message.destination = value,
+				FIELD_SENDER => message.sender = value,
+				FIELD_SIGNATURE => message.signature = value.unwrap_or_default(),
+				_ => {}
+			}
+		}
+		if r.pos != end {
+			return Err(malformed());
+		}
+		try!(r.align(8));
+		message.body = try!(r.take(body_len)).to_vec();
+		Ok(message)
+	}
+}
+
+/// The socket of the system bus, from `DBUS_SYSTEM_BUS_ADDRESS` if set.
+fn system_bus() -> io::Result<String> {
+	let addresses = match env::var("DBUS_SYSTEM_BUS_ADDRESS") {
+		Err(_) => return Ok(SYSTEM_BUS.to_owned()),
+		Ok(addresses) => addresses,
+	};
+	for address in addresses.split(';') {
+		if !address.starts_with("unix:") {
+			continue;
+		}
+		for key_value in address["unix:".len()..].split(',') {
+			if key_value.starts_with("path=") {
+				return unescape(&key_value["path=".len()..]);
+			}
+		}
+	}
+	Err(io::Error::new(
+		io::ErrorKind::InvalidInput,
+		format!("no Unix socket path in D-Bus address {}", addresses),
+	))
+}
+
+/// Undo the %-escaping of D-Bus addresses.
+fn unescape(value: &str) -> io::Result<String> {
+	let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid D-Bus address");
+	let mut bytes = vec![];
+	let mut rest = value.as_bytes();
+	while let Some((&b, tail)) = rest.split_first() {
+		if b == b'%' {
+			if tail.len() < 2 {
+				return Err(invalid());
+			}
+			let hex = try!(::std::str::from_utf8(&tail[..2]).map_err(|_| invalid()));
+			bytes.push(try!(u8::from_str_radix(hex, 16).map_err(|_| invalid())));
+			rest = &tail[2..];
+		} else {
+			bytes.push(b);
+			rest = tail;
+		}
+	}
+	String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// A connection to the system bus.
+pub struct Connection {
+	stream: UnixStream,
+	serial: u32,
+	/// Messages received while waiting for a reply
+	pending: VecDeque<Message>,
+}
+
+impl Connection {
+	/// Connect and authenticate to the system bus.
+	fn system() -> io::Result<Connection> {
+		let mut stream = try!(UnixStream::connect(try!(system_bus())));
+		let uid = unsafe { libc::getuid() }.to_string();
+		let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+		try!(stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes()));
+		let mut line = vec![];
+		let mut byte = [0u8];
+		while !line.ends_with(b"\r\n") {
+			if line.len() > 512 {
+				return Err(malformed());
+			}
+			try!(stream.read_exact(&mut byte));
+			line.push(byte[0]);
+		}
+		if !line.starts_with(b"OK ") {
+			return Err(io::Error::new(
+				io::ErrorKind::PermissionDenied,
+				format!(
+					"the bus refused authentication: {}",
+					String::from_utf8_lossy(&line).trim()
+				),
+			));
+		}
+		try!(stream.write_all(b"BEGIN\r\n"));
+		let mut connection = Connection {
+			stream,
+			serial: 0,
+			pending: VecDeque::new(),
+		};
+		try!(connection.call(Message::method_call(
+			"org.freedesktop.DBus",
+			"/org/freedesktop/DBus",
+			"org.freedesktop.DBus",
+			"Hello",
+			"",
+			vec![]
+		)));
+		Ok(connection)
+	}
+
+	fn send(&mut self, mut message: Message) -> io::Result<u32> {
+		self.serial += 1;
+		message.serial = self.serial;
+		try!(self.stream.write_all(&message.encode()));
+		Ok(self.serial)
+	}
+
+	/// The next message, or `None` for one that is dropped.
+	fn receive(&mut self) -> io::Result<Option<Message>> {
+		let mut fixed = [0u8; 16];
+		try!(self.stream.read_exact(&mut fixed));
+		let read_u32 = if fixed[0] == b'B' {
+			BigEndian::read_u32
+		} else {
+			LittleEndian::read_u32
+		};
+		let body_len = read_u32(&fixed[4..8]) as u64;
+		let fields_len = read_u32(&fixed[12..16]) as u64;
+		let len = (16 + fields_len + 7) / 8 * 8 + body_len;
+		if len > MAX_MESSAGE {
+			let skipped = try!(io::copy(
+				&mut (&self.stream).take(len - 16),
+				&mut io::sink()
+			));
+			return if skipped == len - 16 {
+				Ok(None)
+			} else {
+				Err(io::ErrorKind::UnexpectedEof.into())
+			};
+		}
+		let mut data = fixed.to_vec();
+		data.resize(len as usize, 0);
+		try!(self.stream.read_exact(&mut data[16..]));
+		Ok(Message::decode(&data).ok())
+	}
+
+	/// The next message that is not a reply to `call`.
+	fn next(&mut self) -> io::Result<Message> {
+		if let Some(message) = self.pending.pop_front() {
+			return Ok(message);
+		}
+		loop {
+			if let Some(message) = try!(self.receive()) {
+				return Ok(message);
+			}
+		}
+	}
+
+	/// Call a method and wait for its reply.
+	fn call(&mut self, call: Message) -> io::Result<Message> {
+		let serial = try!(self.send(call));
+		loop {
+			let message = match try!(self.receive()) {
+				None => continue,
+				Some(message) => message,
+			};
+			if message.reply_serial != Some(serial) {
+				self.pending.push_back(message);
+				continue;
+			}
+			if message.kind == ERROR {
+				let text = message.strings().ok().and_then(|s| s.into_iter().next());
+				return Err(io::Error::new(
+					io::ErrorKind::Other,
+					format!(
+						"{}: {}",
+						message.error_name.unwrap_or_default(),
+						text.unwrap_or_default()
+					),
+				));
+			}
+			return Ok(message);
+		}
+	}
+
+	fn request_name(&mut self, name: &str) -> io::Result<()> {
+		const DO_NOT_QUEUE: u32 = 4;
+		const PRIMARY_OWNER: u32 = 1;
+
+		let mut body = Writer(vec![]);
+		body.string(name);
+		body.u32(DO_NOT_QUEUE);
+		let reply = try!(self.call(Message::method_call(
+			"org.freedesktop.DBus",
+			"/org/freedesktop/DBus",
+			"org.freedesktop.DBus",
+			"RequestName",
+			"su",
+			body.0
+		)));
+		if reply.signature != "u" || try!(reply.body().u32()) != PRIMARY_OWNER {
+			return Err(io::Error::new(
+				io::ErrorKind::AddrInUse,
+				format!("{} is already owned", name),
+			));
+		}
+		Ok(())
+	}
+
+	/// Whether polkit authorizes the client `sender` for `action`, after
+	/// prompting for authentication if the policy asks for it.
+	fn authorized(&mut self, sender: &str, action: &str) -> io::Result<bool> {
+		const ALLOW_USER_INTERACTION: u32 = 1;
+
+		let mut body = Writer(vec![]);
+		// The subject: (sa{sv})
+		body.align(8);
+		body.string("system-bus-name");
+		let details = body.begin_array(8);
+		body.align(8);
+		body.string("name");
+		body.signature("s");
+		body.string(sender);
+		body.end_array(details);
+		body.string(action);
+		// No details: a{ss}
+		let details = body.begin_array(8);
+		body.end_array(details);
+		body.u32(ALLOW_USER_INTERACTION);
+		// No cancellation ID
+		body.string("");
+		let reply = try!(self.call(Message::method_call(
+			"org.freedesktop.PolicyKit1",
+			"/org/freedesktop/PolicyKit1/Authority",
+			"org.freedesktop.PolicyKit1.Authority",
+			"CheckAuthorization",
+			"(sa{sv})sa{ss}us",
+			body.0
+		)));
+		if !reply.signature.starts_with("(bb") {
+			return Err(malformed());
+		}
+		// The struct is at the start of the body, already aligned
+		reply.body().u32().map(|authorized| authorized != 0)
+	}
+}
+
+/// Handle a method call, returning the reply or error.
+fn respond(
+	connection: &mut Connection,
+	config: &config::Config,
+	broker: Option<&broker::Client>,
+	log: &journal::Log,
+	call: &Message,
+) -> Message {
+	let path = call.path.as_ref().map_or("", String::as_str);
+	let interface = call.interface.as_ref().map(String::as_str);
+	let member = call.member.as_ref().map_or("", String::as_str);
+	if path != PATH {
+		let message = format!("No object {}", path);
+		return call.error("org.freedesktop.DBus.Error.UnknownObject", &message);
+	}
+	if interface.map_or(true, |i| i == INTROSPECTABLE) && member == "Introspect" {
+		return call.reply(Some(INTROSPECTION));
+	}
+	if interface.map_or(false, |i| i != INTERFACE) {
+		let message = format!("No interface {}", interface.unwrap());
+		return call.error("org.freedesktop.DBus.Error.UnknownInterface", &message);
+	}
+	let (action, signature) = match member {
+		"Query" => ("org.nvme.AtaSecurity.query", "s"),
+		"Unlock" => ("org.nvme.AtaSecurity.unlock", "ss"),
+		"Freeze" => ("org.nvme.AtaSecurity.freeze", "s"),
+		_ => {
+			let message = format!("No method {}", member);
+			return call.error("org.freedesktop.DBus.Error.UnknownMethod", &message);
+		}
+	};
+	let args = match call.strings() {
+		Ok(ref args) if call.signature == signature => args.clone(),
+		_ => {
+			let message = format!("{} takes arguments of type {}", member, signature);
+			return call.error("org.freedesktop.DBus.Error.InvalidArgs", &message);
+		}
+	};
+	let sender = call.sender.as_ref().map_or("", String::as_str);
+	match connection.authorized(sender, action) {
+		Err(e) => return call.error(FAILED, &format!("Unable to check authorization: {}", e)),
+		Ok(false) => {
+			return call.error("org.freedesktop.DBus.Error.AccessDenied", "Not authorized")
+		}
+		Ok(true) => {}
+	}
+	let result = match member {
+		"Query" => control::query(broker, &args[0]).map(|report| Some(report.to_string())),
+		"Unlock" => control::unlock(config, broker, log, &args[0], &args[1]).map(|()| None),
+		_ => control::freeze(broker, log, &args[0]).map(|()| None),
+	};
+	match result {
+		Err(e) => call.error(FAILED, &e),
+		Ok(report) => call.reply(report.as_ref().map(String::as_str)),
+	}
+}
+
+/// Connect to the system bus and own `NAME`, before the daemon drops its
+/// privileges and enters the sandbox. Calls wait until `serve` handles them.
+pub fn connect() -> io::Result<Connection> {
+	let mut connection = try!(Connection::system());
+	try!(connection.request_name(NAME));
+	Ok(connection)
+}
+
+/// Handle the calls on `connection` in the background. With `broker`, drive
+/// commands are sent through it.
+pub fn serve(
+	mut connection: Connection,
+	config: Arc<config::Config>,
+	broker: Option<Arc<broker::Client>>,
+) {
+	thread::spawn(move || {
+		let broker = broker.as_ref().map(|client| &**client);
+		let log = journal::Log::daemon();
+		loop {
+			let call = match connection.next() {
+				Err(e) => {
+					eprintln!("Lost the connection to the system bus: {}", e);
+					return;
+				}
+				Ok(message) => message,
+			};
+			if call.kind != METHOD_CALL {
+				continue;
+			}
+			let reply = respond(&mut connection, &config, broker, &log, &call);
+			if call.flags & NO_REPLY_EXPECTED != 0 {
+				continue;
+			}
+			if let Err(e) = connection.send(reply) {
+				eprintln!("Lost the connection to the system bus: {}", e);
+				return;
+			}
+		}
+	});
+}
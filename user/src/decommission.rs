@@ -0,0 +1,275 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! `decommission`: erase a drive for disposal.
+//!
+//! The steps are those of a careful decommissioning by hand: check the
+//! password if one is set, perform the erase `erase-advisor` recommends,
+//! wait for it to finish, make sure no password is left on the drive and
+//! write a certificate of erasure, see `erasure`.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use advisor::{self, Method};
+use erasure::Certificate;
+use interrupt;
+use normalize;
+use nvme;
+use nvme::log::SanitizeState;
+use nvme::security::{AtaSecurityIdentify, EraseTime};
+use ops::{self, Result};
+use state::State;
+use {
+	ata_security_identify, check_firmware_activation, check_not_in_use, claim_for_erase, finish,
+	format_namespace, print_erase_estimate, read_password, release_claim, sanitize_wait,
+	security_disable_password, security_erase, security_verify_password, write_report, DriveInfo,
+	PasswordSource, EXIT_FAILURE, EXIT_UNSUPPORTED,
+};
+
+/// How often to report the time an erase has taken
+const PROGRESS_SECS: u64 = 60;
+
+pub struct Options<'a> {
+	pub master: bool,
+	pub normalize: Option<normalize::Normalization>,
+	pub force: bool,
+	pub allow_fabrics: bool,
+	pub report: &'a str,
+	pub sign_key: Option<&'a str>,
+}
+
+fn other(msg: &str) -> ops::Error {
+	io::Error::new(io::ErrorKind::Other, msg).into()
+}
+
+/// Run `erase`, telling how long it has been running every `PROGRESS_SECS`.
+fn with_progress<F: FnOnce() -> Result<()>>(estimate: Option<EraseTime>, erase: F) -> Result<()> {
+	let (done, stopped) = mpsc::channel::<()>();
+	let start = Instant::now();
+	let ticker = thread::spawn(move || {
+		while let Err(RecvTimeoutError::Timeout) =
+			stopped.recv_timeout(Duration::from_secs(PROGRESS_SECS))
+		{
+			let minutes = start.elapsed().as_secs() / 60;
+			match estimate {
+				Some(estimate) => eprintln!(
+					"Erasing, {} minutes so far, the drive estimates {}",
+					minutes, estimate
+				),
+				None => eprintln!("Erasing, {} minutes so far", minutes),
+			}
+		}
+	});
+	let result = erase();
+	drop(done);
+	let _ = ticker.join();
+	result
+}
+
+/// Wait for the sanitize operation started on `fd` to finish successfully.
+pub fn wait_for_sanitize(fd: RawFd) -> Result<()> {
+	match try!(sanitize_wait(fd)).state() {
+		SanitizeState::Completed | SanitizeState::CompletedWithoutDeallocate => Ok(()),
+		SanitizeState::Failed => Err(other("the sanitize operation failed")),
+		_ => Err(other("the drive does not report the sanitize operation")),
+	}
+}
+
+fn erase(
+	f: &File,
+	method: Method,
+	security: Option<&AtaSecurityIdentify>,
+	password: Option<[u8; 32]>,
+	master: bool,
+) -> Result<()> {
+	let fd = f.as_raw_fd();
+	match method {
+		Method::EnhancedAtaErase | Method::AtaErase => {
+			// The advisor only recommends this with a password set
+			let enhanced = method == Method::EnhancedAtaErase;
+			let estimate = security.and_then(|s| s.erase_time(enhanced));
+			if let Some(security) = security {
+				print_erase_estimate(security, enhanced);
+			}
+			eprintln!("Performing SECURITY ERASE...");
+			interrupt::during(interrupt::Operation::Erase);
+			let result = with_progress(estimate, || {
+				security_erase(f, password.unwrap(), master, enhanced)
+			});
+			interrupt::done();
+			result
+		}
+		Method::SanitizeCryptoErase | Method::SanitizeBlockErase => {
+			eprintln!("Starting sanitize...");
+			try!(ops::sanitize(
+				fd,
+				if method == Method::SanitizeCryptoErase {
+					nvme::SanitizeAction::CryptoErase
+				} else {
+					nvme::SanitizeAction::BlockErase
+				},
+			));
+			wait_for_sanitize(fd)
+		}
+		Method::FormatCryptoErase | Method::FormatUserDataErase => {
+			eprintln!("Performing FORMAT NVM...");
+			interrupt::during(interrupt::Operation::Format);
+			let result = with_progress(None, || {
+				format_namespace(
+					fd,
+					if method == Method::FormatCryptoErase {
+						nvme::SecureErase::Cryptographic
+					} else {
+						nvme::SecureErase::UserData
+					},
+				)
+			});
+			interrupt::done();
+			result
+		}
+	}
+}
+
+/// Make sure no password is left on the drive. An ATA security erase
+/// removes it, sanitize and Format NVM leave it in place.
+fn remove_password(
+	f: &File,
+	password: Option<[u8; 32]>,
+	master: bool,
+) -> Result<AtaSecurityIdentify> {
+	let security = try!(ata_security_identify(f));
+	let security = match password {
+		Some(password) if security.s_enabld() => {
+			eprintln!("Performing SECURITY DISABLE PASSWORD...");
+			try!(security_disable_password(f, password, master));
+			try!(ata_security_identify(f))
+		}
+		_ => security,
+	};
+	if security.s_enabld() {
+		return Err(other("a password is still set after the erase"));
+	}
+	Ok(security)
+}
+
+pub fn decommission(
+	f: &File,
+	dev: &str,
+	info: &DriveInfo,
+	password: PasswordSource,
+	options: &Options,
+) -> i32 {
+	let (identity, security) = match info.0 {
+		Err(ref e) => {
+			eprintln!(
+				"There was an error obtaining NVMe identity information:\n{:?}",
+				e
+			);
+			return EXIT_FAILURE;
+		}
+		Ok((ref i, Ok(Some((_, Ok(Some(ref s))))))) => (i, Some(s)),
+		Ok((ref i, _)) => (i, None),
+	};
+	if identity.is_fabrics() && !options.allow_fabrics {
+		eprintln!(
+			"{} is an NVMe over Fabrics controller, the target may share the drive with other \
+			 hosts. Use --allow-fabrics to continue anyway.",
+			dev
+		);
+		return EXIT_FAILURE;
+	}
+	if !check_not_in_use(dev, options.force) || !check_firmware_activation(f, options.force) {
+		return EXIT_FAILURE;
+	}
+	let claim = match claim_for_erase(dev, options.force) {
+		Some(claim) => claim,
+		None => return EXIT_FAILURE,
+	};
+
+	let password = match security {
+		Some(s) if s.s_enabld() => {
+			if s.frozen() {
+				eprintln!(
+					"{} is frozen, its password can't be used until it is power cycled, see \
+					 unfreeze",
+					dev
+				);
+				return EXIT_FAILURE;
+			}
+			if s.pwncntex() {
+				eprintln!(
+					"The password attempt counter of {} is exceeded, power cycle the drive first",
+					dev
+				);
+				return EXIT_FAILURE;
+			}
+			let password = match read_password(
+				password,
+				identity,
+				"password",
+				false,
+				None,
+				options.normalize,
+			) {
+				Ok(password) => password,
+				Err(code) => return code,
+			};
+			if options.master && s.maxset() {
+				// The master password can't unlock at maximum security, only
+				// the erase checks it
+				eprintln!("The master password can only be checked by the erase itself");
+			} else {
+				eprintln!("Verifying password...");
+				// Unlocks a locked drive, which proves the password as well
+				match security_verify_password(f, password, options.master) {
+					Err(e) => {
+						eprintln!("The password was not accepted: {:?}", e);
+						return EXIT_FAILURE;
+					}
+					Ok(false) => {
+						eprintln!("The drive is still locked, the password could not be verified");
+						return EXIT_FAILURE;
+					}
+					Ok(true) => {}
+				}
+			}
+			Some(password)
+		}
+		_ => None,
+	};
+
+	let method = match advisor::advise(identity, security).recommended {
+		Some(method) => method,
+		None => {
+			eprintln!("No erase mechanism is available for {}", dev);
+			return EXIT_UNSUPPORTED;
+		}
+	};
+	eprintln!("Erasing {} with {}", dev, method.name());
+	let mut certificate = Certificate::new(dev, identity);
+	certificate.method = Some(method);
+	let result = erase(f, method, security, password, options.master).and_then(|()| {
+		if security.map_or(false, |s| s.s_suprt()) {
+			let after = try!(remove_password(f, password, options.master));
+			certificate.ata_security = Some(State::from(&after).to_string());
+		}
+		Ok(())
+	});
+	release_claim(dev, claim);
+	if !write_report(certificate, &result, options.report, options.sign_key) {
+		return EXIT_FAILURE;
+	}
+	finish(result)
+}
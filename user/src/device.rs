@@ -0,0 +1,314 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Locate NVMe devices through sysfs and the persistent names udev creates in
+//! `/dev/disk/by-id`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use stack;
+
+/// Whether `name` is an NVMe namespace block device such as `nvme0n1`.
+///
+/// Hidden per-path multipath devices (`nvme0c0n1`) are not included.
+pub fn is_namespace_name(name: &str) -> bool {
+	if !name.starts_with("nvme") {
+		return false;
+	}
+	let mut parts = name[4..].splitn(2, 'n');
+	match (parts.next(), parts.next()) {
+		(Some(ctrl), Some(ns)) => {
+			!ctrl.is_empty()
+				&& !ns.is_empty()
+				&& ctrl.bytes().all(|b| b.is_ascii_digit())
+				&& ns.bytes().all(|b| b.is_ascii_digit())
+		}
+		_ => false,
+	}
+}
+
+/// Whether `name` is an NVMe controller character device such as `nvme0`.
+pub fn is_controller_name(name: &str) -> bool {
+	name.len() > 4 && name.starts_with("nvme") && name[4..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Whether `name` is a generic NVMe namespace character device such as
+/// `ng0n1`.
+pub fn is_generic_name(name: &str) -> bool {
+	name.starts_with("ng") && is_namespace_name(&format!("nvme{}", &name[2..]))
+}
+
+/// All NVMe namespace block devices in the system, sorted by name.
+pub fn namespaces() -> io::Result<Vec<PathBuf>> {
+	let mut names = vec![];
+	for entry in try!(fs::read_dir("/sys/block")) {
+		let name = try!(entry).file_name();
+		if let Some(name) = name.to_str() {
+			if is_namespace_name(name) {
+				names.push(name.to_owned());
+			}
+		}
+	}
+	names.sort();
+	Ok(names
+		.into_iter()
+		.map(|name| Path::new("/dev").join(name))
+		.collect())
+}
+
+/// The kernel name of a block device, e.g. `nvme0n1` for `/dev/nvme0n1` or a
+/// symlink to it.
+pub fn kernel_name(dev: &Path) -> io::Result<String> {
+	let dev = try!(fs::canonicalize(dev));
+	dev.file_name()
+		.and_then(|n| n.to_str())
+		.map(ToOwned::to_owned)
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid device path"))
+}
+
+/// The controller character device for the namespace block device `dev`,
+/// e.g. `/dev/nvme0` for `/dev/nvme0n1`. A controller device is returned as
+/// is. For a multipath namespace, this is the controller of the first live
+/// path. A generic device such as `/dev/ng0n1` maps to the controller of
+/// its block device.
+pub fn controller(dev: &Path) -> io::Result<PathBuf> {
+	let name = try!(kernel_name(dev));
+	if is_controller_name(&name) {
+		return Ok(Path::new("/dev").join(name));
+	}
+	if is_generic_name(&name) {
+		return controller(&Path::new("/dev").join(format!("nvme{}", &name[2..])));
+	}
+	let paths = try!(multipath_paths(dev));
+	if let Some(path) = paths.iter().find(|p| p.live()).or(paths.first()) {
+		return Ok(Path::new("/dev").join(&path.controller));
+	}
+	let link = try!(fs::read_link(
+		Path::new("/sys/class/block").join(name).join("device")
+	));
+	match link.file_name().and_then(|n| n.to_str()) {
+		Some(ctrl) if ctrl.starts_with("nvme") => Ok(Path::new("/dev").join(ctrl)),
+		_ => Err(io::Error::new(
+			io::ErrorKind::NotFound,
+			"unable to determine NVMe controller",
+		)),
+	}
+}
+
+/// The trimmed contents of the sysfs attribute `path`, `None` if it is
+/// missing or empty.
+pub fn read_attr(path: &Path) -> Option<String> {
+	fs::read_to_string(path)
+		.ok()
+		.map(|s| s.trim().to_owned())
+		.filter(|s| !s.is_empty())
+}
+
+/// One path of a namespace with native NVMe multipath, e.g. `nvme0c1n1`
+/// through controller `nvme1` of a dual-port drive.
+#[derive(Serialize, Debug)]
+pub struct NamespacePath {
+	pub name: String,
+	pub controller: String,
+	/// The state of the controller, e.g. `live`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub state: Option<String>,
+	/// The ANA state of the path, e.g. `optimized`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ana_state: Option<String>,
+}
+
+impl NamespacePath {
+	pub fn live(&self) -> bool {
+		self.state.as_ref().map(String::as_str) == Some("live")
+	}
+}
+
+/// The paths of `dev` if it is the head of a namespace with native NVMe
+/// multipath, sorted, or nothing for other devices. The kernel sends admin
+/// commands on the head to any of its paths.
+pub fn multipath_paths(dev: &Path) -> io::Result<Vec<NamespacePath>> {
+	let name = try!(kernel_name(dev));
+	let entries = match fs::read_dir(Path::new("/sys/class/block").join(name).join("multipath")) {
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+		r => try!(r),
+	};
+	let mut paths = vec![];
+	for entry in entries {
+		let name = try!(entry).file_name().to_string_lossy().into_owned();
+		let sysfs = Path::new("/sys/class/block").join(&name);
+		let link = match fs::read_link(sysfs.join("device")) {
+			Ok(link) => link,
+			Err(_) => continue,
+		};
+		let controller = match link.file_name().and_then(|n| n.to_str()) {
+			Some(controller) => controller.to_owned(),
+			None => continue,
+		};
+		paths.push(NamespacePath {
+			state: read_attr(&Path::new("/sys/class/nvme").join(&controller).join("state")),
+			ana_state: read_attr(&sysfs.join("ana_state")),
+			name,
+			controller,
+		});
+	}
+	paths.sort_by(|a, b| a.name.cmp(&b.name));
+	Ok(paths)
+}
+
+/// The controller serial number of a namespace, from sysfs.
+pub fn serial(dev: &Path) -> Option<String> {
+	let name = kernel_name(dev).ok()?;
+	read_attr(
+		&Path::new("/sys/class/block")
+			.join(name)
+			.join("device/serial"),
+	)
+}
+
+/// The namespace WWID, e.g. `eui.0025385b71b0e1f2`.
+pub fn wwid(dev: &Path) -> Option<String> {
+	let name = kernel_name(dev).ok()?;
+	read_attr(&Path::new("/sys/class/block").join(name).join("wwid"))
+}
+
+/// The transport of the controller of `dev`, e.g. `pcie`, `tcp` or `rdma`.
+pub fn transport(dev: &Path) -> Option<String> {
+	let ctrl = controller(dev).ok()?;
+	let name = ctrl.file_name()?.to_str()?.to_owned();
+	read_attr(&Path::new("/sys/class/nvme").join(name).join("transport"))
+}
+
+/// The state of the controller of `dev`, e.g. `live`, `resetting` or `dead`.
+pub fn controller_state(dev: &Path) -> Option<String> {
+	let ctrl = controller(dev).ok()?;
+	let name = ctrl.file_name()?.to_str()?.to_owned();
+	read_attr(&Path::new("/sys/class/nvme").join(name).join("state"))
+}
+
+/// What the kernel knows about the controller of a device.
+#[derive(Serialize, Debug)]
+pub struct Controller {
+	/// The kernel name, e.g. `nvme0`
+	pub name: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub state: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub transport: Option<String>,
+	/// The number of I/O queues plus the admin queue
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub queue_count: Option<u32>,
+	/// The firmware revision when the controller was probed, which is
+	/// outdated after activating new firmware without a reset
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub firmware_rev: Option<String>,
+}
+
+/// The sysfs attributes of the controller of `dev`.
+pub fn controller_info(dev: &Path) -> Option<Controller> {
+	let ctrl = controller(dev).ok()?;
+	let name = ctrl.file_name()?.to_str()?.to_owned();
+	let sysfs = Path::new("/sys/class/nvme").join(&name);
+	Some(Controller {
+		state: read_attr(&sysfs.join("state")),
+		transport: read_attr(&sysfs.join("transport")),
+		queue_count: read_attr(&sysfs.join("queue_count")).and_then(|n| n.parse().ok()),
+		firmware_rev: read_attr(&sysfs.join("firmware_rev")),
+		name,
+	})
+}
+
+/// The persistent names for `dev` in `/dev/disk/by-id`, sorted.
+pub fn persistent_names(dev: &Path) -> Vec<PathBuf> {
+	let target = match fs::canonicalize(dev) {
+		Ok(target) => target,
+		Err(_) => return vec![],
+	};
+	let mut names = vec![];
+	if let Ok(entries) = fs::read_dir("/dev/disk/by-id") {
+		for entry in entries.filter_map(|e| e.ok()) {
+			let path = entry.path();
+			if fs::canonicalize(&path).ok().as_ref() == Some(&target) {
+				names.push(path);
+			}
+		}
+	}
+	names.sort();
+	names
+}
+
+/// The first namespace of the controller with the given serial number.
+pub fn find_by_serial(serial_number: &str) -> io::Result<Option<PathBuf>> {
+	Ok(try!(namespaces())
+		.into_iter()
+		.find(|dev| serial(dev).as_ref().map(String::as_str) == Some(serial_number)))
+}
+
+/// The namespace block devices of controller `ctrl`, e.g. `nvme0n1`. With
+/// native multipath these are the heads of its subsystem, which are named
+/// after the subsystem rather than the controller.
+pub fn controller_namespaces(ctrl: &str) -> io::Result<Vec<String>> {
+	let mut names = vec![];
+	for entry in try!(fs::read_dir(Path::new("/sys/class/nvme").join(ctrl))) {
+		let name = try!(entry).file_name().to_string_lossy().into_owned();
+		if is_namespace_name(&name) {
+			names.push(name);
+		}
+	}
+	let subsystems = match fs::read_dir("/sys/class/nvme-subsystem") {
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => vec![],
+		r => try!(try!(r).collect::<io::Result<Vec<_>>>()),
+	};
+	for subsystem in subsystems {
+		let path = subsystem.path();
+		if !path.join(ctrl).exists() {
+			continue;
+		}
+		for entry in try!(fs::read_dir(&path)) {
+			let name = try!(entry).file_name().to_string_lossy().into_owned();
+			if is_namespace_name(&name) {
+				names.push(name);
+			}
+		}
+	}
+	names.sort();
+	names.dedup();
+	Ok(names)
+}
+
+/// Describe what uses the devices in `layer` and what holds them.
+fn layer_users(layer: &stack::Layer, users: &mut Vec<String>) {
+	for mount in &layer.mounts {
+		users.push(format!("{} is mounted on {}", layer.name, mount.path));
+	}
+	if layer.swap {
+		users.push(format!("{} is used as swap", layer.name));
+	}
+	for holder in &layer.holders {
+		if holder.kind != stack::Kind::Partition {
+			users.push(format!("{} is held by {}", layer.name, holder.name));
+		}
+		layer_users(holder, users);
+	}
+}
+
+/// What is using the namespaces of controller `ctrl`: mounted file systems,
+/// swap and stacked devices such as LVM physical volumes or dm-crypt, found
+/// by following the holders like `query --stack` does.
+pub fn users(ctrl: &str) -> io::Result<Vec<String>> {
+	let mut users = vec![];
+	for name in try!(controller_namespaces(ctrl)) {
+		let stack = try!(stack::stack(&Path::new("/dev").join(name)));
+		layer_users(&stack.layers, &mut users);
+	}
+	Ok(users)
+}
@@ -0,0 +1,111 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Certificates of erasure.
+//!
+//! `decommission`, and `erase` and `erase-advisor --auto` with
+//! `--report=<file>`, record what they did to a drive for data destruction
+//! policies and asset disposal records: the drive, the erase method, when it
+//! ran and how it ended. With `--sign-key=<key>`, `openssl` writes a detached
+//! signature next to the certificate, which is checked with
+//! `openssl dgst -sha256 -verify <public key> -signature <file>.sig <file>`.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::Command;
+
+use serde_json;
+
+use advisor::Method;
+use audit;
+use config;
+use launcher;
+use nvme::identify::IdentifyController;
+
+#[derive(Serialize, Debug)]
+pub struct Certificate {
+	pub tool: &'static str,
+	pub version: &'static str,
+	pub device: String,
+	pub model: String,
+	pub serial: String,
+	pub firmware: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub method: Option<Method>,
+	pub started: String,
+	pub finished: String,
+	/// `success` or `error`
+	pub result: &'static str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+	/// ATA security state after the erase, if the drive supports it
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ata_security: Option<String>,
+}
+
+impl Certificate {
+	/// A certificate for an erase of `device` starting now.
+	pub fn new(device: &str, identity: &IdentifyController) -> Certificate {
+		let now = audit::now();
+		Certificate {
+			tool: env!("CARGO_PKG_NAME"),
+			version: env!("CARGO_PKG_VERSION"),
+			device: device.to_owned(),
+			model: String::from_utf8_lossy(identity.mn()).trim().to_owned(),
+			serial: config::serial(identity),
+			firmware: String::from_utf8_lossy(identity.fr()).trim().to_owned(),
+			method: None,
+			started: now.clone(),
+			finished: now,
+			result: "error",
+			error: None,
+			ata_security: None,
+		}
+	}
+
+	/// Record the outcome of the erase.
+	pub fn finish<E: ::std::fmt::Debug>(&mut self, result: &Result<(), E>) {
+		self.finished = audit::now();
+		match *result {
+			Ok(()) => self.result = "success",
+			Err(ref e) => self.error = Some(format!("{:?}", e)),
+		}
+	}
+
+	/// Write the certificate to `path` and, with a private key, its
+	/// signature to `<path>.sig`.
+	pub fn write(&self, path: &str, sign_key: Option<&str>) -> io::Result<()> {
+		let json = try!(serde_json::to_string_pretty(self)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+		try!(try!(File::create(path)).write_all((json + "\n").as_bytes()));
+		match sign_key {
+			Some(key) => sign(path, key),
+			None => Ok(()),
+		}
+	}
+}
+
+/// Sign `path` with the private key in `key` using `openssl dgst`.
+fn sign(path: &str, key: &str) -> io::Result<()> {
+	let mut command = Command::new("openssl");
+	command
+		.args(&["dgst", "-sha256", "-sign", key, "-out"])
+		.arg(format!("{}.sig", path))
+		.arg(path);
+	let status = try!(launcher::status(command)
+		.map_err(|e| io::Error::new(e.kind(), format!("Unable to run openssl: {}", e))));
+	if !status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("openssl dgst failed with {}", status),
+		));
+	}
+	Ok(())
+}
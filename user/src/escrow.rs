@@ -0,0 +1,147 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Escrow of password files for recovery.
+//!
+//! `escrow export` collects the password files of all drives in the
+//! configuration file into a JSON bundle encrypted with `age` to an
+//! administrator's public key, for storage offline. `escrow import` decrypts
+//! such a bundle with the matching identity and writes the password files
+//! back, e.g. on a replacement host, printing the configuration to go with
+//! them.
+//!
+//! Passwords typed at a prompt, and Clevis, FIDO2 and PKCS#11 sources, which
+//! are bound to something other than a file, are not covered.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use serde_json;
+
+use audit;
+use config::Config;
+use launcher;
+use password_file;
+
+pub const FORMAT: &'static str = "nvme-ata-security-escrow-v1";
+
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+	format: String,
+	time: String,
+	drive: Vec<Drive>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Drive {
+	serial: String,
+	password_file: String,
+	/// Contents of the password file in hex
+	content: String,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Run `age` with `input` on stdin, returning its output.
+fn age(args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+	let mut command = Command::new("age");
+	command
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped());
+	let mut child = try!(launcher::spawn(command)
+		.map_err(|e| io::Error::new(e.kind(), format!("Unable to run age: {}", e))));
+	try!(child.stdin.take().unwrap().write_all(input));
+	let output = try!(child.wait_with_output());
+	if !output.status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("age failed with {}", output.status),
+		));
+	}
+	Ok(output.stdout)
+}
+
+/// Write the password files of `config` to `path`, encrypted to the age
+/// `recipient`. Returns the number of drives.
+pub fn export(config: &Config, recipient: &str, path: &str) -> io::Result<usize> {
+	let mut bundle = Bundle {
+		format: FORMAT.to_owned(),
+		time: audit::now(),
+		drive: vec![],
+	};
+	for (serial, device) in &config.device {
+		let password_file = match device.password_file {
+			Some(ref file) => file,
+			None => continue,
+		};
+		let mut content = vec![];
+		try!(File::open(password_file)
+			.and_then(|mut f| f.read_to_end(&mut content))
+			.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", password_file, e))));
+		bundle.drive.push(Drive {
+			serial: serial.clone(),
+			password_file: password_file.clone(),
+			content: content.iter().map(|b| format!("{:02x}", b)).collect(),
+		});
+	}
+	bundle.drive.sort_by(|a, b| a.serial.cmp(&b.serial));
+	let json = try!(
+		serde_json::to_vec(&bundle).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+	);
+	let encrypted = try!(age(&["--encrypt", "--recipient", recipient], &json));
+	let mut f = try!(OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.open(path));
+	try!(f.write_all(&encrypted));
+	Ok(bundle.drive.len())
+}
+
+/// Decrypt the bundle in `path` with the age `identity` file and write the
+/// password files it contains, refusing to overwrite existing ones. Returns
+/// the configuration for the drives.
+pub fn import(identity: &str, path: &str) -> io::Result<String> {
+	let mut encrypted = vec![];
+	try!(try!(File::open(path)).read_to_end(&mut encrypted));
+	let json = try!(age(&["--decrypt", "--identity", identity], &encrypted));
+	let bundle: Bundle =
+		try!(serde_json::from_slice(&json)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+	if bundle.format != FORMAT {
+		return Err(invalid_data("not an escrow bundle"));
+	}
+	let mut config = String::new();
+	for drive in &bundle.drive {
+		let content = try!(password_file::decode_hex(&drive.content)
+			.ok_or_else(|| invalid_data("invalid content")));
+		if let Some(dir) = Path::new(&drive.password_file).parent() {
+			try!(fs::create_dir_all(dir));
+		}
+		let mut f = try!(OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(0o600)
+			.open(&drive.password_file)
+			.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", drive.password_file, e))));
+		try!(f.write_all(&content));
+		config.push_str(&format!(
+			"[device.{}]\npassword_file = \"{}\"\n\n",
+			drive.serial, drive.password_file
+		));
+	}
+	Ok(config)
+}
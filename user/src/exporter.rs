@@ -0,0 +1,211 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Prometheus metrics for `exporter`.
+//!
+//! Serves the ATA security state of all drives on `/metrics` in the
+//! Prometheus text format. HTTP requests are handled one at a time. On every
+//! scrape the drives are queried by a pool of `pool::QUERY_JOBS` blocking
+//! threads, so a slow drive delays the response without stalling the others.
+//! With `--transport=uring` each command is instead submitted for all drives
+//! together on one ring, so any number of drives is queried at the same time
+//! from a single thread.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+
+use config;
+use device;
+use nvme::security::AtaSecurityIdentify;
+use ops;
+use pool;
+use sandbox;
+use DriveInfo;
+
+const GAUGES: &'static [(&'static str, &'static str, fn(&AtaSecurityIdentify) -> bool)] = &[
+	(
+		"nvme_ata_security_enabled",
+		"Whether a user password is set",
+		AtaSecurityIdentify::s_enabld,
+	),
+	(
+		"nvme_ata_security_locked",
+		"Whether the drive is locked",
+		AtaSecurityIdentify::locked,
+	),
+	(
+		"nvme_ata_security_frozen",
+		"Whether the security state is frozen until the next power cycle",
+		AtaSecurityIdentify::frozen,
+	),
+	(
+		"nvme_ata_security_attempts_exceeded",
+		"Whether the password attempt counter is exceeded",
+		AtaSecurityIdentify::pwncntex,
+	),
+];
+
+struct Drive {
+	labels: String,
+	security: Option<AtaSecurityIdentify>,
+}
+
+fn escape(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+fn drive(dev: &Path, info: DriveInfo) -> Option<Drive> {
+	let (identity, security) = match info.0 {
+		Err(_) => return None,
+		Ok((identity, Ok(Some((_, Ok(security)))))) => (identity, security),
+		Ok((identity, _)) => (identity, None),
+	};
+	let labels = format!(
+		"device=\"{}\",serial=\"{}\",model=\"{}\"",
+		escape(&dev.display().to_string()),
+		escape(&config::serial(&identity)),
+		escape(String::from_utf8_lossy(identity.mn()).trim())
+	);
+	Some(Drive { labels, security })
+}
+
+fn drives() -> io::Result<Vec<Drive>> {
+	let devices = try!(device::namespaces());
+	if !ops::uses_uring() {
+		return Ok(pool::map(devices, pool::QUERY_JOBS, |dev| {
+			File::open(dev)
+				.ok()
+				.and_then(|f| drive(dev, DriveInfo::query(&f)))
+		})
+		.into_iter()
+		.filter_map(|d| d)
+		.collect());
+	}
+	let (devices, files): (Vec<_>, Vec<_>) = devices
+		.into_iter()
+		.filter_map(|dev| File::open(&dev).ok().map(|f| (dev, f)))
+		.unzip();
+	Ok(devices
+		.iter()
+		.zip(DriveInfo::query_all(&files))
+		.filter_map(|(dev, info)| drive(dev, info))
+		.collect())
+}
+
+fn header(out: &mut String, name: &str, help: &str) {
+	out.push_str(&format!(
+		"# HELP {} {}\n# TYPE {} gauge\n",
+		name, help, name
+	));
+}
+
+/// All metrics in the Prometheus text format.
+pub fn metrics() -> io::Result<String> {
+	let drives = try!(drives());
+	let mut out = String::new();
+	header(
+		&mut out,
+		"nvme_ata_security_supported",
+		"Whether the drive supports ATA security",
+	);
+	for drive in &drives {
+		out.push_str(&format!(
+			"nvme_ata_security_supported{{{}}} {}\n",
+			drive.labels,
+			drive.security.is_some() as u8
+		));
+	}
+	for &(name, help, get) in GAUGES {
+		header(&mut out, name, help);
+		for drive in &drives {
+			if let Some(ref security) = drive.security {
+				out.push_str(&format!(
+					"{}{{{}}} {}\n",
+					name,
+					drive.labels,
+					get(security) as u8
+				));
+			}
+		}
+	}
+	header(
+		&mut out,
+		"nvme_ata_security_erase_time_minutes",
+		"Time the drive reports for a security erase",
+	);
+	for drive in &drives {
+		if let Some(ref security) = drive.security {
+			for &enhanced in &[false, true] {
+				if let Some(time) = security.erase_time(enhanced) {
+					out.push_str(&format!(
+						"nvme_ata_security_erase_time_minutes{{{},enhanced=\"{}\"}} {}\n",
+						drive.labels, enhanced, time.minutes
+					));
+				}
+			}
+		}
+	}
+	Ok(out)
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+	try!(stream.set_read_timeout(Some(Duration::from_secs(5))));
+	let mut request = String::new();
+	{
+		let mut reader = BufReader::new(&stream);
+		try!(reader.read_line(&mut request));
+		// Skip the headers, the request line is all that matters
+		let mut line = String::new();
+		while try!(reader.read_line(&mut line)) > 0 && line.trim() != "" {
+			line.clear();
+		}
+	}
+	let mut words = request.split_whitespace();
+	let (status, body) = match (words.next(), words.next()) {
+		(Some("GET"), Some("/metrics")) => match metrics() {
+			Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+			Ok(body) => ("200 OK", body),
+		},
+		(Some("GET"), _) => ("404 Not Found", "Metrics are at /metrics\n".to_owned()),
+		_ => ("405 Method Not Allowed", String::new()),
+	};
+	write!(
+		stream,
+		"HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+		 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+		status,
+		body.len(),
+		body
+	)
+}
+
+/// Serve metrics on `addr` until an error occurs, in the seccomp sandbox
+/// with `sandbox`.
+pub fn serve(addr: &str, sandbox: bool) -> io::Result<()> {
+	let listener = try!(TcpListener::bind(addr));
+	if sandbox {
+		try!(sandbox::restrict(sandbox::Profile {
+			exec: false,
+			accept: true,
+		}));
+	}
+	for stream in listener.incoming() {
+		if let Err(e) = stream.and_then(respond) {
+			eprintln!("Unable to serve metrics request: {}", e);
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,88 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! The NVMe features `feature get` and `feature set` know about.
+//!
+//! Only features that matter around security operations are exposed: the
+//! power state and autonomous power state transitions, which can slow down a
+//! long erase, the volatile write cache and the host memory buffer, which is
+//! best disabled before resetting the controller from userspace. Values set
+//! last until the next controller reset.
+
+use nvme::Feature;
+
+const FEATURES: &'static [(&'static str, Feature)] = &[
+	("power-management", Feature::PowerManagement),
+	("volatile-write-cache", Feature::VolatileWriteCache),
+	("apst", Feature::AutonomousPowerStateTransition),
+	("host-memory-buffer", Feature::HostMemoryBuffer),
+];
+
+pub fn parse(name: &str) -> Result<Feature, String> {
+	FEATURES
+		.iter()
+		.find(|f| f.0 == name)
+		.map(|f| f.1)
+		.ok_or_else(|| {
+			format!(
+				"Unknown feature {}, use one of: {}",
+				name,
+				FEATURES.iter().map(|f| f.0).collect::<Vec<_>>().join(", ")
+			)
+		})
+}
+
+pub fn name(feature: Feature) -> &'static str {
+	FEATURES.iter().find(|f| f.1 == feature).unwrap().0
+}
+
+fn on_off(value: u32) -> &'static str {
+	if value & 1 != 0 {
+		"enabled"
+	} else {
+		"disabled"
+	}
+}
+
+/// Describe `value`, Dword 0 of Get Features for `feature`.
+pub fn describe(feature: Feature, value: u32) -> String {
+	match feature {
+		Feature::PowerManagement => format!(
+			"power state {}, workload hint {}",
+			value & 0x1f,
+			(value >> 5) & 0x7
+		),
+		Feature::VolatileWriteCache
+		| Feature::AutonomousPowerStateTransition
+		| Feature::HostMemoryBuffer => on_off(value).to_owned(),
+	}
+}
+
+/// Dword 11 of Set Features to set `feature` to `value`.
+pub fn encode(feature: Feature, value: &str) -> Result<u32, String> {
+	match (feature, value) {
+		(Feature::PowerManagement, _) => match value.parse::<u32>() {
+			Ok(state) if state < 32 => Ok(state),
+			_ => Err(format!("Invalid power state {}, use 0 to 31", value)),
+		},
+		(Feature::VolatileWriteCache, "on") => Ok(1),
+		(Feature::VolatileWriteCache, "off") | (Feature::HostMemoryBuffer, "off") => Ok(0),
+		(Feature::HostMemoryBuffer, _) => Err(
+			"Only the kernel can provide a host memory buffer, it can only be turned off"
+				.to_owned(),
+		),
+		(Feature::AutonomousPowerStateTransition, _) => Err(
+			"Setting apst needs a power state transition table, use the kernel's \
+			 nvme_core.default_ps_max_latency_us instead"
+				.to_owned(),
+		),
+		_ => Err(format!("Invalid value {}, use on or off", value)),
+	}
+}
@@ -0,0 +1,210 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! FIDO2 security keys as a password source.
+//!
+//! Like systemd-cryptenroll, this uses the `hmac-secret` extension: the key
+//! computes an HMAC of a salt with a secret bound to a credential, which
+//! takes a touch of the key. `fido2-enroll` creates the credential and
+//! writes its ID and a random salt to a file:
+//!
+//! ```toml
+//! format = "nvme-ata-security-fido2-v1"
+//! rp_id = "nvme-ata-security"
+//! credential = "<base64>"
+//! salt = "<64 hex digits>"
+//! ```
+//!
+//! `--fido2=<file>` then uses the 32-byte HMAC as the password. The CTAP2
+//! exchange with the key is left to `fido2-token`, `fido2-cred` and
+//! `fido2-assert` from libfido2.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::{Command, Stdio};
+
+use toml;
+
+use launcher;
+use password_file;
+use rand;
+
+pub const FORMAT: &'static str = "nvme-ata-security-fido2-v1";
+
+const RP_ID: &'static str = "nvme-ata-security";
+
+const BASE64: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Enrollment {
+	format: String,
+	rp_id: String,
+	credential: String,
+	salt: String,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+fn base64_encode(buf: &[u8]) -> String {
+	let mut out = String::new();
+	for chunk in buf.chunks(3) {
+		let n = chunk
+			.iter()
+			.enumerate()
+			.fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+		for i in 0..4 {
+			if i <= chunk.len() {
+				out.push(BASE64[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+			} else {
+				out.push('=');
+			}
+		}
+	}
+	out
+}
+
+fn base64_decode(s: &str) -> io::Result<Vec<u8>> {
+	let mut out = vec![];
+	let (mut n, mut bits) = (0u32, 0);
+	for c in s.trim().bytes().filter(|&c| c != b'=') {
+		let v = try!(BASE64
+			.iter()
+			.position(|&b| b == c)
+			.ok_or_else(|| invalid_data("invalid base64")));
+		n = (n << 6 | v as u32) & 0xffff;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			out.push((n >> bits) as u8);
+		}
+	}
+	Ok(out)
+}
+
+fn random(len: usize) -> io::Result<Vec<u8>> {
+	let mut buf = vec![0u8; len];
+	try!(rand::getrandom(&mut buf));
+	Ok(buf)
+}
+
+/// Run a libfido2 tool with `input` on stdin, returning the lines of its
+/// output.
+fn run(tool: &str, args: &[&str], input: &[String]) -> io::Result<Vec<String>> {
+	let mut command = Command::new(tool);
+	command
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped());
+	let mut child = try!(launcher::spawn(command)
+		.map_err(|e| io::Error::new(e.kind(), format!("Unable to run {}: {}", tool, e))));
+	{
+		let stdin = child.stdin.as_mut().unwrap();
+		for line in input {
+			try!(writeln!(stdin, "{}", line));
+		}
+	}
+	let output = try!(child.wait_with_output());
+	if !output.status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("{} failed with {}", tool, output.status),
+		));
+	}
+	Ok(String::from_utf8_lossy(&output.stdout)
+		.lines()
+		.map(str::to_owned)
+		.collect())
+}
+
+/// The first FIDO2 key attached.
+fn device() -> io::Result<String> {
+	let lines = try!(run("fido2-token", &["-L"], &[]));
+	match lines.first().and_then(|l| l.split(':').next()) {
+		Some(dev) if !dev.is_empty() => Ok(dev.to_owned()),
+		_ => Err(io::Error::new(
+			io::ErrorKind::NotFound,
+			"no FIDO2 security key found",
+		)),
+	}
+}
+
+/// Create a credential with the `hmac-secret` extension on the first FIDO2
+/// key and write it to `path`.
+pub fn enroll(path: &str) -> io::Result<()> {
+	let dev = try!(device());
+	eprintln!("Touch the security key to create a credential...");
+	let input = [
+		base64_encode(&try!(random(32))),
+		RP_ID.to_owned(),
+		"nvme-ata-security".to_owned(),
+		base64_encode(&try!(random(32))),
+	];
+	let output = try!(run("fido2-cred", &["-M", "-h", dev.as_str()], &input));
+	// Client data hash, RP ID, format, authenticator data, credential ID, ...
+	let credential = try!(output
+		.get(4)
+		.ok_or_else(|| invalid_data("unexpected output from fido2-cred")));
+	let salt: String = try!(random(32))
+		.iter()
+		.map(|b| format!("{:02x}", b))
+		.collect();
+	let enrollment = Enrollment {
+		format: FORMAT.to_owned(),
+		rp_id: RP_ID.to_owned(),
+		credential: credential.clone(),
+		salt,
+	};
+	let toml = try!(
+		toml::to_string(&enrollment).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+	);
+	let mut f = try!(OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.open(path));
+	f.write_all(toml.as_bytes())
+}
+
+/// The password for the credential in `path`: the HMAC of its salt computed
+/// by the key.
+pub fn password(path: &str) -> io::Result<Vec<u8>> {
+	let mut buf = String::new();
+	try!(try!(File::open(path)).read_to_string(&mut buf));
+	let enrollment: Enrollment =
+		try!(toml::from_str(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+	if enrollment.format != FORMAT {
+		return Err(invalid_data("not a FIDO2 enrollment file"));
+	}
+	let salt = match password_file::decode_hex(&enrollment.salt) {
+		Some(ref salt) if salt.len() == 32 => base64_encode(salt),
+		_ => return Err(invalid_data("salt must be 64 hex digits")),
+	};
+	let dev = try!(device());
+	eprintln!("Touch the security key...");
+	let input = [
+		base64_encode(&try!(random(32))),
+		enrollment.rp_id,
+		enrollment.credential,
+		salt,
+	];
+	let output = try!(run("fido2-assert", &["-G", "-h", dev.as_str()], &input));
+	// The HMAC is the last line, after the optional user ID
+	let secret = try!(base64_decode(try!(output
+		.last()
+		.ok_or_else(|| invalid_data("unexpected output from fido2-assert")))));
+	if secret.len() != 32 {
+		return Err(invalid_data("unexpected output from fido2-assert"));
+	}
+	Ok(secret)
+}
@@ -0,0 +1,150 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Fleet master passwords.
+//!
+//! Instead of storing a master password for each drive, `--fleet=<file>`
+//! derives it from an organization secret, the serial number of the drive
+//! and the master password identifier with HKDF-SHA-256 (RFC 5869).
+//! Provisioning sets it with `set-password -m --id=<id> --fleet=<file>`, and
+//! decommissioning unlocks or erases with `-m --fleet=<file>`, taking the
+//! identifier from the drive. A new identifier gives a new generation of
+//! master passwords from the same secret.
+
+use std::fs::File;
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha256};
+
+const SALT: &'static [u8] = b"nvme-ata-security fleet master password";
+
+/// Shortest organization secret accepted, in bytes
+const MIN_SECRET: usize = 16;
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+	let mut block = [0u8; 64];
+	if key.len() > block.len() {
+		let mut sha256 = Sha256::new();
+		sha256.input(key);
+		block[..32].copy_from_slice(&sha256.result());
+	} else {
+		block[..key.len()].copy_from_slice(key);
+	}
+	let mut inner = Sha256::new();
+	inner.input(&block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+	for d in data {
+		inner.input(d);
+	}
+	let mut outer = Sha256::new();
+	outer.input(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+	outer.input(&inner.result());
+	let mut out = [0u8; 32];
+	out.copy_from_slice(&outer.result());
+	out
+}
+
+/// HKDF-SHA-256 with a single block of output.
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+	let prk = hmac_sha256(salt, &[ikm]);
+	hmac_sha256(&prk, &[info, &[1]])
+}
+
+/// The master password of the drive with serial number `serial` and master
+/// password identifier `id`, from the organization secret in `path`.
+pub fn password(path: &str, serial: &str, id: u16) -> io::Result<Vec<u8>> {
+	let mut secret = vec![];
+	try!(try!(File::open(path)).read_to_end(&mut secret));
+	if secret.len() < MIN_SECRET {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("the fleet secret must be at least {} bytes", MIN_SECRET),
+		));
+	}
+	let info = format!("serial={}\0id={:04x}", serial, id);
+	Ok(hkdf(SALT, &secret, info.as_bytes()).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex(s: &str) -> Vec<u8> {
+		(0..s.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+			.collect()
+	}
+
+	/// The test cases of RFC 4231, except the truncated output of case 5
+	#[test]
+	fn hmac_sha256_rfc4231() {
+		let key: Vec<u8> = (1..26).collect();
+		let cases: &[(&[u8], &[u8], &str)] = &[
+			(
+				&[0x0b; 20],
+				b"Hi There",
+				"b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+			),
+			(
+				b"Jefe",
+				b"what do ya want for nothing?",
+				"5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+			),
+			(
+				&[0xaa; 20],
+				&[0xdd; 50],
+				"773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+			),
+			(
+				&key,
+				&[0xcd; 50],
+				"82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+			),
+			(
+				&[0xaa; 131],
+				b"Test Using Larger Than Block-Size Key - Hash Key First",
+				"60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+			),
+			(
+				&[0xaa; 131],
+				b"This is a test using a larger than block-size key and a larger than \
+				  block-size data. The key needs to be hashed before being used by the \
+				  HMAC algorithm.",
+				"9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+			),
+		];
+		for &(key, data, mac) in cases {
+			assert_eq!(hmac_sha256(key, &[data]).to_vec(), hex(mac));
+		}
+		// Data in pieces is the same as in one
+		assert_eq!(
+			hmac_sha256(b"Jefe", &[b"what do ya ", b"want for nothing?"]).to_vec(),
+			hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+		);
+	}
+
+	/// The first 32 bytes of the output of RFC 5869 test cases 1 and 3
+	#[test]
+	fn hkdf_rfc5869() {
+		assert_eq!(
+			hkdf(
+				&hex("000102030405060708090a0b0c"),
+				&[0x0b; 22],
+				&hex("f0f1f2f3f4f5f6f7f8f9")
+			)
+			.to_vec(),
+			hex("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf")
+		);
+		assert_eq!(
+			hkdf(&[], &[0x0b; 22], &[]).to_vec(),
+			hex("8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d")
+		);
+	}
+}
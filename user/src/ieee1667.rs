@@ -0,0 +1,201 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! IEEE 1667 silo enumeration.
+//!
+//! Drives advertising security protocol 0xee implement IEEE 1667, which
+//! groups their security functions into silos. Windows manages eDrives
+//! through a silo as well, so the silos tell which mechanism a drive is set
+//! up for. Only the Probe command of the Probe silo is implemented, to list
+//! the silos.
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use ops::{self, Result};
+
+/// Security protocol carrying IEEE 1667 commands
+pub const PROTOCOL: u8 = 0xee;
+
+/// Index of the Probe silo, which is always the first one
+const PROBE_SILO: u8 = 0;
+const PROBE_FUNCTION: u8 = 0x01;
+
+/// The version of IEEE 1667 the host implements, sent with the Probe command
+const HOST_VERSION: (u8, u8) = (1, 0);
+
+const HEADER_LENGTH: usize = 8;
+/// The payload header and the fields of the Probe command and response
+/// before the silo list
+const PROBE_LENGTH: usize = 16;
+const SILO_DESCRIPTOR_LENGTH: usize = 8;
+
+/// A silo listed by the Probe command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Silo {
+	/// Index of the silo, which addresses its commands
+	pub index: u8,
+	/// Silo type identifier
+	pub stid: u32,
+	/// The version of the silo specification implemented
+	pub version: (u8, u8),
+}
+
+impl Silo {
+	pub fn name(&self) -> Option<&'static str> {
+		match self.stid {
+			0x0000_0100 => Some("Probe"),
+			0x0000_0101 => Some("Certificate"),
+			0x0000_0102 => Some("Password"),
+			_ => None,
+		}
+	}
+}
+
+/// The response to the Probe command.
+#[derive(Debug, Default)]
+pub struct Probe {
+	/// The version of IEEE 1667 the drive implements
+	pub version: (u8, u8),
+	pub silos: Vec<Silo>,
+}
+
+impl Probe {
+	/// Parse the Probe response: the payload header, with the status in the
+	/// last byte, the IEEE 1667 version and the list of silo descriptors.
+	/// Parsing stops at the end of a cut off response.
+	pub fn parse(buf: &[u8]) -> Result<Probe> {
+		if buf.len() < PROBE_LENGTH {
+			return Err(invalid_response("short Probe response"));
+		}
+		if buf[7] != 0 {
+			return Err(ops::Error::Device(io::Error::new(
+				io::ErrorKind::Other,
+				format!("IEEE 1667 Probe command failed with status {:02x}", buf[7]),
+			)));
+		}
+		let len = ::std::cmp::min(BigEndian::read_u32(&buf[0..4]) as usize, buf.len());
+		let end = ::std::cmp::min(
+			PROBE_LENGTH + BigEndian::read_u32(&buf[12..16]) as usize,
+			len,
+		);
+		let silos = buf[PROBE_LENGTH..::std::cmp::max(end, PROBE_LENGTH)]
+			.chunks(SILO_DESCRIPTOR_LENGTH)
+			.take_while(|d| d.len() == SILO_DESCRIPTOR_LENGTH)
+			.enumerate()
+			.map(|(index, d)| Silo {
+				index: index as u8,
+				stid: BigEndian::read_u32(&d[0..4]),
+				version: (d[4], d[5]),
+			})
+			.collect();
+		Ok(Probe {
+			version: (buf[8], buf[9]),
+			silos,
+		})
+	}
+}
+
+fn invalid_response(msg: &str) -> ops::Error {
+	ops::Error::Device(io::Error::new(
+		io::ErrorKind::InvalidData,
+		format!("invalid IEEE 1667 response: {}", msg),
+	))
+}
+
+/// The Security Protocol Specific field addressing `function` of the silo
+/// at `index`.
+fn spsp(index: u8, function: u8) -> u16 {
+	(index as u16) << 8 | function as u16
+}
+
+/// Send the Probe command and receive the list of silos.
+pub fn probe(fd: RawFd) -> Result<Probe> {
+	let mut command = [0u8; PROBE_LENGTH];
+	BigEndian::write_u32(&mut command[0..4], PROBE_LENGTH as u32);
+	command[HEADER_LENGTH] = HOST_VERSION.0;
+	command[HEADER_LENGTH + 1] = HOST_VERSION.1;
+	let spsp = spsp(PROBE_SILO, PROBE_FUNCTION);
+	try!(ops::security_send(fd, PROTOCOL, spsp, 0, Some(&command)));
+	// The response belongs to the command just sent and can't be received
+	// again with a larger buffer, this holds the descriptors of 62 silos
+	let mut buf = [0u8; 512];
+	try!(ops::security_receive(fd, PROTOCOL, spsp, 0, &mut buf));
+	Probe::parse(&buf)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A Probe response with the Probe, Password and an unknown silo. Each
+	/// descriptor is the STID, the specification version and the
+	/// implementation version.
+	const RESPONSE: [u8; 40] = [
+		0x00, 0x00, 0x00, 0x28, 0, 0, 0, 0x00, // payload header
+		0x01, 0x00, 0, 0, 0x00, 0x00, 0x00, 0x18, // version, silo list length
+		0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x02, 0x03, // Probe
+		0x00, 0x00, 0x01, 0x02, 0x01, 0x01, 0x00, 0x01, // Password
+		0x00, 0x00, 0x01, 0x07, 0x02, 0x00, 0x00, 0x00, // unknown
+	];
+
+	#[test]
+	fn probe() {
+		let probe = Probe::parse(&RESPONSE).unwrap();
+		assert_eq!(probe.version, (1, 0));
+		assert_eq!(
+			probe.silos,
+			vec![
+				Silo {
+					index: 0,
+					stid: 0x100,
+					version: (1, 0),
+				},
+				Silo {
+					index: 1,
+					stid: 0x102,
+					version: (1, 1),
+				},
+				Silo {
+					index: 2,
+					stid: 0x107,
+					version: (2, 0),
+				},
+			]
+		);
+		let names: Vec<_> = probe.silos.iter().map(Silo::name).collect();
+		assert_eq!(names, vec![Some("Probe"), Some("Password"), None]);
+	}
+
+	#[test]
+	fn probe_cut_off() {
+		// A cut off descriptor is left out
+		assert_eq!(Probe::parse(&RESPONSE[..36]).unwrap().silos.len(), 2);
+		// The payload length limits the silo list
+		let mut response = RESPONSE;
+		response[3] = 0x20;
+		assert_eq!(Probe::parse(&response).unwrap().silos.len(), 2);
+		// And the silo list length, within the padding of the buffer
+		let mut response = RESPONSE.to_vec();
+		response[15] = 0x08;
+		response.resize(512, 0);
+		assert_eq!(Probe::parse(&response).unwrap().silos.len(), 1);
+		assert!(Probe::parse(&RESPONSE[..15]).is_err());
+	}
+
+	#[test]
+	fn probe_failed() {
+		let mut response = RESPONSE;
+		response[7] = 0x81;
+		assert!(Probe::parse(&response).is_err());
+	}
+}
@@ -0,0 +1,126 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Ctrl-C during long operations.
+//!
+//! Once a SECURITY ERASE or FORMAT NVM has been sent, the drive finishes it
+//! whatever happens to this process, and the kernel keeps waiting for the
+//! command to complete anyway. Dying silently at that point only loses the
+//! result: the `--report` and audit log entries are never written. During
+//! such operations, SIGINT and SIGTERM are therefore caught by a thread that
+//! tells what state the drive is in, while the operation is waited for and
+//! recorded as usual. Loops waiting for an operation that runs in the
+//! background on the drive, such as a sanitize, stop waiting instead.
+
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+use std::thread;
+
+use nix::sys::signal::{SigSet, Signal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+	Erase,
+	Format,
+	Sanitize,
+}
+
+impl Operation {
+	fn from_usize(n: usize) -> Option<Operation> {
+		match n {
+			1 => Some(Operation::Erase),
+			2 => Some(Operation::Format),
+			3 => Some(Operation::Sanitize),
+			_ => None,
+		}
+	}
+
+	fn to_usize(self) -> usize {
+		match self {
+			Operation::Erase => 1,
+			Operation::Format => 2,
+			Operation::Sanitize => 3,
+		}
+	}
+
+	/// What the drive does now that the user wants to stop.
+	fn explanation(self) -> &'static str {
+		match self {
+			Operation::Erase => {
+				"The drive completes the SECURITY ERASE on its own, the data is lost either way. \
+				 Do not power it off. Waiting for the erase to finish to record the result..."
+			}
+			Operation::Format => {
+				"The drive completes the FORMAT NVM on its own, the data is lost either way. \
+				 Do not power it off. Waiting for the format to finish to record the result..."
+			}
+			Operation::Sanitize => {
+				"The sanitize operation continues on the drive, even after a power cycle; \
+				 use sanitize-status to follow it. No longer waiting for it."
+			}
+		}
+	}
+}
+
+static INSTALL: Once = Once::new();
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn handle(signal: Signal) {
+	match Operation::from_usize(CURRENT.load(Ordering::SeqCst)) {
+		None => {
+			eprintln!("Interrupted by {:?}", signal);
+			process::exit(128 + signal as i32);
+		}
+		Some(operation) => {
+			// A sanitize can not be aborted, and is no longer waited for anyway
+			if INTERRUPTED.swap(true, Ordering::SeqCst) && operation != Operation::Sanitize {
+				eprintln!(
+					"Still waiting for the drive. To ask it to abort the command, run abort-command \
+					 with the command identifier from the nvme_setup_cmd kernel trace events"
+				);
+			} else {
+				eprintln!("Interrupted. {}", operation.explanation());
+			}
+		}
+	}
+}
+
+/// Catch SIGINT and SIGTERM from now on, as described above, while
+/// `operation` is in progress. Call before spawning any threads that should
+/// not receive the signals, such as progress reporting.
+pub fn during(operation: Operation) {
+	CURRENT.store(operation.to_usize(), Ordering::SeqCst);
+	INSTALL.call_once(|| {
+		let mut signals = SigSet::empty();
+		signals.add(Signal::SIGINT);
+		signals.add(Signal::SIGTERM);
+		if let Err(e) = signals.thread_block() {
+			eprintln!("Unable to handle interruptions: {}", e);
+			return;
+		}
+		thread::spawn(move || {
+			while let Ok(signal) = signals.wait() {
+				handle(signal);
+			}
+		});
+	});
+}
+
+/// The operation has finished, a signal now ends the process again.
+pub fn done() {
+	CURRENT.store(0, Ordering::SeqCst);
+}
+
+/// Whether the user asked to stop the current operation.
+pub fn interrupted() -> bool {
+	INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,117 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Event logging for `unlock-all` and `daemon`.
+//!
+//! The daemon sends events to journald using its native protocol, with
+//! `DEVICE`, `DEVICE_SERIAL`, `ACTION` and `RESULT` fields so they can be
+//! found with e.g. `journalctl DEVICE_SERIAL=S3EWNX0K123456`. Without
+//! journald, and when run interactively, events go to stderr.
+
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+const JOURNAL_SOCKET: &'static str = "/run/systemd/journal/socket";
+const IDENTIFIER: &'static str = "nvme-ata-security";
+
+/// syslog(3) priorities
+#[derive(Copy, Clone, Debug)]
+pub enum Priority {
+	Err = 3,
+	Warning = 4,
+	Notice = 5,
+	Info = 6,
+}
+
+pub enum Log {
+	Stderr,
+	/// Stderr with every message prefixed by the device, for drives handled
+	/// concurrently. Progress messages are dropped.
+	Tagged,
+	Journal(UnixDatagram),
+}
+
+/// One outcome of an operation on a drive.
+pub struct Event<'a> {
+	pub priority: Priority,
+	pub dev: &'a Path,
+	pub serial: Option<&'a str>,
+	/// `unlock`, `freeze`, `reset`, ...
+	pub action: &'a str,
+	/// `success`, `skipped` or `error`
+	pub result: &'a str,
+	pub message: String,
+}
+
+/// Append a field in the journal export format. Values containing newlines
+/// use the binary form with an explicit length.
+fn field(buf: &mut Vec<u8>, name: &str, value: &str) {
+	buf.extend_from_slice(name.as_bytes());
+	if value.contains('\n') {
+		buf.push(b'\n');
+		let len = value.len() as u64;
+		for i in 0..8 {
+			buf.push((len >> (i * 8)) as u8);
+		}
+	} else {
+		buf.push(b'=');
+	}
+	buf.extend_from_slice(value.as_bytes());
+	buf.push(b'\n');
+}
+
+impl Log {
+	/// Log to journald if it is running, stderr otherwise.
+	pub fn daemon() -> Log {
+		UnixDatagram::unbound()
+			.and_then(|s| s.connect(JOURNAL_SOCKET).map(|_| s))
+			.map(Log::Journal)
+			.unwrap_or(Log::Stderr)
+	}
+
+	/// Progress messages are only useful on a terminal.
+	pub fn progress(&self, message: &str) {
+		if let Log::Stderr = *self {
+			eprintln!("{}", message);
+		}
+	}
+
+	pub fn event(&self, event: Event) {
+		let socket = match *self {
+			Log::Stderr => {
+				eprintln!("{}", event.message);
+				return;
+			}
+			Log::Tagged => {
+				eprintln!("{}: {}", event.dev.display(), event.message);
+				return;
+			}
+			Log::Journal(ref socket) => socket,
+		};
+		let mut buf = vec![];
+		field(
+			&mut buf,
+			"MESSAGE",
+			&format!("{}: {}", event.dev.display(), event.message),
+		);
+		field(&mut buf, "PRIORITY", &(event.priority as u8).to_string());
+		field(&mut buf, "SYSLOG_IDENTIFIER", IDENTIFIER);
+		field(&mut buf, "DEVICE", &event.dev.to_string_lossy());
+		if let Some(serial) = event.serial {
+			field(&mut buf, "DEVICE_SERIAL", serial);
+		}
+		field(&mut buf, "ACTION", event.action);
+		field(&mut buf, "RESULT", event.result);
+		if let Err(e) = socket.send(&buf) {
+			eprintln!("Unable to log to journald: {}", e);
+			eprintln!("{}", event.message);
+		}
+	}
+}
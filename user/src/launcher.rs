@@ -0,0 +1,131 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Starting helper programs outside the `getrandom` emulation.
+//!
+//! `rand::init` traps `getrandom` with a seccomp filter. Child processes
+//! inherit the filter and keep it across `execve`, but not the SIGSYS
+//! handler that emulates the call, so a helper program such as `fido2-token`
+//! or `clevis` would be killed as soon as it asks for random data. A filter
+//! only applies to the thread that installs it and to the threads and
+//! processes that thread starts afterwards. `start` is called before
+//! `rand::init` and creates a thread without the filter, which starts the
+//! helper programs.
+//!
+//! The launcher thread is part of the process: dropping privileges applies
+//! to it as well, and `sandbox::restrict` installs its filter there too.
+
+use std::io;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use libc;
+
+type Job = Box<FnOnce() + Send>;
+
+/// The process the launcher thread was started in, and its queue. A process
+/// forked later, such as the broker, has no launcher thread.
+static LAUNCHER: Mutex<Option<(libc::pid_t, Sender<Job>)>> = Mutex::new(None);
+
+/// Start the launcher thread. Must be called before `rand::init`.
+pub fn start() {
+	let (jobs, queue) = mpsc::channel::<Job>();
+	thread::spawn(move || {
+		for job in queue {
+			job();
+		}
+	});
+	*LAUNCHER.lock().unwrap() = Some((unsafe { libc::getpid() }, jobs));
+}
+
+fn launcher() -> Option<Sender<Job>> {
+	match *LAUNCHER.lock().unwrap() {
+		Some((pid, ref jobs)) if pid == unsafe { libc::getpid() } => Some(jobs.clone()),
+		_ => None,
+	}
+}
+
+fn call<T, F>(jobs: &Sender<Job>, f: F) -> T
+where
+	T: Send + 'static,
+	F: FnOnce() -> T + Send + 'static,
+{
+	let (result, receiver) = mpsc::channel();
+	jobs.send(Box::new(move || {
+		let _ = result.send(f());
+	}))
+	.expect("the launcher thread ended");
+	receiver.recv().expect("the launcher thread ended")
+}
+
+/// Run `f` on the launcher thread, e.g. to apply a per-thread setting such
+/// as a seccomp filter to the helper programs as well. `None` if there is no
+/// launcher thread in this process.
+pub fn run<T, F>(f: F) -> Option<T>
+where
+	T: Send + 'static,
+	F: FnOnce() -> T + Send + 'static,
+{
+	launcher().map(|jobs| call(&jobs, f))
+}
+
+/// Start `command` from the launcher thread, or from this thread if there
+/// is none.
+pub fn spawn(mut command: Command) -> io::Result<Child> {
+	match launcher() {
+		Some(jobs) => call(&jobs, move || command.spawn()),
+		None => command.spawn(),
+	}
+}
+
+/// Run `command` like `spawn` and wait for it to finish.
+pub fn status(command: Command) -> io::Result<ExitStatus> {
+	try!(spawn(command)).wait()
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+	use std::env;
+	use std::os::unix::process::ExitStatusExt;
+	use std::process::{Command, Stdio};
+	use std::thread;
+
+	use libc;
+
+	use super::*;
+	use rand;
+
+	/// This test program, running only a test that calls `getrandom`.
+	fn helper() -> Command {
+		let mut command = Command::new(env::current_exe().unwrap());
+		command
+			.args(&["--exact", "rand::tests::getrandom_fills_buffer"])
+			.stdout(Stdio::null());
+		command
+	}
+
+	#[test]
+	fn helper_outside_getrandom_emulation() {
+		thread::spawn(|| {
+			start();
+			rand::init();
+			let status = spawn(helper()).unwrap().wait().unwrap();
+			assert!(status.success(), "{}", status);
+			// Started from a thread with the emulation, the helper inherits
+			// the seccomp filter without the SIGSYS handler
+			let status = helper().status().unwrap();
+			assert_eq!(status.signal(), Some(libc::SIGSYS));
+		})
+		.join()
+		.unwrap();
+	}
+}
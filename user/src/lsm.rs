@@ -0,0 +1,334 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Linux security modules.
+//!
+//! SELinux and AppArmor can deny the ioctls on NVMe devices even to root,
+//! which then fail with EACCES just like missing permissions. When a command
+//! is refused, look for an enforcing security module and recent denials of
+//! this process in the audit log to point at the actual cause. For the
+//! daemon, `daemon --generate-policy` prints a minimal AppArmor profile or
+//! SELinux policy module to start from.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process;
+
+use libc;
+
+use config;
+use control;
+use dbus;
+
+const AUDIT_LOG: &'static str = "/var/log/audit/audit.log";
+const BINARY: &'static str = "/usr/bin/nvme-ata-security";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lsm {
+	SELinux,
+	AppArmor,
+}
+
+impl Lsm {
+	pub fn parse(name: &str) -> Result<Lsm, String> {
+		match name {
+			"selinux" => Ok(Lsm::SELinux),
+			"apparmor" => Ok(Lsm::AppArmor),
+			_ => Err(format!(
+				"Unknown security module {}, use apparmor or selinux",
+				name
+			)),
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Lsm::SELinux => "SELinux",
+			Lsm::AppArmor => "AppArmor",
+		}
+	}
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+	let mut s = String::new();
+	if File::open(path)
+		.and_then(|mut f| f.read_to_string(&mut s))
+		.is_err()
+	{
+		return None;
+	}
+	Some(
+		s.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+			.to_owned(),
+	)
+}
+
+/// The security context of this process.
+fn process_context() -> Option<String> {
+	read_trimmed("/proc/self/attr/apparmor/current")
+		.or_else(|| read_trimmed("/proc/self/attr/current"))
+}
+
+/// The SELinux label of `path`.
+fn file_context(path: &Path) -> Option<String> {
+	use std::os::unix::ffi::OsStrExt;
+
+	let path = match CString::new(path.as_os_str().as_bytes()) {
+		Err(_) => return None,
+		Ok(path) => path,
+	};
+	let name = CString::new("security.selinux").unwrap();
+	let mut buf = [0u8; 256];
+	let len = unsafe {
+		libc::getxattr(
+			path.as_ptr(),
+			name.as_ptr(),
+			buf.as_mut_ptr() as *mut libc::c_void,
+			buf.len(),
+		)
+	};
+	if len <= 0 {
+		return None;
+	}
+	let label = String::from_utf8_lossy(&buf[..len as usize]);
+	Some(label.trim_right_matches('\0').to_owned())
+}
+
+/// The security module that may be denying access to this process: SELinux
+/// in enforcing mode, or AppArmor if the process is confined.
+pub fn active() -> Option<Lsm> {
+	if read_trimmed("/sys/fs/selinux/enforce").map_or(false, |s| s == "1") {
+		return Some(Lsm::SELinux);
+	}
+	if read_trimmed("/sys/module/apparmor/parameters/enabled").map_or(false, |s| s == "Y") {
+		match process_context() {
+			Some(ref context) if context != "unconfined" => return Some(Lsm::AppArmor),
+			_ => {}
+		}
+	}
+	None
+}
+
+/// The denials of this process in the last part of the audit log.
+fn audit_denials() -> Vec<String> {
+	// Only the end of the log can be about this process
+	const TAIL: u64 = 256 * 1024;
+
+	let mut log = match File::open(AUDIT_LOG) {
+		Err(_) => return vec![],
+		Ok(f) => f,
+	};
+	let mut buf = vec![];
+	let read = log
+		.seek(SeekFrom::End(0))
+		.and_then(|len| log.seek(SeekFrom::Start(len.saturating_sub(TAIL))))
+		.and_then(|_| log.read_to_end(&mut buf));
+	if read.is_err() {
+		return vec![];
+	}
+	let pid = format!(" pid={} ", process::id());
+	String::from_utf8_lossy(&buf)
+		.lines()
+		.filter(|l| l.contains(&pid))
+		.filter(|l| l.contains("avc:  denied") || l.contains("apparmor=\"DENIED\""))
+		.map(str::to_owned)
+		.collect()
+}
+
+/// Explain an EACCES or EPERM from accessing `dev` if a security module may
+/// be the cause. Prints nothing without an enforcing security module.
+pub fn explain_denial(dev: Option<&Path>) {
+	let lsm = match active() {
+		None => return,
+		Some(lsm) => lsm,
+	};
+	eprintln!(
+		"{} is enforcing and may have denied the access, the context of this process is {}",
+		lsm.name(),
+		process_context().unwrap_or_else(|| "unknown".to_owned())
+	);
+	if let (Lsm::SELinux, Some(dev)) = (lsm, dev) {
+		if let Some(context) = file_context(dev) {
+			eprintln!("{} is labeled {}", dev.display(), context);
+		}
+	}
+	let denials = audit_denials();
+	if denials.is_empty() {
+		match lsm {
+			Lsm::SELinux => {
+				eprintln!("Look for denials with `ausearch -m avc -ts recent` or `journalctl -k`")
+			}
+			Lsm::AppArmor => {
+				eprintln!("Look for denials with `journalctl -k | grep apparmor=\\\"DENIED\\\"`")
+			}
+		}
+	} else {
+		eprintln!("Denials in {}:", AUDIT_LOG);
+		for denial in &denials {
+			eprintln!("  {}", denial);
+		}
+	}
+	match lsm {
+		Lsm::SELinux => eprintln!(
+			"Allow the access with a policy module, `daemon --generate-policy=selinux` prints \
+			 one for the daemon"
+		),
+		Lsm::AppArmor => eprintln!(
+			"Allow the access in the profile of this program, `daemon --generate-policy=apparmor` \
+			 prints one for the daemon"
+		),
+	}
+}
+
+/// The files the daemon reads besides the configuration file: password
+/// files and Clevis JWEs of configured drives.
+fn secrets(config: &config::Config) -> Vec<&str> {
+	let mut secrets: Vec<&str> = config
+		.device
+		.values()
+		.flat_map(|d| d.password_file.iter().chain(d.clevis.iter()))
+		.map(String::as_str)
+		.collect();
+	secrets.sort();
+	secrets.dedup();
+	secrets
+}
+
+fn apparmor(config: &config::Config, config_path: &str) -> String {
+	let mut profile = format!(
+		"# AppArmor profile for `nvme-ata-security daemon`, install as\n\
+		 # /etc/apparmor.d/usr.bin.nvme-ata-security\n\
+		 abi <abi/3.0>,\n\
+		 include <tunables/global>\n\
+		 \n\
+		 {} {{\n\
+		 \x20 include <abstractions/base>\n\
+		 \n\
+		 \x20 capability sys_admin,\n\
+		 \x20 network netlink raw,\n\
+		 \x20 network unix stream,\n\
+		 \x20 network unix dgram,\n\
+		 \n\
+		 \x20 /dev/nvme* rw,\n\
+		 \x20 /dev/ng* rw,\n\
+		 \x20 /sys/class/nvme/ r,\n\
+		 \x20 /sys/class/block/ r,\n\
+		 \x20 /sys/devices/** r,\n\
+		 \x20 @{{PROC}}/@{{pid}}/status r,\n\
+		 \x20 @{{PROC}}/@{{pid}}/attr/{{,apparmor/}}current r,\n\
+		 \x20 /run/systemd/journal/socket w,\n\
+		 \x20 {} rw,\n\
+		 \x20 {} r,\n",
+		BINARY,
+		control::SOCKET,
+		config_path
+	);
+	if let Some(ref audit_log) = config.audit_log {
+		profile += &format!("  {} a,\n", audit_log);
+	}
+	for secret in secrets(config) {
+		profile += &format!("  {} r,\n", secret);
+	}
+	profile += &format!(
+		"\n\
+		 \x20 # daemon --dbus\n\
+		 \x20 include <abstractions/dbus-strict>\n\
+		 \x20 dbus bind bus=system name={},\n\
+		 \x20 dbus receive bus=system path=/org/nvme/AtaSecurity,\n\
+		 \x20 dbus send bus=system path=/org/freedesktop/DBus interface=org.freedesktop.DBus,\n\
+		 \x20 dbus send bus=system path=/org/freedesktop/PolicyKit1/Authority\n\
+		 \x20   interface=org.freedesktop.PolicyKit1.Authority member=CheckAuthorization,\n",
+		dbus::NAME
+	);
+	if config.daemon_user.is_some() {
+		profile += "\n  # daemon_user: switch to it\n";
+		profile += "  include <abstractions/nameservice>\n";
+		profile += "  capability setuid setgid,\n";
+	}
+	if config.device.values().any(|d| d.clevis.is_some()) {
+		profile += "\n  # Clevis runs further programs, confine them with their own profiles\n";
+		profile += "  /usr/bin/clevis{,-*} Px,\n";
+	}
+	profile += "}\n";
+	profile
+}
+
+fn selinux(config: &config::Config, config_path: &str) -> String {
+	let mut module = format!(
+		"# SELinux policy module for `nvme-ata-security daemon`, build with\n\
+		 # make -f /usr/share/selinux/devel/Makefile nvme_ata_security.pp\n\
+		 # together with nvme_ata_security.fc containing\n\
+		 # {} -- gen_context(system_u:object_r:nvme_ata_security_exec_t,s0)\n\
+		 # {} -- gen_context(system_u:object_r:nvme_ata_security_conf_t,s0)\n\
+		 policy_module(nvme_ata_security, 1.0)\n\
+		 \n\
+		 gen_require(`\n\
+		 \ttype nvme_device_t;\n\
+		 ')\n\
+		 \n\
+		 type nvme_ata_security_t;\n\
+		 type nvme_ata_security_exec_t;\n\
+		 init_daemon_domain(nvme_ata_security_t, nvme_ata_security_exec_t)\n\
+		 \n\
+		 type nvme_ata_security_conf_t;\n\
+		 files_config_file(nvme_ata_security_conf_t)\n\
+		 type nvme_ata_security_var_run_t;\n\
+		 files_pid_file(nvme_ata_security_var_run_t)\n\
+		 \n\
+		 allow nvme_ata_security_t self:capability sys_admin;\n\
+		 allow nvme_ata_security_t self:netlink_kobject_uevent_socket create_socket_perms;\n\
+		 allow nvme_ata_security_t self:unix_stream_socket create_stream_socket_perms;\n\
+		 allow nvme_ata_security_t nvme_device_t:chr_file rw_chr_file_perms;\n\
+		 storage_raw_rw_fixed_disk(nvme_ata_security_t)\n\
+		 dev_read_sysfs(nvme_ata_security_t)\n\
+		 kernel_read_system_state(nvme_ata_security_t)\n\
+		 logging_send_syslog_msg(nvme_ata_security_t)\n\
+		 read_files_pattern(nvme_ata_security_t, nvme_ata_security_conf_t, nvme_ata_security_conf_t)\n\
+		 manage_sock_files_pattern(nvme_ata_security_t, nvme_ata_security_var_run_t, nvme_ata_security_var_run_t)\n\
+		 files_pid_filetrans(nvme_ata_security_t, nvme_ata_security_var_run_t, sock_file)\n\
+		 \n\
+		 # daemon --dbus\n\
+		 optional_policy(`\n\
+		 \tdbus_system_bus_client(nvme_ata_security_t)\n\
+		 \tdbus_connect_system_bus(nvme_ata_security_t)\n\
+		 \tpolicykit_dbus_chat(nvme_ata_security_t)\n\
+		 ')\n",
+		BINARY, config_path
+	);
+	if config.daemon_user.is_some() {
+		module += "\n# daemon_user: switch to it\n";
+		module += "allow nvme_ata_security_t self:capability { setuid setgid };\n";
+		module += "auth_use_nsswitch(nvme_ata_security_t)\n";
+	}
+	let secrets = secrets(config);
+	if !secrets.is_empty() || config.audit_log.is_some() {
+		module += "\n# Label these files nvme_ata_security_conf_t as well:\n";
+		for secret in secrets {
+			module += &format!("#   {} (read)\n", secret);
+		}
+		if let Some(ref audit_log) = config.audit_log {
+			module += &format!("#   {} (append)\n", audit_log);
+			module += "append_files_pattern(nvme_ata_security_t, nvme_ata_security_conf_t, \
+			           nvme_ata_security_conf_t)\n";
+		}
+	}
+	module
+}
+
+/// A minimal policy for the daemon with the files of `config`, read from
+/// `config_path`.
+pub fn policy(lsm: Lsm, config: &config::Config, config_path: &str) -> String {
+	match lsm {
+		Lsm::AppArmor => apparmor(config, config_path),
+		Lsm::SELinux => selinux(config, config_path),
+	}
+}
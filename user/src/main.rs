@@ -21,25 +21,78 @@ extern crate serde;
 extern crate serde_derive;
 extern crate libc;
 extern crate rpassword;
+extern crate serde_json;
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
 extern crate sha2;
+extern crate toml;
+extern crate unicode_normalization;
 
+mod advisor;
+mod askpass;
+mod audit;
+mod benchmark;
+mod broker;
+mod check;
+mod clevis;
+mod config;
+mod control;
+mod dbus;
+mod decommission;
+mod device;
+mod erasure;
+mod escrow;
+mod exporter;
+mod features;
+mod fido2;
+mod fleet;
+mod ieee1667;
+mod interrupt;
+mod journal;
+mod launcher;
+mod lsm;
+mod manifest;
+mod normalize;
 mod nvme;
 mod ops;
+mod output;
+mod password_file;
+mod pkcs11;
+mod pool;
+mod power;
+mod quirks;
 mod rand;
+mod report;
+mod sandbox;
+mod session;
+mod snapshot;
+mod stack;
+mod state;
+mod table;
+mod tcg;
+mod telemetry;
+mod throttle;
+mod tui;
+mod uevent;
+mod uring;
+mod verify;
+mod x509;
 
-use std::fmt;
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::os::unix::fs::FileTypeExt;
-use std::os::unix::io::AsRawFd;
-use std::result::Result as StdResult;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use sha2::{Digest, Sha256};
 
 use nvme::identify::IdentifyController;
-use nvme::security::Protocol::AtaSecurity as ProtocolAtaSecurity;
-use nvme::security::{AtaSecurityIdentify, AtaSecurityPassword, AtaSecuritySpecific};
+use nvme::security::{AtaSecurityIdentify, AtaSecurityPassword};
+use nvme::Payload;
 use ops::Result;
+use password_file::PasswordFile;
 
 fn security_protocols(
 	f: &File,
@@ -55,40 +108,82 @@ fn security_protocols(
 		if bytes > 0 {
 			supported.resize(bytes as usize + 8, 0);
 			try!(ops::security_receive(fd, 0, 0, 0, &mut supported));
-			Ok(Some(
-				supported
-					.into_iter()
-					.skip(8)
-					.map(Into::<nvme::security::Protocol>::into)
-					.collect(),
-			))
-		} else {
-			Ok(Some(Vec::with_capacity(0)))
 		}
+		Ok(Some(nvme::security::parse_supported_protocols(&supported)))
 	} else {
 		Ok(None)
 	}
 }
 
+/// The certificate data page of the Security Protocol Information protocol.
+fn security_certificate(f: &File) -> Result<Vec<u8>> {
+	use byteorder::{BigEndian, ReadBytesExt};
+
+	let fd = f.as_raw_fd();
+	let spsp = nvme::security::SecurityProtocolInformationSpecific::CertificateData as u16;
+	let length = |buf: &[u8]| (&buf[2..4]).read_u16::<BigEndian>().unwrap() as usize + 4;
+	let mut buf = try!(ops::security_receive_sized(fd, 0, spsp, 0, 4, length));
+	if buf.len() < length(&buf) {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!(
+				"the certificate data of {} bytes exceeds the maximum transfer size of the \
+				 controller",
+				length(&buf) - 4
+			),
+		)
+		.into());
+	}
+	Ok(buf.split_off(4))
+}
+
+/// The security compliance page of the Security Protocol Information
+/// protocol.
+fn security_compliance(f: &File) -> Result<Vec<nvme::security::ComplianceDescriptor>> {
+	use byteorder::{BigEndian, ReadBytesExt};
+
+	// The page is a handful of fixed-size descriptors, don't trust the drive
+	// to report a sane length.
+	const MAX_LENGTH: usize = 0x10000;
+
+	let fd = f.as_raw_fd();
+	let spsp = nvme::security::SecurityProtocolInformationSpecific::SecurityCompliance as u16;
+	// A cut off response still has the descriptors that fit
+	let buf = try!(ops::security_receive_sized(fd, 0, spsp, 0, 4, |buf| {
+		let bytes = (&buf[0..4]).read_u32::<BigEndian>().unwrap() as usize;
+		std::cmp::min(bytes, MAX_LENGTH) + 4
+	}));
+	Ok(nvme::security::parse_security_compliance(&buf))
+}
+
 fn ata_identify(
 	f: &File,
 	protocols: &[nvme::security::Protocol],
 ) -> Result<Option<AtaSecurityIdentify>> {
-	if !protocols.contains(&ProtocolAtaSecurity) {
+	if !protocols.contains(&nvme::security::Protocol::from(quirks::protocol())) {
 		return Ok(None);
 	}
 
-	let mut buf = [0u8; 16];
+	ata_security_identify(f).map(Some)
+}
+
+fn ata_security_identify(f: &File) -> Result<AtaSecurityIdentify> {
+	let fd = f.as_raw_fd();
+	let mut buf = vec![0u8; quirks::current().transfer_length(AtaSecurityIdentify::SIZE)];
 	try!(ops::security_receive(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
+		fd,
+		quirks::protocol(),
 		0,
 		0,
 		&mut buf
 	));
-	Ok(Some(AtaSecurityIdentify::from(buf)))
+	Ok(try!(AtaSecurityIdentify::from_wire(
+		&buf[..AtaSecurityIdentify::SIZE]
+	)))
 }
 
+/// Identify data, supported security protocols and ATA security state of a
+/// drive. Queried once per drive and shared by everything that needs it.
 struct DriveInfo(
 	Result<(
 		IdentifyController,
@@ -101,93 +196,6 @@ struct DriveInfo(
 	)>,
 );
 
-impl fmt::Display for DriveInfo {
-	fn fmt(&self, fmt: &mut fmt::Formatter) -> StdResult<(), fmt::Error> {
-		let r_i = &self.0;
-		let (i, r_p) = match r_i {
-			&Err(ref e) => {
-				try!(writeln!(
-					fmt,
-					"There was an error obtaining NVMe identity information:\n{:?}",
-					e
-				));
-				return Ok(());
-			}
-			&Ok((ref i, ref r_p)) => (i, r_p),
-		};
-		try!(writeln!(
-			fmt,
-			"vid:ssvid: {:04x}:{:04x}
-model: {}
-serial: {}
-firmware: {}
-oacs: {:?}",
-			i.vid(),
-			i.ssvid(),
-			String::from_utf8_lossy(i.sn()),
-			String::from_utf8_lossy(i.mn()),
-			String::from_utf8_lossy(i.fr()),
-			i.oacs()
-		));
-		let (protocols, r_s) = match r_p {
-			&Err(ref e) => {
-				try!(writeln!(
-					fmt,
-					"There was an error enumerating supported NVMe security protocols:\n{:?}",
-					e
-				));
-				return Ok(());
-			}
-			&Ok(None) => {
-				try!(writeln!(
-					fmt,
-					"This drive does not support NVMe security commands."
-				));
-				return Ok(());
-			}
-			&Ok(Some((ref p, ref r_s))) => (p, r_s),
-		};
-		try!(writeln!(fmt, "protocols: {:?}", protocols));
-		let security = match r_s {
-			&Err(ref e) => {
-				try!(writeln!(
-					fmt,
-					"There was an error obtaining ATA security information:\n{:?}",
-					e
-				));
-				return Ok(());
-			}
-			&Ok(None) => {
-				try!(writeln!(
-					fmt,
-					"This drive does not support ATA security commands."
-				));
-				return Ok(());
-			}
-			&Ok(Some(ref s)) => s,
-		};
-		try!(writeln!(
-			fmt,
-			"ata security: erase time: {} enhanced erase time: {}, master pwd id: {:04x} maxset: {}
-s_suprt: {} s_enabld: {} locked: {} frozen: {} pwncntex: {} en_er_sup: {}",
-			security.security_erase_time(),
-			security.enhanced_security_erase_time(),
-			security.master_password_identifier(),
-			security.maxset(),
-			security.s_suprt(),
-			security.s_enabld(),
-			security.locked(),
-			security.frozen(),
-			security.pwncntex(),
-			security.en_er_sup()
-		));
-		if !security.s_suprt() {
-			try!(writeln!(fmt, "This drive does not support ATA security."));
-		}
-		Ok(())
-	}
-}
-
 impl DriveInfo {
 	fn query(f: &File) -> DriveInfo {
 		DriveInfo(ops::identify_controller(f.as_raw_fd()).map(|i| {
@@ -201,358 +209,4028 @@ impl DriveInfo {
 		}))
 	}
 
-	fn check_support(self) -> Option<(IdentifyController, AtaSecurityIdentify)> {
+	/// `query` for several drives, sending each command to all of them at
+	/// once with `ops::identify_controllers` and `ops::security_receives`.
+	fn query_all(files: &[File]) -> Vec<DriveInfo> {
+		use byteorder::{BigEndian, ReadBytesExt};
+
+		let fds: Vec<RawFd> = files.iter().map(|f| f.as_raw_fd()).collect();
+		let identities = ops::identify_controllers(&fds);
+
+		// The supported protocols of the drives with security commands, first
+		// their number and then the list
+		let secure: Vec<usize> = (0..fds.len())
+			.filter(|&i| match identities[i] {
+				Ok(ref identity) => identity.oacs().contains(nvme::identify::Oacs::SECURITY),
+				Err(_) => false,
+			})
+			.collect();
+		let mut supported: Vec<Option<Result<Vec<u8>>>> = fds.iter().map(|_| None).collect();
+		let headers = ops::security_receives(
+			0,
+			0,
+			0,
+			secure.iter().map(|&i| (fds[i], vec![0u8; 8])).collect(),
+		);
+		let mut longer = vec![];
+		for (&i, header) in secure.iter().zip(headers) {
+			let bytes = match header {
+				Ok(ref buf) => (&buf[6..8]).read_u16::<BigEndian>().unwrap(),
+				Err(_) => 0,
+			};
+			if bytes > 0 {
+				longer.push((i, bytes as usize + 8));
+			} else {
+				supported[i] = Some(header);
+			}
+		}
+		let lists = ops::security_receives(
+			0,
+			0,
+			0,
+			longer
+				.iter()
+				.map(|&(i, len)| (fds[i], vec![0u8; len]))
+				.collect(),
+		);
+		for (&(i, _), list) in longer.iter().zip(lists) {
+			supported[i] = Some(list);
+		}
+		let protocols: Vec<Option<Result<Vec<nvme::security::Protocol>>>> = supported
+			.into_iter()
+			.map(|s| s.map(|s| s.map(|buf| nvme::security::parse_supported_protocols(&buf))))
+			.collect();
+
+		// The ATA security state of the drives with the ATA security protocol
+		let ata: Vec<usize> = (0..fds.len())
+			.filter(|&i| match protocols[i] {
+				Some(Ok(ref p)) => p.contains(&nvme::security::Protocol::from(quirks::protocol())),
+				_ => false,
+			})
+			.collect();
+		let len = quirks::current().transfer_length(AtaSecurityIdentify::SIZE);
+		let mut security: Vec<Option<Result<AtaSecurityIdentify>>> =
+			fds.iter().map(|_| None).collect();
+		let states = ops::security_receives(
+			quirks::protocol(),
+			0,
+			0,
+			ata.iter().map(|&i| (fds[i], vec![0u8; len])).collect(),
+		);
+		for (&i, state) in ata.iter().zip(states) {
+			security[i] = Some(state.and_then(|buf| {
+				Ok(try!(AtaSecurityIdentify::from_wire(
+					&buf[..AtaSecurityIdentify::SIZE]
+				)))
+			}));
+		}
+
+		identities
+			.into_iter()
+			.zip(protocols.into_iter().zip(security))
+			.map(|(identity, (protocols, security))| {
+				DriveInfo(identity.map(|i| {
+					let p = match protocols {
+						None => Ok(None),
+						Some(Err(e)) => Err(e),
+						Some(Ok(p)) => Ok(Some((p, security.map_or(Ok(None), |s| s.map(Some))))),
+					};
+					(i, p)
+				}))
+			})
+			.collect()
+	}
+
+	/// Check that the drive supports ATA security. On failure, the reason is
+	/// printed and the exit status returned.
+	fn check_support(self) -> std::result::Result<(IdentifyController, AtaSecurityIdentify), i32> {
 		match self.0 {
 			Err(e) => {
 				eprintln!(
 					"There was an error obtaining NVMe identity information:\n{:?}",
 					e
 				);
-				None
+				if e.is_access_denied() {
+					lsm::explain_denial(None);
+				}
+				Err(EXIT_FAILURE)
 			}
 			Ok((_, Err(e))) => {
 				eprintln!(
 					"There was an error enumerating supported NVMe security protocols:\n{:?}",
 					e
 				);
-				None
+				Err(EXIT_FAILURE)
 			}
 			Ok((_, Ok(None))) => {
-				eprintln!("This drive does not support NVMe security commands.");
-				None
+				eprintln!(
+					"This drive does not support NVMe security commands \
+					 (Security Send/Receive are not in OACS)."
+				);
+				Err(EXIT_UNSUPPORTED)
 			}
 			Ok((_, Ok(Some((_, Err(e)))))) => {
 				eprintln!(
 					"There was an error obtaining ATA security information:\n{:?}",
 					e
 				);
-				None
+				Err(EXIT_FAILURE)
 			}
-			Ok((_, Ok(Some((_, Ok(None)))))) => {
-				eprintln!("This drive does not support ATA security commands.");
-				None
+			Ok((_, Ok(Some((p, Ok(None)))))) => {
+				eprintln!(
+					"This drive does not support ATA security commands, \
+					 the supported security protocols are: {}",
+					p.iter()
+						.map(|p| p.info().to_string())
+						.collect::<Vec<_>>()
+						.join(", ")
+				);
+				Err(EXIT_UNSUPPORTED)
+			}
+			Ok((_, Ok(Some((_, Ok(Some(ref s))))))) if !s.s_suprt() => {
+				eprintln!(
+					"This drive accepts the ATA security protocol but reports the ATA \
+					 security feature set as not supported."
+				);
+				Err(EXIT_UNSUPPORTED)
+			}
+			Ok((i, Ok(Some((_, Ok(Some(s))))))) => Ok((i, s)),
+		}
+	}
+}
+
+/// Exit status for errors
+const EXIT_FAILURE: i32 = 1;
+/// Exit status if the drive lacks the security feature the command needs
+const EXIT_UNSUPPORTED: i32 = 2;
+/// Exit status of `status --field` if the field could not be read, 1 means
+/// no
+const EXIT_FIELD_ERROR: i32 = 3;
+/// What `status_field` returns for no, told apart from errors of `run` that
+/// also exit with 1
+const FIELD_NO: i32 = -1;
+
+/// The NVMe driver only allows admin commands (including SECURITY SEND and
+/// SECURITY RECEIVE) with CAP_SYS_ADMIN, check for it up front instead of
+/// failing with EPERM on the first ioctl.
+fn check_privileges() -> bool {
+	const CAP_SYS_ADMIN: u32 = 21;
+
+	let mut status = String::new();
+	if File::open("/proc/self/status")
+		.and_then(|mut f| f.read_to_string(&mut status))
+		.is_err()
+	{
+		return true;
+	}
+	let effective = status
+		.lines()
+		.find(|l| l.starts_with("CapEff:"))
+		.and_then(|l| u64::from_str_radix(l["CapEff:".len()..].trim(), 16).ok());
+	match effective {
+		Some(caps) if caps & (1 << CAP_SYS_ADMIN) == 0 => {
+			eprintln!("NVMe admin commands require the CAP_SYS_ADMIN capability, run as root");
+			false
+		}
+		_ => true,
+	}
+}
+
+/// Report the outcome of a command and return the exit status.
+fn finish(result: Result<()>) -> i32 {
+	match result {
+		Err(e) => {
+			eprintln!("There was an error executing the command: {:?}", e);
+			if e.is_access_denied() {
+				lsm::explain_denial(None);
 			}
-			Ok((i, Ok(Some((_, Ok(Some(s))))))) => Some((i, s)),
+			EXIT_FAILURE
+		}
+		Ok(()) => {
+			eprintln!("Success!");
+			0
 		}
 	}
 }
 
 fn security_set_password_user(f: &File, password: [u8; 32], maximum_security: bool) -> Result<()> {
-	let buf: [u8; 36] =
-		AtaSecurityPassword::new(password, false, Some(maximum_security), None).into();
-	ops::security_send(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
-		AtaSecuritySpecific::SetPassword as u16,
-		0,
-		Some(&buf),
-	)
+	let mut builder = AtaSecurityPassword::builder(password);
+	if maximum_security {
+		builder = builder.maximum_security();
+	}
+	let data = try!(builder.build());
+	session::SecuritySession::new(f).set_password(&data)
 }
 
 fn security_set_password_master(f: &File, password: [u8; 32], id: u16) -> Result<()> {
-	let buf: [u8; 36] = AtaSecurityPassword::new(password, true, None, Some(id)).into();
-	ops::security_send(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
-		AtaSecuritySpecific::SetPassword as u16,
-		0,
-		Some(&buf),
-	)
+	let data = try!(AtaSecurityPassword::builder(password)
+		.master()
+		.identifier(id)
+		.build());
+	session::SecuritySession::new(f).set_password(&data)
 }
 
-fn security_unlock(f: &File, password: [u8; 32], master: bool) -> Result<()> {
-	let buf: [u8; 36] = AtaSecurityPassword::new(password, master, None, None).into();
-	try!(ops::security_send(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
-		AtaSecuritySpecific::Unlock as u16,
-		0,
-		Some(&buf)
-	));
-	ops::ioctl_blkrrpart(f.as_raw_fd())
+/// The master password identifier to set for `--id=<id>`, `auto` increments
+/// the current one. 0000h and FFFFh mean the identifier is not supported.
+fn master_password_identifier(
+	id: &str,
+	security: &AtaSecurityIdentify,
+) -> std::result::Result<u16, String> {
+	if id != "auto" {
+		return id
+			.parse()
+			.map_err(|_| format!("Invalid master password identifier: {}", id));
+	}
+	if !security.master_password_identifier_supported() {
+		return Err("The drive does not report a master password identifier, \
+		            specify --id=<id>"
+			.to_owned());
+	}
+	match security.master_password_identifier() + 1 {
+		0xffff => Ok(1),
+		next => Ok(next),
+	}
 }
 
-fn security_erase(f: &File, password: [u8; 32], master: bool, enhanced: bool) -> Result<()> {
-	try!(ops::security_send(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
-		AtaSecuritySpecific::ErasePrepare as u16,
-		0,
-		None
-	));
-	let buf: [u8; 36] = AtaSecurityPassword::new(password, master, Some(enhanced), None).into();
-	ops::security_send(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
-		AtaSecuritySpecific::EraseUnit as u16,
-		0,
-		Some(&buf),
-	)
+/// Read back the master password identifier after SET PASSWORD, some drives
+/// silently ignore it.
+fn confirm_master_password_identifier(f: &File, id: u16) -> Result<()> {
+	let security = try!(ata_security_identify(f));
+	if !security.master_password_identifier_supported() {
+		eprintln!("The drive does not report a master password identifier");
+	} else if security.master_password_identifier() != id {
+		eprintln!(
+			"Warning: the drive reports master password identifier {:04x}, not {:04x}",
+			security.master_password_identifier(),
+			id
+		);
+	} else {
+		eprintln!("Master password identifier is now {:04x}", id);
+	}
+	Ok(())
 }
 
-fn security_freeze(f: &File) -> Result<()> {
-	ops::security_send(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
-		AtaSecuritySpecific::FreezeLock as u16,
-		0,
-		None,
-	)
+fn security_unlock(f: &File, password: [u8; 32], master: bool) -> Result<()> {
+	try!(security_send_unlock(f, password, master));
+	// Through a controller device the kernel rescans namespaces by itself
+	if try!(f.metadata()).file_type().is_block_device() {
+		try!(ops::ioctl_blkrrpart(f.as_raw_fd()));
+	}
+	Ok(())
 }
 
-fn security_disable_password(f: &File, password: [u8; 32], master: bool) -> Result<()> {
-	let buf: [u8; 36] = AtaSecurityPassword::new(password, master, None, None).into();
-	ops::security_send(
-		f.as_raw_fd(),
-		ProtocolAtaSecurity.into(),
-		AtaSecuritySpecific::DisablePassword as u16,
-		0,
-		Some(&buf),
-	)
+fn security_send_unlock(f: &File, password: [u8; 32], master: bool) -> Result<()> {
+	let data = try!(session::password_data(password, master).build());
+	session::SecuritySession::new(f).unlock(&data)
 }
 
-fn read_password_err(
-	src: Option<String>,
-	identity: &IdentifyController,
-	confirm: bool,
-) -> std::result::Result<[u8; 32], io::Error> {
-	let mut f_file;
-	let mut f_stdin;
-	let f_password;
-	let mut f_password_ptr;
-	let f: &mut Read = if let Some(src) = src {
-		f_file = try!(File::open(src));
-		&mut f_file
-	} else {
-		if nix::unistd::isatty(0).unwrap_or(false) {
-			loop {
-				eprint!(
-					"Please enter password for {} {}:",
-					String::from_utf8_lossy(identity.mn()).trim(),
-					String::from_utf8_lossy(identity.sn()).trim()
-				);
-				let password1 = try!(rpassword::read_password());
-				if password1.len() == 0 {
-					continue;
-				} else if password1.len() > 32 {
-					eprintln!("Password too long!");
-					continue;
-				}
-				if confirm {
-					eprint!("Enter password again:");
-					let password2 = try!(rpassword::read_password());
-					if password1 != password2 {
-						eprintln!("Passwords don't match!");
-						continue;
-					}
-				}
-				f_password = password1;
-				break;
-			}
-			f_password_ptr = f_password.as_bytes();
-			&mut f_password_ptr
-		} else {
-			f_stdin = io::stdin();
-			&mut f_stdin
-		}
-	};
+/// How often `unlock` prompts for the password on a terminal.
+const UNLOCK_TRIES: u8 = 3;
 
-	let mut buf = vec![];
-	try!(f.read_to_end(&mut buf));
-	let mut out = [0u8; 32];
-	let mut sha256 = Sha256::new();
-	sha256.input(&buf);
-	sha256.input(&identity.mn());
-	sha256.input(&identity.sn());
-	out.copy_from_slice(&sha256.result());
-	Ok(out)
+/// Whether the drive stopped accepting passwords until the next power cycle.
+/// The drive allows a fixed number of failed attempts (5 in ACS-3) but does
+/// not report how many remain.
+fn attempts_exceeded(f: &File) -> bool {
+	ata_security_identify(f)
+		.map(|s| s.pwncntex())
+		.unwrap_or(false)
 }
 
-fn read_password(src: Option<String>, identity: &IdentifyController, confirm: bool) -> [u8; 32] {
-	match read_password_err(src, identity, confirm) {
-		Err(e) => {
-			eprintln!("Error trying to read password: {}", e);
-			std::process::exit(1);
-		}
-		Ok(v) => v,
+/// Check the current password by sending SECURITY UNLOCK and reading back the
+/// security state. On an unlocked drive the unlock is a no-op when the
+/// password is correct.
+fn security_verify_password(f: &File, password: [u8; 32], master: bool) -> Result<bool> {
+	try!(security_send_unlock(f, password, master));
+	Ok(!try!(ata_security_identify(f)).locked())
+}
+
+/// Tell how long the drive estimates the erase to take.
+fn print_erase_estimate(security: &AtaSecurityIdentify, enhanced: bool) {
+	if let Some(time) = security.erase_time(enhanced) {
+		eprintln!("The drive estimates the erase takes {}", time);
 	}
 }
 
-trait RetryIterator: Iterator {
-	fn retry_results<T, E>(&mut self) -> std::result::Result<T, E>
-	where
-		Self: Iterator<Item = std::result::Result<T, E>>,
-	{
-		let mut last = None;
-		loop {
-			let cur = self.next();
-			match cur {
-				Some(v @ Ok(_)) => {
-					return v;
-				}
-				Some(e @ Err(_)) => {
-					last = Some(e);
-				}
-				None => if let Some(e) = last {
-					return e;
-				},
+fn security_erase(f: &File, password: [u8; 32], master: bool, enhanced: bool) -> Result<()> {
+	let mut builder = session::password_data(password, master);
+	if enhanced {
+		builder = builder.enhanced();
+	}
+	let data = try!(builder.build());
+	try!(session::SecuritySession::new(f).erase_prepare())
+		.erase_unit(&data)
+		.map(|_| ())
+}
+
+fn security_freeze(f: &File) -> Result<()> {
+	session::SecuritySession::new(f).freeze_lock()
+}
+
+/// `lockdown`: SECURITY FREEZE LOCK and TCG Block SID Authentication, so
+/// that neither an ATA password nor the TCG SID password can be set until the
+/// next power cycle.
+fn lockdown(f: &File, info: &DriveInfo) -> i32 {
+	let (protocols, security) = match info.0 {
+		Ok((_, Ok(Some((ref p, ref s))))) => (p, s),
+		Ok((_, Ok(None))) => {
+			eprintln!("This drive does not support NVMe security commands.");
+			return EXIT_UNSUPPORTED;
+		}
+		_ => {
+			eprintln!("Unable to determine the supported NVMe security protocols");
+			return EXIT_FAILURE;
+		}
+	};
+	let mut supported = false;
+	let result = match *security {
+		Ok(Some(ref s)) if s.s_suprt() => {
+			supported = true;
+			if s.frozen() {
+				eprintln!("ATA security is already frozen");
+				Ok(())
+			} else {
+				eprintln!("Performing SECURITY FREEZE...");
+				security_freeze(f)
 			}
 		}
+		_ => Ok(()),
+	};
+	let discovery = if protocols.contains(&nvme::security::Protocol::Tcg(tcg::PROTOCOL)) {
+		tcg::discovery(f.as_raw_fd()).map(|d| d.block_sid)
+	} else {
+		Ok(None)
+	};
+	let result = result.and(discovery).and_then(|block_sid| match block_sid {
+		Some(ref b) if b.sid_blocked => {
+			supported = true;
+			eprintln!("TCG SID authentication is already blocked");
+			Ok(())
+		}
+		Some(_) => {
+			supported = true;
+			eprintln!("Performing TCG Block SID Authentication...");
+			tcg::block_sid(f.as_raw_fd(), false)
+		}
+		None => Ok(()),
+	});
+	if result.is_ok() && !supported {
+		eprintln!("This drive supports neither ATA security nor TCG Block SID Authentication");
+		return EXIT_UNSUPPORTED;
 	}
+	finish(result)
+}
 
-	fn retry_options<T>(&mut self) -> Option<T>
-	where
-		Self: Iterator<Item = Option<T>>,
-	{
-		let mut last = None;
-		loop {
-			let cur = self.next();
-			match cur {
-				Some(v @ Some(_)) => {
-					return v;
-				}
-				Some(e @ None) => {
-					last = Some(e);
-				}
-				None => if let Some(e) = last {
-					return e;
-				},
+/// Check that no file system, swap or stacked device uses the drive before
+/// destroying its contents or powering it off. Returns whether to go ahead.
+fn check_not_in_use(dev: &str, force: bool) -> bool {
+	let users = device::controller(Path::new(dev))
+		.and_then(|ctrl| device::users(&ctrl.file_name().unwrap().to_string_lossy()));
+	match users {
+		Err(e) => {
+			eprintln!("Unable to check whether {} is in use: {}", dev, e);
+			force
+		}
+		Ok(ref users) if !users.is_empty() => {
+			eprintln!("{} is in use:", dev);
+			for user in users {
+				eprintln!("  {}", user);
+			}
+			if !force {
+				eprintln!("Unmount or close these first");
 			}
+			force
 		}
+		Ok(_) => true,
 	}
 }
 
-impl<T: Iterator> RetryIterator for T {}
+/// Open the block device `dev` exclusively, like mounting it does, so that
+/// nothing starts using it during an erase, and drop its cached contents so
+/// that reads afterwards come from the drive. The claim lasts until the
+/// returned file is closed, see `release_claim`. Controller devices have no
+/// block device to claim.
+fn claim_exclusive(dev: &str) -> Result<Option<File>> {
+	use std::os::unix::fs::OpenOptionsExt;
 
-fn main() {
-	rand::init();
+	if !try!(std::fs::metadata(dev)).file_type().is_block_device() {
+		return Ok(None);
+	}
+	let f = try!(std::fs::OpenOptions::new()
+		.read(true)
+		.custom_flags(libc::O_EXCL)
+		.open(dev));
+	try!(drop_cache(&f));
+	Ok(Some(f))
+}
 
-	#[derive(Deserialize, Debug)]
-	struct Args {
-		cmd_query: bool,
-		cmd_set_password: bool,
-		cmd_unlock: bool,
-		cmd_disable_password: bool,
-		cmd_erase: bool,
-		cmd_freeze: bool,
-		arg_dev: String,
-		flag_password_file: Option<String>,
-		flag_tries: Option<u8>,
-		flag_id: u16,
-		flag_user: bool,
-		flag_master: bool,
-		flag_high: bool,
-		flag_max: bool,
-		flag_enhanced: bool,
-	}
-
-	const USAGE: &'static str = "
-Usage:
-	nvme-ata-security query <dev>
-	nvme-ata-security set-password -u (--high|--max) [--password-file=<file>] <dev>
-	nvme-ata-security set-password -m --id=<id> [--password-file=<file>] <dev>
-	nvme-ata-security unlock (-u|-m) [--password-file=<file>|--tries=<num>] <dev>
-	nvme-ata-security disable-password (-u|-m) [--password-file=<file>] <dev>
-	nvme-ata-security erase (-u|-m) [--enhanced] [--password-file=<file>] <dev>
-	nvme-ata-security freeze <dev>
-	nvme-ata-security --help
-	
-Options:
-	-u, --user                         Specify the user password
-	-m, --master                       Specify the master password
-	-i <file>, --password-file=<file>  Read the password from <file> instead of stdin
-	-t <num>, --tries=<num>            When reading from stdin, try unlocking <num> times
-	--high                             Configure high security
-	--max                              Configure maximum security
-	--id=<id>                          Set the master password identifier
-	--enhanced                         Perform an enhanced security erase
-";
+/// Drop the buffers and page cache of the block device open as `f`.
+fn drop_cache(f: &File) -> Result<()> {
+	try!(ops::ioctl_blkflsbuf(f.as_raw_fd()));
+	match unsafe { libc::posix_fadvise(f.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+		0 => Ok(()),
+		e => Err(io::Error::from_raw_os_error(e).into()),
+	}
+}
 
-	let args: Args = docopt::Docopt::new(USAGE)
-		.and_then(|d| d.argv(std::env::args()).deserialize())
-		.unwrap_or_else(|e| e.exit());
-	let f = match File::open(&args.arg_dev) {
-		Err(e) => {
-			eprintln!("Unable to open {} for reading: {}", args.arg_dev, e);
-			return;
+/// Drop what was cached of the claimed `dev` while it was erased, so that
+/// nothing reads the old contents from memory afterwards.
+fn release_claim(dev: &str, claim: Option<File>) {
+	if let Some(f) = claim {
+		if let Err(e) = drop_cache(&f) {
+			eprintln!("Unable to drop the cached contents of {}: {:?}", dev, e);
 		}
-		Ok(f) => f,
-	};
-	match f.metadata() {
+	}
+}
+
+/// Claim `dev` before erasing it, see `claim_exclusive`. Returns `None` if
+/// not to go ahead; with `force` the erase goes ahead without the claim.
+fn claim_for_erase(dev: &str, force: bool) -> Option<Option<File>> {
+	match claim_exclusive(dev) {
+		Ok(claim) => Some(claim),
 		Err(e) => {
-			eprintln!("Unable to stat {}: {}", args.arg_dev, e);
-			return;
-		}
-		Ok(ref m) if !m.file_type().is_block_device() => {
-			eprintln!("{} is not a block device", args.arg_dev);
-			return;
+			eprintln!("Unable to open {} exclusively: {:?}", dev, e);
+			if force {
+				Some(None)
+			} else {
+				eprintln!(
+					"Something else has it open exclusively, e.g. a mount or a stacked device"
+				);
+				None
+			}
 		}
-		Ok(_) => {}
-	};
+	}
+}
+
+/// Open `dev` read-only for commands that only query the drive and for
+/// writing for those that change it. The kernel only lets users without
+/// `CAP_SYS_ADMIN` send commands that change the drive through a writable
+/// file, and security modules may make the same distinction.
+fn open_device<P: AsRef<Path>>(dev: P, write: bool) -> io::Result<File> {
+	std::fs::OpenOptions::new()
+		.read(true)
+		.write(write)
+		.open(dev)
+}
+
+fn access(write: bool) -> &'static str {
+	if write {
+		"writing"
+	} else {
+		"reading"
+	}
+}
+
+/// `--all-paths`: run `op` through each live path of multipath namespace
+/// `dev`, given the security state seen through it, then check that all
+/// paths report the same security state.
+fn on_all_paths<F>(dev: &str, op: F) -> Result<()>
+where
+	F: Fn(&File, &AtaSecurityIdentify) -> Result<()>,
+{
+	let paths = try!(device::multipath_paths(Path::new(dev)));
+	let mut controllers = vec![];
+	for path in paths.iter().filter(|p| p.live()) {
+		let f = try!(open_device(&format!("/dev/{}", path.controller), true));
+		eprintln!("Through {}:", path.controller);
+		try!(op(&f, &try!(ata_security_identify(&f))));
+		controllers.push((&path.controller, f));
+	}
+	let mut states = vec![];
+	for &(ctrl, ref f) in &controllers {
+		states.push((ctrl, state::State::from(&try!(ata_security_identify(f)))));
+	}
+	if states.iter().any(|s| s.1 != states[0].1) {
+		for &(ctrl, state) in &states {
+			eprintln!("{} reports {}", ctrl, state);
+		}
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			"the paths of the namespace report different security states",
+		)
+		.into());
+	}
+	Ok(())
+}
+
+/// The controller device of the path of multipath namespace `dev` through
+/// controller `ctrl`, to send commands to that path. Everything else, such
+/// as checking that the namespace is unused and claiming it for an erase,
+/// still goes through `dev`.
+fn select_path(dev: &str, ctrl: &str, write: bool) -> std::result::Result<File, String> {
+	let paths = try!(device::multipath_paths(Path::new(dev))
+		.map_err(|e| format!("Unable to find the paths of {}: {}", dev, e)));
+	if paths.is_empty() {
+		return Err(format!("{} is not a multipath namespace", dev));
+	}
+	let path = try!(paths
+		.iter()
+		.find(|p| p.controller == ctrl || p.name == ctrl)
+		.ok_or_else(|| format!(
+			"{} has no path through {}, only through {}",
+			dev,
+			ctrl,
+			paths
+				.iter()
+				.map(|p| p.controller.as_str())
+				.collect::<Vec<_>>()
+				.join(", ")
+		)));
+	if !path.live() {
+		eprintln!("Warning: the controller {} is not live", path.controller);
+	}
+	let ctrl_dev = format!("/dev/{}", path.controller);
+	open_device(&ctrl_dev, write)
+		.map_err(|e| format!("Unable to open {} for {}: {}", ctrl_dev, access(write), e))
+}
+
+/// Read back sampled blocks after an erase and summarize what they contain.
+fn verify_erase(dev: &Path, prepared: bool) -> Result<()> {
+	eprintln!("Verifying erase...");
+	let summary = try!(verify::check(dev));
+	eprintln!(
+		"{} blocks sampled, {} zeroed, {} with other data",
+		summary.sampled,
+		summary.zeroed,
+		summary.sampled - summary.zeroed - summary.sentinel
+	);
+	if summary.sentinel > 0 {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!(
+				"{} sampled blocks still contain the verification pattern",
+				summary.sentinel
+			),
+		)
+		.into());
+	}
+	if !prepared {
+		eprintln!("Without --verify-prepare, only zeroed blocks can be detected");
+	}
+	Ok(())
+}
+
+/// Check that no firmware activation is pending before erasing. A controller
+/// reset for the firmware commit in the middle of an erase may leave the
+/// security state inconsistent. Returns whether to go ahead.
+fn check_firmware_activation(f: &File, force: bool) -> bool {
+	let log = match ops::firmware_slot_log(f.as_raw_fd()) {
+		Err(e) => {
+			eprintln!("Warning: unable to read the firmware slot log: {:?}", e);
+			return true;
+		}
+		Ok(log) => log,
+	};
+	let slot = match log.next_slot() {
+		None => return true,
+		Some(slot) => slot,
+	};
+	eprintln!(
+		"Warning: firmware {} in slot {} will replace the running firmware from slot {} at \
+		 the next controller reset",
+		String::from_utf8_lossy(log.revision(slot)).trim(),
+		slot,
+		log.active_slot()
+	);
+	if force {
+		true
+	} else {
+		eprintln!("Reset the controller first, or use --force to erase anyway");
+		false
+	}
+}
+
+/// FORMAT NVM of the namespace of `fd`, keeping its LBA format.
+fn format_namespace(fd: RawFd, ses: nvme::SecureErase) -> Result<()> {
+	let nsid = try!(ops::nvme_ioctl_id(fd));
+	let lba_format = try!(ops::identify_namespace(fd, nsid)).lba_format();
+	ops::format_nvm(fd, nsid, lba_format, ses)
+}
+
+/// How often to read the Sanitize Status log page
+const SANITIZE_POLL_SECS: u64 = 5;
+
+/// Wait until no sanitize operation is in progress on `fd`, telling its
+/// progress, and return the final status.
+fn sanitize_wait(fd: RawFd) -> Result<nvme::log::SanitizeStatus> {
+	interrupt::during(interrupt::Operation::Sanitize);
+	let status = sanitize_poll(fd);
+	interrupt::done();
+	status
+}
+
+fn sanitize_poll(fd: RawFd) -> Result<nvme::log::SanitizeStatus> {
+	let mut reported = None;
+	loop {
+		let status = try!(ops::sanitize_status_log(fd));
+		if status.state() != nvme::log::SanitizeState::InProgress {
+			return Ok(status);
+		}
+		if interrupt::interrupted() {
+			return Err(io::Error::new(
+				io::ErrorKind::Interrupted,
+				"interrupted while the sanitize operation is in progress",
+			)
+			.into());
+		}
+		if reported != Some(status.percent()) {
+			eprintln!("Sanitizing, {}% done", status.percent());
+			reported = Some(status.percent());
+		}
+		std::thread::sleep(std::time::Duration::from_secs(SANITIZE_POLL_SECS));
+	}
+}
+
+/// `sanitize-status`: show the Sanitize Status log page, with `--wait` once
+/// no sanitize operation is in progress. Fails if the last one failed.
+fn sanitize_status(out: &output::Output, f: &File, wait: bool) -> i32 {
+	let fd = f.as_raw_fd();
+	let status = if wait {
+		sanitize_wait(fd)
+	} else {
+		ops::sanitize_status_log(fd)
+	};
+	let status = match status {
+		Err(e) => {
+			eprintln!("Unable to read the sanitize status: {:?}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(status) => status,
+	};
+	#[derive(Serialize)]
+	struct SanitizeStatus {
+		state: nvme::log::SanitizeState,
+		in_progress: bool,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		progress_percent: Option<u32>,
+		global_data_erased: bool,
+	}
+	let state = status.state();
+	let in_progress = state == nvme::log::SanitizeState::InProgress;
+	if out.is_human() {
+		writeln!(out, "most recent sanitize: {}", state);
+		if in_progress {
+			writeln!(out, "progress: {}%", status.percent());
+		}
+		writeln!(
+			out,
+			"global data erased: {}",
+			if status.global_data_erased() {
+				"yes"
+			} else {
+				"no"
+			}
+		);
+	} else {
+		out.structured(&SanitizeStatus {
+			state,
+			in_progress,
+			progress_percent: if in_progress {
+				Some(status.percent())
+			} else {
+				None
+			},
+			global_data_erased: status.global_data_erased(),
+		});
+	}
+	if state == nvme::log::SanitizeState::Failed {
+		EXIT_FAILURE
+	} else {
+		0
+	}
+}
+
+/// `feature get` and `feature set`.
+fn feature(out: &output::Output, f: &File, name: &str, value: Option<&str>) -> i32 {
+	let feature = match features::parse(name) {
+		Err(e) => {
+			eprintln!("{}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(feature) => feature,
+	};
+	let fd = f.as_raw_fd();
+	if let Some(value) = value {
+		let value = match features::encode(feature, value) {
+			Err(e) => {
+				eprintln!("{}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(value) => value,
+		};
+		eprintln!("Performing SET FEATURES...");
+		return finish(ops::set_features(fd, feature, value));
+	}
+	let value = match ops::get_features(fd, feature) {
+		Err(e) => {
+			eprintln!("There was an error executing the command: {:?}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(value) => value,
+	};
+	#[derive(Serialize)]
+	struct FeatureValue {
+		feature: &'static str,
+		value: u32,
+		description: String,
+	}
+	if out.is_human() {
+		writeln!(
+			out,
+			"{}: {}",
+			features::name(feature),
+			features::describe(feature, value)
+		);
+	} else {
+		out.structured(&FeatureValue {
+			feature: features::name(feature),
+			value,
+			description: features::describe(feature, value),
+		});
+	}
+	0
+}
+
+/// `self-test`: start or abort a device self-test.
+fn self_test(f: &File, info: &DriveInfo, code: nvme::SelfTest) -> i32 {
+	match info.0 {
+		Ok((ref i, _)) if !i.oacs().contains(nvme::identify::Oacs::SELF_TEST) => {
+			eprintln!("This drive does not support device self-tests.");
+			return EXIT_UNSUPPORTED;
+		}
+		_ => {}
+	}
+	if code == nvme::SelfTest::Abort {
+		eprintln!("Aborting device self-test...");
+	} else {
+		eprintln!("Starting device self-test, see self-test-status for its progress...");
+	}
+	finish(ops::device_self_test(f.as_raw_fd(), code))
+}
+
+fn print_layer(out: &output::Output, layer: &stack::Layer, depth: usize) {
+	let mut line = format!("{:indent$}{}: ", "", layer.name, indent = depth * 2);
+	line.push_str(layer.kind.name());
+	if let Some(ref detail) = layer.detail {
+		line.push_str(&format!(" {}", detail));
+	}
+	for mount in &layer.mounts {
+		line.push_str(&format!(", mounted on {} ({})", mount.path, mount.fstype));
+	}
+	if layer.swap {
+		line.push_str(", swap");
+	}
+	writeln!(out, "{}", line);
+	for holder in &layer.holders {
+		print_layer(out, holder, depth + 1);
+	}
+}
+
+/// `query --stack`: show what is layered on top of the namespace and warn
+/// about what an erase would destroy.
+fn query_stack(out: &output::Output, dev: &str) -> i32 {
+	let stack = match stack::stack(Path::new(dev)) {
+		Err(e) => {
+			eprintln!("Unable to find what uses {}: {}", dev, e);
+			return EXIT_FAILURE;
+		}
+		Ok(stack) => stack,
+	};
+	if out.is_human() {
+		print_layer(out, &stack.layers, 0);
+		for warning in &stack.warnings {
+			eprintln!("Warning: {}", warning);
+		}
+	} else {
+		out.structured(&stack);
+	}
+	0
+}
+
+/// `abort-command`: ask the controller to abort a command in flight, e.g. an
+/// erase that was interrupted.
+fn abort_command(f: &File, cid: &str, sqid: Option<&str>) -> i32 {
+	let parse = |s: &str| {
+		parse_number(s).and_then(|n| {
+			if n <= 0xffff {
+				Ok(n as u16)
+			} else {
+				Err(format!("Invalid identifier: {}", s))
+			}
+		})
+	};
+	let (cid, sqid) = match (parse(cid), sqid.map_or(Ok(0), parse)) {
+		(Err(e), _) | (_, Err(e)) => {
+			eprintln!("{}", e);
+			return EXIT_FAILURE;
+		}
+		(Ok(cid), Ok(sqid)) => (cid, sqid),
+	};
+	eprintln!("Performing ABORT...");
+	match ops::abort(f.as_raw_fd(), cid, sqid) {
+		Err(e) => finish(Err(e)),
+		Ok(true) => {
+			eprintln!("The controller aborted the command");
+			0
+		}
+		Ok(false) => {
+			eprintln!("The controller did not abort the command, it continues");
+			EXIT_FAILURE
+		}
+	}
+}
+
+/// `self-test-status`: show the self-test in progress and the results of
+/// the recent ones. Fails if the most recent one failed.
+fn self_test_status(out: &output::Output, f: &File) -> i32 {
+	let log = match ops::self_test_log(f.as_raw_fd()) {
+		Err(e) => {
+			eprintln!("Unable to read the device self-test log: {:?}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(log) => log,
+	};
+	#[derive(Serialize)]
+	struct SelfTestStatus {
+		#[serde(skip_serializing_if = "Option::is_none")]
+		current: Option<nvme::log::SelfTestOperation>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		progress_percent: Option<u8>,
+		results: Vec<nvme::log::SelfTestResult>,
+	}
+	let results = log.results();
+	let failed = results.first().map_or(false, |r| !r.passed);
+	if out.is_human() {
+		match log.current() {
+			Some((operation, percent)) => {
+				writeln!(out, "{} in progress, {}% done", operation, percent)
+			}
+			None => writeln!(out, "no self-test in progress"),
+		}
+		for r in &results {
+			write!(
+				out,
+				"{}: {} at {} power-on hours",
+				r.operation, r.result, r.power_on_hours
+			);
+			if let Some(segment) = r.segment {
+				write!(out, ", segment {}", segment);
+			}
+			if let Some(lba) = r.failing_lba {
+				write!(out, ", LBA {}", lba);
+			}
+			writeln!(out);
+		}
+	} else {
+		out.structured(&SelfTestStatus {
+			current: log.current().map(|c| c.0),
+			progress_percent: log.current().map(|c| c.1),
+			results,
+		});
+	}
+	if failed {
+		EXIT_FAILURE
+	} else {
+		0
+	}
+}
+
+/// `erase-advisor`: show the available erase mechanisms and, with `--auto`,
+/// perform the best one.
+fn erase_advisor(
+	out: &output::Output,
+	f: &File,
+	dev: &str,
+	info: &DriveInfo,
+	auto: bool,
+	force: bool,
+	password: PasswordSource,
+	normalize: Option<normalize::Normalization>,
+	report: Option<&str>,
+	sign_key: Option<&str>,
+) -> i32 {
+	let (identity, security) = match info.0 {
+		Err(ref e) => {
+			eprintln!(
+				"There was an error obtaining NVMe identity information:\n{:?}",
+				e
+			);
+			return EXIT_FAILURE;
+		}
+		Ok((ref i, Ok(Some((_, Ok(Some(ref s))))))) => (i, Some(s)),
+		Ok((ref i, _)) => (i, None),
+	};
+	let advice = advisor::advise(identity, security);
+	if out.is_human() {
+		for c in &advice.candidates {
+			let marker = if Some(c.method) == advice.recommended {
+				"*"
+			} else {
+				" "
+			};
+			let status = c.unavailable.unwrap_or("available");
+			writeln!(out, "{} {}: {}", marker, c.method.name(), status);
+			for note in &c.notes {
+				writeln!(out, "      {}", note);
+			}
+		}
+	} else {
+		out.structured(&advice);
+	}
+	let method = match advice.recommended {
+		Some(method) => method,
+		None => {
+			eprintln!("No erase mechanism is available for this drive");
+			return EXIT_UNSUPPORTED;
+		}
+	};
+	if !auto {
+		return 0;
+	}
+	if !check_not_in_use(dev, force) || !check_firmware_activation(f, force) {
+		return EXIT_FAILURE;
+	}
+	let claim = match claim_for_erase(dev, force) {
+		Some(claim) => claim,
+		None => return EXIT_FAILURE,
+	};
+
+	let fd = f.as_raw_fd();
+	let mut certificate = erasure::Certificate::new(dev, identity);
+	certificate.method = Some(method);
+	let result = match method {
+		advisor::Method::EnhancedAtaErase | advisor::Method::AtaErase => {
+			let enhanced = method == advisor::Method::EnhancedAtaErase;
+			let password =
+				match read_password(password, identity, "user password", false, None, normalize) {
+					Ok(password) => password,
+					Err(code) => return code,
+				};
+			if let Some(security) = security {
+				print_erase_estimate(security, enhanced);
+			}
+			eprintln!("Performing SECURITY ERASE...");
+			interrupt::during(interrupt::Operation::Erase);
+			security_erase(f, password, false, enhanced)
+		}
+		advisor::Method::SanitizeCryptoErase | advisor::Method::SanitizeBlockErase => {
+			eprintln!("Starting sanitize...");
+			interrupt::during(interrupt::Operation::Sanitize);
+			let started = ops::sanitize(
+				fd,
+				if method == advisor::Method::SanitizeCryptoErase {
+					nvme::SanitizeAction::CryptoErase
+				} else {
+					nvme::SanitizeAction::BlockErase
+				},
+			);
+			// The drive stays claimed and the report is about the finished
+			// sanitize
+			started.and_then(|()| decommission::wait_for_sanitize(fd))
+		}
+		advisor::Method::FormatCryptoErase | advisor::Method::FormatUserDataErase => {
+			eprintln!("Performing FORMAT NVM...");
+			interrupt::during(interrupt::Operation::Format);
+			format_namespace(
+				fd,
+				if method == advisor::Method::FormatCryptoErase {
+					nvme::SecureErase::Cryptographic
+				} else {
+					nvme::SecureErase::UserData
+				},
+			)
+		}
+	};
+	interrupt::done();
+	release_claim(dev, claim);
+	if let Some(path) = report {
+		if !write_report(certificate, &result, path, sign_key) {
+			return EXIT_FAILURE;
+		}
+	}
+	finish(result)
+}
+
+/// Record the layout of `dev` before erasing it in the `--manifest` file
+/// and save its LUKS headers to the `--backup-luks-headers` directory.
+/// Returns whether to go ahead.
+fn write_manifest(
+	dev: &str,
+	identity: &IdentifyController,
+	path: Option<&str>,
+	luks_dir: Option<&str>,
+) -> bool {
+	let mut manifest = match manifest::Manifest::new(Path::new(dev), identity) {
+		Err(e) => {
+			eprintln!("Unable to read the layout of {}: {}", dev, e);
+			return false;
+		}
+		Ok(manifest) => manifest,
+	};
+	if let Some(dir) = luks_dir {
+		match manifest.backup_luks_headers(dir) {
+			Err(e) => {
+				eprintln!("Unable to back up LUKS headers: {}", e);
+				return false;
+			}
+			Ok(0) => eprintln!("No LUKS headers on {}", dev),
+			Ok(n) => eprintln!("Saved {} LUKS headers to {}", n, dir),
+		}
+	}
+	if let Some(path) = path {
+		if let Err(e) = manifest.write(path) {
+			eprintln!("Unable to write the manifest {}: {}", path, e);
+			return false;
+		}
+		eprintln!("Recorded the layout of {} in {}", dev, path);
+	}
+	true
+}
+
+/// Record the outcome of an erase in the `--report` file. Returns whether
+/// the report was written.
+fn write_report(
+	mut certificate: erasure::Certificate,
+	result: &Result<()>,
+	path: &str,
+	sign_key: Option<&str>,
+) -> bool {
+	certificate.finish(result);
+	match certificate.write(path, sign_key) {
+		Err(e) => {
+			eprintln!("Unable to write the erasure report {}: {}", path, e);
+			false
+		}
+		Ok(()) => {
+			eprintln!("Wrote the erasure report to {}", path);
+			true
+		}
+	}
+}
+
+/// How long to wait for a controller to be ready after a reset, in seconds
+static RESET_TIMEOUT: AtomicUsize = AtomicUsize::new(30);
+
+/// Reset the controller of `dev` and wait up to `RESET_TIMEOUT` seconds until
+/// it is live again and answers commands through `f`.
+fn controller_reset(f: &File, dev: &Path) -> Result<()> {
+	let ctrl = try!(open_device(try!(device::controller(dev)), true));
+	try!(ops::nvme_ioctl_reset(ctrl.as_raw_fd()));
+	let timeout = RESET_TIMEOUT.load(Ordering::SeqCst) as u64;
+	let start = std::time::Instant::now();
+	loop {
+		// Without sysfs, answering Identify has to do
+		let live = device::controller_state(dev).map_or(true, |state| state == "live");
+		if live && ops::identify_controller(f.as_raw_fd()).is_ok() {
+			return Ok(());
+		}
+		if start.elapsed().as_secs() >= timeout {
+			return Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				format!(
+					"the controller is not ready {} seconds after the reset",
+					timeout
+				),
+			)
+			.into());
+		}
+		std::thread::sleep(std::time::Duration::from_millis(100));
+	}
+}
+
+/// Wait until udev has handled the events of an unlock, e.g. created the
+/// partition devices and their `/dev/disk` links.
+fn udev_settle() -> io::Result<()> {
+	let mut command = std::process::Command::new("udevadm");
+	command.arg("settle");
+	let status = try!(launcher::status(command)
+		.map_err(|e| io::Error::new(e.kind(), format!("Unable to run udevadm: {}", e))));
+	if !status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("udevadm settle failed with {}", status),
+		));
+	}
+	Ok(())
+}
+
+fn after_unlock(f: &File, dev: &Path, freeze: bool, reset: bool, settle: bool) -> Result<()> {
+	if reset {
+		eprintln!("Resetting controller...");
+		try!(controller_reset(f, dev));
+		// The namespace may only report its real contents after the reset
+		if try!(f.metadata()).file_type().is_block_device() {
+			try!(ops::ioctl_blkrrpart(f.as_raw_fd()));
+		}
+	}
+	if freeze {
+		eprintln!("Performing SECURITY FREEZE...");
+		try!(security_freeze(f));
+	}
+	if settle {
+		eprintln!("Waiting for udev...");
+		try!(udev_settle());
+	}
+	Ok(())
+}
+
+fn security_disable_password(f: &File, password: [u8; 32], master: bool) -> Result<()> {
+	let data = try!(session::password_data(password, master).build());
+	session::SecuritySession::new(f).disable_password(&data)
+}
+
+enum PasswordSource {
+	/// Prompt on the terminal, or read stdin if it is not a terminal
+	Stdin,
+	File(String),
+	Env(String),
+	/// A `user` key in the kernel keyring, by description
+	Keyring(String),
+	/// The systemd password agents, e.g. Plymouth during boot
+	Agent,
+	/// A FIDO2 key enrolled with `fido2-enroll`, by enrollment file
+	Fido2(String),
+	/// A password wrapped with `pkcs11-enroll`, by enrollment file
+	Pkcs11(String),
+	/// A password encrypted with `clevis encrypt`, by JWE file
+	Clevis(String),
+	/// An inherited file descriptor, e.g. 0 for `--password-file=-`
+	Fd(RawFd),
+	/// A master password derived from an organization secret, by secret
+	/// file and master password identifier
+	Fleet {
+		secret: String,
+		id: u16,
+	},
+}
+
+impl std::fmt::Display for PasswordSource {
+	fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			PasswordSource::Stdin => write!(fmt, "prompt"),
+			PasswordSource::File(ref path) => write!(fmt, "file {}", path),
+			PasswordSource::Env(ref var) => write!(fmt, "environment variable {}", var),
+			PasswordSource::Fd(fd) => write!(fmt, "file descriptor {}", fd),
+			PasswordSource::Keyring(ref key) => write!(fmt, "keyring key {}", key),
+			PasswordSource::Agent => write!(fmt, "password agent"),
+			PasswordSource::Fido2(ref path) => write!(fmt, "FIDO2 key of {}", path),
+			PasswordSource::Pkcs11(ref path) => write!(fmt, "PKCS#11 token of {}", path),
+			PasswordSource::Clevis(ref path) => write!(fmt, "Clevis JWE {}", path),
+			PasswordSource::Fleet { ref secret, id } => {
+				write!(fmt, "fleet secret {} (identifier {:04x})", secret, id)
+			}
+		}
+	}
+}
+
+/// Parse `--password-chain`, a comma-separated list of `file:<path>`,
+/// `fd:<n>`, `env:<var>`, `keyring:<description>`, `fido2:<file>`, `pkcs11:<file>`,
+/// `clevis:<file>`, `agent` and `prompt`, tried in order.
+fn parse_password_chain(s: &str) -> std::result::Result<Vec<PasswordSource>, String> {
+	s.split(',')
+		.map(|source| {
+			let mut parts = source.splitn(2, ':');
+			match (parts.next(), parts.next()) {
+				(Some("file"), Some(path)) => Ok(PasswordSource::File(path.to_owned())),
+				(Some("env"), Some(var)) => Ok(PasswordSource::Env(var.to_owned())),
+				(Some("fd"), Some(fd)) => fd
+					.parse()
+					.map(PasswordSource::Fd)
+					.map_err(|_| format!("Invalid file descriptor: {}", fd)),
+				(Some("keyring"), Some(key)) => Ok(PasswordSource::Keyring(key.to_owned())),
+				(Some("prompt"), None) => Ok(PasswordSource::Stdin),
+				(Some("agent"), None) => Ok(PasswordSource::Agent),
+				(Some("fido2"), Some(path)) => Ok(PasswordSource::Fido2(path.to_owned())),
+				(Some("pkcs11"), Some(path)) => Ok(PasswordSource::Pkcs11(path.to_owned())),
+				(Some("clevis"), Some(path)) => Ok(PasswordSource::Clevis(path.to_owned())),
+				_ => Err(format!("Invalid password source: {}", source)),
+			}
+		})
+		.collect()
+}
+
+/// The file descriptor a password file refers to: `-` and `/dev/stdin` for
+/// stdin, `/dev/fd/<n>` as passed by process substitution for `<n>`. Reading
+/// it directly instead of opening it again also works for sockets and
+/// without `/dev/fd`.
+fn password_fd(path: &str) -> Option<RawFd> {
+	match path {
+		"-" | "/dev/stdin" => Some(0),
+		_ if path.starts_with("/dev/fd/") => path[8..].parse().ok(),
+		_ if path.starts_with("/proc/self/fd/") => path[14..].parse().ok(),
+		_ => None,
+	}
+}
+
+impl From<Option<String>> for PasswordSource {
+	fn from(file: Option<String>) -> PasswordSource {
+		match file {
+			None => PasswordSource::Stdin,
+			Some(file) => match password_fd(&file) {
+				Some(fd) => PasswordSource::Fd(fd),
+				None => PasswordSource::File(file),
+			},
+		}
+	}
+}
+
+extern "C" {
+	static mut environ: *const *mut libc::c_char;
+}
+
+/// Read and unset the environment variable `var`.
+///
+/// The value is also overwritten in the original environment block, so that it
+/// does not remain visible in `/proc/<pid>/environ`.
+fn take_env(var: &str) -> std::result::Result<Vec<u8>, io::Error> {
+	use std::ffi::CStr;
+	use std::os::unix::ffi::OsStringExt;
+
+	let value = match std::env::var_os(var) {
+		Some(value) => value.into_vec(),
+		None => {
+			return Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("environment variable {} is not set", var),
+			))
+		}
+	};
+	unsafe {
+		let mut entry = environ;
+		while !entry.is_null() && !(*entry).is_null() {
+			let len = CStr::from_ptr(*entry).to_bytes().len();
+			let matches = {
+				let bytes = CStr::from_ptr(*entry).to_bytes();
+				bytes.len() > var.len()
+					&& bytes.starts_with(var.as_bytes())
+					&& bytes[var.len()] == b'='
+			};
+			if matches {
+				for i in (var.len() + 1)..len {
+					std::ptr::write_volatile((*entry).offset(i as isize), 0);
+				}
+			}
+			entry = entry.offset(1);
+		}
+	}
+	std::env::remove_var(var);
+	Ok(value)
+}
+
+/// Read the `user` key `description` from the kernel keyrings of the process,
+/// e.g. added with `keyctl padd user <description> @u`.
+fn read_keyring(description: &str) -> std::result::Result<Vec<u8>, io::Error> {
+	use std::ffi::CString;
+	const KEYCTL_READ: libc::c_long = 11;
+
+	let description =
+		try!(CString::new(description).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+	let id = unsafe {
+		libc::syscall(
+			libc::SYS_request_key,
+			b"user\0".as_ptr(),
+			description.as_ptr(),
+			std::ptr::null::<libc::c_char>(),
+			0,
+		)
+	};
+	if id < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	let mut buf = vec![0u8; 64];
+	loop {
+		let len = unsafe {
+			libc::syscall(
+				libc::SYS_keyctl,
+				KEYCTL_READ,
+				id,
+				buf.as_mut_ptr(),
+				buf.len(),
+			)
+		};
+		if len < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		// The full length is returned if the buffer was too small
+		if len as usize <= buf.len() {
+			buf.truncate(len as usize);
+			return Ok(buf);
+		}
+		buf.resize(len as usize, 0);
+	}
+}
+
+/// Seconds to wait for a password typed on the terminal, 0 for no limit.
+static PROMPT_TIMEOUT: AtomicUsize = AtomicUsize::new(0);
+
+/// Wait up to `PROMPT_TIMEOUT` seconds for a line typed on the terminal.
+/// Echo is off meanwhile, rpassword only turns it off once it starts reading.
+fn wait_for_password() -> io::Result<()> {
+	use nix::poll::{poll, EventFlags, PollFd};
+	use nix::sys::termios::{self, LocalFlags, SetArg};
+
+	fn io_error(e: nix::Error) -> io::Error {
+		io::Error::new(io::ErrorKind::Other, e)
+	}
+
+	let secs = PROMPT_TIMEOUT.load(Ordering::SeqCst);
+	if secs == 0 {
+		return Ok(());
+	}
+	let original = try!(termios::tcgetattr(0).map_err(io_error));
+	let mut noecho = original.clone();
+	noecho.local_flags.remove(LocalFlags::ECHO);
+	noecho.local_flags.insert(LocalFlags::ECHONL);
+	try!(termios::tcsetattr(0, SetArg::TCSANOW, &noecho).map_err(io_error));
+	let ready = poll(
+		&mut [PollFd::new(0, EventFlags::POLLIN)],
+		(secs * 1000) as libc::c_int,
+	);
+	let _ = termios::tcsetattr(0, SetArg::TCSANOW, &original);
+	match try!(ready.map_err(io_error)) {
+		0 => Err(io::Error::new(
+			io::ErrorKind::TimedOut,
+			format!("no password was entered within {} seconds", secs),
+		)),
+		_ => Ok(()),
+	}
+}
+
+fn read_password_err(
+	src: PasswordSource,
+	identity: &IdentifyController,
+	what: &str,
+	confirm: bool,
+	policy: Option<&config::PasswordPolicy>,
+	normalize: Option<normalize::Normalization>,
+) -> std::result::Result<[u8; 32], io::Error> {
+	let mut f_file;
+	let mut f_fd;
+	let f_env;
+	let mut f_env_ptr;
+	let mut f_stdin;
+	let f_password;
+	let mut f_password_ptr;
+	let from_file = match src {
+		PasswordSource::File(_) | PasswordSource::Fd(_) => true,
+		_ => false,
+	};
+	// Only passwords typed by someone are normalized, key material is used
+	// as is
+	let typed = match src {
+		PasswordSource::Stdin
+		| PasswordSource::Env(_)
+		| PasswordSource::Keyring(_)
+		| PasswordSource::Agent => true,
+		_ => false,
+	};
+	let f: &mut Read = if let PasswordSource::File(src) = src {
+		f_file = try!(File::open(src));
+		&mut f_file
+	} else if let PasswordSource::Fd(fd) = src {
+		// Not closed, it belongs to whoever passed it
+		f_fd = std::mem::ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+		&mut *f_fd
+	} else if let PasswordSource::Env(var) = src {
+		f_env = try!(take_env(&var));
+		f_env_ptr = &f_env[..];
+		&mut f_env_ptr
+	} else if let PasswordSource::Keyring(key) = src {
+		f_env = try!(read_keyring(&key));
+		f_env_ptr = &f_env[..];
+		&mut f_env_ptr
+	} else if let PasswordSource::Fido2(path) = src {
+		f_env = try!(fido2::password(&path));
+		f_env_ptr = &f_env[..];
+		&mut f_env_ptr
+	} else if let PasswordSource::Pkcs11(path) = src {
+		f_env = try!(pkcs11::password(&path));
+		f_env_ptr = &f_env[..];
+		&mut f_env_ptr
+	} else if let PasswordSource::Clevis(path) = src {
+		f_env = try!(clevis::decrypt(&path));
+		f_env_ptr = &f_env[..];
+		&mut f_env_ptr
+	} else if let PasswordSource::Fleet { secret, id } = src {
+		f_env = try!(fleet::password(&secret, &config::serial(identity), id));
+		f_env_ptr = &f_env[..];
+		&mut f_env_ptr
+	} else if let PasswordSource::Agent = src {
+		f_env = try!(askpass::ask(
+			&format!(
+				"Please enter {} for {} {}",
+				what,
+				String::from_utf8_lossy(identity.mn()).trim(),
+				String::from_utf8_lossy(identity.sn()).trim()
+			),
+			&format!("nvme-ata-security:{}", config::serial(identity)),
+			PROMPT_TIMEOUT.load(Ordering::SeqCst) as u64,
+		));
+		f_env_ptr = &f_env[..];
+		&mut f_env_ptr
+	} else {
+		if nix::unistd::isatty(0).unwrap_or(false) {
+			loop {
+				eprint!(
+					"Please enter {} for {} {}:",
+					what,
+					String::from_utf8_lossy(identity.mn()).trim(),
+					String::from_utf8_lossy(identity.sn()).trim()
+				);
+				try!(wait_for_password());
+				let password1 = try!(rpassword::read_password());
+				if password1.len() == 0 {
+					continue;
+				} else if password1.len() > 32 {
+					eprintln!("Password too long!");
+					continue;
+				}
+				if let Some(policy) = policy {
+					let problems = policy.check(&password1);
+					for problem in &problems {
+						eprintln!("Weak password: {}", problem);
+					}
+					if policy.enforce && !problems.is_empty() {
+						continue;
+					}
+				}
+				if confirm {
+					eprint!("Enter password again:");
+					let password2 = try!(rpassword::read_password());
+					if password1 != password2 {
+						eprintln!("Passwords don't match!");
+						continue;
+					}
+				}
+				f_password = password1;
+				break;
+			}
+			f_password_ptr = f_password.as_bytes();
+			&mut f_password_ptr
+		} else {
+			f_stdin = io::stdin();
+			&mut f_stdin
+		}
+	};
+
+	let mut buf = vec![];
+	try!(f.read_to_end(&mut buf));
+	if from_file {
+		if let Some(file) = try!(PasswordFile::parse(&buf)) {
+			try!(file.check_serial(&config::serial(identity)));
+			return file.password();
+		}
+	}
+	derive_password(buf, identity, if typed { normalize } else { None })
+}
+
+/// The 32 bytes sent to the drive for the password `buf`: SHA-256 of the
+/// (normalized) password, model and serial number.
+fn derive_password(
+	mut buf: Vec<u8>,
+	identity: &IdentifyController,
+	normalize: Option<normalize::Normalization>,
+) -> std::result::Result<[u8; 32], io::Error> {
+	if let Some(normalize) = normalize {
+		buf = try!(normalize.apply(&buf));
+	}
+	let mut out = [0u8; 32];
+	let mut sha256 = Sha256::new();
+	sha256.input(&buf);
+	sha256.input(&identity.mn());
+	sha256.input(&identity.sn());
+	out.copy_from_slice(&sha256.result());
+	Ok(out)
+}
+
+/// `read_password_err`, reporting an error and returning the exit status
+/// instead.
+fn read_password(
+	src: PasswordSource,
+	identity: &IdentifyController,
+	what: &str,
+	confirm: bool,
+	policy: Option<&config::PasswordPolicy>,
+	normalize: Option<normalize::Normalization>,
+) -> std::result::Result<[u8; 32], i32> {
+	read_password_err(src, identity, what, confirm, policy, normalize).map_err(|e| {
+		eprintln!("Error trying to read password: {}", e);
+		EXIT_FAILURE
+	})
+}
+
+fn print_status(out: &output::Output, security: &AtaSecurityIdentify, explain: bool) {
+	writeln!(out, "state: {}", state::State::from(security));
+	if security.pwncntex() {
+		writeln!(out, "password attempt counter exceeded");
+	}
+	if !explain {
+		return;
+	}
+	for op in state::OPERATIONS {
+		match state::blocker(security, op) {
+			None => writeln!(out, "{}: possible", op),
+			Some(reason) => writeln!(out, "{}: will fail, {}", op, reason),
+		}
+	}
+	for step in state::next_steps(security) {
+		writeln!(out, "next step: {}", step);
+	}
+}
+
+/// `status --field=<name>`: print yes or no and exit with 0 or 1 accordingly,
+/// or `EXIT_FIELD_ERROR` if the state can't be read. Drives without ATA
+/// security are neither enabled, locked nor frozen.
+fn status_field(out: &output::Output, field: &str, info: &DriveInfo) -> i32 {
+	let security = match info.0 {
+		Ok((_, Ok(Some((_, Ok(ref s)))))) => s.as_ref(),
+		Ok((_, Ok(None))) => None,
+		_ => {
+			eprintln!("Unable to read the ATA security state of the drive");
+			return EXIT_FIELD_ERROR;
+		}
+	};
+	let value = match field {
+		"supported" => security.map_or(false, |s| s.s_suprt()),
+		"enabled" => security.map_or(false, |s| s.s_enabld()),
+		"locked" => security.map_or(false, |s| s.locked()),
+		"frozen" => security.map_or(false, |s| s.frozen()),
+		_ => {
+			eprintln!(
+				"Unknown field {}, expected locked, frozen, enabled or supported",
+				field
+			);
+			return EXIT_FIELD_ERROR;
+		}
+	};
+	writeln!(out, "{}", if value { "yes" } else { "no" });
+	if value {
+		0
+	} else {
+		FIELD_NO
+	}
+}
+
+/// Unlock all configured drives. With more than one job, drives are handled
+/// concurrently without prompting and messages go to stderr instead of `log`.
+/// With `broker`, the commands are sent through the privileged broker.
+fn unlock_all(
+	config: &config::Config,
+	interactive: bool,
+	log: &journal::Log,
+	jobs: usize,
+	broker: Option<&broker::Client>,
+) -> i32 {
+	let devices = match device::namespaces() {
+		Err(e) => {
+			eprintln!("Unable to enumerate NVMe devices: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(devices) => devices,
+	};
+	if jobs > 1 {
+		let config = Arc::new(config.clone());
+		return pool::run(devices, jobs, move |dev| {
+			unlock_configured(&config, dev, false, &journal::Log::Tagged, None)
+		});
+	}
+	let mut status = 0;
+	for dev in devices {
+		match unlock_configured(config, &dev, interactive, log, broker) {
+			0 => {}
+			code => status = code,
+		}
+	}
+	status
+}
+
+/// `erase-all`: SECURITY ERASE every configured drive with its password
+/// file, up to `jobs` at a time. Drives without a password file are left
+/// alone.
+fn erase_all(config: &config::Config, enhanced: bool, force: bool, jobs: usize) -> i32 {
+	let devices = match device::namespaces() {
+		Err(e) => {
+			eprintln!("Unable to enumerate NVMe devices: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(devices) => devices,
+	};
+	let config = Arc::new(config.clone());
+	pool::run(devices, jobs, move |dev| {
+		erase_configured(&config, dev, enhanced, force)
+	})
+}
+
+/// Erase `dev` with its configured password file, if it is in `config`.
+fn erase_configured(config: &config::Config, dev: &Path, enhanced: bool, force: bool) -> i32 {
+	let name = dev.display().to_string();
+	let f = match open_device(dev, true) {
+		Err(e) => {
+			eprintln!("Unable to open {} for writing: {}", name, e);
+			return EXIT_FAILURE;
+		}
+		Ok(f) => f,
+	};
+	let info = DriveInfo::query(&f);
+	let device_config = match info.0 {
+		Err(ref e) => {
+			eprintln!(
+				"{}: unable to obtain NVMe identity information: {:?}",
+				name, e
+			);
+			return EXIT_FAILURE;
+		}
+		Ok((ref identity, _)) => match config.device(identity) {
+			Some(c) => c.clone(),
+			None => return 0,
+		},
+	};
+	let password_file = match device_config.password_file {
+		Some(ref path) => path.clone(),
+		None => {
+			eprintln!("{}: no password file configured, not erasing", name);
+			return 0;
+		}
+	};
+	let (identity, security) = match info.check_support() {
+		Ok(v) => v,
+		Err(code) => return code,
+	};
+	if !security.s_enabld() || security.frozen() || security.pwncntex() {
+		eprintln!(
+			"{}: the drive can't be erased, it has no password, is frozen or exceeded the \
+			 password attempt counter",
+			name
+		);
+		return EXIT_FAILURE;
+	}
+	if !check_not_in_use(&name, force) || !check_firmware_activation(&f, force) {
+		return EXIT_FAILURE;
+	}
+	let claim = match claim_for_erase(&name, force) {
+		Some(claim) => claim,
+		None => return EXIT_FAILURE,
+	};
+	let password = match read_password_err(
+		PasswordSource::File(password_file),
+		&identity,
+		"password",
+		false,
+		None,
+		device_config.normalize,
+	) {
+		Err(e) => {
+			eprintln!("{}: unable to read the password: {}", name, e);
+			return EXIT_FAILURE;
+		}
+		Ok(password) => password,
+	};
+	if let Some(time) = security.erase_time(enhanced) {
+		eprintln!("{}: the drive estimates the erase takes {}", name, time);
+	}
+	let erased = security_erase(&f, password, false, enhanced);
+	release_claim(&name, claim);
+	match erased {
+		Err(e) => {
+			eprintln!(
+				"{}: there was an error executing the command: {:?}",
+				name, e
+			);
+			EXIT_FAILURE
+		}
+		Ok(()) => 0,
+	}
+}
+
+/// Unlock `dev` as configured, if it is in `config`. Without `interactive`,
+/// drives without a configured password file are skipped.
+fn unlock_configured(
+	config: &config::Config,
+	dev: &Path,
+	interactive: bool,
+	log: &journal::Log,
+	broker: Option<&broker::Client>,
+) -> i32 {
+	let error = |serial: Option<&str>, action, message| {
+		log.event(journal::Event {
+			priority: journal::Priority::Err,
+			dev,
+			serial,
+			action,
+			result: "error",
+			message,
+		});
+		EXIT_FAILURE
+	};
+	let drive = match broker::Drive::open(dev, true, broker) {
+		Err(e) => {
+			let message = format!("Unable to open {} for writing: {}", dev.display(), e);
+			return error(None, "unlock", message);
+		}
+		Ok(drive) => drive,
+	};
+	let info = drive.query();
+	let device_config = match info.0 {
+		Err(ref e) => {
+			let message = format!("Unable to obtain NVMe identity information: {:?}", e);
+			return error(None, "unlock", message);
+		}
+		Ok((ref identity, _)) => match config.device(identity) {
+			Some(c) => c,
+			None => return 0,
+		},
+	};
+	log.progress(&format!("{}:", dev.display()));
+	let (identity, security) = match info.check_support() {
+		Ok(v) => v,
+		Err(code) => return code,
+	};
+	let serial_number = config::serial(&identity);
+	let serial = Some(&serial_number[..]);
+	if security.locked() {
+		if security.pwncntex() {
+			let message = "The password attempt counter is exceeded, power cycle the drive \
+			               before trying again"
+				.to_owned();
+			return error(serial, "unlock", message);
+		}
+		if !interactive && device_config.password_file.is_none() && device_config.clevis.is_none() {
+			log.event(journal::Event {
+				priority: journal::Priority::Warning,
+				dev,
+				serial,
+				action: "unlock",
+				result: "skipped",
+				message: "No password file configured, not unlocking".to_owned(),
+			});
+			return 0;
+		}
+		let source = match device_config.clevis {
+			Some(ref jwe) => PasswordSource::Clevis(jwe.clone()),
+			None => device_config.password_file.clone().into(),
+		};
+		let password = match read_password_err(
+			source,
+			&identity,
+			"password",
+			false,
+			None,
+			device_config.normalize,
+		) {
+			Err(e) => {
+				let message = format!("Error trying to read password: {}", e);
+				return error(serial, "unlock", message);
+			}
+			Ok(v) => v,
+		};
+		log.progress("Performing SECURITY UNLOCK...");
+		if let Err(e) = drive.unlock(password, true) {
+			let message = format!("There was an error executing the command: {:?}", e);
+			return error(serial, "unlock", message);
+		}
+		log.event(journal::Event {
+			priority: journal::Priority::Notice,
+			dev,
+			serial,
+			action: "unlock",
+			result: "success",
+			message: "Unlocked".to_owned(),
+		});
+	}
+	let freeze = device_config.freeze && !security.frozen();
+	let reset = device_config.reset && security.locked();
+	let action = match (freeze, reset) {
+		(false, false) => return 0,
+		(true, false) => "freeze",
+		(false, true) => "reset",
+		(true, true) => "reset,freeze",
+	};
+	if let Err(e) = drive.after_unlock(freeze, reset) {
+		let message = format!("There was an error executing the command: {:?}", e);
+		return error(serial, action, message);
+	}
+	log.event(journal::Event {
+		priority: journal::Priority::Info,
+		dev,
+		serial,
+		action,
+		result: "success",
+		message: "Success!".to_owned(),
+	});
+	0
+}
+
+fn parse_number(s: &str) -> std::result::Result<u32, String> {
+	if s.starts_with("0x") {
+		u32::from_str_radix(&s[2..], 16)
+	} else {
+		s.parse()
+	}
+	.map_err(|_| format!("Invalid number: {}", s))
+}
+
+fn parse_protocol(s: &str) -> std::result::Result<nvme::security::Protocol, String> {
+	let hex = if s.starts_with("0x") { &s[2..] } else { s };
+	u8::from_str_radix(hex, 16)
+		.map(nvme::security::Protocol::from)
+		.map_err(|_| format!("Invalid security protocol: {}", s))
+}
+
+/// Raw SECURITY SEND/RECEIVE for `security-send` and `security-recv`.
+fn raw_security(
+	out: &output::Output,
+	f: &File,
+	send: bool,
+	protocol: &str,
+	spsp: Option<&str>,
+	length: Option<&str>,
+	data_file: Option<&str>,
+) -> std::result::Result<(), String> {
+	let protocol = try!(parse_protocol(protocol));
+	let spsp = try!(spsp.map_or(Ok(0), parse_number));
+	if spsp > 0xffff {
+		return Err(format!("Invalid SP specific value: {}", spsp));
+	}
+	if send {
+		let mut data = vec![];
+		match data_file {
+			Some(path) => try!(File::open(path)
+				.and_then(|mut f| f.read_to_end(&mut data))
+				.map_err(|e| format!("Unable to read {}: {}", path, e))),
+			None if !nix::unistd::isatty(0).unwrap_or(false) => try!(io::stdin()
+				.read_to_end(&mut data)
+				.map_err(|e| format!("Unable to read stdin: {}", e))),
+			None => 0,
+		};
+		eprintln!("Performing SECURITY SEND...");
+		ops::security_send(
+			f.as_raw_fd(),
+			protocol.into(),
+			spsp as u16,
+			0,
+			if data.is_empty() {
+				None
+			} else {
+				Some(&data[..])
+			},
+		)
+		.map_err(|e| format!("{:?}", e))
+	} else {
+		let len = try!(length.map_or(Ok(512), parse_number));
+		let mut data = vec![0u8; len as usize];
+		eprintln!("Performing SECURITY RECEIVE...");
+		try!(
+			ops::security_receive(f.as_raw_fd(), protocol.into(), spsp as u16, 0, &mut data)
+				.map_err(|e| format!("{:?}", e))
+		);
+		write!(out, "{}", output::hexdump(&data));
+		Ok(())
+	}
+}
+
+/// Unlock configured drives now and whenever one is attached, in the
+/// seccomp sandbox with `sandbox`. With `daemon_user` configured, the drive
+/// commands go through the privileged broker.
+fn daemon(config: &config::Config, control: bool, dbus: bool, sandbox: bool) -> i32 {
+	// Before forking, so the broker records the commands it executes
+	if let Some(ref path) = config.audit_log {
+		if let Err(e) = audit::open_for_process(path) {
+			eprintln!("Unable to open audit log {}: {}", path, e);
+			return EXIT_FAILURE;
+		}
+	}
+	// Fork before starting any threads
+	let broker = match config.daemon_user {
+		None => None,
+		Some(_) => match broker::spawn(sandbox) {
+			Err(e) => {
+				eprintln!("Unable to start the privileged broker: {}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(client) => Some(Arc::new(client)),
+		},
+	};
+	// Set up the sockets while still root, but only start handling requests
+	// once privileges are dropped and the sandbox is installed
+	let listener = if control {
+		match control::bind() {
+			Err(e) => {
+				eprintln!("Unable to listen on {}: {}", control::SOCKET, e);
+				return EXIT_FAILURE;
+			}
+			Ok(listener) => Some(listener),
+		}
+	} else {
+		None
+	};
+	let connection = if dbus {
+		match dbus::connect() {
+			Err(e) => {
+				eprintln!("Unable to provide {} on the system bus: {}", dbus::NAME, e);
+				return EXIT_FAILURE;
+			}
+			Ok(connection) => Some(connection),
+		}
+	} else {
+		None
+	};
+	// Listen before scanning so no drive is missed in between
+	let socket = match uevent::Socket::open() {
+		Err(e) => {
+			eprintln!("Unable to listen for device events: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(socket) => socket,
+	};
+	if let Some(ref user) = config.daemon_user {
+		if let Err(e) = broker::drop_privileges(user) {
+			eprintln!("Unable to switch to user {}: {}", user, e);
+			return EXIT_FAILURE;
+		}
+	}
+	if sandbox {
+		let profile = sandbox::Profile {
+			exec: config.device.values().any(|d| d.clevis.is_some()),
+			accept: control,
+		};
+		if let Err(e) = sandbox::restrict(profile) {
+			eprintln!("Unable to install the seccomp sandbox: {}", e);
+			return EXIT_FAILURE;
+		}
+	}
+	if let Some(listener) = listener {
+		control::listen(listener, Arc::new(config.clone()), broker.clone());
+	}
+	if let Some(connection) = connection {
+		dbus::serve(connection, Arc::new(config.clone()), broker.clone());
+	}
+	let log = journal::Log::daemon();
+	let broker = broker.as_ref().map(|client| &**client);
+	unlock_all(config, false, &log, 1, broker);
+	loop {
+		let event = match socket.recv() {
+			// The socket buffer overflowed during a burst of events
+			Err(ref e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+				eprintln!("Device events were lost, rescanning all drives");
+				unlock_all(config, false, &log, 1, broker);
+				continue;
+			}
+			Err(e) => {
+				eprintln!("Unable to receive device event: {}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(event) => event,
+		};
+		if event.action != "add"
+			|| event.get("SUBSYSTEM") != Some("block")
+			|| event.get("DEVTYPE") != Some("disk")
+		{
+			continue;
+		}
+		match event.get("DEVNAME") {
+			Some(name) if device::is_namespace_name(name) => {
+				unlock_configured(config, &Path::new("/dev").join(name), false, &log, broker);
+			}
+			_ => {}
+		}
+	}
+}
+
+enum PowerCycle {
+	/// Suspend to RAM, resuming after this many seconds
+	Suspend(u32),
+	D3cold,
+	Slot,
+}
+
+/// Power cycle the drive with serial number `serial` to clear the frozen
+/// state for `unfreeze`, then check that it worked. Unless `force`, drives
+/// that are in use or come back locked are refused.
+fn unfreeze(
+	dev: &str,
+	serial: &str,
+	security: &AtaSecurityIdentify,
+	method: PowerCycle,
+	force: bool,
+) -> i32 {
+	let ctrl = match device::controller(Path::new(dev)) {
+		Err(e) => {
+			eprintln!("Unable to determine the NVMe controller of {}: {}", dev, e);
+			return EXIT_FAILURE;
+		}
+		Ok(ctrl) => ctrl.file_name().unwrap().to_string_lossy().into_owned(),
+	};
+	// Removing the device under a mounted file system loses data, and after
+	// a suspend the drive comes back locked if it has a password, failing all
+	// I/O of its users until it is unlocked again
+	if !check_not_in_use(dev, force) {
+		return EXIT_FAILURE;
+	}
+	if security.s_enabld() && !force {
+		eprintln!(
+			"The drive has a password and comes back locked, unlock it afterwards. \
+			 Use --force to power cycle it anyway."
+		);
+		return EXIT_FAILURE;
+	}
+	let result = match method {
+		PowerCycle::Suspend(wake_after) => {
+			if !power::deep_sleep() {
+				eprintln!("Warning: suspend to RAM uses s2idle, the drive may stay powered");
+			}
+			eprintln!("Suspending to RAM for {} seconds...", wake_after);
+			power::suspend(wake_after)
+		}
+		PowerCycle::D3cold => power::pci_device(&ctrl).and_then(|pci| {
+			eprintln!("Power cycling {} through D3cold...", pci.display());
+			power::d3cold_cycle(&pci)
+		}),
+		PowerCycle::Slot => power::pci_device(&ctrl).and_then(|pci| {
+			let slot = try!(power::slot(&pci));
+			eprintln!("Power cycling slot {}...", slot.display());
+			power::slot_cycle(&pci, &slot)
+		}),
+	};
+	if let Err(e) = result {
+		eprintln!("Unable to power cycle {}: {}", ctrl, e);
+		return EXIT_FAILURE;
+	}
+
+	// Wait for the driver to probe the controller again
+	let dev = (0..100)
+		.map(|_| {
+			std::thread::sleep(std::time::Duration::from_millis(100));
+			device::find_by_serial(serial).ok().and_then(|d| d)
+		})
+		.retry_options();
+	let security = match dev {
+		None => {
+			eprintln!("The drive with serial number {} did not come back", serial);
+			return EXIT_FAILURE;
+		}
+		Some(dev) => File::open(&dev)
+			.map_err(ops::Error::from)
+			.and_then(|f| ata_security_identify(&f)),
+	};
+	match security {
+		Err(e) => {
+			eprintln!("There was an error executing the command: {:?}", e);
+			EXIT_FAILURE
+		}
+		Ok(ref s) if s.frozen() => {
+			eprintln!("The drive is still frozen");
+			EXIT_FAILURE
+		}
+		Ok(s) => {
+			eprintln!("No longer frozen, state: {}", state::State::from(&s));
+			0
+		}
+	}
+}
+
+/// Ask `question` on the terminal and return the answer.
+fn ask(question: &str) -> io::Result<String> {
+	eprint!("{} ", question);
+	let mut answer = String::new();
+	try!(io::stdin().read_line(&mut answer));
+	Ok(answer.trim().to_owned())
+}
+
+/// Ask a yes/no `question`, an empty answer means `default`.
+fn ask_yes_no(question: &str, default: bool) -> io::Result<bool> {
+	let prompt = format!("{} [{}]", question, if default { "Y/n" } else { "y/N" });
+	loop {
+		match &try!(ask(&prompt)).to_lowercase()[..] {
+			"" => return Ok(default),
+			"y" | "yes" => return Ok(true),
+			"n" | "no" => return Ok(false),
+			_ => {}
+		}
+	}
+}
+
+/// `serial` as a file name. The serial number comes from the drive and may
+/// contain `/` or be `..`, so anything but letters, digits, `-` and `_` is
+/// replaced.
+fn file_name(serial: &str) -> String {
+	serial
+		.chars()
+		.map(|c| match c {
+			'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => c,
+			_ => '_',
+		})
+		.collect()
+}
+
+/// Walk through enabling ATA security for `setup`.
+fn setup(
+	out: &output::Output,
+	f: &File,
+	identity: &IdentifyController,
+	security: &AtaSecurityIdentify,
+	policy: &config::PasswordPolicy,
+) -> io::Result<i32> {
+	print_status(out, security, false);
+	match state::State::from(security) {
+		state::State::Disabled => {}
+		state::State::DisabledFrozen => {
+			eprintln!(
+				"The drive is frozen, so no password can be set. Power cycle it, for example \
+				 with unfreeze, and run setup again."
+			);
+			return Ok(EXIT_FAILURE);
+		}
+		_ => {
+			eprintln!("A password is already set. Use change-password to change it.");
+			return Ok(EXIT_FAILURE);
+		}
+	}
+
+	eprintln!();
+	eprintln!("Setting a user password locks the drive at every power cycle until it is");
+	eprintln!("unlocked with that password. The drive also has a master password, set by");
+	eprintln!("the manufacturer unless you change it with set-password -m.");
+	eprintln!();
+	eprintln!("  high: the master password can unlock the drive as well as erase it.");
+	eprintln!("  max:  the master password can only erase the drive. If the user password");
+	eprintln!("        is lost, so is the data.");
+	eprintln!();
+	let maximum_security = loop {
+		match &try!(ask("Security level [high/max]:"))[..] {
+			"high" => break false,
+			"max" => break true,
+			_ => {}
+		}
+	};
+
+	let serial = config::serial(identity);
+	let level = if maximum_security {
+		config::SecurityLevel::Max
+	} else {
+		config::SecurityLevel::High
+	};
+	let generate = try!(ask_yes_no(
+		"Generate a random password and store it in a password file?",
+		true
+	));
+	let (password, password_file) = if generate {
+		let mut password = [0u8; 32];
+		try!(rand::getrandom(&mut password));
+		let default_path = format!("/etc/nvme-ata-security/{}.key", file_name(&serial));
+		let path = match &try!(ask(&format!("Password file [{}]:", default_path)))[..] {
+			"" => default_path,
+			path => path.to_owned(),
+		};
+		(password, Some(path))
+	} else {
+		let password = try!(read_password_err(
+			PasswordSource::Stdin,
+			identity,
+			"password",
+			true,
+			Some(policy),
+			None
+		));
+		(password, None)
+	};
+
+	if !try!(ask_yes_no("Set the user password now?", false)) {
+		return Ok(EXIT_FAILURE);
+	}
+	// Written before the drive gets the password, so a generated password
+	// can't be lost, and removed again if the drive doesn't take it
+	if let Some(ref path) = password_file {
+		let file = PasswordFile::new(
+			serial.clone(),
+			password_file::Slot::User,
+			Some(level),
+			&password,
+		);
+		try!(file.save(path));
+	}
+	eprintln!("Performing SECURITY SET PASSWORD...");
+	if let Err(e) = security_set_password_user(f, password, maximum_security) {
+		eprintln!("There was an error executing the command: {:?}", e);
+		if let Some(ref path) = password_file {
+			if let Err(e) = std::fs::remove_file(path) {
+				eprintln!("Unable to remove the password file {}: {}", path, e);
+			}
+		}
+		return Ok(EXIT_FAILURE);
+	}
+	if let Some(ref path) = password_file {
+		eprintln!(
+			"Password file written to {}. Keep a copy in a safe place.",
+			path
+		);
+	}
+
+	let freeze = try!(ask_yes_no(
+		"Freeze the security state until the next power cycle, so nobody else can change \
+		 the password?",
+		true
+	));
+	if freeze {
+		eprintln!("Performing SECURITY FREEZE...");
+		if let Err(e) = security_freeze(f) {
+			eprintln!("There was an error executing the command: {:?}", e);
+			return Ok(EXIT_FAILURE);
+		}
+	}
+
+	eprintln!();
+	eprintln!(
+		"To unlock the drive at boot, add this to {}:",
+		config::DEFAULT_PATH
+	);
+	eprintln!();
+	eprintln!("[device.{}]", serial);
+	if let Some(path) = password_file {
+		eprintln!("password_file = \"{}\"", path);
+	}
+	eprintln!(
+		"security = \"{}\"",
+		if maximum_security { "max" } else { "high" }
+	);
+	eprintln!("freeze = {}", freeze);
+	Ok(0)
+}
+
+/// Generate a random password for `gen-password`, hex encoded if `hex`.
+fn gen_password(out: &output::Output, hex: bool, path: Option<String>) -> io::Result<()> {
+	use std::fs::OpenOptions;
+	use std::os::unix::fs::OpenOptionsExt;
+
+	let mut password = [0u8; 32];
+	try!(rand::getrandom(&mut password));
+	let encoded = if hex {
+		let mut s: String = password.iter().map(|b| format!("{:02x}", b)).collect();
+		s.push('\n');
+		s.into_bytes()
+	} else {
+		password.to_vec()
+	};
+	match path {
+		Some(path) => {
+			let mut f = try!(OpenOptions::new()
+				.write(true)
+				.create_new(true)
+				.mode(0o600)
+				.open(&path));
+			try!(f.write_all(&encoded));
+			eprintln!("Password written to {}", path);
+			Ok(())
+		}
+		None => {
+			if !hex && nix::unistd::isatty(1).unwrap_or(false) {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"refusing to write a binary password to a terminal, use --hex",
+				));
+			}
+			out.write_bytes(&encoded)
+		}
+	}
+}
+
+/// Add TCG Level 0 Discovery information to `report` for drives supporting
+/// the TCG security protocol.
+fn add_tcg_discovery(f: &File, info: &DriveInfo, report: &mut report::DriveReport) {
+	match info.0 {
+		Ok((_, Ok(Some((ref p, _)))))
+			if p.contains(&nvme::security::Protocol::Tcg(tcg::PROTOCOL)) => {}
+		_ => return,
+	}
+	match tcg::discovery(f.as_raw_fd()) {
+		Err(e) => eprintln!("Unable to perform TCG Level 0 Discovery: {:?}", e),
+		Ok(d) => report.tcg = Some(report::Tcg::from(&d)),
+	}
+}
+
+fn add_ieee1667_silos(f: &File, info: &DriveInfo, report: &mut report::DriveReport) {
+	match info.0 {
+		Ok((_, Ok(Some((ref p, _))))) if p.contains(&nvme::security::Protocol::Ieee1667) => {}
+		_ => return,
+	}
+	match ieee1667::probe(f.as_raw_fd()) {
+		Err(e) => eprintln!("Unable to enumerate IEEE 1667 silos: {:?}", e),
+		Ok(p) => report.ieee1667 = Some(report::Ieee1667::from(&p)),
+	}
+}
+
+/// The Opal family SSC implemented by the drive and its base ComID.
+fn opal_family(fd: std::os::unix::io::RawFd) -> std::result::Result<(tcg::Ssc, u16), i32> {
+	match tcg::discovery(fd).map(|d| d.opal_family()) {
+		Err(e) => {
+			eprintln!("Unable to perform TCG Level 0 Discovery: {:?}", e);
+			Err(EXIT_FAILURE)
+		}
+		Ok(None) => {
+			eprintln!("This drive does not implement Opal, Opalite, Pyrite or Ruby");
+			Err(EXIT_UNSUPPORTED)
+		}
+		Ok(Some(v)) => Ok(v),
+	}
+}
+
+/// `opal take-ownership` and `opal revert`, for drives implementing one of
+/// the Opal family SSCs.
+fn opal(f: &File, info: &DriveInfo, take_ownership: bool, password: PasswordSource) -> i32 {
+	let fd = f.as_raw_fd();
+	let identity = match info.0 {
+		Err(ref e) => {
+			eprintln!(
+				"There was an error obtaining NVMe identity information:\n{:?}",
+				e
+			);
+			return EXIT_FAILURE;
+		}
+		Ok((ref identity, _)) => identity,
+	};
+	let (ssc, comid) = match opal_family(fd) {
+		Ok(v) => v,
+		Err(code) => return code,
+	};
+	let result = if take_ownership {
+		let password = match read_password(password, identity, "SID password", true, None, None) {
+			Ok(password) => password,
+			Err(code) => return code,
+		};
+		eprintln!("Taking ownership of the {} Admin SP...", ssc.name());
+		tcg::take_ownership(fd, comid, &password)
+	} else {
+		let password = match read_password(password, identity, "SID password", false, None, None) {
+			Ok(password) => password,
+			Err(code) => return code,
+		};
+		eprintln!("Reverting the {} Admin SP...", ssc.name());
+		tcg::revert(fd, comid, &password)
+	};
+	finish(result)
+}
+
+/// Read the PSID printed on the drive label. Spaces and dashes are ignored.
+fn read_psid() -> io::Result<String> {
+	if nix::unistd::isatty(0).unwrap_or(false) {
+		eprint!("Please enter the PSID from the drive label:");
+	}
+	let mut line = String::new();
+	try!(io::stdin().read_line(&mut line));
+	let psid: String = line
+		.chars()
+		.filter(|c| !c.is_whitespace() && *c != '-')
+		.collect();
+	if psid.len() != 32 || !psid.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"a PSID consists of 32 letters and digits",
+		));
+	}
+	Ok(psid)
+}
+
+/// `revert-psid`: revert an Opal family drive to its factory state with the
+/// PSID, for when the SID password is lost.
+fn revert_psid(f: &File) -> i32 {
+	let fd = f.as_raw_fd();
+	let (ssc, comid) = match opal_family(fd) {
+		Ok(v) => v,
+		Err(code) => return code,
+	};
+	let psid = match read_psid() {
+		Err(e) => {
+			eprintln!("Error trying to read PSID: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(psid) => psid,
+	};
+	eprintln!(
+		"Reverting the {} drive with the PSID, all data will be lost...",
+		ssc.name()
+	);
+	finish(tcg::revert_psid(fd, comid, psid.as_bytes()))
+}
+
+/// Query all drives for `list` and `snapshot`.
+/// All drives in the system. Through `broker`, there is no TCG discovery.
+/// Local drives are queried several at a time.
+fn inventory(broker: Option<&broker::Client>) -> io::Result<report::Inventory> {
+	let devices = try!(device::namespaces());
+	let drive = match broker {
+		Some(broker) => devices
+			.into_iter()
+			.map(|dev| inventory_drive(&dev, Some(broker)))
+			.collect(),
+		None => pool::map(devices, pool::QUERY_JOBS, |dev| inventory_drive(dev, None)),
+	};
+	Ok(report::Inventory {
+		drive: drive.into_iter().filter_map(|d| d).collect(),
+	})
+}
+
+fn inventory_drive(dev: &Path, broker: Option<&broker::Client>) -> Option<report::DriveReport> {
+	let drive = match broker::Drive::open(dev, false, broker) {
+		Err(e) => {
+			eprintln!("Unable to open {} for reading: {}", dev.display(), e);
+			return None;
+		}
+		Ok(drive) => drive,
+	};
+	let info = drive.query();
+	let mut report = report::DriveReport::new(&dev.display().to_string(), &info);
+	if let broker::Drive::Local(_, ref f) = drive {
+		add_tcg_discovery(f, &info, &mut report);
+	}
+	Some(report)
+}
+
+fn list(out: &output::Output) -> i32 {
+	let inventory = match inventory(None) {
+		Err(e) => {
+			eprintln!("Unable to enumerate NVMe devices: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(inventory) => inventory,
+	};
+	if out.is_human() {
+		write!(out, "{}", table::list(&inventory.drive, out.color));
+	} else {
+		out.structured(&inventory);
+	}
+	0
+}
+
+/// `snapshot save` and `snapshot diff`. A diff exits with 1 if anything
+/// changed, like diff(1).
+fn snapshot(out: &output::Output, save: bool, path: &str) -> i32 {
+	let inventory = match inventory(None) {
+		Err(e) => {
+			eprintln!("Unable to enumerate NVMe devices: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(inventory) => inventory,
+	};
+	if save {
+		if let Err(e) = snapshot::save(&inventory, path) {
+			eprintln!("Unable to write snapshot {}: {}", path, e);
+			return EXIT_FAILURE;
+		}
+		return 0;
+	}
+	let changes = match snapshot::load(path).and_then(|old| snapshot::diff(&old, &inventory)) {
+		Err(e) => {
+			eprintln!("Unable to read snapshot {}: {}", path, e);
+			return EXIT_FAILURE;
+		}
+		Ok(changes) => changes,
+	};
+	for change in &changes {
+		writeln!(out, "{}", change);
+	}
+	if changes.is_empty() {
+		0
+	} else {
+		EXIT_FAILURE
+	}
+}
+
+trait RetryIterator: Iterator {
+	/// The first success, or the last error. `None` if there was nothing to
+	/// try.
+	fn retry_results<T, E>(&mut self) -> Option<std::result::Result<T, E>>
+	where
+		Self: Iterator<Item = std::result::Result<T, E>>,
+	{
+		let mut last = None;
+		loop {
+			let cur = self.next();
+			match cur {
+				Some(v @ Ok(_)) => {
+					return Some(v);
+				}
+				Some(e @ Err(_)) => {
+					last = Some(e);
+				}
+				None => return last,
+			}
+		}
+	}
+
+	fn retry_options<T>(&mut self) -> Option<T>
+	where
+		Self: Iterator<Item = Option<T>>,
+	{
+		let mut last = None;
+		loop {
+			let cur = self.next();
+			match cur {
+				Some(v @ Some(_)) => {
+					return v;
+				}
+				Some(e @ None) => {
+					last = Some(e);
+				}
+				None => return last.unwrap_or(None),
+			}
+		}
+	}
+}
+
+impl<T: Iterator> RetryIterator for T {}
+
+#[derive(Deserialize, Debug)]
+struct Args {
+	cmd_query: bool,
+	cmd_list: bool,
+	cmd_tui: bool,
+	cmd_snapshot: bool,
+	cmd_save: bool,
+	cmd_diff: bool,
+	cmd_exporter: bool,
+	cmd_check: bool,
+	cmd_status: bool,
+	cmd_master_id: bool,
+	cmd_setup: bool,
+	cmd_set_password: bool,
+	cmd_change_password: bool,
+	cmd_gen_password_file: bool,
+	cmd_gen_password: bool,
+	cmd_fido2_enroll: bool,
+	cmd_pkcs11_enroll: bool,
+	cmd_escrow: bool,
+	cmd_export: bool,
+	cmd_import: bool,
+	cmd_unlock: bool,
+	cmd_unlock_all: bool,
+	cmd_erase_all: bool,
+	cmd_verify: bool,
+	cmd_daemon: bool,
+	cmd_remote_unlock: bool,
+	cmd_security_recv: bool,
+	cmd_security_send: bool,
+	cmd_disable_password: bool,
+	cmd_erase: bool,
+	cmd_erase_advisor: bool,
+	cmd_decommission: bool,
+	cmd_freeze: bool,
+	cmd_lockdown: bool,
+	cmd_sanitize_status: bool,
+	cmd_self_test: bool,
+	cmd_abort_command: bool,
+	cmd_self_test_status: bool,
+	cmd_feature: bool,
+	cmd_get: bool,
+	cmd_set: bool,
+	arg_name: Option<String>,
+	arg_value: Option<String>,
+	cmd_unfreeze: bool,
+	cmd_opal: bool,
+	cmd_take_ownership: bool,
+	cmd_revert: bool,
+	cmd_revert_psid: bool,
+	arg_dev: String,
+	arg_file: String,
+	flag_password_file: Option<String>,
+	flag_new_password_file: Option<String>,
+	flag_password_env: Option<String>,
+	flag_password_fd: Option<i32>,
+	flag_password_chain: Option<String>,
+	flag_ask_password: bool,
+	flag_fido2: Option<String>,
+	flag_pkcs11: Option<String>,
+	flag_clevis: Option<String>,
+	flag_fleet: Option<String>,
+	flag_report: Option<String>,
+	flag_manifest: Option<String>,
+	flag_backup_luks_headers: Option<String>,
+	flag_capture_telemetry: Option<String>,
+	flag_sign_key: Option<String>,
+	flag_recipient: Option<String>,
+	flag_identity: Option<String>,
+	flag_key_id: Option<String>,
+	flag_module: Option<String>,
+	flag_normalize: Option<String>,
+	flag_tries: Option<u8>,
+	flag_no_retry: bool,
+	flag_prompt_timeout: Option<u32>,
+	flag_reset_timeout: Option<u32>,
+	flag_id: Option<String>,
+	flag_user: bool,
+	flag_master: bool,
+	flag_high: bool,
+	flag_max: bool,
+	flag_enhanced: bool,
+	flag_verify_prepare: bool,
+	flag_verify: bool,
+	flag_benchmark_report: Option<String>,
+	flag_auto: bool,
+	flag_wait: bool,
+	flag_short: bool,
+	flag_extended: bool,
+	flag_abort: bool,
+	flag_force: bool,
+	flag_yes_erase_all_drives: bool,
+	flag_allow_fabrics: bool,
+	flag_explain: bool,
+	flag_field: Option<String>,
+	flag_freeze: bool,
+	flag_reset: bool,
+	flag_settle: bool,
+	flag_config: Option<String>,
+	flag_audit_log: Option<String>,
+	flag_format: Option<String>,
+	flag_no_color: bool,
+	flag_verbose: bool,
+	flag_stack: bool,
+	flag_file: Option<String>,
+	flag_hex: bool,
+	flag_serial: Option<String>,
+	flag_fd: Option<i32>,
+	flag_path: Option<String>,
+	flag_all_paths: bool,
+	flag_protocol: Option<String>,
+	flag_spsp: Option<String>,
+	flag_cid: Option<String>,
+	flag_sqid: Option<String>,
+	flag_length: Option<String>,
+	flag_data_file: Option<String>,
+	flag_via_suspend: bool,
+	flag_via_d3cold: bool,
+	flag_via_slot: bool,
+	flag_wake_after: u32,
+	flag_jobs: usize,
+	flag_listen: String,
+	flag_control: bool,
+	flag_dbus: bool,
+	flag_no_sandbox: bool,
+	flag_generate_policy: Option<String>,
+	flag_socket: Option<String>,
+	flag_quirk: Option<String>,
+	flag_transport: String,
+}
+
+const USAGE: &'static str = "
+Usage:
+	nvme-ata-security query [--format=<fmt>] [--no-color] [--verbose|--stack] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security list [--format=<fmt>] [--no-color] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security tui [--config=<file>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security snapshot (save|diff) <file> [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security exporter [--listen=<addr>] [--no-sandbox] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security check [--config=<file>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security status [--explain|--field=<name>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security master-id [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security setup [--allow-fabrics] [--config=<file>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security set-password -u [--high|--max] [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>|--fido2=<file>|--pkcs11=<file>|--clevis=<file>] [--normalize=<form>] [--config=<file>] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security set-password -m --id=<id> [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>|--fleet=<file>] [--normalize=<form>] [--config=<file>] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security change-password -u [--high|--max] [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>] [--new-password-file=<file>] [--normalize=<form>] [--config=<file>] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security change-password -m --id=<id> [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>] [--new-password-file=<file>] [--normalize=<form>] [--config=<file>] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security gen-password [--hex] [--file=<out>]
+	nvme-ata-security fido2-enroll --file=<out>
+	nvme-ata-security pkcs11-enroll --key-id=<id> [--module=<so>] --file=<out>
+	nvme-ata-security escrow export --recipient=<key> --file=<out> [--config=<file>]
+	nvme-ata-security escrow import --identity=<file> --file=<out>
+	nvme-ata-security gen-password-file (-u|-m) [--high|--max] [--password-file=<file>|--password-fd=<n>|--password-env=<var>] --file=<out> [--normalize=<form>] [--config=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security unlock (-u|-m) [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>|--password-chain=<list>|--ask-password|--fido2=<file>|--pkcs11=<file>|--clevis=<file>|--fleet=<file>|--tries=<num>|--no-retry] [--prompt-timeout=<secs>] [--freeze] [--reset [--reset-timeout=<secs>]] [--settle] [--all-paths] [--normalize=<form>] [--config=<file>] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security verify (-u|-m) [--password-file=<file>|--password-fd=<n>|--password-env=<var>|--fido2=<file>|--pkcs11=<file>|--clevis=<file>] [--normalize=<form>] [--config=<file>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security unlock-all [--jobs=<n>] [--reset-timeout=<secs>] [--audit-log=<file>] [--format=<fmt>] [--config=<file>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security daemon [--control] [--dbus] [--reset-timeout=<secs>] [--no-sandbox] [--config=<file>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security daemon --generate-policy=<lsm> [--config=<file>]
+	nvme-ata-security remote-unlock [--socket=<path>]
+	nvme-ata-security opal take-ownership [--password-file=<file>|--password-fd=<n>|--password-env=<var>] [--audit-log=<file>] [--format=<fmt>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security opal revert [--password-file=<file>|--password-fd=<n>|--password-env=<var>] [--audit-log=<file>] [--format=<fmt>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security revert-psid [--audit-log=<file>] [--format=<fmt>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security disable-password (-u|-m) [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>|--fleet=<file>] [--normalize=<form>] [--config=<file>] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security erase (-u|-m) [--enhanced] [--verify-prepare] [--verify] [--benchmark-report=<file>] [--manifest=<file>] [--backup-luks-headers=<dir>] [--report=<file> [--sign-key=<key>]] [--force] [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>|--fleet=<file>] [--normalize=<form>] [--config=<file>] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security erase-all --yes-erase-all-drives [--enhanced] [--jobs=<n>] [--force] [--audit-log=<file>] [--format=<fmt>] [--config=<file>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>]
+	nvme-ata-security erase-advisor [--auto [--force] [--report=<file> [--sign-key=<key>]]] [--normalize=<form>] [--audit-log=<file>] [--format=<fmt>] [--password-file=<file>|--password-fd=<n>|--password-env=<var>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security decommission (-u|-m) --report=<file> [--sign-key=<key>] [--force] [--allow-fabrics] [--password-file=<file>|--password-fd=<n>|--password-env=<var>|--fleet=<file>] [--normalize=<form>] [--config=<file>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security freeze [--all-paths] [--capture-telemetry=<dir>] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security unfreeze (--via-suspend [--wake-after=<secs>]|--via-d3cold|--via-slot) [--force] [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security security-recv --protocol=<hex> [--spsp=<num>] [--length=<num>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security security-send --protocol=<hex> [--spsp=<num>] [--data-file=<file>] [--audit-log=<file>] [--format=<fmt>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security sanitize-status [--wait] [--format=<fmt>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security self-test (--short|--extended|--abort) [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security abort-command --cid=<cid> [--sqid=<sqid>] [--audit-log=<file>] [--format=<fmt>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security self-test-status [--format=<fmt>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security feature get <name> [--format=<fmt>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security feature set <name> <value> [--audit-log=<file>] [--format=<fmt>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security lockdown [--audit-log=<file>] [--format=<fmt>] [--quirk=<list>] [--protocol=<hex>] [--transport=<t>] [--path=<ctrl>] (<dev>|--serial=<sn>|--fd=<n>)
+	nvme-ata-security --help
+	
+Options:
+	-u, --user                         Specify the user password
+	-m, --master                       Specify the master password
+	-i <file>, --password-file=<file>  Read the password from <file>, - for stdin, instead of
+	                                   prompting
+	--password-fd=<n>                  Read the password from file descriptor <n>
+	--password-env=<var>               Read the password from environment variable <var>
+	--password-chain=<list>            Try the comma-separated password sources in <list> in
+	                                   order: file:<file>, fd:<n>, env:<var>, keyring:<key>,
+	                                   fido2:<file>, pkcs11:<file>, clevis:<file>, agent
+	                                   or prompt
+	--ask-password                     Ask the systemd password agents, e.g. Plymouth, for the
+	                                   password
+	--fido2=<file>                     Use the FIDO2 key enrolled in <file> with fido2-enroll
+	--pkcs11=<file>                    Unwrap the password in <file>, written by pkcs11-enroll,
+	                                   on its PKCS#11 token
+	--key-id=<id>                      Wrap the password with the RSA key with ID <id> (hex)
+	--module=<so>                      Use the PKCS#11 module <so> instead of the OpenSC one
+	--clevis=<file>                    Decrypt the password from the Clevis JWE <file>
+	--fleet=<file>                     Derive the master password from the organization secret
+	                                   in <file>, the serial number and the identifier
+	--recipient=<key>                  Encrypt the escrow bundle to the age public key <key>
+	--identity=<file>                  Decrypt the escrow bundle with the age identity <file>
+	--normalize=<form>                 Normalize a typed password before use: nfc, nfkd or ascii
+	--new-password-file=<file>         Read the new password from <file> when changing passwords
+	--file=<out>                       Write the generated password (file) to <out>
+	--hex                              Hex encode the generated password
+	-t <num>, --tries=<num>            When reading from stdin, try unlocking <num> times
+	--prompt-timeout=<secs>            Give up if no password is typed on the terminal or given
+	                                   by an agent within <secs> seconds, or try the next source
+	                                   of the chain
+	--no-retry                         Do not prompt again for the password when unlocking
+	                                   fails
+	--high                             Configure high security
+	--max                              Configure maximum security
+	--id=<id>                          Set the master password identifier, auto increments the
+	                                   current one
+	--enhanced                         Perform an enhanced security erase
+	--yes-erase-all-drives             Confirm that erase-all destroys the data of every
+	                                   configured drive with a password file
+	--verify-prepare                   Write a pattern to sample blocks before erasing
+	--verify                           Check sample blocks after erasing
+	--benchmark-report=<file>          Append the duration of the erase to <file>
+	--auto                             Perform the erase recommended by erase-advisor
+	--wait                             Wait until no sanitize operation is in progress
+	--short                            Start a short device self-test
+	--extended                         Start an extended device self-test
+	--abort                            Abort the device self-test in progress
+	--cid=<cid>                        Abort the command with identifier <cid>
+	--sqid=<sqid>                      Submission queue of the command, 0 for admin [default: 0]
+	--capture-telemetry=<dir>          If the command fails, save the host-initiated
+	                                   telemetry of the drive for its vendor to <dir>
+	--manifest=<file>                  Record the partitions, file systems and LUKS headers on
+	                                   the namespace in <file> before erasing
+	--backup-luks-headers=<dir>        Save the headers of LUKS containers on the namespace to
+	                                   <dir> with cryptsetup before erasing
+	--report=<file>                    Write a report of the erase to <file>
+	--sign-key=<key>                   Sign the report with the private key <key> using
+	                                   openssl, to <file>.sig
+	--force                            Erase even if the drive is in use or a firmware
+	                                   activation is pending; unfreeze even if the drive is
+	                                   in use or has a password
+	--allow-fabrics                    Change the security state of an NVMe over Fabrics controller
+	--explain                          Explain which operations are currently possible
+	--field=<name>                     Print only yes or no for locked, frozen, enabled or
+	                                   supported, exiting with 1 for no and 3 on errors
+	--freeze                           Perform SECURITY FREEZE after unlocking
+	--reset                            Reset the controller after unlocking
+	--reset-timeout=<secs>             Wait up to <secs> seconds for the controller to be ready
+	                                   after a reset, 30 by default
+	--settle                           Wait for udev to create the partition devices after
+	                                   unlocking
+	--via-suspend                      Power cycle the drive by suspending the system to RAM
+	--wake-after=<secs>                Resume from suspend after <secs> seconds [default: 10]
+	--via-d3cold                       Power cycle the drive by putting it into PCIe D3cold
+	--via-slot                         Power cycle the drive by switching off its hot-plug slot
+	--quirk=<list>                     Apply the comma-separated workarounds in <list>: pad-512,
+	                                   spsp-high
+	--path=<ctrl>                      Send commands for a multipath namespace through
+	                                   controller <ctrl>, e.g. nvme1
+	--all-paths                        Unlock or freeze through every path of a multipath
+	                                   namespace and check that they agree
+	--transport=<t>                    Send NVMe commands with ioctl or uring, the io_uring
+	                                   passthrough of Linux 6.0 [default: ioctl]
+	--listen=<addr>                    Serve metrics on <addr> [default: 127.0.0.1:9716]
+	--control                          Accept passwords from remote-unlock on
+	                                   /run/nvme-ata-security.sock
+	--dbus                             Provide the org.nvme.AtaSecurity service on the system
+	                                   bus, authorized by polkit
+	--no-sandbox                       Do not restrict the system calls of the daemon or
+	                                   exporter with seccomp
+	--generate-policy=<lsm>            Print a minimal apparmor profile or selinux policy
+	                                   module for the daemon
+	--socket=<path>                    Connect to the daemon on <path> instead of
+	                                   /run/nvme-ata-security.sock
+	-j <n>, --jobs=<n>                 Handle up to <n> drives at once, without prompting for
+	                                   passwords [default: 1]
+	--format=<fmt>                     Output format: human, json, yaml or toml [default: human]
+	--no-color                         Do not color human output
+	-v, --verbose                      Also show controller capabilities and state, certificate
+	                                   and security compliance information
+	--stack                            Show the devices, file systems and swap on top of the
+	                                   namespace instead
+	--serial=<sn>                      Select the NVMe device by controller serial number
+	--fd=<n>                           Use the NVMe device already open as file descriptor <n>
+	--protocol=<hex>                   Security protocol for raw commands, or for ATA security
+	                                   commands instead of ef
+	--spsp=<num>                       SP specific value for raw commands [default: 0]
+	--length=<num>                     Allocation length for security-recv [default: 512]
+	--data-file=<file>                 Read the data for security-send from <file>
+	-c <file>, --config=<file>         Read per-device defaults from <file> instead of
+	                                   /etc/nvme-ata-security.conf
+	--audit-log=<file>                 Append a record of security-altering commands to <file>
+";
+
+fn main() {
+	// Before the getrandom emulation, which helper programs can't inherit
+	launcher::start();
+	rand::init();
+
+	let args: Args = docopt::Docopt::new(USAGE)
+		.and_then(|d| d.argv(std::env::args()).deserialize())
+		.unwrap_or_else(|e| e.exit());
+	let structured = args.flag_format.as_ref().and_then(|f| match f.parse() {
+		Ok(report::Format::Structured(format)) => Some(format),
+		_ => None,
+	});
+	let mut record = command_name(&args).map(report::CommandResult::new);
+	if let Some(ref mut record) = record {
+		if args.cmd_set_password
+			|| args.cmd_change_password
+			|| args.cmd_unlock
+			|| args.cmd_disable_password
+			|| args.cmd_erase
+			|| args.cmd_decommission
+		{
+			record.slot = Some(if args.flag_master { "master" } else { "user" });
+		}
+	}
+	// erase-advisor writes its own output and is only recorded for the audit log
+	let own_output = args.cmd_erase_advisor;
+
+	let field = args.flag_field.is_some();
+
+	let mut status = run(args, record.as_mut());
+	if field {
+		// Any error, e.g. opening the device, must not read as no
+		status = match status {
+			0 => 0,
+			FIELD_NO => EXIT_FAILURE,
+			_ => EXIT_FIELD_ERROR,
+		};
+	}
+
+	if let Some(mut record) = record {
+		record.result = match status {
+			0 => "success",
+			EXIT_UNSUPPORTED => "unsupported",
+			_ => "error",
+		};
+		if let Some(mut log) = record.audit_log.take() {
+			if let Err(e) = log.append(&record) {
+				eprintln!("Unable to write audit log: {}", e);
+				status = EXIT_FAILURE;
+			}
+		}
+		if let (Some(format), false) = (structured, own_output) {
+			output::Output::new(report::Format::Structured(format), true).structured(&record);
+		}
+	}
+	std::process::exit(status);
+}
+
+/// The name of the command for the structured result record and audit log.
+/// Commands that neither write their own output nor alter the drive have none.
+fn command_name(args: &Args) -> Option<&'static str> {
+	let commands = [
+		(args.cmd_setup, "setup"),
+		(args.cmd_set_password, "set-password"),
+		(args.cmd_change_password, "change-password"),
+		(args.cmd_gen_password_file, "gen-password-file"),
+		(args.cmd_unlock, "unlock"),
+		(args.cmd_unlock_all, "unlock-all"),
+		(args.cmd_verify, "verify"),
+		(args.cmd_take_ownership, "opal take-ownership"),
+		(args.cmd_revert, "opal revert"),
+		(args.cmd_revert_psid, "revert-psid"),
+		(args.cmd_disable_password, "disable-password"),
+		(args.cmd_erase, "erase"),
+		(args.cmd_erase_all, "erase-all"),
+		(args.cmd_erase_advisor && args.flag_auto, "erase-advisor"),
+		(args.cmd_decommission, "decommission"),
+		(args.cmd_freeze, "freeze"),
+		(args.cmd_unfreeze, "unfreeze"),
+		(args.cmd_security_send, "security-send"),
+		(args.cmd_lockdown, "lockdown"),
+		(args.cmd_feature && args.cmd_set, "feature set"),
+		(args.cmd_abort_command, "abort-command"),
+	];
+	commands.iter().find(|c| c.0).map(|c| c.1)
+}
+
+fn run(mut args: Args, mut record: Option<&mut report::CommandResult>) -> i32 {
+	let config = match config::Config::load(args.flag_config.as_ref().map(String::as_str)) {
+		Err(e) => {
+			eprintln!("{}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(config) => config,
+	};
+
+	if let Some(ref mut record) = record {
+		// gen-password-file only reads the drive
+		if record.command != "gen-password-file" {
+			if let Some(path) = args.flag_audit_log.as_ref().or(config.audit_log.as_ref()) {
+				match audit::AuditLog::open(path) {
+					Err(e) => {
+						eprintln!("Unable to open audit log {}: {}", path, e);
+						return EXIT_FAILURE;
+					}
+					Ok(log) => record.audit_log = Some(log),
+				}
+			}
+		}
+	}
+
+	let format = match args
+		.flag_format
+		.as_ref()
+		.map_or(Ok(report::Format::Human), |f| f.parse())
+	{
+		Err(e) => {
+			eprintln!("{}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(format) => format,
+	};
+
+	let out = output::Output::new(format, args.flag_no_color);
+
+	let normalize = match args.flag_normalize.as_ref().map(|n| n.parse()) {
+		Some(Err(e)) => {
+			eprintln!("{}", e);
+			return EXIT_FAILURE;
+		}
+		Some(Ok(normalize)) => Some(normalize),
+		None => None,
+	};
+
+	if let Some(fd) = args.flag_password_fd {
+		args.flag_password_file = Some(format!("/dev/fd/{}", fd));
+	}
+
+	if args.flag_fleet.is_some() && !args.flag_master {
+		eprintln!("--fleet only derives master passwords, use it with -m");
+		return EXIT_FAILURE;
+	}
+
+	if let Some(secs) = args.flag_prompt_timeout {
+		PROMPT_TIMEOUT.store(secs as usize, Ordering::SeqCst);
+	}
+	if let Some(secs) = args.flag_reset_timeout {
+		RESET_TIMEOUT.store(secs as usize, Ordering::SeqCst);
+	}
+
+	match &args.flag_transport[..] {
+		"ioctl" => {}
+		"uring" => ops::use_uring(),
+		t => {
+			eprintln!("Unknown transport {}, use ioctl or uring", t);
+			return EXIT_FAILURE;
+		}
+	}
+
+	let raw = args.cmd_security_recv || args.cmd_security_send;
+	if let (false, Some(protocol)) = (raw, args.flag_protocol.as_ref()) {
+		match parse_protocol(protocol) {
+			Err(e) => {
+				eprintln!("{}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(p) => quirks::force_protocol(p.into()),
+		}
+	}
+
+	if let Some(ref list) = args.flag_quirk {
+		match quirks::parse(list) {
+			Err(e) => {
+				eprintln!("{}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(q) => quirks::force(q),
+		}
+	}
+
+	if let Some(ref name) = args.flag_generate_policy {
+		let lsm = match lsm::Lsm::parse(name) {
+			Err(e) => {
+				eprintln!("{}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(lsm) => lsm,
+		};
+		let config_path = args
+			.flag_config
+			.as_ref()
+			.map_or(config::DEFAULT_PATH, String::as_str);
+		print!("{}", lsm::policy(lsm, &config, config_path));
+		return 0;
+	}
+
+	if args.cmd_fido2_enroll {
+		let path = args.flag_file.unwrap();
+		if let Err(e) = fido2::enroll(&path) {
+			eprintln!("Unable to enroll FIDO2 key: {}", e);
+			return EXIT_FAILURE;
+		}
+		eprintln!("Wrote {}, use it with --fido2={}", path, path);
+		return 0;
+	}
+
+	if args.cmd_pkcs11_enroll {
+		let path = args.flag_file.unwrap();
+		let module = args.flag_module.as_ref().map(String::as_str);
+		if let Err(e) = pkcs11::enroll(module, &args.flag_key_id.unwrap(), &path) {
+			eprintln!("Unable to wrap password: {}", e);
+			return EXIT_FAILURE;
+		}
+		eprintln!("Wrote {}, use it with --pkcs11={}", path, path);
+		return 0;
+	}
+
+	// With --fd, a privileged parent opened the device and its access checks
+	// apply, not the capabilities of this process
+	if !args.cmd_gen_password && args.flag_fd.is_none() && !check_privileges() {
+		return EXIT_FAILURE;
+	}
+
+	if args.cmd_list {
+		return list(&out);
+	}
+
+	if args.cmd_tui {
+		return tui::run(&config);
+	}
+
+	if args.cmd_snapshot {
+		return snapshot(&out, args.cmd_save, &args.arg_file);
+	}
+
+	if args.cmd_check {
+		return check::run(&config);
+	}
+
+	if args.cmd_exporter {
+		if let Err(e) = exporter::serve(&args.flag_listen, !args.flag_no_sandbox) {
+			eprintln!("Unable to serve metrics on {}: {}", args.flag_listen, e);
+		}
+		return EXIT_FAILURE;
+	}
+
+	if args.cmd_gen_password {
+		if let Err(e) = gen_password(&out, args.flag_hex, args.flag_file) {
+			eprintln!("Unable to generate password: {}", e);
+			return EXIT_FAILURE;
+		}
+		return 0;
+	}
+
+	if args.cmd_escrow {
+		let path = args.flag_file.unwrap();
+		if args.cmd_export {
+			return match escrow::export(&config, &args.flag_recipient.unwrap(), &path) {
+				Err(e) => {
+					eprintln!("Unable to export password files: {}", e);
+					EXIT_FAILURE
+				}
+				Ok(n) => {
+					eprintln!("Wrote the password files of {} drives to {}", n, path);
+					0
+				}
+			};
+		}
+		return match escrow::import(&args.flag_identity.unwrap(), &path) {
+			Err(e) => {
+				eprintln!("Unable to import password files: {}", e);
+				EXIT_FAILURE
+			}
+			Ok(config) => {
+				eprintln!("Add the following to {}:", config::DEFAULT_PATH);
+				print!("{}", config);
+				0
+			}
+		};
+	}
+
+	if args.cmd_unlock_all {
+		return unlock_all(&config, true, &journal::Log::Stderr, args.flag_jobs, None);
+	}
+
+	if args.cmd_erase_all {
+		return erase_all(&config, args.flag_enhanced, args.flag_force, args.flag_jobs);
+	}
+
+	if args.cmd_daemon {
+		return daemon(
+			&config,
+			args.flag_control,
+			args.flag_dbus,
+			!args.flag_no_sandbox,
+		);
+	}
+
+	if args.cmd_remote_unlock {
+		return control::remote_unlock(Path::new(
+			args.flag_socket
+				.as_ref()
+				.map_or(control::SOCKET, String::as_str),
+		));
+	}
+
+	// Commands that change the drive open it for writing
+	let write =
+		command_name(&args).map_or(false, |c| c != "gen-password-file") || args.cmd_self_test;
+	let (dev, f) = match args.flag_fd {
+		// Opened by a wrapper that has access to /dev
+		Some(fd) => match std::fs::read_link(format!("/proc/self/fd/{}", fd)) {
+			Err(e) => {
+				eprintln!("Invalid file descriptor {}: {}", fd, e);
+				return EXIT_FAILURE;
+			}
+			Ok(path) => (path.display().to_string(), unsafe { File::from_raw_fd(fd) }),
+		},
+		None => {
+			let dev = match args.flag_serial {
+				Some(ref serial) => match device::find_by_serial(serial) {
+					Err(e) => {
+						eprintln!("Unable to enumerate NVMe devices: {}", e);
+						return EXIT_FAILURE;
+					}
+					Ok(None) => {
+						eprintln!("There is no NVMe device with serial number {}", serial);
+						return EXIT_FAILURE;
+					}
+					Ok(Some(dev)) => dev.display().to_string(),
+				},
+				None => args.arg_dev.clone(),
+			};
+			match open_device(&dev, write) {
+				Err(e) => {
+					eprintln!("Unable to open {} for {}: {}", dev, access(write), e);
+					if e.kind() == io::ErrorKind::PermissionDenied {
+						eprintln!(
+							"Run as root or a member of the group owning {}, or pass an open \
+							 file descriptor with --fd",
+							dev
+						);
+						lsm::explain_denial(Some(Path::new(&dev)));
+					}
+					if e.raw_os_error() == Some(libc::EROFS) {
+						eprintln!(
+							"{} is read-only, its security state can not be changed",
+							dev
+						);
+					}
+					return EXIT_FAILURE;
+				}
+				Ok(f) => (dev, f),
+			}
+		}
+	};
+	if write && args.flag_fd.is_some() {
+		let flags = unsafe { libc::fcntl(f.as_raw_fd(), libc::F_GETFL) };
+		if flags >= 0 && flags & libc::O_ACCMODE == libc::O_RDONLY {
+			eprintln!(
+				"Warning: file descriptor {} is open read-only, the kernel or security policy \
+				 may refuse commands that change the drive",
+				f.as_raw_fd()
+			);
+		}
+	}
+	let f = match args.flag_path {
+		None => f,
+		Some(_) if args.flag_fd.is_some() => {
+			eprintln!("--path needs a device, not a file descriptor");
+			return EXIT_FAILURE;
+		}
+		Some(ref ctrl) => match select_path(&dev, ctrl, write) {
+			Err(e) => {
+				eprintln!("{}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(path) => path,
+		},
+	};
+	if args.flag_all_paths
+		&& (args.flag_path.is_some()
+			|| device::multipath_paths(Path::new(&dev)).map_or(true, |p| p.is_empty()))
+	{
+		eprintln!("--all-paths needs a multipath namespace, without --path");
+		return EXIT_FAILURE;
+	}
+	match device::multipath_paths(Path::new(&dev)) {
+		Ok(ref paths) if paths.len() > 1 => eprintln!(
+			"{} is a multipath namespace, the kernel sends commands through any of {}; \
+			 choose one with --path",
+			dev,
+			paths
+				.iter()
+				.map(|p| p.controller.as_str())
+				.collect::<Vec<_>>()
+				.join(", ")
+		),
+		_ => {}
+	}
+	match f.metadata() {
+		Err(e) => {
+			eprintln!("Unable to stat {}: {}", dev, e);
+			return EXIT_FAILURE;
+		}
+		Ok(ref m)
+			if !m.file_type().is_block_device()
+				&& !(m.file_type().is_char_device()
+					&& device::kernel_name(Path::new(&dev)).map_or(false, |n| {
+						device::is_controller_name(&n) || device::is_generic_name(&n)
+					})) =>
+		{
+			eprintln!("{} is not an NVMe block, controller or generic device", dev);
+			return EXIT_FAILURE;
+		}
+		Ok(_) => {}
+	};
+
+	match device::controller_state(Path::new(&dev)) {
+		Some(ref state) if state != "live" => eprintln!(
+			"Warning: the controller of {} is {}, commands may fail until it is live",
+			dev, state
+		),
+		_ => {}
+	}
 
 	let info = DriveInfo::query(&f);
-	let identity = if args.cmd_query {
-		eprint!("{}", info);
-		return;
+	if let Some(ref mut record) = record {
+		record.device = Some(dev.clone());
+		if let Ok((ref identity, _)) = info.0 {
+			record.serial = Some(config::serial(identity));
+		}
+	}
+
+	if args.cmd_security_recv || args.cmd_security_send {
+		if let Err(e) = raw_security(
+			&out,
+			&f,
+			args.cmd_security_send,
+			args.flag_protocol.as_ref().unwrap(),
+			args.flag_spsp.as_ref().map(String::as_str),
+			args.flag_length.as_ref().map(String::as_str),
+			args.flag_data_file.as_ref().map(String::as_str),
+		) {
+			eprintln!("There was an error executing the command: {}", e);
+			return EXIT_FAILURE;
+		}
+		return 0;
+	}
+
+	if args.cmd_opal {
+		let password = match args.flag_password_env {
+			Some(var) => PasswordSource::Env(var),
+			None => PasswordSource::from(args.flag_password_file),
+		};
+		return opal(&f, &info, args.cmd_take_ownership, password);
+	}
+
+	if args.cmd_revert_psid {
+		return revert_psid(&f);
+	}
+
+	if args.cmd_lockdown {
+		return lockdown(&f, &info);
+	}
+
+	if args.cmd_sanitize_status {
+		return sanitize_status(&out, &f, args.flag_wait);
+	}
+
+	if args.cmd_self_test {
+		let code = if args.flag_short {
+			nvme::SelfTest::Short
+		} else if args.flag_extended {
+			nvme::SelfTest::Extended
+		} else {
+			nvme::SelfTest::Abort
+		};
+		return self_test(&f, &info, code);
+	}
+
+	if args.cmd_abort_command {
+		return abort_command(
+			&f,
+			args.flag_cid.as_ref().unwrap(),
+			args.flag_sqid.as_ref().map(String::as_str),
+		);
+	}
+
+	if args.cmd_self_test_status {
+		return self_test_status(&out, &f);
+	}
+
+	if args.cmd_feature {
+		return feature(
+			&out,
+			&f,
+			args.arg_name.as_ref().unwrap(),
+			args.arg_value.as_ref().map(String::as_str),
+		);
+	}
+
+	if args.cmd_erase_advisor {
+		let password = match args.flag_password_env {
+			Some(var) => PasswordSource::Env(var),
+			None => PasswordSource::from(args.flag_password_file),
+		};
+		return erase_advisor(
+			&out,
+			&f,
+			&dev,
+			&info,
+			args.flag_auto,
+			args.flag_force,
+			password,
+			normalize.or_else(|| {
+				info.0
+					.as_ref()
+					.ok()
+					.and_then(|i| config.device(&i.0))
+					.and_then(|c| c.normalize)
+			}),
+			args.flag_report.as_ref().map(String::as_str),
+			args.flag_sign_key.as_ref().map(String::as_str),
+		);
+	}
+
+	if args.cmd_decommission {
+		let security = match info.0 {
+			Ok((_, Ok(Some((_, Ok(Some(ref s))))))) => Some(s),
+			_ => None,
+		};
+		let device_config = info
+			.0
+			.as_ref()
+			.ok()
+			.and_then(|i| config.device(&i.0))
+			.cloned()
+			.unwrap_or_default();
+		let password = match (args.flag_password_env, args.flag_fleet) {
+			(Some(var), _) => PasswordSource::Env(var),
+			(None, Some(secret)) => PasswordSource::Fleet {
+				secret,
+				id: security.map_or(0, |s| s.master_password_identifier()),
+			},
+			(None, None) => PasswordSource::from(
+				args.flag_password_file
+					.or_else(|| device_config.password_file.clone()),
+			),
+		};
+		return decommission::decommission(
+			&f,
+			&dev,
+			&info,
+			password,
+			&decommission::Options {
+				master: args.flag_master,
+				normalize: normalize.or(device_config.normalize),
+				force: args.flag_force,
+				allow_fabrics: args.flag_allow_fabrics,
+				report: args.flag_report.as_ref().unwrap(),
+				sign_key: args.flag_sign_key.as_ref().map(String::as_str),
+			},
+		);
+	}
+
+	if let Some(ref field) = args.flag_field {
+		return status_field(&out, field, &info);
+	}
+
+	let (identity, security) = if args.cmd_query && args.flag_stack {
+		return query_stack(&out, &dev);
+	} else if args.cmd_query {
+		let mut report = report::DriveReport::new(&dev, &info);
+		add_tcg_discovery(&f, &info, &mut report);
+		if args.flag_verbose {
+			report.controller = device::controller_info(Path::new(&dev));
+			report.paths = device::multipath_paths(Path::new(&dev)).unwrap_or_default();
+		}
+		if args.flag_verbose && report.protocols.is_some() {
+			match security_certificate(&f) {
+				Ok(cert) => {
+					report.certificate_length = Some(cert.len());
+					if let Some((subject, issuer)) = x509::subject_issuer(&cert) {
+						report.certificate_subject = Some(subject);
+						report.certificate_issuer = Some(issuer);
+					}
+				}
+				Err(e) => eprintln!("Unable to read certificate data: {:?}", e),
+			}
+			match security_compliance(&f) {
+				Ok(c) => report.compliance = c.iter().map(report::Compliance::from).collect(),
+				Err(e) => eprintln!("Unable to read security compliance information: {:?}", e),
+			}
+			add_ieee1667_silos(&f, &info, &mut report);
+		}
+		if out.is_human() {
+			write!(
+				out,
+				"{}",
+				table::detail(&report, out.color, args.flag_verbose)
+			);
+		} else {
+			out.structured(&report);
+		}
+		return 0;
 	} else {
 		match info.check_support() {
-			Some((identity, _)) => identity,
-			None => {
-				return;
+			Ok(v) => v,
+			Err(code) => {
+				return code;
+			}
+		}
+	};
+
+	if args.cmd_status {
+		print_status(&out, &security, args.flag_explain);
+		return 0;
+	}
+
+	if args.cmd_master_id {
+		if !security.master_password_identifier_supported() {
+			eprintln!("{} does not report a master password identifier", dev);
+			return EXIT_UNSUPPORTED;
+		}
+		#[derive(Serialize)]
+		struct MasterId {
+			master_password_identifier: u16,
+		}
+		let id = security.master_password_identifier();
+		if out.is_human() {
+			writeln!(out, "{:04x}", id);
+		} else {
+			out.structured(&MasterId {
+				master_password_identifier: id,
+			});
+		}
+		return 0;
+	}
+
+	let master_id = match args.flag_id {
+		Some(ref id) => match master_password_identifier(id, &security) {
+			Ok(id) => id,
+			Err(e) => {
+				eprintln!("{}", e);
+				return EXIT_FAILURE;
+			}
+		},
+		None => 0,
+	};
+
+	if args.cmd_unfreeze {
+		if !security.frozen() {
+			eprintln!("{} is not frozen", dev);
+			return 0;
+		}
+		// The device goes away during the power cycle
+		drop(f);
+		let method = if args.flag_via_d3cold {
+			PowerCycle::D3cold
+		} else if args.flag_via_slot {
+			PowerCycle::Slot
+		} else {
+			PowerCycle::Suspend(args.flag_wake_after)
+		};
+		return unfreeze(
+			&dev,
+			&config::serial(&identity),
+			&security,
+			method,
+			args.flag_force,
+		);
+	}
+
+	// SECURITY UNLOCK and SECURITY ERASE UNIT are aborted once the attempt
+	// counter expires, further attempts would only fail
+	let checks_password =
+		args.cmd_unlock || args.cmd_verify || args.cmd_change_password || args.cmd_erase;
+	if checks_password && security.pwncntex() {
+		eprintln!(
+			"The password attempt counter of {} is exceeded. The drive rejects all passwords \
+			 until it is power cycled (or suspended and resumed).",
+			dev
+		);
+		return EXIT_FAILURE;
+	}
+
+	let alters_state = args.cmd_setup
+		|| args.cmd_set_password
+		|| args.cmd_change_password
+		|| args.cmd_unlock
+		|| args.cmd_disable_password
+		|| args.cmd_erase;
+	if alters_state && identity.is_fabrics() && !args.flag_allow_fabrics {
+		eprintln!(
+			"{} is an NVMe over Fabrics controller. ATA security over fabrics is unusual, \
+			 the target may share the drive with other hosts. Use --allow-fabrics to \
+			 continue anyway.",
+			dev
+		);
+		return EXIT_FAILURE;
+	}
+
+	let device_config = config.device(&identity).cloned().unwrap_or_default();
+	let normalize = normalize.or(device_config.normalize);
+	let password = match args.flag_password_env.clone() {
+		Some(var) => PasswordSource::Env(var),
+		None if args.flag_ask_password => PasswordSource::Agent,
+		None if args.flag_fido2.is_some() => {
+			PasswordSource::Fido2(args.flag_fido2.clone().unwrap())
+		}
+		None if args.flag_pkcs11.is_some() => {
+			PasswordSource::Pkcs11(args.flag_pkcs11.clone().unwrap())
+		}
+		None if args.flag_clevis.is_some() => {
+			PasswordSource::Clevis(args.flag_clevis.clone().unwrap())
+		}
+		None if args.flag_fleet.is_some() => PasswordSource::Fleet {
+			secret: args.flag_fleet.clone().unwrap(),
+			// Provisioning sets the identifier, everything else reads it
+			id: if args.flag_id.is_some() {
+				master_id
+			} else {
+				security.master_password_identifier()
+			},
+		},
+		None if args.flag_password_file.is_none() && device_config.clevis.is_some() => {
+			PasswordSource::Clevis(device_config.clevis.clone().unwrap())
+		}
+		None => PasswordSource::from(
+			args.flag_password_file
+				.clone()
+				.or_else(|| device_config.password_file.clone()),
+		),
+	};
+	let password_chain = match args.flag_password_chain {
+		Some(ref chain) => match parse_password_chain(chain) {
+			Err(e) => {
+				eprintln!("{}", e);
+				return EXIT_FAILURE;
 			}
+			Ok(chain) => Some(chain),
+		},
+		None => None,
+	};
+	let needs_password = args.cmd_set_password
+		|| args.cmd_change_password
+		|| args.cmd_gen_password_file
+		|| args.cmd_unlock
+		|| args.cmd_verify
+		|| args.cmd_disable_password
+		|| args.cmd_erase;
+	let password_metadata = match password {
+		PasswordSource::File(ref path) if needs_password => match PasswordFile::load(path) {
+			Err(e) => {
+				eprintln!("Error trying to read password: {}", e);
+				return EXIT_FAILURE;
+			}
+			Ok(metadata) => metadata,
+		},
+		_ => None,
+	};
+	if let Some(ref metadata) = password_metadata {
+		if let Err(e) = metadata.check_serial(&config::serial(&identity)) {
+			eprintln!("Error trying to read password: {}", e);
+			return EXIT_FAILURE;
+		}
+		if (metadata.slot == password_file::Slot::Master) != args.flag_master {
+			eprintln!(
+				"The password file does not contain a {} password",
+				if args.flag_master { "master" } else { "user" }
+			);
+			return EXIT_FAILURE;
 		}
+	}
+	let maximum_security = if args.flag_max {
+		Some(true)
+	} else if args.flag_high {
+		Some(false)
+	} else {
+		password_metadata
+			.as_ref()
+			.and_then(|metadata| metadata.security)
+			.or(device_config.security)
+			.map(|level| level == config::SecurityLevel::Max)
 	};
+	if args.flag_user
+		&& (args.cmd_set_password || args.cmd_change_password)
+		&& maximum_security.is_none()
+	{
+		eprintln!("Specify --high or --max, or configure a security level for this drive");
+		return EXIT_FAILURE;
+	}
+
+	if args.cmd_verify {
+		if !security.s_enabld() {
+			eprintln!("No password is set, there is nothing to verify");
+			return EXIT_FAILURE;
+		}
+		// A frozen drive aborts SECURITY UNLOCK whatever the password
+		if security.frozen() {
+			eprintln!(
+				"The drive is frozen, the password can not be verified until it is power cycled"
+			);
+			return EXIT_UNSUPPORTED;
+		}
+		if security.locked() {
+			eprintln!(
+				"The drive is locked, checking the password would unlock it. Use unlock \
+				 instead."
+			);
+			return EXIT_UNSUPPORTED;
+		}
+		// The master password can't unlock at maximum security, SECURITY
+		// UNLOCK would fail whatever the password and use up an attempt
+		if args.flag_master && security.maxset() {
+			eprintln!("The master password can't be verified at maximum security");
+			return EXIT_UNSUPPORTED;
+		}
+		let password = match read_password(password, &identity, "password", false, None, normalize)
+		{
+			Ok(password) => password,
+			Err(code) => return code,
+		};
+		eprintln!("Verifying password...");
+		// On most firmware SECURITY UNLOCK of an unlocked drive only checks
+		// the password, but a wrong one counts towards the attempt limit.
+		return match security_verify_password(&f, password, args.flag_master) {
+			Err(e) => {
+				eprintln!("The password was not accepted: {:?}", e);
+				EXIT_FAILURE
+			}
+			Ok(false) => {
+				eprintln!("The drive is locked, the password could not be verified");
+				EXIT_FAILURE
+			}
+			Ok(true) => {
+				eprintln!("The password was accepted");
+				0
+			}
+		};
+	}
+
+	if args.cmd_setup {
+		if !nix::unistd::isatty(0).unwrap_or(false) {
+			eprintln!("setup is interactive and needs a terminal");
+			return EXIT_FAILURE;
+		}
+		return match setup(&out, &f, &identity, &security, &config.password_policy) {
+			Err(e) => {
+				eprintln!("{}", e);
+				EXIT_FAILURE
+			}
+			Ok(code) => code,
+		};
+	}
+
+	if args.cmd_gen_password_file {
+		let path = args.flag_file.unwrap();
+		let (slot, security) = if args.flag_master {
+			(password_file::Slot::Master, None)
+		} else {
+			(
+				password_file::Slot::User,
+				maximum_security.map(|max| {
+					if max {
+						config::SecurityLevel::Max
+					} else {
+						config::SecurityLevel::High
+					}
+				}),
+			)
+		};
+		let password = match read_password(
+			password,
+			&identity,
+			"password",
+			true,
+			Some(&config.password_policy),
+			normalize,
+		) {
+			Ok(password) => password,
+			Err(code) => return code,
+		};
+		let file = PasswordFile::new(config::serial(&identity), slot, security, &password);
+		match file.save(&path) {
+			Err(e) => {
+				eprintln!("Unable to write {}: {}", path, e);
+				return EXIT_FAILURE;
+			}
+			Ok(()) => eprintln!("Password file written to {}", path),
+		}
+		return 0;
+	}
 
 	let result = if args.cmd_set_password {
+		let password = match read_password(
+			password,
+			&identity,
+			"password",
+			true,
+			Some(&config.password_policy),
+			normalize,
+		) {
+			Ok(password) => password,
+			Err(code) => return code,
+		};
 		eprintln!("Performing SECURITY SET PASSWORD...");
 		if args.flag_user {
-			security_set_password_user(
-				&f,
-				read_password(args.flag_password_file, &identity, true),
-				args.flag_max,
-			)
+			security_set_password_user(&f, password, maximum_security.unwrap())
 		} else {
-			security_set_password_master(
-				&f,
-				read_password(args.flag_password_file, &identity, true),
-				args.flag_id,
-			)
+			security_set_password_master(&f, password, master_id)
+				.and_then(|()| confirm_master_password_identifier(&f, master_id))
 		}
-	} else if args.cmd_unlock {
-		if let Some(_) = args.flag_password_file {
-			eprintln!("Performing SECURITY UNLOCK...");
-			security_unlock(
-				&f,
-				read_password(args.flag_password_file, &identity, false),
-				args.flag_master,
-			)
+	} else if args.cmd_change_password {
+		let stdin = match password {
+			PasswordSource::Stdin | PasswordSource::Fd(0) => true,
+			_ => false,
+		};
+		if stdin
+			&& args.flag_new_password_file.is_none()
+			&& !nix::unistd::isatty(0).unwrap_or(false)
+		{
+			eprintln!("Reading both passwords from stdin is not supported");
+			return EXIT_FAILURE;
+		}
+		// A frozen drive aborts both SECURITY UNLOCK and SET PASSWORD
+		if security.frozen() {
+			eprintln!(
+				"The drive is frozen, the password can not be changed until it is power cycled"
+			);
+			return EXIT_UNSUPPORTED;
+		}
+		if args.flag_master && security.maxset() {
+			// The master password can't unlock at maximum security, trying
+			// would only use up an attempt
+			eprintln!(
+				"The master password can't be verified at maximum security, changing it without \
+				 checking the current one"
+			);
+		} else {
+			let current = match read_password(
+				password,
+				&identity,
+				"current password",
+				false,
+				None,
+				normalize,
+			) {
+				Ok(password) => password,
+				Err(code) => return code,
+			};
+			eprintln!("Verifying current password...");
+			match security_verify_password(&f, current, args.flag_master) {
+				Err(e) => {
+					eprintln!("The current password was not accepted: {:?}", e);
+					return EXIT_FAILURE;
+				}
+				Ok(false) => {
+					eprintln!("The drive is still locked, the current password was wrong");
+					return EXIT_FAILURE;
+				}
+				Ok(true) => {}
+			}
+		}
+		let new = match read_password(
+			args.flag_new_password_file.into(),
+			&identity,
+			"new password",
+			true,
+			Some(&config.password_policy),
+			normalize,
+		) {
+			Ok(password) => password,
+			Err(code) => return code,
+		};
+		eprintln!("Performing SECURITY SET PASSWORD...");
+		if args.flag_user {
+			security_set_password_user(&f, new, maximum_security.unwrap())
 		} else {
-			if args.flag_tries == Some(0) {
-				return;
-			}
-			std::iter::repeat(())
-				.take(args.flag_tries.unwrap_or(1) as usize)
-				.map(|_| {
-					eprintln!("Performing SECURITY UNLOCK...");
-					security_unlock(&f, read_password(None, &identity, false), args.flag_master)
+			security_set_password_master(&f, new, master_id)
+				.and_then(|()| confirm_master_password_identifier(&f, master_id))
+		}
+	} else if args.cmd_unlock {
+		let accepted = std::cell::Cell::new(None);
+		let unlock = |password: [u8; 32]| {
+			let unlocked = security_unlock(&f, password, args.flag_master);
+			if unlocked.is_ok() {
+				accepted.set(Some(password));
+			}
+			unlocked
+		};
+		let unlocked = if let Some(chain) = password_chain {
+			chain
+				.into_iter()
+				.take_while(|_| !attempts_exceeded(&f))
+				.map(|src| {
+					let from = src.to_string();
+					match read_password_err(src, &identity, "password", false, None, normalize) {
+						Err(e) => {
+							eprintln!("Unable to read password from {}: {}", from, e);
+							Err(e.into())
+						}
+						Ok(password) => {
+							eprintln!("Performing SECURITY UNLOCK with password from {}...", from);
+							unlock(password)
+						}
+					}
 				})
 				.retry_results()
+				// The attempt counter was exceeded before the first password
+				.unwrap_or_else(|| {
+					Err(ops::Error::Device(io::Error::new(
+						io::ErrorKind::PermissionDenied,
+						"the password attempt counter is exceeded",
+					)))
+				})
+		} else if let PasswordSource::Stdin = password {
+			let tries = match args.flag_tries {
+				Some(tries) => tries,
+				None if !args.flag_no_retry && nix::unistd::isatty(0).unwrap_or(false) => {
+					UNLOCK_TRIES
+				}
+				None => 1,
+			};
+			if tries == 0 {
+				return EXIT_FAILURE;
+			}
+			let mut tried = 0;
+			loop {
+				let password = match read_password(
+					PasswordSource::Stdin,
+					&identity,
+					"password",
+					false,
+					None,
+					normalize,
+				) {
+					Ok(password) => password,
+					Err(code) => return code,
+				};
+				eprintln!("Performing SECURITY UNLOCK...");
+				let unlocked = unlock(password);
+				tried += 1;
+				match unlocked {
+					Err(ref e)
+						if e.is_authentication_failure()
+							&& tried < tries && !attempts_exceeded(&f) =>
+					{
+						eprintln!("The password was not accepted");
+					}
+					unlocked => break unlocked,
+				}
+			}
+		} else {
+			let password =
+				match read_password(password, &identity, "password", false, None, normalize) {
+					Ok(password) => password,
+					Err(code) => return code,
+				};
+			eprintln!("Performing SECURITY UNLOCK...");
+			unlock(password)
+		};
+		if unlocked.is_ok() {
+			throttle::accepted(&config::serial(&identity));
+		}
+		if unlocked.is_err() && attempts_exceeded(&f) {
+			eprintln!(
+				"The password attempt counter is now exceeded, power cycle the drive before \
+				 trying again"
+			);
 		}
+		let unlocked = match accepted.get() {
+			Some(password) if args.flag_all_paths => on_all_paths(&dev, |f, security| {
+				if !security.locked() {
+					return Ok(());
+				}
+				eprintln!("Performing SECURITY UNLOCK...");
+				security_unlock(f, password, args.flag_master)
+			}),
+			_ => unlocked,
+		};
+		unlocked.and_then(|()| {
+			after_unlock(
+				&f,
+				Path::new(&dev),
+				args.flag_freeze || device_config.freeze,
+				args.flag_reset || device_config.reset,
+				args.flag_settle,
+			)
+		})
 	} else if args.cmd_disable_password {
+		let password = match read_password(password, &identity, "password", false, None, normalize)
+		{
+			Ok(password) => password,
+			Err(code) => return code,
+		};
 		eprintln!("Performing SECURITY DISABLE PASSWORD...");
-		security_disable_password(
-			&f,
-			read_password(args.flag_password_file, &identity, false),
-			args.flag_master,
-		)
+		security_disable_password(&f, password, args.flag_master)
 	} else if args.cmd_erase {
+		if !check_not_in_use(&dev, args.flag_force)
+			|| !check_firmware_activation(&f, args.flag_force)
+		{
+			return EXIT_FAILURE;
+		}
+		let claim = match claim_for_erase(&dev, args.flag_force) {
+			Some(claim) => claim,
+			None => return EXIT_FAILURE,
+		};
+		if args.flag_manifest.is_some() || args.flag_backup_luks_headers.is_some() {
+			if !write_manifest(
+				&dev,
+				&identity,
+				args.flag_manifest.as_ref().map(String::as_str),
+				args.flag_backup_luks_headers.as_ref().map(String::as_str),
+			) {
+				return EXIT_FAILURE;
+			}
+		}
+		let password = match read_password(password, &identity, "password", true, None, normalize) {
+			Ok(password) => password,
+			Err(code) => return code,
+		};
+		let prepared = if args.flag_verify_prepare {
+			eprintln!("Writing verification pattern...");
+			match verify::prepare(Path::new(&dev)) {
+				Err(e) => {
+					eprintln!("Unable to write verification pattern: {}", e);
+					return EXIT_FAILURE;
+				}
+				Ok(prepared) => Some(prepared),
+			}
+		} else {
+			None
+		};
+		print_erase_estimate(&security, args.flag_enhanced);
+		let mut certificate = erasure::Certificate::new(&dev, &identity);
+		certificate.method = Some(if args.flag_enhanced {
+			advisor::Method::EnhancedAtaErase
+		} else {
+			advisor::Method::AtaErase
+		});
 		eprintln!("Performing SECURITY ERASE...");
-		security_erase(
-			&f,
-			read_password(args.flag_password_file, &identity, true),
-			args.flag_master,
-			args.flag_enhanced,
-		)
+		interrupt::during(interrupt::Operation::Erase);
+		let start = std::time::Instant::now();
+		let erased = security_erase(&f, password, args.flag_master, args.flag_enhanced);
+		interrupt::done();
+		release_claim(&dev, claim);
+		if let (&Err(_), Some(prepared)) = (&erased, prepared) {
+			eprintln!("Restoring the blocks overwritten with the verification pattern...");
+			if let Err(e) = prepared.restore() {
+				eprintln!("Unable to restore the blocks: {}", e);
+			}
+		}
+		match (&erased, &args.flag_benchmark_report) {
+			(&Ok(()), &Some(ref path)) => {
+				let record = benchmark::Record::new(
+					&identity,
+					&security,
+					args.flag_enhanced,
+					start.elapsed(),
+				);
+				eprintln!(
+					"The erase took {} seconds, the drive advertises {}",
+					record.duration,
+					record
+						.advertised
+						.map_or("no erase time".to_owned(), |a| format!("{} seconds", a))
+				);
+				if record.suspicious {
+					eprintln!("Warning: the erase was suspiciously fast, check the drive");
+				}
+				if let Err(e) = record.append(path) {
+					eprintln!("Unable to write {}: {}", path, e);
+				}
+			}
+			_ => {}
+		}
+		let result = erased.and_then(|()| {
+			if args.flag_verify {
+				verify_erase(Path::new(&dev), args.flag_verify_prepare)
+			} else {
+				Ok(())
+			}
+		});
+		if let Some(ref path) = args.flag_report {
+			let sign_key = args.flag_sign_key.as_ref().map(String::as_str);
+			if !write_report(certificate, &result, path, sign_key) {
+				return EXIT_FAILURE;
+			}
+		}
+		result
+	} else if args.cmd_freeze && args.flag_all_paths {
+		on_all_paths(&dev, |f, security| {
+			if security.frozen() {
+				return Ok(());
+			}
+			eprintln!("Performing SECURITY FREEZE...");
+			security_freeze(f)
+		})
 	} else if args.cmd_freeze {
 		eprintln!("Performing SECURITY FREEZE...");
 		security_freeze(&f)
@@ -560,9 +4238,13 @@ Options:
 		unreachable!()
 	};
 
-	if let Err(e) = result {
-		eprintln!("There was an error executing the command: {:?}", e);
-	} else {
-		eprintln!("Success!");
+	if let (&Err(_), &Some(ref dir)) = (&result, &args.flag_capture_telemetry) {
+		eprintln!("Capturing telemetry...");
+		match telemetry::capture(f.as_raw_fd(), &identity, dir) {
+			Err(e) => eprintln!("Unable to capture telemetry: {:?}", e),
+			Ok(path) => eprintln!("Saved telemetry to {}", path.display()),
+		}
 	}
+
+	finish(result)
 }
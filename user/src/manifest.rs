@@ -0,0 +1,301 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Manifests of what an erase destroys.
+//!
+//! `erase --manifest=<file>` records the layout of the namespace before
+//! erasing it: the partition table, the file system types, labels and UUIDs
+//! as udev knows them, and where LUKS headers are, but not their contents.
+//! Afterwards this tells what was on the drive, e.g. to prove the scope of
+//! an erase for an audit.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::Command;
+
+use byteorder::{BigEndian, ByteOrder};
+use serde_json;
+
+use audit;
+use config;
+use launcher;
+use nvme::identify::IdentifyController;
+
+/// Block device sizes and offsets in sysfs are in 512-byte sectors
+const SECTOR: u64 = 512;
+
+const LUKS_MAGIC: &'static [u8] = b"LUKS\xba\xbe";
+
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+	pub tool: &'static str,
+	pub version: &'static str,
+	pub created: String,
+	pub device: String,
+	pub model: String,
+	pub serial: String,
+	pub size_bytes: u64,
+	/// `gpt` or `dos`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub partition_table: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub partition_table_uuid: Option<String>,
+	/// A file system or LUKS container on the whole namespace
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub filesystem: Option<Filesystem>,
+	pub partitions: Vec<Partition>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Partition {
+	pub device: String,
+	pub number: u32,
+	pub start_bytes: u64,
+	pub size_bytes: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub uuid: Option<String>,
+	/// The partition type GUID or MBR type
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub partition_type: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub filesystem: Option<Filesystem>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Filesystem {
+	/// As named by blkid, e.g. `ext4` or `crypto_LUKS`
+	#[serde(rename = "type")]
+	pub fstype: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub label: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub uuid: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub luks: Option<Luks>,
+}
+
+/// Where the headers of a LUKS container are, as byte offsets on the
+/// namespace.
+#[derive(Serialize, Debug)]
+pub struct Luks {
+	pub version: u16,
+	/// LUKS2 keeps a second copy of the header
+	pub header_offsets: Vec<u64>,
+	/// The size of each header, for LUKS1 including the key slots
+	pub header_size: u64,
+	/// Where `--backup-luks-headers` saved the header
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub backup: Option<String>,
+}
+
+/// The udev database entry of a block device, `E:KEY=value` lines.
+fn udev_properties(sysfs: &Path) -> Vec<(String, String)> {
+	let dev = match fs::read_to_string(sysfs.join("dev")) {
+		Ok(dev) => dev,
+		Err(_) => return vec![],
+	};
+	let db = match fs::read_to_string(format!("/run/udev/data/b{}", dev.trim())) {
+		Ok(db) => db,
+		Err(_) => return vec![],
+	};
+	db.lines()
+		.filter(|l| l.starts_with("E:"))
+		.filter_map(|l| {
+			let mut kv = l[2..].splitn(2, '=');
+			match (kv.next(), kv.next()) {
+				(Some(k), Some(v)) => Some((k.to_owned(), v.to_owned())),
+				_ => None,
+			}
+		})
+		.collect()
+}
+
+fn property(properties: &[(String, String)], key: &str) -> Option<String> {
+	properties
+		.iter()
+		.find(|p| p.0 == key)
+		.map(|p| p.1.clone())
+		.filter(|v| !v.is_empty())
+}
+
+fn sysfs_number(path: &Path) -> io::Result<u64> {
+	try!(fs::read_to_string(path))
+		.trim()
+		.parse()
+		.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid sysfs number"))
+}
+
+/// Locate the LUKS headers on `dev`, which starts at byte `offset` of the
+/// namespace.
+fn luks(dev: &Path, offset: u64) -> io::Result<Option<Luks>> {
+	let mut buf = [0u8; 112];
+	try!(try!(File::open(dev)).read_exact(&mut buf));
+	if &buf[..6] != LUKS_MAGIC {
+		return Ok(None);
+	}
+	let version = BigEndian::read_u16(&buf[6..8]);
+	Ok(Some(match version {
+		1 => Luks {
+			version,
+			header_offsets: vec![offset],
+			// The payload offset, in sectors
+			header_size: BigEndian::read_u32(&buf[104..108]) as u64 * SECTOR,
+			backup: None,
+		},
+		_ => {
+			let header_size = BigEndian::read_u64(&buf[8..16]);
+			Luks {
+				version,
+				header_offsets: vec![offset, offset + header_size],
+				header_size,
+				backup: None,
+			}
+		}
+	}))
+}
+
+fn filesystem(dev: &Path, sysfs: &Path, offset: u64) -> io::Result<Option<Filesystem>> {
+	let properties = udev_properties(sysfs);
+	let fstype = match property(&properties, "ID_FS_TYPE") {
+		Some(fstype) => fstype,
+		None => return Ok(None),
+	};
+	let luks = if fstype == "crypto_LUKS" {
+		try!(luks(dev, offset))
+	} else {
+		None
+	};
+	Ok(Some(Filesystem {
+		fstype,
+		label: property(&properties, "ID_FS_LABEL"),
+		uuid: property(&properties, "ID_FS_UUID"),
+		luks,
+	}))
+}
+
+/// Save the LUKS header of `dev` to `path`.
+fn header_backup(dev: &Path, path: &Path) -> io::Result<()> {
+	let mut command = Command::new("cryptsetup");
+	command
+		.arg("luksHeaderBackup")
+		.arg(dev)
+		.arg("--header-backup-file")
+		.arg(path);
+	let status = try!(launcher::status(command)
+		.map_err(|e| io::Error::new(e.kind(), format!("Unable to run cryptsetup: {}", e))));
+	if !status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!(
+				"cryptsetup luksHeaderBackup of {} failed with {}",
+				dev.display(),
+				status
+			),
+		));
+	}
+	Ok(())
+}
+
+impl Manifest {
+	/// Record the layout of the namespace block device `dev`.
+	pub fn new(dev: &Path, identity: &IdentifyController) -> io::Result<Manifest> {
+		let dev = try!(fs::canonicalize(dev));
+		let name = try!(dev
+			.file_name()
+			.map(|n| n.to_string_lossy().into_owned())
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid device path")));
+		let sysfs = Path::new("/sys/class/block").join(&name);
+		let properties = udev_properties(&sysfs);
+		let mut partitions = vec![];
+		for entry in try!(fs::read_dir(&sysfs)) {
+			let part = try!(entry).path();
+			if !part.join("partition").exists() {
+				continue;
+			}
+			let part_name = part.file_name().unwrap().to_string_lossy().into_owned();
+			let part_dev = Path::new("/dev").join(&part_name);
+			let start_bytes = try!(sysfs_number(&part.join("start"))) * SECTOR;
+			let part_properties = udev_properties(&part);
+			partitions.push(Partition {
+				device: part_dev.display().to_string(),
+				number: try!(sysfs_number(&part.join("partition"))) as u32,
+				start_bytes,
+				size_bytes: try!(sysfs_number(&part.join("size"))) * SECTOR,
+				name: property(&part_properties, "ID_PART_ENTRY_NAME"),
+				uuid: property(&part_properties, "ID_PART_ENTRY_UUID"),
+				partition_type: property(&part_properties, "ID_PART_ENTRY_TYPE"),
+				filesystem: try!(filesystem(&part_dev, &part, start_bytes)),
+			});
+		}
+		partitions.sort_by_key(|p| p.number);
+		Ok(Manifest {
+			tool: env!("CARGO_PKG_NAME"),
+			version: env!("CARGO_PKG_VERSION"),
+			created: audit::now(),
+			device: dev.display().to_string(),
+			model: String::from_utf8_lossy(identity.mn()).trim().to_owned(),
+			serial: config::serial(identity),
+			size_bytes: try!(sysfs_number(&sysfs.join("size"))) * SECTOR,
+			partition_table: property(&properties, "ID_PART_TABLE_TYPE"),
+			partition_table_uuid: property(&properties, "ID_PART_TABLE_UUID"),
+			filesystem: try!(filesystem(&dev, &sysfs, 0)),
+			partitions,
+		})
+	}
+
+	/// Save the headers of all LUKS containers to `dir` with `cryptsetup
+	/// luksHeaderBackup`, which includes the key slots. Returns how many
+	/// were saved.
+	pub fn backup_luks_headers(&mut self, dir: &str) -> io::Result<usize> {
+		let serial = self.serial.clone();
+		let device = self.device.clone();
+		let mut containers: Vec<(&str, &mut Filesystem)> = vec![];
+		if let Some(ref mut fs) = self.filesystem {
+			containers.push((&device, fs));
+		}
+		for part in &mut self.partitions {
+			if let Some(ref mut fs) = part.filesystem {
+				containers.push((&part.device, fs));
+			}
+		}
+		let mut saved = 0;
+		for (dev, fs) in containers {
+			let luks = match fs.luks {
+				Some(ref mut luks) => luks,
+				None => continue,
+			};
+			let name = Path::new(dev)
+				.file_name()
+				.unwrap()
+				.to_string_lossy()
+				.into_owned();
+			let path = Path::new(dir).join(format!(
+				"{}-{}-{}.luksheader",
+				serial,
+				name,
+				fs.uuid.as_ref().map_or("unknown", String::as_str)
+			));
+			try!(header_backup(Path::new(dev), &path));
+			luks.backup = Some(path.display().to_string());
+			saved += 1;
+		}
+		Ok(saved)
+	}
+
+	/// Write the manifest to `path` as JSON.
+	pub fn write(&self, path: &str) -> io::Result<()> {
+		let json = try!(serde_json::to_string_pretty(self)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+		try!(File::create(path)).write_all((json + "\n").as_bytes())
+	}
+}
@@ -0,0 +1,75 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Unicode normalization of passwords.
+//!
+//! The same password typed with different keyboard layouts or input methods
+//! may produce different UTF-8, e.g. `é` as one precomposed character or as
+//! `e` followed by a combining accent. Normalizing the password before it is
+//! hashed makes it unlock the drive on any machine. The normalization must be
+//! the same when setting and when unlocking.
+
+use std::io;
+use std::str::{self, FromStr};
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+	/// Canonical composition
+	Nfc,
+	/// Compatibility decomposition, e.g. `ﬁ` becomes `fi`
+	Nfkd,
+	/// Compatibility decomposition without accents, which must leave only
+	/// ASCII characters
+	Ascii,
+}
+
+impl FromStr for Normalization {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Normalization, String> {
+		match s {
+			"nfc" => Ok(Normalization::Nfc),
+			"nfkd" => Ok(Normalization::Nfkd),
+			"ascii" => Ok(Normalization::Ascii),
+			_ => Err(format!("Unknown normalization: {}", s)),
+		}
+	}
+}
+
+impl Normalization {
+	/// Normalize `password`, which must be UTF-8.
+	pub fn apply(self, password: &[u8]) -> io::Result<Vec<u8>> {
+		let password = try!(str::from_utf8(password).map_err(|_| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				"the password is not valid UTF-8 and can't be normalized",
+			)
+		}));
+		let normalized: String = match self {
+			Normalization::Nfc => password.nfc().collect(),
+			Normalization::Nfkd => password.nfkd().collect(),
+			Normalization::Ascii => {
+				let ascii: String = password.nfkd().filter(|&c| !is_combining_mark(c)).collect();
+				if !ascii.is_ascii() {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						"the password contains characters that have no ASCII equivalent",
+					));
+				}
+				ascii
+			}
+		};
+		Ok(normalized.into_bytes())
+	}
+}
@@ -9,14 +9,62 @@
  * any later version.
  */
 
+use std::fmt;
+
 #[repr(u8)]
 pub enum Opcode {
+	AdminGetLogPage = 0x02,
 	AdminIdentify = 0x06,
+	AdminAbort = 0x08,
+	AdminSetFeatures = 0x09,
+	AdminGetFeatures = 0x0a,
+	AdminDeviceSelfTest = 0x14,
+	AdminFormatNvm = 0x80,
 	AdminSecuritySend = 0x81,
 	AdminSecurityReceive = 0x82,
+	AdminSanitize = 0x84,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Sanitize Action (SANACT) of the Sanitize command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+#[repr(u8)]
+pub enum SanitizeAction {
+	BlockErase = 2,
+	Overwrite = 3,
+	CryptoErase = 4,
+}
+
+/// Feature Identifiers of Get Features and Set Features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Feature {
+	PowerManagement = 0x02,
+	VolatileWriteCache = 0x06,
+	AutonomousPowerStateTransition = 0x0c,
+	HostMemoryBuffer = 0x0d,
+}
+
+/// Self-test Code (STC) of the Device Self-test command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SelfTest {
+	Short = 1,
+	Extended = 2,
+	Abort = 0xf,
+}
+
+/// Secure Erase Settings (SES) of the Format NVM command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+#[repr(u8)]
+pub enum SecureErase {
+	None = 0,
+	UserData = 1,
+	Cryptographic = 2,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StatusCode {
 	SuccessfulCompletion,                           // 0x000
 	InvalidCommandOpcode,                           // 0x001
@@ -176,16 +224,79 @@ impl From<u16> for StatusCode {
 	}
 }
 
+/// A payload that doesn't have the layout of its type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadError {
+	/// The payload is `actual` bytes long instead of `expected`
+	Length { expected: usize, actual: usize },
+	/// A field holds a value the type doesn't allow
+	Invalid(&'static str),
+}
+
+impl fmt::Display for PayloadError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			PayloadError::Length { expected, actual } => {
+				write!(fmt, "payload of {} bytes instead of {}", actual, expected)
+			}
+			PayloadError::Invalid(what) => write!(fmt, "malformed payload: {}", what),
+		}
+	}
+}
+
+/// The data of a command or its response, of a fixed size.
+pub trait Payload: AsRef<[u8]> + Sized {
+	/// Size in bytes
+	const SIZE: usize;
+
+	/// Parse a payload of exactly `SIZE` bytes.
+	fn from_wire(buf: &[u8]) -> Result<Self, PayloadError>;
+
+	/// The bytes to transfer.
+	fn to_wire(&self) -> &[u8] {
+		self.as_ref()
+	}
+}
+
+/// Implement `Payload` for a newtype around `[u8; $size]`, accepting the
+/// bytes that pass `$check`.
+macro_rules! payload {
+	($name:ident, $size:expr) => {
+		payload!($name, $size, |_| Ok(()));
+	};
+	($name:ident, $size:expr, $check:expr) => {
+		impl AsRef<[u8]> for $name {
+			fn as_ref(&self) -> &[u8] {
+				&self.0
+			}
+		}
+
+		impl super::Payload for $name {
+			const SIZE: usize = $size;
+
+			fn from_wire(buf: &[u8]) -> Result<Self, super::PayloadError> {
+				if buf.len() != $size {
+					return Err(super::PayloadError::Length {
+						expected: $size,
+						actual: buf.len(),
+					});
+				}
+				let check: fn(&[u8]) -> Result<(), super::PayloadError> = $check;
+				try!(check(buf));
+				let mut array = [0u8; $size];
+				array.copy_from_slice(buf);
+				Ok($name(array))
+			}
+		}
+	};
+}
+
 pub mod identify {
 	use byteorder::{LittleEndian, ReadBytesExt};
 
 	pub struct IdentifyController([u8; 4096]);
 
-	impl From<[u8; 4096]> for IdentifyController {
-		fn from(array: [u8; 4096]) -> Self {
-			IdentifyController(array)
-		}
-	}
+	payload!(IdentifyController, 4096);
 
 	impl IdentifyController {
 		pub fn vid(&self) -> u16 {
@@ -208,9 +319,99 @@ pub mod identify {
 			&self.0[64..72]
 		}
 
+		/// Maximum Data Transfer Size as a power of two in units of the
+		/// minimum memory page size, zero if there is no limit
+		pub fn mdts(&self) -> u8 {
+			self.0[77]
+		}
+
+		/// Maximum data transfer size in bytes. Assumes a minimum memory
+		/// page size of 4 KiB, the page size Linux uses for NVMe.
+		pub fn max_transfer(&self) -> Option<usize> {
+			match self.mdts() {
+				0 => None,
+				// Larger values exceed any transfer this tool makes
+				mdts if mdts > 20 => None,
+				mdts => Some(4096 << mdts),
+			}
+		}
+
+		/// Version, e.g. `(1, 4, 0)`. All zero for controllers older than
+		/// NVMe 1.2.
+		pub fn ver(&self) -> (u16, u8, u8) {
+			let ver = (&self.0[80..84]).read_u32::<LittleEndian>().unwrap();
+			((ver >> 16) as u16, (ver >> 8) as u8, ver as u8)
+		}
+
 		pub fn oacs(&self) -> Oacs {
 			Oacs::from_bits_truncate((&self.0[256..258]).read_u16::<LittleEndian>().unwrap())
 		}
+
+		pub fn frmw(&self) -> Frmw {
+			Frmw(self.0[260])
+		}
+
+		pub fn lpa(&self) -> Lpa {
+			Lpa::from_bits_truncate(self.0[261])
+		}
+
+		/// Number of supported power states, zero-based
+		pub fn npss(&self) -> u8 {
+			self.0[263]
+		}
+
+		pub fn sanicap(&self) -> Sanicap {
+			Sanicap::from_bits_truncate((&self.0[328..332]).read_u32::<LittleEndian>().unwrap())
+		}
+
+		pub fn oncs(&self) -> Oncs {
+			Oncs::from_bits_truncate((&self.0[520..522]).read_u16::<LittleEndian>().unwrap())
+		}
+
+		pub fn fna(&self) -> Fna {
+			Fna::from_bits_truncate(self.0[524])
+		}
+
+		/// Whether this is an NVMe over Fabrics controller. IOCCSZ is only
+		/// defined for fabrics, PCIe controllers report zero.
+		pub fn is_fabrics(&self) -> bool {
+			(&self.0[1792..1796]).read_u32::<LittleEndian>().unwrap() != 0
+		}
+
+		pub fn subnqn(&self) -> &[u8] {
+			let subnqn = &self.0[768..1024];
+			let len = subnqn.iter().position(|&b| b == 0).unwrap_or(subnqn.len());
+			&subnqn[..len]
+		}
+	}
+
+	pub struct IdentifyNamespace([u8; 4096]);
+
+	payload!(IdentifyNamespace, 4096);
+
+	impl IdentifyNamespace {
+		/// Index of the LBA format the namespace is formatted with
+		pub fn lba_format(&self) -> u8 {
+			self.0[26] & 0x0f
+		}
+	}
+
+	/// Firmware updates
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct Frmw(pub u8);
+
+	impl Frmw {
+		pub fn slot1_read_only(self) -> bool {
+			self.0 & 0x01 != 0
+		}
+
+		pub fn slots(self) -> u8 {
+			(self.0 >> 1) & 0x07
+		}
+
+		pub fn activation_without_reset(self) -> bool {
+			self.0 & 0x10 != 0
+		}
 	}
 
 	bitflags! {
@@ -219,7 +420,7 @@ pub mod identify {
 			const FORMAT         = 0x0002;
 			const FIRMWARE       = 0x0004;
 			const NAMESPACE      = 0x0008;
-			const RESERVED1      = 0x0010;
+			const SELF_TEST      = 0x0010;
 			const RESERVED2      = 0x0020;
 			const RESERVED3      = 0x0040;
 			const RESERVED4      = 0x0080;
@@ -233,13 +434,448 @@ pub mod identify {
 			const RESERVED12     = 0x8000;
 		}
 	}
+
+	bitflags! {
+		pub struct Oncs: u16 {
+			const COMPARE             = 0x0001;
+			const WRITE_UNCORRECTABLE = 0x0002;
+			const DATASET_MANAGEMENT  = 0x0004;
+			const WRITE_ZEROES        = 0x0008;
+			const SAVE_SELECT         = 0x0010;
+			const RESERVATIONS        = 0x0020;
+			const TIMESTAMP           = 0x0040;
+			const VERIFY              = 0x0080;
+		}
+	}
+
+	bitflags! {
+		/// Log Page Attributes
+		pub struct Lpa: u8 {
+			const SMART_PER_NAMESPACE = 0x01;
+			const COMMAND_EFFECTS     = 0x02;
+			/// Get Log Page supports offsets and large transfers
+			const EXTENDED_DATA       = 0x04;
+			const TELEMETRY           = 0x08;
+		}
+	}
+
+	bitflags! {
+		pub struct Fna: u8 {
+			/// Format applies to all namespaces
+			const FORMAT_ALL   = 0x01;
+			/// Secure erase applies to all namespaces
+			const ERASE_ALL    = 0x02;
+			/// Cryptographic erase is supported as part of secure erase
+			const CRYPTO_ERASE = 0x04;
+		}
+	}
+
+	bitflags! {
+		pub struct Sanicap: u32 {
+			const CRYPTO_ERASE          = 0x0000_0001;
+			const BLOCK_ERASE           = 0x0000_0002;
+			const OVERWRITE             = 0x0000_0004;
+			const NO_DEALLOCATE_INHIBIT = 0x2000_0000;
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::super::Payload;
+		use super::*;
+
+		#[test]
+		fn identify_controller() {
+			let mut buf = [0u8; 4096];
+			buf[0..4].copy_from_slice(&[0x4d, 0x14, 0x4d, 0x14]);
+			buf[4..24].copy_from_slice(b"S4EWNX0R123456      ");
+			buf[24..64].copy_from_slice(b"Example NVMe SSD 1TB                    ");
+			buf[64..72].copy_from_slice(b"2B2QEXM7");
+			buf[77] = 5;
+			buf[80..84].copy_from_slice(&[0x00, 0x04, 0x01, 0x00]);
+			buf[256..258].copy_from_slice(&[0x17, 0x00]);
+			buf[260] = 0x16;
+			buf[261] = 0x0e;
+			buf[263] = 4;
+			buf[328..332].copy_from_slice(&[0x03, 0x00, 0x00, 0x20]);
+			buf[520..522].copy_from_slice(&[0x5f, 0x00]);
+			buf[524] = 0x04;
+			buf[768..789].copy_from_slice(b"nqn.2014.08.org.nvmex");
+
+			let identity = IdentifyController::from_wire(&buf).unwrap();
+			assert_eq!(identity.vid(), 0x144d);
+			assert_eq!(identity.ssvid(), 0x144d);
+			assert_eq!(identity.sn(), b"S4EWNX0R123456      ");
+			assert_eq!(&identity.mn()[..20], b"Example NVMe SSD 1TB");
+			assert_eq!(identity.fr(), b"2B2QEXM7");
+			assert_eq!(identity.mdts(), 5);
+			assert_eq!(identity.max_transfer(), Some(128 * 1024));
+			assert_eq!(identity.ver(), (1, 4, 0));
+			assert_eq!(
+				identity.oacs(),
+				Oacs::SECURITY | Oacs::FORMAT | Oacs::FIRMWARE | Oacs::SELF_TEST
+			);
+			assert!(!identity.frmw().slot1_read_only());
+			assert_eq!(identity.frmw().slots(), 3);
+			assert!(identity.frmw().activation_without_reset());
+			assert_eq!(
+				identity.lpa(),
+				Lpa::COMMAND_EFFECTS | Lpa::EXTENDED_DATA | Lpa::TELEMETRY
+			);
+			assert_eq!(identity.npss(), 4);
+			assert_eq!(
+				identity.sanicap(),
+				Sanicap::CRYPTO_ERASE | Sanicap::BLOCK_ERASE | Sanicap::NO_DEALLOCATE_INHIBIT
+			);
+			assert!(identity
+				.oncs()
+				.contains(Oncs::WRITE_ZEROES | Oncs::TIMESTAMP));
+			assert!(!identity.oncs().contains(Oncs::VERIFY));
+			assert_eq!(identity.fna(), Fna::CRYPTO_ERASE);
+			assert_eq!(identity.subnqn(), b"nqn.2014.08.org.nvmex");
+			assert!(!identity.is_fabrics());
+
+			buf[77] = 0;
+			buf[1792] = 0x04;
+			let identity = IdentifyController::from_wire(&buf).unwrap();
+			assert_eq!(identity.max_transfer(), None);
+			assert!(identity.is_fabrics());
+
+			assert!(IdentifyController::from_wire(&buf[..4095]).is_err());
+		}
+	}
+}
+
+pub mod log {
+	use byteorder::{LittleEndian, ReadBytesExt};
+	use std::fmt;
+
+	/// Log page identifiers
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	#[repr(u8)]
+	pub enum Page {
+		FirmwareSlot = 0x03,
+		SelfTest = 0x06,
+		TelemetryHostInitiated = 0x07,
+		SanitizeStatus = 0x81,
+	}
+
+	pub struct FirmwareSlot([u8; 512]);
+
+	payload!(FirmwareSlot, 512);
+
+	impl FirmwareSlot {
+		/// The slot the running firmware was loaded from
+		pub fn active_slot(&self) -> u8 {
+			self.0[0] & 0x07
+		}
+
+		/// The slot that will be activated at the next controller reset, if
+		/// a firmware activation is pending
+		pub fn next_slot(&self) -> Option<u8> {
+			match (self.0[0] >> 4) & 0x07 {
+				0 => None,
+				slot => Some(slot),
+			}
+		}
+
+		/// Firmware revision in slot 1 through 7
+		pub fn revision(&self, slot: u8) -> &[u8] {
+			assert!(slot >= 1 && slot <= 7);
+			let start = 8 * slot as usize;
+			&self.0[start..start + 8]
+		}
+	}
+
+	/// The header of the telemetry log pages
+	pub struct TelemetryHeader([u8; 512]);
+
+	payload!(TelemetryHeader, 512);
+
+	impl TelemetryHeader {
+		/// The last 512-byte block of data area 1, 2 or 3, counting the
+		/// header as block 0. Each area includes the smaller ones.
+		pub fn last_block(&self, area: usize) -> u16 {
+			assert!(area >= 1 && area <= 3);
+			let start = 8 + 2 * (area - 1);
+			(&self.0[start..start + 2])
+				.read_u16::<LittleEndian>()
+				.unwrap()
+		}
+	}
+
+	/// The operation of a device self-test
+	#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+	#[serde(rename_all = "kebab-case")]
+	pub enum SelfTestOperation {
+		Short,
+		Extended,
+		VendorSpecific,
+		Reserved(u8),
+	}
+
+	impl From<u8> for SelfTestOperation {
+		fn from(code: u8) -> SelfTestOperation {
+			match code {
+				0x1 => SelfTestOperation::Short,
+				0x2 => SelfTestOperation::Extended,
+				0xe => SelfTestOperation::VendorSpecific,
+				_ => SelfTestOperation::Reserved(code),
+			}
+		}
+	}
+
+	impl fmt::Display for SelfTestOperation {
+		fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+			match *self {
+				SelfTestOperation::Short => write!(fmt, "short self-test"),
+				SelfTestOperation::Extended => write!(fmt, "extended self-test"),
+				SelfTestOperation::VendorSpecific => write!(fmt, "vendor specific self-test"),
+				SelfTestOperation::Reserved(n) => write!(fmt, "self-test {:x}h", n),
+			}
+		}
+	}
+
+	/// A Self-test Result Data Structure of the Device Self-test log page
+	#[derive(Serialize, Debug)]
+	pub struct SelfTestResult {
+		pub operation: SelfTestOperation,
+		pub result: &'static str,
+		pub passed: bool,
+		pub power_on_hours: u64,
+		/// The first segment that failed
+		#[serde(skip_serializing_if = "Option::is_none")]
+		pub segment: Option<u8>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		pub nsid: Option<u32>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		pub failing_lba: Option<u64>,
+	}
+
+	fn self_test_result(code: u8) -> &'static str {
+		match code {
+			0x0 => "completed without error",
+			0x1 => "aborted by a Device Self-test command",
+			0x2 => "aborted by a controller reset",
+			0x3 => "aborted by a namespace removal",
+			0x4 => "aborted by a Format NVM command",
+			0x5 => "fatal or unknown test error",
+			0x6 => "completed with a failed segment that is not known",
+			0x7 => "completed with one or more failed segments",
+			0x8 => "aborted for an unknown reason",
+			0x9 => "aborted by a sanitize operation",
+			_ => "reserved",
+		}
+	}
+
+	/// Entries in the Device Self-test log page
+	const SELF_TEST_RESULTS: usize = 20;
+
+	pub struct SelfTestLog([u8; 564]);
+
+	payload!(SelfTestLog, 564);
+
+	impl SelfTestLog {
+		/// The self-test in progress and its completion in percent
+		pub fn current(&self) -> Option<(SelfTestOperation, u8)> {
+			match self.0[0] & 0x0f {
+				0 => None,
+				code => Some((SelfTestOperation::from(code), self.0[1] & 0x7f)),
+			}
+		}
+
+		/// The results of the most recent self-tests, newest first
+		pub fn results(&self) -> Vec<SelfTestResult> {
+			self.0[4..]
+				.chunks(28)
+				.take(SELF_TEST_RESULTS)
+				.filter(|entry| entry[0] & 0x0f != 0xf)
+				.map(|entry| {
+					let valid = entry[2];
+					let result = entry[0] & 0x0f;
+					SelfTestResult {
+						operation: SelfTestOperation::from(entry[0] >> 4),
+						result: self_test_result(result),
+						passed: result == 0,
+						power_on_hours: (&entry[4..12]).read_u64::<LittleEndian>().unwrap(),
+						segment: if result == 0x7 { Some(entry[1]) } else { None },
+						nsid: if valid & 0x1 != 0 {
+							Some((&entry[12..16]).read_u32::<LittleEndian>().unwrap())
+						} else {
+							None
+						},
+						failing_lba: if valid & 0x2 != 0 {
+							Some((&entry[16..24]).read_u64::<LittleEndian>().unwrap())
+						} else {
+							None
+						},
+					}
+				})
+				.collect()
+		}
+	}
+
+	/// Most recent sanitize operation, bits 2:0 of SSTAT
+	#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+	#[serde(rename_all = "kebab-case")]
+	pub enum SanitizeState {
+		NeverSanitized,
+		Completed,
+		InProgress,
+		Failed,
+		CompletedWithoutDeallocate,
+		Reserved(u8),
+	}
+
+	impl fmt::Display for SanitizeState {
+		fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+			match *self {
+				SanitizeState::NeverSanitized => write!(fmt, "never sanitized"),
+				SanitizeState::Completed => write!(fmt, "completed"),
+				SanitizeState::InProgress => write!(fmt, "in progress"),
+				SanitizeState::Failed => write!(fmt, "failed"),
+				SanitizeState::CompletedWithoutDeallocate => {
+					write!(fmt, "completed without deallocation")
+				}
+				SanitizeState::Reserved(n) => write!(fmt, "reserved ({})", n),
+			}
+		}
+	}
+
+	pub struct SanitizeStatus([u8; 512]);
+
+	payload!(SanitizeStatus, 512);
+
+	impl SanitizeStatus {
+		/// Progress of the sanitize operation in progress, in 65536ths
+		pub fn progress(&self) -> u16 {
+			(&self.0[0..2]).read_u16::<LittleEndian>().unwrap()
+		}
+
+		/// Progress of the sanitize operation in progress, in percent
+		pub fn percent(&self) -> u32 {
+			self.progress() as u32 * 100 / 65536
+		}
+
+		fn sstat(&self) -> u16 {
+			(&self.0[2..4]).read_u16::<LittleEndian>().unwrap()
+		}
+
+		pub fn state(&self) -> SanitizeState {
+			match self.sstat() & 0x7 {
+				0 => SanitizeState::NeverSanitized,
+				1 => SanitizeState::Completed,
+				2 => SanitizeState::InProgress,
+				3 => SanitizeState::Failed,
+				4 => SanitizeState::CompletedWithoutDeallocate,
+				n => SanitizeState::Reserved(n as u8),
+			}
+		}
+
+		/// Whether no user data has been written since the last sanitize or
+		/// the controller was manufactured
+		pub fn global_data_erased(&self) -> bool {
+			self.sstat() & 0x100 != 0
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::super::Payload;
+		use super::*;
+
+		#[test]
+		fn firmware_slot() {
+			let mut buf = [0u8; 512];
+			buf[0] = 0x21;
+			buf[8..16].copy_from_slice(b"2B2QEXM7");
+			buf[16..24].copy_from_slice(b"2B2QEXM8");
+			let log = FirmwareSlot::from_wire(&buf).unwrap();
+			assert_eq!(log.active_slot(), 1);
+			assert_eq!(log.next_slot(), Some(2));
+			assert_eq!(log.revision(1), b"2B2QEXM7");
+			assert_eq!(log.revision(2), b"2B2QEXM8");
+			assert_eq!(log.revision(7), &[0u8; 8]);
+
+			buf[0] = 0x02;
+			assert_eq!(FirmwareSlot::from_wire(&buf).unwrap().next_slot(), None);
+		}
+
+		#[test]
+		fn self_test_log() {
+			let mut buf = [0u8; 564];
+			buf[0] = 0x02;
+			buf[1] = 0x2a;
+			for entry in buf[4..].chunks_mut(28) {
+				entry[0] = 0x0f;
+			}
+			// A short self-test that failed in segment 3 at LBA 1000h of
+			// namespace 1
+			buf[4..7].copy_from_slice(&[0x17, 3, 0x03]);
+			buf[8..10].copy_from_slice(&[0xd2, 0x04]);
+			buf[16] = 1;
+			buf[20..22].copy_from_slice(&[0x00, 0x10]);
+			// An extended self-test that passed, with an NSID that is not valid
+			buf[32] = 0x20;
+			buf[36..38].copy_from_slice(&[0xe8, 0x03]);
+			buf[44] = 1;
+
+			let log = SelfTestLog::from_wire(&buf).unwrap();
+			assert_eq!(log.current(), Some((SelfTestOperation::Extended, 42)));
+			let results = log.results();
+			assert_eq!(results.len(), 2);
+			assert_eq!(results[0].operation, SelfTestOperation::Short);
+			assert_eq!(
+				results[0].result,
+				"completed with one or more failed segments"
+			);
+			assert!(!results[0].passed);
+			assert_eq!(results[0].power_on_hours, 1234);
+			assert_eq!(results[0].segment, Some(3));
+			assert_eq!(results[0].nsid, Some(1));
+			assert_eq!(results[0].failing_lba, Some(0x1000));
+			assert_eq!(results[1].operation, SelfTestOperation::Extended);
+			assert!(results[1].passed);
+			assert_eq!(results[1].power_on_hours, 1000);
+			assert_eq!(results[1].segment, None);
+			assert_eq!(results[1].nsid, None);
+			assert_eq!(results[1].failing_lba, None);
+
+			buf[0] = 0;
+			assert_eq!(SelfTestLog::from_wire(&buf).unwrap().current(), None);
+			assert!(SelfTestLog::from_wire(&buf[..512]).is_err());
+		}
+
+		#[test]
+		fn sanitize_status() {
+			let mut buf = [0u8; 512];
+			buf[0..4].copy_from_slice(&[0x00, 0x80, 0x02, 0x01]);
+			let status = SanitizeStatus::from_wire(&buf).unwrap();
+			assert_eq!(status.progress(), 0x8000);
+			assert_eq!(status.percent(), 50);
+			assert_eq!(status.state(), SanitizeState::InProgress);
+			assert!(status.global_data_erased());
+
+			buf[2..4].copy_from_slice(&[0x04, 0x00]);
+			let status = SanitizeStatus::from_wire(&buf).unwrap();
+			assert_eq!(status.state(), SanitizeState::CompletedWithoutDeallocate);
+			assert!(!status.global_data_erased());
+
+			buf[2] = 0x07;
+			let status = SanitizeStatus::from_wire(&buf).unwrap();
+			assert_eq!(status.state(), SanitizeState::Reserved(7));
+		}
+	}
 }
 
 pub mod security {
 	use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+	use std::fmt;
 	use std::io::Write;
 
-	#[derive(Debug, PartialEq, Eq)]
+	use super::PayloadError;
+
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 	pub enum Protocol {
 		Info,                        // 0x00
 		Tcg(u8),                     // 0x01 ... 0x06
@@ -279,6 +915,70 @@ pub mod security {
 		}
 	}
 
+	impl Protocol {
+		/// The name and range of the protocol, for display.
+		pub fn info(&self) -> ProtocolInfo {
+			use self::Protocol::*;
+			let (range, name) = match *self {
+				Info => (ProtocolRange::Information, "Security protocol information"),
+				Tcg(0x01) => (ProtocolRange::Tcg, "TCG Opal"),
+				Tcg(0x02) => (ProtocolRange::Tcg, "TCG ComID management"),
+				Tcg(_) => (ProtocolRange::Tcg, "TCG"),
+				CbCs => (ProtocolRange::Standard, "CbCS"),
+				TapeDataEncryption => (ProtocolRange::Standard, "Tape data encryption"),
+				DataEncryptionConfiguration => {
+					(ProtocolRange::Standard, "Data encryption configuration")
+				}
+				SaCreationCapabilities => (ProtocolRange::Standard, "SA creation capabilities"),
+				IkeV2Scsi => (ProtocolRange::Standard, "IKEv2-SCSI"),
+				Nvme => (ProtocolRange::Standard, "NVMe"),
+				JedecUniversalFlashStorage => (ProtocolRange::Standard, "JEDEC UFS"),
+				SdCardTrusteFlash => (ProtocolRange::Standard, "SD Card TrustedFlash"),
+				Ieee1667 => (ProtocolRange::Ieee1667, "IEEE 1667"),
+				AtaSecurity => (ProtocolRange::AtaSecurity, "ATA security"),
+				Vendor(_) => (ProtocolRange::VendorSpecific, "Vendor specific"),
+				Other(_) => (ProtocolRange::Reserved, "Reserved"),
+			};
+			ProtocolInfo {
+				code: (*self).into(),
+				range,
+				name,
+			}
+		}
+	}
+
+	/// The ranges of security protocol numbers, see SPC-5 table 259.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub enum ProtocolRange {
+		/// Security protocol information, 0x00
+		Information,
+		/// Defined by the TCG, 0x01 through 0x06
+		Tcg,
+		/// Defined by another standard
+		Standard,
+		/// IEEE 1667 silos, 0xee
+		Ieee1667,
+		/// The ATA Device Server Password Security protocol of SAT, 0xef
+		AtaSecurity,
+		/// 0xf0 through 0xff
+		VendorSpecific,
+		Reserved,
+	}
+
+	/// A security protocol with its name, displayed as e.g. "TCG Opal (0x01)".
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct ProtocolInfo {
+		pub code: u8,
+		pub range: ProtocolRange,
+		pub name: &'static str,
+	}
+
+	impl fmt::Display for ProtocolInfo {
+		fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+			write!(fmt, "{} (0x{:02x})", self.name, self.code)
+		}
+	}
+
 	impl Into<u8> for Protocol {
 		fn into(self) -> u8 {
 			use self::Protocol::*;
@@ -304,17 +1004,128 @@ pub mod security {
 	pub enum SecurityProtocolInformationSpecific {
 		SupportedProtocols = 0,
 		CertificateData = 1,
+		SecurityCompliance = 2,
 	}
 
-	pub struct AtaSecurityIdentify([u8; 16]);
+	#[derive(Debug, Clone, PartialEq, Eq)]
+	pub enum ComplianceDescriptor {
+		Fips140 {
+			related_standard: u8,
+			overall_security_level: u8,
+			hardware_version: String,
+			version: String,
+			module_name: String,
+		},
+		Other(u16),
+	}
+
+	fn ascii(buf: &[u8]) -> String {
+		String::from_utf8_lossy(buf)
+			.trim_matches(|c| c == ' ' || c == '\0')
+			.to_owned()
+	}
+
+	/// Parse the supported security protocol list page, see SPC-5 7.7.1.3.
+	pub fn parse_supported_protocols(buf: &[u8]) -> Vec<Protocol> {
+		if buf.len() < 8 {
+			return vec![];
+		}
+		let len = ::std::cmp::min(
+			(&buf[6..8]).read_u16::<BigEndian>().unwrap() as usize + 8,
+			buf.len(),
+		);
+		buf[8..len].iter().map(|&p| Protocol::from(p)).collect()
+	}
+
+	/// Parse the security compliance information page, see SPC-5 7.7.1.5.
+	pub fn parse_security_compliance(buf: &[u8]) -> Vec<ComplianceDescriptor> {
+		const COMPLIANCE_FIPS140: u16 = 0x0001;
+
+		let mut out = vec![];
+		if buf.len() < 4 {
+			return out;
+		}
+		let len = ::std::cmp::min(
+			(&buf[0..4]).read_u32::<BigEndian>().unwrap() as usize + 4,
+			buf.len(),
+		);
+		let mut pos = 4;
+		while pos + 8 <= len {
+			let descriptor_type = (&buf[pos..pos + 2]).read_u16::<BigEndian>().unwrap();
+			let descriptor_len = (&buf[pos + 4..pos + 8]).read_u32::<BigEndian>().unwrap() as usize;
+			let end = ::std::cmp::min(pos + 8 + descriptor_len, len);
+			let body = &buf[pos + 8..end];
+			out.push(
+				if descriptor_type == COMPLIANCE_FIPS140 && body.len() >= 392 {
+					ComplianceDescriptor::Fips140 {
+						related_standard: body[0],
+						overall_security_level: body[1],
+						hardware_version: ascii(&body[8..136]),
+						version: ascii(&body[136..264]),
+						module_name: ascii(&body[264..392]),
+					}
+				} else {
+					ComplianceDescriptor::Other(descriptor_type)
+				},
+			);
+			pos = end;
+		}
+		out
+	}
+
+	/// The time a security erase takes according to the drive.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct EraseTime {
+		pub minutes: u32,
+		/// The drive reported the maximum value, the erase takes longer than
+		/// `minutes`
+		pub longer: bool,
+	}
+
+	impl EraseTime {
+		/// Decode an erase time field. In the normal format bits 7:0 and in
+		/// the extended format (bit 15 set) bits 14:0 are the time in units
+		/// of 2 minutes, with the maximum value meaning longer than the
+		/// value before it (ACS-3 7.12.7.40).
+		pub fn decode(time: u16) -> Option<EraseTime> {
+			let (value, max) = if time & 0x8000 != 0 {
+				(time & 0x7fff, 0x7fff)
+			} else {
+				(time & 0xff, 0xff)
+			};
+			match value {
+				0 => None,
+				v if v == max => Some(EraseTime {
+					minutes: (v as u32 - 1) * 2,
+					longer: true,
+				}),
+				v => Some(EraseTime {
+					minutes: v as u32 * 2,
+					longer: false,
+				}),
+			}
+		}
+	}
 
-	impl From<[u8; 16]> for AtaSecurityIdentify {
-		fn from(array: [u8; 16]) -> Self {
-			assert_eq!(array[1], 0xe);
-			AtaSecurityIdentify(array)
+	impl fmt::Display for EraseTime {
+		fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+			if self.longer {
+				write!(fmt, ">{} minutes", self.minutes)
+			} else {
+				write!(fmt, "{} minutes", self.minutes)
+			}
 		}
 	}
 
+	pub struct AtaSecurityIdentify([u8; 16]);
+
+	// The second byte is the length of the remaining data
+	payload!(AtaSecurityIdentify, 16, |buf| if buf[1] == 0x0e {
+		Ok(())
+	} else {
+		Err(PayloadError::Invalid("ATA security identify length"))
+	});
+
 	impl AtaSecurityIdentify {
 		pub fn security_erase_time(&self) -> u16 {
 			(&self.0[2..4]).read_u16::<BigEndian>().unwrap()
@@ -324,10 +1135,29 @@ pub mod security {
 			(&self.0[4..6]).read_u16::<BigEndian>().unwrap()
 		}
 
+		/// The time SECURITY ERASE UNIT takes, if reported.
+		pub fn erase_time(&self, enhanced: bool) -> Option<EraseTime> {
+			EraseTime::decode(if enhanced {
+				self.enhanced_security_erase_time()
+			} else {
+				self.security_erase_time()
+			})
+		}
+
 		pub fn master_password_identifier(&self) -> u16 {
 			(&self.0[6..8]).read_u16::<BigEndian>().unwrap()
 		}
 
+		/// Whether the drive keeps a master password identifier (called the
+		/// master password revision code in older ATA standards). 0000h and
+		/// FFFFh mean it is not supported.
+		pub fn master_password_identifier_supported(&self) -> bool {
+			match self.master_password_identifier() {
+				0x0000 | 0xffff => false,
+				_ => true,
+			}
+		}
+
 		pub fn maxset(&self) -> bool {
 			const MAXSET: u8 = 0x01;
 			(self.0[8] & MAXSET) == MAXSET
@@ -377,29 +1207,332 @@ pub mod security {
 	#[repr(packed)]
 	pub struct AtaSecurityPassword([u8; 36]);
 
-	impl AtaSecurityPassword {
-		pub fn new(
-			password: [u8; 32],
-			master_password: bool,
-			flag: Option<bool>,
-			master_password_id: Option<u16>,
-		) -> AtaSecurityPassword {
+	/// A combination of password fields that ACS does not define.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub enum PasswordError {
+		/// A master password identifier on a user password
+		IdentifierOnUserPassword,
+		/// A security level on a master password
+		SecurityLevelOnMasterPassword,
+		/// Maximum security and enhanced erase share the flag, each belongs
+		/// to a different command
+		MaximumSecurityAndEnhanced,
+	}
+
+	impl fmt::Display for PasswordError {
+		fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+			fmt.write_str(match *self {
+				PasswordError::IdentifierOnUserPassword => {
+					"only the master password has an identifier"
+				}
+				PasswordError::SecurityLevelOnMasterPassword => {
+					"only the user password sets the security level"
+				}
+				PasswordError::MaximumSecurityAndEnhanced => {
+					"maximum security and enhanced erase are for different commands"
+				}
+			})
+		}
+	}
+
+	/// Builds the data of SECURITY SET PASSWORD, UNLOCK, ERASE UNIT and
+	/// DISABLE PASSWORD. Without further settings, this is the user password
+	/// with high security and a normal erase.
+	pub struct AtaSecurityPasswordBuilder {
+		password: [u8; 32],
+		master: bool,
+		maximum_security: bool,
+		enhanced: bool,
+		identifier: Option<u16>,
+	}
+
+	impl AtaSecurityPasswordBuilder {
+		/// The master password instead of the user password.
+		pub fn master(mut self) -> Self {
+			self.master = true;
+			self
+		}
+
+		/// Maximum instead of high security, for SET PASSWORD of the user
+		/// password.
+		pub fn maximum_security(mut self) -> Self {
+			self.maximum_security = true;
+			self
+		}
+
+		/// Enhanced instead of normal erase, for ERASE UNIT.
+		pub fn enhanced(mut self) -> Self {
+			self.enhanced = true;
+			self
+		}
+
+		/// The master password identifier, for SET PASSWORD of the master
+		/// password.
+		pub fn identifier(mut self, id: u16) -> Self {
+			self.identifier = Some(id);
+			self
+		}
+
+		pub fn build(self) -> Result<AtaSecurityPassword, PasswordError> {
+			if self.identifier.is_some() && !self.master {
+				return Err(PasswordError::IdentifierOnUserPassword);
+			}
+			if self.maximum_security && self.master {
+				return Err(PasswordError::SecurityLevelOnMasterPassword);
+			}
+			if self.maximum_security && self.enhanced {
+				return Err(PasswordError::MaximumSecurityAndEnhanced);
+			}
 			let mut buf = [0u8; 36];
 			{
 				let mut ptr = &mut buf[..];
-				ptr.write_u8(flag.unwrap_or(false) as u8).unwrap();
-				ptr.write_u8(master_password as u8).unwrap();
-				ptr.write_all(&password).unwrap();
-				ptr.write_u16::<BigEndian>(master_password_id.unwrap_or(0))
+				ptr.write_u8((self.maximum_security || self.enhanced) as u8)
+					.unwrap();
+				ptr.write_u8(self.master as u8).unwrap();
+				ptr.write_all(&self.password).unwrap();
+				ptr.write_u16::<BigEndian>(self.identifier.unwrap_or(0))
 					.unwrap();
 			}
-			AtaSecurityPassword(buf)
+			Ok(AtaSecurityPassword(buf))
+		}
+	}
+
+	impl AtaSecurityPassword {
+		pub fn builder(password: [u8; 32]) -> AtaSecurityPasswordBuilder {
+			AtaSecurityPasswordBuilder {
+				password,
+				master: false,
+				maximum_security: false,
+				enhanced: false,
+				identifier: None,
+			}
 		}
 	}
 
-	impl Into<[u8; 36]> for AtaSecurityPassword {
-		fn into(self) -> [u8; 36] {
-			self.0
+	// Decoding the fields is only needed to check the encoding
+	#[allow(dead_code)]
+	impl AtaSecurityPassword {
+		pub fn password(&self) -> [u8; 32] {
+			let mut password = [0u8; 32];
+			password.copy_from_slice(&self.0[2..34]);
+			password
+		}
+
+		pub fn master(&self) -> bool {
+			self.0[1] == 1
+		}
+
+		/// Maximum security for SET PASSWORD, enhanced erase for ERASE UNIT
+		pub fn flag(&self) -> bool {
+			self.0[0] == 1
+		}
+
+		/// The master password identifier, 0 if none was given.
+		pub fn identifier(&self) -> u16 {
+			(&self.0[34..36]).read_u16::<BigEndian>().unwrap()
+		}
+	}
+
+	payload!(AtaSecurityPassword, 36, |buf| {
+		if buf[0] > 1 || buf[1] > 1 {
+			return Err(PayloadError::Invalid("ATA security password flags"));
+		}
+		if buf[1] == 0 && (buf[34] != 0 || buf[35] != 0) {
+			return Err(PayloadError::Invalid(
+				"master password identifier on a user password",
+			));
+		}
+		Ok(())
+	});
+
+	#[cfg(test)]
+	mod tests {
+		use super::super::Payload;
+		use super::*;
+
+		/// Deterministic xorshift, so a failure can be reproduced
+		struct Rng(u64);
+
+		impl Rng {
+			fn next(&mut self) -> u64 {
+				self.0 ^= self.0 << 13;
+				self.0 ^= self.0 >> 7;
+				self.0 ^= self.0 << 17;
+				self.0
+			}
+		}
+
+		#[test]
+		fn password_round_trip() {
+			let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+			let mut built = 0;
+			for _ in 0..100_000 {
+				let bits = rng.next();
+				let master = bits & 1 != 0;
+				let maximum_security = bits & 2 != 0;
+				let enhanced = bits & 4 != 0;
+				let identifier = if bits & 8 != 0 {
+					Some((bits >> 16) as u16)
+				} else {
+					None
+				};
+				let mut password = [0u8; 32];
+				for chunk in password.chunks_mut(8) {
+					let word = rng.next();
+					for (i, byte) in chunk.iter_mut().enumerate() {
+						*byte = (word >> (i * 8)) as u8;
+					}
+				}
+
+				let mut builder = AtaSecurityPassword::builder(password);
+				if master {
+					builder = builder.master();
+				}
+				if maximum_security {
+					builder = builder.maximum_security();
+				}
+				if enhanced {
+					builder = builder.enhanced();
+				}
+				if let Some(identifier) = identifier {
+					builder = builder.identifier(identifier);
+				}
+				let encoded = match builder.build() {
+					Err(PasswordError::IdentifierOnUserPassword) => {
+						assert!(identifier.is_some() && !master);
+						continue;
+					}
+					Err(PasswordError::SecurityLevelOnMasterPassword) => {
+						assert!(maximum_security && master);
+						continue;
+					}
+					Err(PasswordError::MaximumSecurityAndEnhanced) => {
+						assert!(maximum_security && enhanced);
+						continue;
+					}
+					Ok(encoded) => encoded,
+				};
+				built += 1;
+
+				let wire = encoded.to_wire();
+				assert_eq!(wire.len(), 36);
+				let decoded = AtaSecurityPassword::from_wire(wire).unwrap();
+				assert_eq!(decoded.to_wire(), wire);
+				assert_eq!(decoded.password(), password);
+				assert_eq!(decoded.master(), master);
+				assert_eq!(decoded.flag(), maximum_security || enhanced);
+				assert_eq!(decoded.identifier(), identifier.unwrap_or(0));
+			}
+			assert!(built > 0);
+		}
+
+		#[test]
+		fn erase_time() {
+			let time = |minutes, longer| Some(EraseTime { minutes, longer });
+			assert_eq!(EraseTime::decode(0), None);
+			assert_eq!(EraseTime::decode(1), time(2, false));
+			assert_eq!(EraseTime::decode(0xfe), time(508, false));
+			assert_eq!(EraseTime::decode(0xff), time(508, true));
+			// Bits 14:8 are reserved in the normal format
+			assert_eq!(EraseTime::decode(0x0102), time(4, false));
+			assert_eq!(EraseTime::decode(0x0100), None);
+			assert_eq!(EraseTime::decode(0x8000), None);
+			assert_eq!(EraseTime::decode(0x8001), time(2, false));
+			assert_eq!(EraseTime::decode(0x80ff), time(510, false));
+			assert_eq!(EraseTime::decode(0xfffe), time(65532, false));
+			assert_eq!(EraseTime::decode(0x8000 | 0x7fff), time(65532, true));
+			assert_eq!(
+				format!("{}", EraseTime::decode(0xff).unwrap()),
+				">508 minutes"
+			);
+			assert_eq!(format!("{}", EraseTime::decode(1).unwrap()), "2 minutes");
+		}
+
+		#[test]
+		fn ata_security_identify() {
+			let buf = [
+				0x00, 0x0e, 0x00, 0x02, 0x80, 0x10, 0xff, 0xfe, 0x01, 0x2b, 0, 0, 0, 0, 0, 0,
+			];
+			let security = AtaSecurityIdentify::from_wire(&buf).unwrap();
+			assert_eq!(security.security_erase_time(), 0x0002);
+			assert_eq!(security.enhanced_security_erase_time(), 0x8010);
+			assert_eq!(
+				security.erase_time(false),
+				Some(EraseTime {
+					minutes: 4,
+					longer: false
+				})
+			);
+			assert_eq!(
+				security.erase_time(true),
+				Some(EraseTime {
+					minutes: 32,
+					longer: false
+				})
+			);
+			assert_eq!(security.master_password_identifier(), 0xfffe);
+			assert!(security.master_password_identifier_supported());
+			assert!(security.maxset());
+			assert!(security.s_suprt());
+			assert!(security.s_enabld());
+			assert!(!security.locked());
+			assert!(security.frozen());
+			assert!(!security.pwncntex());
+			assert!(security.en_er_sup());
+
+			let mut bad = buf;
+			bad[1] = 0x0c;
+			assert!(AtaSecurityIdentify::from_wire(&bad).is_err());
+			assert!(AtaSecurityIdentify::from_wire(&buf[..15]).is_err());
+		}
+
+		#[test]
+		fn security_compliance() {
+			let mut fips = vec![0u8; 400];
+			fips[1] = 0x01;
+			fips[6] = 0x01;
+			fips[7] = 0x88;
+			fips[8] = b'3';
+			fips[9] = b'2';
+			fips[16..21].copy_from_slice(b"HW1.0");
+			fips[144..148].copy_from_slice(b"FW 3");
+			for b in &mut fips[148..272] {
+				*b = b' ';
+			}
+			fips[272..283].copy_from_slice(b"Example SED");
+			let other = [0x80, 0x00, 0, 0, 0, 0, 0, 4, 1, 2, 3, 4];
+			let mut page = vec![0, 0, 0x01, 0x9c];
+			page.extend_from_slice(&fips);
+			page.extend_from_slice(&other);
+
+			let expected = vec![
+				ComplianceDescriptor::Fips140 {
+					related_standard: b'3',
+					overall_security_level: b'2',
+					hardware_version: "HW1.0".to_owned(),
+					version: "FW 3".to_owned(),
+					module_name: "Example SED".to_owned(),
+				},
+				ComplianceDescriptor::Other(0x8000),
+			];
+			assert_eq!(parse_security_compliance(&page), expected);
+			// A page cut off in the second descriptor keeps the first
+			assert_eq!(parse_security_compliance(&page[..406]), &expected[..1]);
+			assert_eq!(parse_security_compliance(&page[..3]), vec![]);
+		}
+
+		#[test]
+		fn password_rejects_invalid() {
+			let mut wire = [0u8; 36];
+			assert!(AtaSecurityPassword::from_wire(&wire).is_ok());
+			assert!(AtaSecurityPassword::from_wire(&wire[..35]).is_err());
+			wire[0] = 2;
+			assert!(AtaSecurityPassword::from_wire(&wire).is_err());
+			wire[0] = 0;
+			wire[35] = 1;
+			assert!(AtaSecurityPassword::from_wire(&wire).is_err());
+			wire[1] = 1;
+			assert!(AtaSecurityPassword::from_wire(&wire).is_ok());
 		}
 	}
 }
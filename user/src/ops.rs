@@ -9,14 +9,51 @@
  * any later version.
  */
 
+use device;
+use libc;
+use nix::errno::Errno;
 use nix::Error as NixError;
-use nvme;
-use std::os::unix::io::RawFd;
+use nvme::{self, Payload};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tcg;
+use uring;
 
 #[derive(Debug)]
 pub enum Error {
 	Io(NixError),
 	Nvme(nvme::StatusCode),
+	Device(io::Error),
+	Tcg(tcg::Status),
+}
+
+impl Error {
+	/// Whether the drive refused an ATA security command for the password,
+	/// e.g. SECURITY UNLOCK with a wrong one. ACS aborts the command, which
+	/// reaches NVMe as Access Denied. Other errors, such as an invalid field
+	/// or a command sequence error when frozen, say nothing about the
+	/// password.
+	pub fn is_authentication_failure(&self) -> bool {
+		match *self {
+			Error::Nvme(nvme::StatusCode::AccessDenied) => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the kernel refused the command, for lack of privileges or
+	/// because a security module denied it.
+	pub fn is_access_denied(&self) -> bool {
+		match *self {
+			Error::Io(NixError::Sys(Errno::EACCES)) | Error::Io(NixError::Sys(Errno::EPERM)) => {
+				true
+			}
+			Error::Device(ref e) => e.kind() == io::ErrorKind::PermissionDenied,
+			_ => false,
+		}
+	}
 }
 
 impl From<NixError> for Error {
@@ -25,16 +62,40 @@ impl From<NixError> for Error {
 	}
 }
 
+impl From<io::Error> for Error {
+	fn from(err: io::Error) -> Error {
+		Error::Device(err)
+	}
+}
+
 impl From<nvme::StatusCode> for Error {
 	fn from(err: nvme::StatusCode) -> Error {
 		Error::Nvme(err)
 	}
 }
 
+impl From<nvme::security::PasswordError> for Error {
+	fn from(err: nvme::security::PasswordError) -> Error {
+		Error::Device(io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+	}
+}
+
+impl From<nvme::PayloadError> for Error {
+	fn from(err: nvme::PayloadError) -> Error {
+		Error::Device(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+	}
+}
+
+impl From<tcg::Status> for Error {
+	fn from(err: tcg::Status) -> Error {
+		Error::Tcg(err)
+	}
+}
+
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 pub fn identify_controller(fd: RawFd) -> Result<nvme::identify::IdentifyController> {
-	let mut buf = [0u8; 4096];
+	let mut buf = [0u8; nvme::identify::IdentifyController::SIZE];
 	unsafe {
 		try!(nvme_ioctl_admin_cmd(
 			fd,
@@ -48,9 +109,190 @@ pub fn identify_controller(fd: RawFd) -> Result<nvme::identify::IdentifyControll
 			}
 		))
 	}
-	return Ok(nvme::identify::IdentifyController::from(buf));
+	Ok(try!(nvme::identify::IdentifyController::from_wire(&buf)))
+}
+
+pub fn identify_namespace(fd: RawFd, nsid: u32) -> Result<nvme::identify::IdentifyNamespace> {
+	let mut buf = [0u8; nvme::identify::IdentifyNamespace::SIZE];
+	unsafe {
+		try!(nvme_ioctl_admin_cmd(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminIdentify as u8,
+				addr: &mut buf as *mut _ as usize as u64,
+				data_len: 4096,
+				nsid,
+				cdw10: 0,
+				..Default::default()
+			}
+		))
+	}
+	Ok(try!(nvme::identify::IdentifyNamespace::from_wire(&buf)))
+}
+
+/// Read the controller-wide log page `page` into `buf`, whose length must be
+/// a multiple of 4.
+pub fn get_log_page(fd: RawFd, page: nvme::log::Page, buf: &mut [u8]) -> Result<()> {
+	get_log_page_at(fd, page, 0, 0, buf)
+}
+
+/// Read part of the log page `page`, starting at byte `offset`, with the
+/// Log Specific Field `lsp`. Offsets need the extended data log page
+/// attribute. Reads longer than the controller transfers at once are split.
+pub fn get_log_page_at(
+	fd: RawFd,
+	page: nvme::log::Page,
+	lsp: u8,
+	offset: u64,
+	buf: &mut [u8],
+) -> Result<()> {
+	assert!(buf.len() % 4 == 0 && buf.len() > 0 && buf.len() <= 0x4_0000);
+	if buf.len() > SMALL_TRANSFER {
+		if let Some(max) = try!(max_transfer(fd)) {
+			if buf.len() > max {
+				for (i, chunk) in buf.chunks_mut(max).enumerate() {
+					try!(get_log_page_at(
+						fd,
+						page,
+						lsp,
+						offset + (i * max) as u64,
+						chunk
+					));
+				}
+				return Ok(());
+			}
+		}
+	}
+	let numd = (buf.len() / 4 - 1) as u32;
+	unsafe {
+		nvme_ioctl_admin_cmd(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminGetLogPage as u8,
+				addr: buf.as_mut_ptr() as usize as u64,
+				data_len: buf.len() as u32,
+				nsid: 0xffff_ffff,
+				cdw10: (numd & 0xffff) << 16 | (lsp as u32 & 0x7f) << 8 | page as u32,
+				cdw11: numd >> 16,
+				cdw12: offset as u32,
+				cdw13: (offset >> 32) as u32,
+				..Default::default()
+			},
+		)
+	}
+}
+
+pub fn firmware_slot_log(fd: RawFd) -> Result<nvme::log::FirmwareSlot> {
+	let mut buf = [0u8; nvme::log::FirmwareSlot::SIZE];
+	try!(get_log_page(fd, nvme::log::Page::FirmwareSlot, &mut buf));
+	Ok(try!(nvme::log::FirmwareSlot::from_wire(&buf)))
+}
+
+/// The current value of `feature`, Dword 0 of the Get Features completion.
+pub fn get_features(fd: RawFd, feature: nvme::Feature) -> Result<u32> {
+	unsafe {
+		nvme_ioctl_admin_cmd_result(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminGetFeatures as u8,
+				cdw10: feature as u32,
+				..Default::default()
+			},
+		)
+	}
 }
 
+/// Set `feature` to `value` (Dword 11) until the next controller reset.
+pub fn set_features(fd: RawFd, feature: nvme::Feature, value: u32) -> Result<()> {
+	unsafe {
+		nvme_ioctl_admin_cmd(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminSetFeatures as u8,
+				cdw10: feature as u32,
+				cdw11: value,
+				..Default::default()
+			},
+		)
+	}
+}
+
+pub fn self_test_log(fd: RawFd) -> Result<nvme::log::SelfTestLog> {
+	let mut buf = [0u8; nvme::log::SelfTestLog::SIZE];
+	try!(get_log_page(fd, nvme::log::Page::SelfTest, &mut buf));
+	Ok(try!(nvme::log::SelfTestLog::from_wire(&buf)))
+}
+
+/// Ask the controller to abort command `cid` on submission queue `sqid`, 0
+/// being the admin queue. Returns whether the command was aborted, the
+/// controller may also let it complete.
+pub fn abort(fd: RawFd, cid: u16, sqid: u16) -> Result<bool> {
+	let result = try!(unsafe {
+		nvme_ioctl_admin_cmd_result(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminAbort as u8,
+				cdw10: (cid as u32) << 16 | sqid as u32,
+				..Default::default()
+			},
+		)
+	});
+	Ok(result & 1 == 0)
+}
+
+/// Start or abort a device self-test of the controller and all its
+/// namespaces. The test runs in the background.
+pub fn device_self_test(fd: RawFd, code: nvme::SelfTest) -> Result<()> {
+	unsafe {
+		nvme_ioctl_admin_cmd(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminDeviceSelfTest as u8,
+				nsid: 0xffff_ffff,
+				cdw10: code as u32,
+				..Default::default()
+			},
+		)
+	}
+}
+
+pub fn sanitize_status_log(fd: RawFd) -> Result<nvme::log::SanitizeStatus> {
+	let mut buf = [0u8; nvme::log::SanitizeStatus::SIZE];
+	try!(get_log_page(fd, nvme::log::Page::SanitizeStatus, &mut buf));
+	Ok(try!(nvme::log::SanitizeStatus::from_wire(&buf)))
+}
+
+/// Start a sanitize operation. It applies to the whole NVM subsystem and
+/// continues in the background after the command completes.
+pub fn sanitize(fd: RawFd, action: nvme::SanitizeAction) -> Result<()> {
+	unsafe {
+		nvme_ioctl_admin_cmd(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminSanitize as u8,
+				cdw10: action as u32,
+				..Default::default()
+			},
+		)
+	}
+}
+
+pub fn format_nvm(fd: RawFd, nsid: u32, lba_format: u8, ses: nvme::SecureErase) -> Result<()> {
+	unsafe {
+		nvme_ioctl_admin_cmd(
+			fd,
+			NvmeAdminCmd {
+				opcode: nvme::Opcode::AdminFormatNvm as u8,
+				nsid,
+				cdw10: (lba_format & 0x0f) as u32 | (ses as u32) << 9,
+				..Default::default()
+			},
+		)
+	}
+}
+
+/// SECURITY SEND of `data`. NVMe gives the transfer length in CDW11 in
+/// bytes, there is no INC_512 as in SCSI SECURITY PROTOCOL OUT.
 pub fn security_send(fd: RawFd, secp: u8, spsp: u16, nssf: u8, data: Option<&[u8]>) -> Result<()> {
 	assert!(data.map(|d| d.len()).unwrap_or(0) <= ::std::u32::MAX as usize);
 	unsafe {
@@ -58,7 +300,7 @@ pub fn security_send(fd: RawFd, secp: u8, spsp: u16, nssf: u8, data: Option<&[u8
 			fd,
 			NvmeAdminCmd {
 				opcode: nvme::Opcode::AdminSecuritySend as u8,
-				nsid: try!(nvme_ioctl_id(fd)),
+				nsid: try!(security_nsid(fd)),
 				addr: data.map(|d| d.as_ptr() as usize as u64).unwrap_or(0),
 				data_len: data.map(|d| d.len() as u32).unwrap_or(0),
 				cdw11: data.map(|d| d.len() as u32).unwrap_or(0),
@@ -69,6 +311,46 @@ pub fn security_send(fd: RawFd, secp: u8, spsp: u16, nssf: u8, data: Option<&[u8
 	}
 }
 
+/// The largest transfer the controller open as `fd` accepts, `None` if it
+/// does not report a limit.
+pub fn max_transfer(fd: RawFd) -> Result<Option<usize>> {
+	identify_controller(fd).map(|i| i.max_transfer())
+}
+
+/// The longest response `security_receive_sized` and the TCG receive path
+/// allocate for, whatever length the drive reports, the same bound as log
+/// page reads.
+pub const MAX_SECURITY_RECEIVE: usize = 0x4_0000;
+
+/// Receive a response whose length is only known from its first `header`
+/// bytes, as given by `length`. The length comes from the drive and is
+/// capped at `MAX_SECURITY_RECEIVE`. The security protocols have no offsets
+/// to receive a response in parts, so there is no chunking: a response
+/// longer than the cap or than the controller can transfer at once is cut
+/// off there; callers compare `length` of the result to its size.
+pub fn security_receive_sized<F: Fn(&[u8]) -> usize>(
+	fd: RawFd,
+	secp: u8,
+	spsp: u16,
+	nssf: u8,
+	header: usize,
+	length: F,
+) -> Result<Vec<u8>> {
+	let mut buf = vec![0u8; header];
+	try!(security_receive(fd, secp, spsp, nssf, &mut buf));
+	let len = length(&buf);
+	if len > buf.len() {
+		let limit = try!(max_transfer(fd)).unwrap_or(MAX_SECURITY_RECEIVE);
+		buf.resize(
+			::std::cmp::min(len, ::std::cmp::min(limit, MAX_SECURITY_RECEIVE)),
+			0,
+		);
+		try!(security_receive(fd, secp, spsp, nssf, &mut buf));
+	}
+	Ok(buf)
+}
+
+/// SECURITY RECEIVE into `data`, with the allocation length in bytes.
 pub fn security_receive(fd: RawFd, secp: u8, spsp: u16, nssf: u8, data: &mut [u8]) -> Result<()> {
 	assert!(data.len() <= ::std::u32::MAX as usize);
 	unsafe {
@@ -76,7 +358,7 @@ pub fn security_receive(fd: RawFd, secp: u8, spsp: u16, nssf: u8, data: &mut [u8
 			fd,
 			NvmeAdminCmd {
 				opcode: nvme::Opcode::AdminSecurityReceive as u8,
-				nsid: try!(nvme_ioctl_id(fd)),
+				nsid: try!(security_nsid(fd)),
 				addr: data.as_mut_ptr() as usize as u64,
 				data_len: data.len() as u32,
 				cdw11: data.len() as u32,
@@ -87,12 +369,216 @@ pub fn security_receive(fd: RawFd, secp: u8, spsp: u16, nssf: u8, data: &mut [u8
 	}
 }
 
-unsafe fn nvme_ioctl_admin_cmd(fd: RawFd, mut cmd: NvmeAdminCmd) -> Result<()> {
-	let ret = raw_nvme_ioctl_admin_cmd(fd, &mut cmd)?;
+/// IDENTIFY CONTROLLER for several drives at once, see `admin_cmds`.
+pub fn identify_controllers(fds: &[RawFd]) -> Vec<Result<nvme::identify::IdentifyController>> {
+	let mut bufs: Vec<_> = fds
+		.iter()
+		.map(|_| [0u8; nvme::identify::IdentifyController::SIZE])
+		.collect();
+	let cmds = fds
+		.iter()
+		.zip(bufs.iter_mut())
+		.map(|(&fd, buf)| {
+			Ok((
+				fd,
+				NvmeAdminCmd {
+					opcode: nvme::Opcode::AdminIdentify as u8,
+					addr: buf as *mut _ as usize as u64,
+					data_len: 4096,
+					nsid: 0,
+					cdw10: 1,
+					..Default::default()
+				},
+			))
+		})
+		.collect();
+	let results = unsafe { admin_cmds(cmds) };
+	results
+		.into_iter()
+		.zip(bufs.iter())
+		.map(|(result, buf)| {
+			try!(result);
+			Ok(try!(nvme::identify::IdentifyController::from_wire(buf)))
+		})
+		.collect()
+}
+
+/// SECURITY RECEIVE for several drives at once, each into the buffer given
+/// with it, see `admin_cmds`. Returns the filled buffers.
+pub fn security_receives(
+	secp: u8,
+	spsp: u16,
+	nssf: u8,
+	mut requests: Vec<(RawFd, Vec<u8>)>,
+) -> Vec<Result<Vec<u8>>> {
+	let cmds = requests
+		.iter_mut()
+		.map(|&mut (fd, ref mut data)| {
+			assert!(data.len() <= ::std::u32::MAX as usize);
+			Ok((
+				fd,
+				NvmeAdminCmd {
+					opcode: nvme::Opcode::AdminSecurityReceive as u8,
+					nsid: try!(security_nsid(fd)),
+					addr: data.as_mut_ptr() as usize as u64,
+					data_len: data.len() as u32,
+					cdw11: data.len() as u32,
+					cdw10: (secp as u32) << 24 | (spsp as u32) << 8 | (nssf as u32),
+					..Default::default()
+				},
+			))
+		})
+		.collect();
+	let results = unsafe { admin_cmds(cmds) };
+	results
+		.into_iter()
+		.zip(requests)
+		.map(|(result, (_, data))| result.map(|_| data))
+		.collect()
+}
+
+/// Send admin commands to several drives. Through io_uring they are all
+/// submitted on one ring and run at the same time, with the ioctl they are
+/// sent one after another. Returns Dword 0 of each completion.
+unsafe fn admin_cmds(cmds: Vec<Result<(RawFd, NvmeAdminCmd)>>) -> Vec<Result<u32>> {
+	if !URING.load(Ordering::SeqCst) {
+		return cmds
+			.into_iter()
+			.map(|cmd| cmd.and_then(|(fd, cmd)| nvme_ioctl_admin_cmd_result(fd, cmd)))
+			.collect();
+	}
+
+	// Resolve the controllers first, they have to stay open until the
+	// commands complete
+	let mut ready = vec![];
+	let mut results: Vec<Option<Result<u32>>> = vec![];
+	for cmd in cmds {
+		let ctrl = cmd.and_then(|(fd, cmd)| {
+			try!(check_transfer(fd, cmd.data_len as usize));
+			Ok((fd, try!(uring_controller(fd)), uring_cmd(&cmd)))
+		});
+		match ctrl {
+			Err(e) => results.push(Some(Err(e))),
+			Ok(ctrl) => {
+				ready.push(ctrl);
+				results.push(None);
+			}
+		}
+	}
+	let submitted = uring::admin_cmds(
+		&ready
+			.iter()
+			.map(|&(fd, ref ctrl, ref cmd)| (ctrl.as_ref().map_or(fd, |c| c.as_raw_fd()), cmd))
+			.collect::<Vec<_>>(),
+	);
+	let mut completions = match submitted {
+		Err(e) => ready.iter().map(|_| Err(e)).collect(),
+		Ok(completions) => completions,
+	}
+	.into_iter();
+	results
+		.into_iter()
+		.map(|r| {
+			r.unwrap_or_else(|| match try!(completions.next().unwrap()) {
+				(0, result) => Ok(result),
+				(ret, _) => Err(Error::Nvme(nvme::StatusCode::from(ret as u16))),
+			})
+		})
+		.collect()
+}
+
+static URING: AtomicBool = AtomicBool::new(false);
+
+/// Send admin commands through io_uring passthrough instead of the admin
+/// command ioctl.
+pub fn use_uring() {
+	URING.store(true, Ordering::SeqCst);
+}
+
+pub fn uses_uring() -> bool {
+	URING.load(Ordering::SeqCst)
+}
+
+unsafe fn nvme_ioctl_admin_cmd(fd: RawFd, cmd: NvmeAdminCmd) -> Result<()> {
+	nvme_ioctl_admin_cmd_result(fd, cmd).map(|_| ())
+}
+
+/// Transfers up to this size fit any controller: the smallest maximum
+/// transfer size a controller can report is two memory pages.
+const SMALL_TRANSFER: usize = 4096;
+
+/// Check a transfer of `len` bytes against the maximum transfer size of the
+/// controller, which the kernel would reject with a bare `EINVAL`.
+fn check_transfer(fd: RawFd, len: usize) -> Result<()> {
+	if len <= SMALL_TRANSFER {
+		return Ok(());
+	}
+	match try!(max_transfer(fd)) {
+		Some(max) if len > max => Err(Error::Device(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!(
+				"transfer of {} bytes exceeds the controller's maximum transfer size of {} bytes",
+				len, max
+			),
+		))),
+		_ => Ok(()),
+	}
+}
+
+/// Send an admin command and return Dword 0 of its completion.
+unsafe fn nvme_ioctl_admin_cmd_result(fd: RawFd, mut cmd: NvmeAdminCmd) -> Result<u32> {
+	try!(check_transfer(fd, cmd.data_len as usize));
+	let (ret, result) = if URING.load(Ordering::SeqCst) {
+		match try!(uring_controller(fd)) {
+			Some(ctrl) => uring::admin_cmd(ctrl.as_raw_fd(), &uring_cmd(&cmd))?,
+			None => uring::admin_cmd(fd, &uring_cmd(&cmd))?,
+		}
+	} else {
+		(raw_nvme_ioctl_admin_cmd(fd, &mut cmd)?, cmd.result)
+	};
 	if ret != 0 {
 		Err(Error::Nvme(nvme::StatusCode::from(ret as u16)))
 	} else {
-		Ok(())
+		Ok(result)
+	}
+}
+
+/// Admin commands through io_uring are only accepted on the controller
+/// character device, so a namespace opened as `fd` has its controller opened
+/// with the same access mode. `None` when `fd` is a controller already.
+fn uring_controller(fd: RawFd) -> Result<Option<File>> {
+	let path = try!(fs::read_link(format!("/proc/self/fd/{}", fd)));
+	let ctrl = try!(device::controller(&path));
+	if ctrl == path {
+		return Ok(None);
+	}
+	let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+	let write = flags >= 0 && flags & libc::O_ACCMODE != libc::O_RDONLY;
+	Ok(Some(try!(OpenOptions::new()
+		.read(true)
+		.write(write)
+		.open(ctrl))))
+}
+
+fn uring_cmd(cmd: &NvmeAdminCmd) -> uring::UringCmd {
+	uring::UringCmd {
+		opcode: cmd.opcode,
+		flags: cmd.flags,
+		nsid: cmd.nsid,
+		cdw2: cmd.cdw2,
+		cdw3: cmd.cdw3,
+		metadata: cmd.metadata,
+		addr: cmd.addr,
+		metadata_len: cmd.metadata_len,
+		data_len: cmd.data_len,
+		cdw10: cmd.cdw10,
+		cdw11: cmd.cdw11,
+		cdw12: cmd.cdw12,
+		cdw13: cmd.cdw13,
+		cdw14: cmd.cdw14,
+		cdw15: cmd.cdw15,
+		timeout_ms: cmd.timeout_ms,
+		..Default::default()
 	}
 }
 
@@ -105,11 +591,50 @@ pub fn ioctl_blkrrpart(fd: RawFd) -> Result<()> {
 	}
 }
 
+/// Write back and drop the cached blocks of a block device.
+pub fn ioctl_blkflsbuf(fd: RawFd) -> Result<()> {
+	unsafe { raw_ioctl_blkflsbuf(fd) }?;
+	Ok(())
+}
+
+/// The namespace ID for security commands. Controller character devices
+/// have no namespace, commands sent through them use NSID 0.
+fn security_nsid(fd: RawFd) -> Result<u32> {
+	match nvme_ioctl_id(fd) {
+		Err(Error::Io(NixError::Sys(Errno::ENOTTY))) => Ok(0),
+		r => r,
+	}
+}
+
 pub fn nvme_ioctl_id(fd: RawFd) -> Result<u32> {
 	let ret = unsafe { raw_nvme_ioctl_id(fd) }?;
 	Ok(ret as u32)
 }
 
+pub fn nvme_ioctl_reset(fd: RawFd) -> Result<()> {
+	unsafe { raw_nvme_ioctl_reset(fd) }?;
+	Ok(())
+}
+
+/// The ioctl requests sent by this module, for the seccomp filter of the
+/// daemon.
+pub fn ioctl_requests() -> [u32; 5] {
+	// _IOC() of the generic Linux ioctl.h
+	fn request(dir: u32, ty: u8, nr: u8, size: usize) -> u32 {
+		dir << 30 | (size as u32) << 16 | (ty as u32) << 8 | nr as u32
+	}
+	const NONE: u32 = 0;
+	const READ_WRITE: u32 = 3;
+
+	[
+		request(NONE, b'N', 0x40, 0),
+		request(READ_WRITE, b'N', 0x41, mem::size_of::<NvmeAdminCmd>()),
+		request(NONE, b'N', 0x44, 0),
+		request(NONE, 0x12, 95, 0),
+		request(NONE, 0x12, 97, 0),
+	]
+}
+
 use self::ioctl::*;
 mod ioctl {
 	#[derive(Default)]
@@ -137,6 +662,8 @@ mod ioctl {
 
 	ioctl_none!(raw_nvme_ioctl_id, b'N', 0x40);
 	ioctl_readwrite!(raw_nvme_ioctl_admin_cmd, b'N', 0x41, NvmeAdminCmd);
+	ioctl_none!(raw_nvme_ioctl_reset, b'N', 0x44);
 
 	ioctl_none!(raw_ioctl_blkrrpart, 0x12, 95);
+	ioctl_none!(raw_ioctl_blkflsbuf, 0x12, 97);
 }
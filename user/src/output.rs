@@ -0,0 +1,84 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Command output.
+//!
+//! Data a user may want to process further (query results, status reports)
+//! is written to stdout through `Output`. Progress messages and errors go to
+//! stderr with `eprintln!`.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use nix::unistd::isatty;
+use serde::Serialize;
+
+use report::{Format, Structured};
+
+pub struct Output {
+	pub format: Format,
+	pub color: bool,
+}
+
+impl Output {
+	pub fn new(format: Format, no_color: bool) -> Output {
+		Output {
+			format,
+			color: !no_color && isatty(1).unwrap_or(false),
+		}
+	}
+
+	pub fn is_human(&self) -> bool {
+		self.format == Format::Human
+	}
+
+	/// Write data to stdout, for use with `write!`/`writeln!`.
+	///
+	/// Errors such as a closed pipe are ignored, there is nobody left to read
+	/// the data anyway.
+	pub fn write_fmt(&self, args: fmt::Arguments) {
+		let stdout = io::stdout();
+		let mut stdout = stdout.lock();
+		let _ = stdout.write_fmt(args).and_then(|_| stdout.flush());
+	}
+
+	/// Write raw bytes to stdout.
+	pub fn write_bytes(&self, buf: &[u8]) -> io::Result<()> {
+		let stdout = io::stdout();
+		let mut stdout = stdout.lock();
+		stdout.write_all(buf).and_then(|_| stdout.flush())
+	}
+
+	/// Write `value` to stdout in the selected machine-readable format, or
+	/// as JSON for values without a human-readable form.
+	pub fn structured<T: Serialize>(&self, value: &T) {
+		let format = match self.format {
+			Format::Structured(format) => format,
+			Format::Human => Structured::Json,
+		};
+		match format.serialize(value) {
+			Err(e) => eprintln!("Unable to format output: {}", e),
+			Ok(s) => write!(self, "{}", s),
+		}
+	}
+}
+
+/// Format `buf` as a hex dump with 16 bytes per line.
+pub fn hexdump(buf: &[u8]) -> String {
+	let mut out = String::new();
+	for (i, line) in buf.chunks(16).enumerate() {
+		out.push_str(&format!("{:04x}:", i * 16));
+		for b in line {
+			out.push_str(&format!(" {:02x}", b));
+		}
+		out.push('\n');
+	}
+	out
+}
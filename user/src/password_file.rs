@@ -0,0 +1,164 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Password files with metadata.
+//!
+//! Besides arbitrary bytes that are hashed into a password, `--password-file`
+//! accepts a TOML file written by `gen-password-file`. Such a file contains
+//! the final 32-byte password and is bound to a single drive:
+//!
+//! ```toml
+//! format = "nvme-ata-security-password-v1"
+//! serial = "S3EWNX0K123456"
+//! slot = "user"
+//! security = "high"
+//! password = "<64 hex digits>"
+//! ```
+
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use std::str;
+
+use toml;
+
+use config::SecurityLevel;
+
+pub const FORMAT: &'static str = "nvme-ata-security-password-v1";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Slot {
+	User,
+	Master,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordFile {
+	pub format: String,
+	pub serial: String,
+	pub slot: Slot,
+	pub security: Option<SecurityLevel>,
+	pub password: String,
+}
+
+/// Decode a string of hex digits.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+	if hex.len() % 2 != 0 {
+		return None;
+	}
+	hex.as_bytes()
+		.chunks(2)
+		.map(|pair| {
+			str::from_utf8(pair)
+				.ok()
+				.and_then(|s| u8::from_str_radix(s, 16).ok())
+		})
+		.collect()
+}
+
+fn invalid_data(msg: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl PasswordFile {
+	pub fn new(
+		serial: String,
+		slot: Slot,
+		security: Option<SecurityLevel>,
+		password: &[u8; 32],
+	) -> PasswordFile {
+		PasswordFile {
+			format: FORMAT.to_owned(),
+			serial,
+			slot,
+			security,
+			password: password.iter().map(|b| format!("{:02x}", b)).collect(),
+		}
+	}
+
+	/// Parse `buf` as a password file. Returns `None` if `buf` is not a
+	/// structured password file, in which case it is a plain password.
+	pub fn parse(buf: &[u8]) -> io::Result<Option<PasswordFile>> {
+		let text = match str::from_utf8(buf) {
+			Ok(text) => text,
+			Err(_) => return Ok(None),
+		};
+		match text.parse::<toml::Value>() {
+			Ok(ref value) if value.as_table().map_or(false, |t| t.contains_key("format")) => {}
+			_ => return Ok(None),
+		}
+		let file: PasswordFile =
+			try!(toml::from_str(text)
+				.map_err(|e| invalid_data(format!("invalid password file: {}", e))));
+		if file.format != FORMAT {
+			return Err(invalid_data(format!(
+				"unsupported password file format {}",
+				file.format
+			)));
+		}
+		try!(file.password());
+		Ok(Some(file))
+	}
+
+	/// Load `path` if it is a structured password file.
+	pub fn load(path: &str) -> io::Result<Option<PasswordFile>> {
+		let mut buf = vec![];
+		try!(try!(File::open(path)).read_to_end(&mut buf));
+		PasswordFile::parse(&buf)
+	}
+
+	/// Write the password file to `path`, readable only by the owner. An
+	/// existing file is never overwritten. Missing directories are created,
+	/// accessible only by the owner.
+	pub fn save(&self, path: &str) -> io::Result<()> {
+		let contents = try!(toml::to_string(self).map_err(|e| invalid_data(e.to_string())));
+		if let Some(dir) = Path::new(path).parent() {
+			if !dir.as_os_str().is_empty() {
+				try!(DirBuilder::new().recursive(true).mode(0o700).create(dir));
+			}
+		}
+		let mut f = try!(OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(0o600)
+			.open(path));
+		f.write_all(contents.as_bytes())
+	}
+
+	pub fn password(&self) -> io::Result<[u8; 32]> {
+		let hex = self.password.as_bytes();
+		if hex.len() != 64 {
+			return Err(invalid_data("password must be 64 hex digits".to_owned()));
+		}
+		let mut out = [0u8; 32];
+		for (out, pair) in out.iter_mut().zip(hex.chunks(2)) {
+			*out = try!(str::from_utf8(pair)
+				.ok()
+				.and_then(|s| u8::from_str_radix(s, 16).ok())
+				.ok_or_else(|| invalid_data("password must be 64 hex digits".to_owned())));
+		}
+		Ok(out)
+	}
+
+	/// Check that this file belongs to the drive with the given serial.
+	pub fn check_serial(&self, serial: &str) -> io::Result<()> {
+		if self.serial == serial {
+			Ok(())
+		} else {
+			Err(invalid_data(format!(
+				"password file is bound to drive {}, not {}",
+				self.serial, serial
+			)))
+		}
+	}
+}
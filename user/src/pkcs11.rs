@@ -0,0 +1,137 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Passwords wrapped with a key on a PKCS#11 token, such as a smartcard.
+//!
+//! `pkcs11-enroll` generates a random password and encrypts it with the
+//! public part of an RSA key on the token:
+//!
+//! ```toml
+//! format = "nvme-ata-security-pkcs11-v1"
+//! module = "/usr/lib/opensc-pkcs11.so"
+//! key_id = "01"
+//! wrapped = "<hex>"
+//! ```
+//!
+//! `--pkcs11=<file>` decrypts it on the token, after asking for the PIN, so
+//! the password itself is stored nowhere. The PKCS#11 calls are left to
+//! `pkcs11-tool` from OpenSC.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::{Command, Stdio};
+
+use toml;
+
+use launcher;
+use password_file;
+use rand;
+
+pub const FORMAT: &'static str = "nvme-ata-security-pkcs11-v1";
+
+const MECHANISM: &'static str = "RSA-PKCS-OAEP";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Enrollment {
+	format: String,
+	/// PKCS#11 module, the default of pkcs11-tool if not set
+	module: Option<String>,
+	key_id: String,
+	wrapped: String,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Run `pkcs11-tool` with `input` on stdin, returning its output. The
+/// terminal stays available for the PIN prompt.
+fn pkcs11_tool(module: Option<&str>, args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+	let mut command = Command::new("pkcs11-tool");
+	if let Some(module) = module {
+		command.arg("--module").arg(module);
+	}
+	command
+		.args(args)
+		.args(&["--input-file", "/dev/stdin", "--output-file", "/dev/stdout"])
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped());
+	let mut child = try!(launcher::spawn(command)
+		.map_err(|e| io::Error::new(e.kind(), format!("Unable to run pkcs11-tool: {}", e))));
+	try!(child.stdin.take().unwrap().write_all(input));
+	let output = try!(child.wait_with_output());
+	if !output.status.success() {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("pkcs11-tool failed with {}", output.status),
+		));
+	}
+	Ok(output.stdout)
+}
+
+/// Generate a password, wrap it with key `key_id` on the token and write it
+/// to `path`.
+pub fn enroll(module: Option<&str>, key_id: &str, path: &str) -> io::Result<()> {
+	let mut password = [0u8; 32];
+	try!(rand::getrandom(&mut password));
+	let wrapped = try!(pkcs11_tool(
+		module,
+		&["--encrypt", "--id", key_id, "--mechanism", MECHANISM],
+		&password
+	));
+	let enrollment = Enrollment {
+		format: FORMAT.to_owned(),
+		module: module.map(str::to_owned),
+		key_id: key_id.to_owned(),
+		wrapped: wrapped.iter().map(|b| format!("{:02x}", b)).collect(),
+	};
+	let toml = try!(
+		toml::to_string(&enrollment).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+	);
+	let mut f = try!(OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.open(path));
+	f.write_all(toml.as_bytes())
+}
+
+/// Unwrap the password in `path` on the token.
+pub fn password(path: &str) -> io::Result<Vec<u8>> {
+	let mut buf = String::new();
+	try!(try!(File::open(path)).read_to_string(&mut buf));
+	let enrollment: Enrollment =
+		try!(toml::from_str(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+	if enrollment.format != FORMAT {
+		return Err(invalid_data("not a PKCS#11 enrollment file"));
+	}
+	let wrapped = try!(password_file::decode_hex(&enrollment.wrapped)
+		.ok_or_else(|| invalid_data("invalid wrapped password")));
+	let password = try!(pkcs11_tool(
+		enrollment.module.as_ref().map(String::as_str),
+		&[
+			"--decrypt",
+			"--login",
+			"--id",
+			enrollment.key_id.as_str(),
+			"--mechanism",
+			MECHANISM,
+		],
+		&wrapped
+	));
+	if password.len() != 32 {
+		return Err(invalid_data(
+			"the token returned a password of the wrong length",
+		));
+	}
+	Ok(password)
+}
@@ -0,0 +1,106 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Bounded worker pool for running an operation on several drives at once.
+
+use std::cmp;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// Drives queried at the same time by read-only commands such as `list`
+pub const QUERY_JOBS: usize = 8;
+
+/// Start at most `jobs` threads running `op` on the devices. Results arrive
+/// as they finish, with the index of the device.
+fn spawn<T, F>(devices: Vec<PathBuf>, jobs: usize, op: F) -> mpsc::Receiver<(usize, PathBuf, T)>
+where
+	T: Send + 'static,
+	F: Fn(&Path) -> T + Send + Sync + 'static,
+{
+	let total = devices.len();
+	let queue = Arc::new(Mutex::new(devices.into_iter().enumerate()));
+	let op = Arc::new(op);
+	let (tx, rx) = mpsc::channel();
+	for _ in 0..cmp::min(jobs, total) {
+		let queue = queue.clone();
+		let op = op.clone();
+		let tx = tx.clone();
+		thread::spawn(move || loop {
+			let (i, dev) = match queue.lock().unwrap().next() {
+				Some(next) => next,
+				None => break,
+			};
+			let result = op(&dev);
+			let _ = tx.send((i, dev, result));
+		});
+	}
+	rx
+}
+
+/// Run `op` on every device, at most `jobs` at a time, and return the
+/// results in the order of `devices`.
+pub fn map<T, F>(devices: Vec<PathBuf>, jobs: usize, op: F) -> Vec<T>
+where
+	T: Send + 'static,
+	F: Fn(&Path) -> T + Send + Sync + 'static,
+{
+	let mut results: Vec<_> = spawn(devices, jobs, op)
+		.into_iter()
+		.map(|(i, _, result)| (i, result))
+		.collect();
+	results.sort_by_key(|&(i, _)| i);
+	results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Run `op` on every device, at most `jobs` at a time. A line is printed when
+/// each device starts and finishes, and a summary at the end. Returns the
+/// exit status of the last failed device, or 0.
+pub fn run<F>(devices: Vec<PathBuf>, jobs: usize, op: F) -> i32
+where
+	F: Fn(&Path) -> i32 + Send + Sync + 'static,
+{
+	let total = devices.len();
+	let rx = spawn(devices, jobs, move |dev| {
+		eprintln!("{}: started", dev.display());
+		let start = Instant::now();
+		let status = op(dev);
+		(status, start.elapsed().as_secs())
+	});
+
+	let mut status = 0;
+	let mut failed = vec![];
+	for (_, dev, (code, secs)) in rx {
+		let result = if code == 0 { "done" } else { "failed" };
+		eprintln!(
+			"{}: {} after {}:{:02}",
+			dev.display(),
+			result,
+			secs / 60,
+			secs % 60
+		);
+		if code != 0 {
+			status = code;
+			failed.push(dev);
+		}
+	}
+	eprintln!(
+		"{} drives: {} succeeded, {} failed",
+		total,
+		total - failed.len(),
+		failed.len()
+	);
+	for dev in failed {
+		eprintln!("Failed: {}", dev.display());
+	}
+	status
+}
@@ -0,0 +1,143 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Power cycle NVMe drives to clear the ATA security frozen state.
+//!
+//! Only removing power clears the frozen state. This is done either by
+//! suspending the whole system to RAM with an RTC wake alarm, by unbinding
+//! the driver and letting runtime power management put the PCI function into
+//! D3cold, which needs platform support for the root port, or by switching
+//! off the power of a hot-plug slot. Disabling the PCIe link is not enough
+//! as the drive stays powered.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAKEALARM: &'static str = "/sys/class/rtc/rtc0/wakealarm";
+/// How long to wait for the device to reach D3cold
+const D3COLD_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn write_attr<P: AsRef<Path>>(path: P, value: &str) -> io::Result<()> {
+	let path = path.as_ref();
+	fs::write(path, value)
+		.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+fn read_attr<P: AsRef<Path>>(path: P) -> io::Result<String> {
+	fs::read_to_string(path).map(|s| s.trim().to_owned())
+}
+
+/// Whether suspend to RAM powers off devices. With `s2idle` as the
+/// `mem_sleep` mode, PCIe devices usually stay powered.
+pub fn deep_sleep() -> bool {
+	read_attr("/sys/power/mem_sleep")
+		.map(|modes| modes.contains("[deep]"))
+		.unwrap_or(false)
+}
+
+/// Suspend to RAM and wake up again after `wake_after` seconds. Returns after
+/// the system has resumed.
+pub fn suspend(wake_after: u32) -> io::Result<()> {
+	// An alarm that is already set has to be cleared first
+	try!(write_attr(WAKEALARM, "0"));
+	try!(write_attr(WAKEALARM, &format!("+{}", wake_after)));
+	write_attr("/sys/power/state", "mem")
+}
+
+/// The PCI device of the NVMe controller `ctrl`, e.g. `nvme0`.
+pub fn pci_device(ctrl: &str) -> io::Result<PathBuf> {
+	let dev = try!(fs::canonicalize(
+		Path::new("/sys/class/nvme").join(ctrl).join("device")
+	));
+	let subsystem = try!(fs::canonicalize(dev.join("subsystem")));
+	if subsystem.file_name().and_then(|n| n.to_str()) != Some("pci") {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("{} is not a PCIe controller", ctrl),
+		));
+	}
+	Ok(dev)
+}
+
+/// Unbind the driver of the PCI device `pci`, wait for it to reach D3cold
+/// and bind the driver again. The driver is rebound even if D3cold isn't
+/// reached.
+pub fn d3cold_cycle(pci: &Path) -> io::Result<()> {
+	let address = try!(pci
+		.file_name()
+		.and_then(|n| n.to_str())
+		.map(ToOwned::to_owned)
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid PCI device")));
+	let driver = try!(fs::canonicalize(pci.join("driver")));
+	try!(write_attr(pci.join("d3cold_allowed"), "1"));
+	try!(write_attr(driver.join("unbind"), &address));
+	let result = write_attr(pci.join("power/control"), "auto").and_then(|()| {
+		let start = Instant::now();
+		loop {
+			if try!(read_attr(pci.join("power_state"))) == "D3cold" {
+				return Ok(());
+			}
+			if start.elapsed() > D3COLD_TIMEOUT {
+				return Err(io::Error::new(
+					io::ErrorKind::TimedOut,
+					"the device did not enter D3cold, the platform may not support it",
+				));
+			}
+			thread::sleep(Duration::from_millis(100));
+		}
+	});
+	let rebind = write_attr(pci.join("power/control"), "on")
+		.and_then(|()| write_attr(driver.join("bind"), &address));
+	result.and(rebind)
+}
+
+/// The hot-plug slot the PCI device `pci` is in, if the slot's power can be
+/// controlled.
+pub fn slot(pci: &Path) -> io::Result<PathBuf> {
+	let name = pci.file_name().and_then(|n| n.to_str()).unwrap_or("");
+	// Slot addresses are domain:bus:device without the function
+	let address = match name.rfind('.') {
+		Some(dot) => &name[..dot],
+		None => name,
+	};
+	for entry in try!(fs::read_dir("/sys/bus/pci/slots")) {
+		let path = try!(entry).path();
+		if read_attr(path.join("address"))
+			.ok()
+			.as_ref()
+			.map(String::as_str)
+			== Some(address)
+			&& path.join("power").exists()
+		{
+			return Ok(path);
+		}
+	}
+	Err(io::Error::new(
+		io::ErrorKind::NotFound,
+		format!("{} is not in a hot-plug slot with power control", name),
+	))
+}
+
+/// Remove the PCI device `pci`, switch off the power of `slot` and switch it
+/// back on. The hot-plug driver enumerates the device again. Once removed,
+/// the slot is switched on and the bus rescanned even if a step fails.
+pub fn slot_cycle(pci: &Path, slot: &Path) -> io::Result<()> {
+	try!(write_attr(pci.join("remove"), "1"));
+	let result = write_attr(slot.join("power"), "0").map(|()| {
+		// Give the drive time to discharge
+		thread::sleep(Duration::from_secs(1));
+	});
+	let power_on = write_attr(slot.join("power"), "1");
+	let rescan = write_attr("/sys/bus/pci/rescan", "1");
+	result.and(power_on).and(rescan)
+}
@@ -0,0 +1,104 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Workarounds for controllers that deviate from the usual encoding of ATA
+//! security commands in NVMe SECURITY SEND and RECEIVE.
+//!
+//! No drives are known to need them yet, so quirks only apply when given with
+//! `--quirk=<name>`. `--protocol=<hex>` sends the ATA security commands on
+//! another security protocol than EFh.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nvme::security::Protocol;
+
+bitflags! {
+	pub struct Quirks: u8 {
+		/// Pad transfers to 512 bytes
+		const PAD_512   = 0x01;
+		/// Put the ATA security command in SPSP1 instead of SPSP0
+		const SPSP_HIGH = 0x02;
+	}
+}
+
+const NAMES: &'static [(&'static str, Quirks)] = &[
+	("pad-512", Quirks::PAD_512),
+	("spsp-high", Quirks::SPSP_HIGH),
+];
+
+/// Quirks given on the command line.
+static QUIRKS: AtomicUsize = AtomicUsize::new(0);
+const OVERRIDDEN: usize = 0x100;
+
+/// The security protocol forced on the command line, with bit 8 set. Zero
+/// means the ATA security protocol.
+static PROTOCOL: AtomicUsize = AtomicUsize::new(0);
+
+/// Parse a comma-separated list of quirk names for `--quirk`.
+pub fn parse(list: &str) -> Result<Quirks, String> {
+	let mut quirks = Quirks::empty();
+	for name in list.split(',') {
+		match NAMES.iter().find(|&&(n, _)| n == name) {
+			Some(&(_, q)) => quirks |= q,
+			None => {
+				let names: Vec<_> = NAMES.iter().map(|&(n, _)| n).collect();
+				return Err(format!(
+					"Unknown quirk {}, known quirks are: {}",
+					name,
+					names.join(", ")
+				));
+			}
+		}
+	}
+	Ok(quirks)
+}
+
+/// Use `quirks` for all drives.
+pub fn force(quirks: Quirks) {
+	QUIRKS.store(quirks.bits() as usize, Ordering::SeqCst);
+}
+
+/// Send ATA security commands on `protocol` for all drives.
+pub fn force_protocol(protocol: u8) {
+	PROTOCOL.store(OVERRIDDEN | protocol as usize, Ordering::SeqCst);
+}
+
+/// The security protocol for ATA security commands.
+pub fn protocol() -> u8 {
+	match PROTOCOL.load(Ordering::SeqCst) {
+		0 => Protocol::AtaSecurity.into(),
+		p => p as u8,
+	}
+}
+
+/// The quirks to apply to all drives.
+pub fn current() -> Quirks {
+	Quirks::from_bits_truncate(QUIRKS.load(Ordering::SeqCst) as u8)
+}
+
+impl Quirks {
+	/// The SPSP field for ATA security command `command`.
+	pub fn spsp(self, command: u16) -> u16 {
+		if self.contains(Quirks::SPSP_HIGH) {
+			command << 8
+		} else {
+			command
+		}
+	}
+
+	/// The transfer length for `len` bytes of data.
+	pub fn transfer_length(self, len: usize) -> usize {
+		if self.contains(Quirks::PAD_512) && len < 512 {
+			512
+		} else {
+			len
+		}
+	}
+}
@@ -15,17 +15,27 @@
 //! anything, but dependencies use `HashMap` which requires entropy to
 //! initialize. This module installs a seccomp filter that catches `getrandom`
 //! syscalls and emulates the call using the `RDRAND` instruction.
+//!
+//! Other architectures than x86-64 have no `RDRAND`, there `getrandom` is not
+//! emulated and may block during early boot.
 
+use std::io;
 use std::mem;
+#[cfg(target_arch = "x86_64")]
 use std::slice;
 
+#[cfg(target_arch = "x86_64")]
 use byteorder::{ByteOrder, NativeEndian};
-use libc::{SIGSYS, *};
+#[cfg(target_arch = "x86_64")]
+use libc::SIGSYS;
+use libc::*;
+#[cfg(target_arch = "x86_64")]
 use nix::sys::signal::{sigaction, *};
 
-#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
-compile_error!("This module will only work on Linux x86-64");
+#[cfg(not(target_os = "linux"))]
+compile_error!("This module will only work on Linux");
 
+#[cfg(target_arch = "x86_64")]
 extern "C" fn handle_sigsys(_signo: c_int, info: *mut siginfo_t, context: *mut c_void) {
 	fn rdrand64() -> u64 {
 		unsafe {
@@ -80,6 +90,10 @@ extern "C" fn handle_sigsys(_signo: c_int, info: *mut siginfo_t, context: *mut c
 	}
 }
 
+#[cfg(not(target_arch = "x86_64"))]
+pub fn init() {}
+
+#[cfg(target_arch = "x86_64")]
 pub fn init() {
 	static FILTER: &'static [sock_filter] = &[
 		/* [0] Load architecture from 'seccomp_data' buffer into accumulator */
@@ -167,39 +181,66 @@ pub fn init() {
 	}
 }
 
+/// Fill `buf` with random data using the `getrandom` syscall.
+///
+/// After `init` this is served by the emulation above.
+pub fn getrandom(buf: &mut [u8]) -> io::Result<()> {
+	let mut filled = 0;
+	while filled < buf.len() {
+		let ret = unsafe {
+			syscall(
+				SYS_getrandom,
+				buf[filled..].as_mut_ptr(),
+				buf.len() - filled,
+				0,
+			)
+		};
+		if ret < 0 {
+			let err = io::Error::last_os_error();
+			if err.kind() != io::ErrorKind::Interrupted {
+				return Err(err);
+			}
+		} else {
+			filled += ret as usize;
+		}
+	}
+	Ok(())
+}
+
 // ======== BPF definitions ========
 
 #[repr(C)]
-struct sock_fprog {
-	len: c_ushort,              /* Number of BPF instructions */
-	filter: *const sock_filter, /* Pointer to array of BPF instructions */
+pub struct sock_fprog {
+	pub len: c_ushort,              /* Number of BPF instructions */
+	pub filter: *const sock_filter, /* Pointer to array of BPF instructions */
 }
 
 #[repr(C)]
-struct sock_filter {
+pub struct sock_filter {
 	/* Filter block */
-	code: u16, /* Actual filter code */
-	jt: u8,    /* Jump true */
-	jf: u8,    /* Jump false */
-	k: u32,    /* Generic multiuse field */
+	pub code: u16, /* Actual filter code */
+	pub jt: u8,    /* Jump true */
+	pub jf: u8,    /* Jump false */
+	pub k: u32,    /* Generic multiuse field */
 }
 
-const BPF_LD: u16 = 0x00;
-const BPF_JMP: u16 = 0x05;
-const BPF_RET: u16 = 0x06;
-const BPF_W: u16 = 0x00;
-const BPF_ABS: u16 = 0x20;
-const BPF_JEQ: u16 = 0x10;
-const BPF_JGT: u16 = 0x20;
-const BPF_K: u16 = 0x00;
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+pub const BPF_W: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_JGT: u16 = 0x20;
+pub const BPF_K: u16 = 0x00;
 
 // ======== seccomp definitions ========
 
-const SCMP_ARCH_X86_64: u32 = 0xc000003e;
+pub const SCMP_ARCH_X86_64: u32 = 0xc000003e;
 
-const SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000; /* kill the process */
-const SECCOMP_RET_TRAP: u32 = 0x00030000;
-const SECCOMP_RET_ALLOW: u32 = 0x7fff0000;
+pub const SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000; /* kill the process */
+pub const SECCOMP_RET_TRAP: u32 = 0x00030000;
+pub const SECCOMP_RET_ERRNO: u32 = 0x00050000;
+pub const SECCOMP_RET_ALLOW: u32 = 0x7fff0000;
 
 #[allow(unused)]
 #[repr(C)]
@@ -210,8 +251,9 @@ struct seccomp_data {
 	args: [u64; 6],           /* Up to 6 system call arguments */
 }
 
-const OFFSET_OF_SECCOMP_DATA_NR: u32 = 0;
-const OFFSET_OF_SECCOMP_DATA_ARCH: u32 = 4;
+pub const OFFSET_OF_SECCOMP_DATA_NR: u32 = 0;
+pub const OFFSET_OF_SECCOMP_DATA_ARCH: u32 = 4;
+pub const OFFSET_OF_SECCOMP_DATA_ARGS: u32 = 16;
 
 #[allow(unused)]
 fn _seccomp_data_arch_offset_check() {
@@ -235,13 +277,15 @@ fn _seccomp_data_arch_offset_check() {
 
 // ======== siginfo definitions ========
 
+#[cfg(target_arch = "x86_64")]
 const SYS_SECCOMP: i32 = 1;
 
 // ======== architectural definitions ========
 
 const X32_SYSCALL_BIT: u32 = 0x40000000;
-const UPPER_SYSCALL_NR: u32 = X32_SYSCALL_BIT - 1;
+pub const UPPER_SYSCALL_NR: u32 = X32_SYSCALL_BIT - 1;
 
+#[cfg(target_arch = "x86_64")]
 #[repr(C)]
 #[allow(unused)]
 enum Greg {
@@ -269,3 +313,38 @@ enum Greg {
 	OLDMASK,
 	CR2,
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn getrandom_fills_buffer() {
+		let mut a = [0u8; 61];
+		let mut b = [0u8; 61];
+		getrandom(&mut a).unwrap();
+		getrandom(&mut b).unwrap();
+		assert!(a[..] != b[..]);
+	}
+
+	#[cfg(target_arch = "x86_64")]
+	#[test]
+	fn emulated_getrandom() {
+		// The filter only applies to the thread that installs it and the
+		// threads and processes it starts
+		::std::thread::spawn(|| {
+			init();
+			let mut empty = [];
+			getrandom(&mut empty).unwrap();
+			// Not a multiple of 8, to cover the partial last chunk
+			let mut a = [0u8; 61];
+			let mut b = [0u8; 61];
+			getrandom(&mut a).unwrap();
+			getrandom(&mut b).unwrap();
+			assert!(a[..] != b[..]);
+			assert!(a[56..].iter().any(|&byte| byte != 0));
+		})
+		.join()
+		.unwrap();
+	}
+}
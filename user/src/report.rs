@@ -0,0 +1,400 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Machine-readable drive information.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json;
+#[cfg(feature = "yaml")]
+use serde_yaml;
+use toml;
+
+use audit::AuditLog;
+use device;
+use ieee1667;
+use nvme::identify::IdentifyController;
+use nvme::security::{AtaSecurityIdentify, ComplianceDescriptor};
+use state::State;
+use tcg;
+use DriveInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	Human,
+	Structured(Structured),
+}
+
+/// The machine-readable formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Structured {
+	Json,
+	#[cfg(feature = "yaml")]
+	Yaml,
+	Toml,
+}
+
+impl FromStr for Format {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Format, String> {
+		match s {
+			"human" => Ok(Format::Human),
+			"json" => Ok(Format::Structured(Structured::Json)),
+			#[cfg(feature = "yaml")]
+			"yaml" => Ok(Format::Structured(Structured::Yaml)),
+			#[cfg(not(feature = "yaml"))]
+			"yaml" => Err("This build does not support YAML output".to_owned()),
+			"toml" => Ok(Format::Structured(Structured::Toml)),
+			_ => Err(format!("Unknown output format: {}", s)),
+		}
+	}
+}
+
+impl Structured {
+	/// Serialize `value` in this format.
+	pub fn serialize<T: Serialize>(self, value: &T) -> Result<String, String> {
+		match self {
+			Structured::Json => serde_json::to_string_pretty(value)
+				.map(|s| s + "\n")
+				.map_err(|e| e.to_string()),
+			#[cfg(feature = "yaml")]
+			Structured::Yaml => serde_yaml::to_string(value)
+				.map(|s| s + "\n")
+				.map_err(|e| e.to_string()),
+			Structured::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+		}
+	}
+}
+
+// Plain values must come before nested structures for the TOML serializer.
+#[derive(Serialize, Debug)]
+pub struct DriveReport {
+	pub device: String,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub by_id: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub wwid: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub transport: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub protocols: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub certificate_length: Option<usize>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub certificate_subject: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub certificate_issuer: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub controller: Option<device::Controller>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub identity: Option<Identity>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ata_security: Option<AtaSecurity>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub tcg: Option<Tcg>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub compliance: Vec<Compliance>,
+	/// Only filled in by `query --verbose`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ieee1667: Option<Ieee1667>,
+	/// The paths of a multipath namespace, only filled in by `query --verbose`
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub paths: Vec<device::NamespacePath>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Identity {
+	pub vid: u16,
+	pub ssvid: u16,
+	pub model: String,
+	pub serial: String,
+	pub firmware: String,
+	pub version: String,
+	pub oacs: u16,
+	pub oncs: u16,
+	pub fna: u8,
+	pub sanicap: u32,
+	pub frmw: u8,
+	pub npss: u8,
+	pub subnqn: String,
+	pub fabrics: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AtaSecurity {
+	pub state: String,
+	pub security_erase_time: u16,
+	pub enhanced_security_erase_time: u16,
+	pub master_password_identifier: u16,
+	pub master_password_identifier_supported: bool,
+	pub maxset: bool,
+	pub s_suprt: bool,
+	pub s_enabld: bool,
+	pub locked: bool,
+	pub frozen: bool,
+	pub pwncntex: bool,
+	pub en_er_sup: bool,
+}
+
+/// TCG Level 0 Discovery information.
+#[derive(Serialize, Debug)]
+pub struct Tcg {
+	pub ssc: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub locking: Option<tcg::Locking>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub block_sid: Option<tcg::BlockSid>,
+}
+
+/// A security compliance descriptor, only filled in by `query --verbose`.
+#[derive(Serialize, Debug)]
+pub struct Compliance {
+	pub descriptor_type: u16,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub standard: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub security_level: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub hardware_version: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub version: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub module_name: Option<String>,
+}
+
+/// The response to the IEEE 1667 Probe command.
+#[derive(Serialize, Debug)]
+pub struct Ieee1667 {
+	pub version: String,
+	pub silos: Vec<Silo>,
+}
+
+/// An IEEE 1667 silo.
+#[derive(Serialize, Debug)]
+pub struct Silo {
+	pub index: u8,
+	pub stid: u32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	pub version: String,
+}
+
+/// The outcome of a command that does not write its own output, for
+/// `--format` audit records.
+#[derive(Serialize, Debug)]
+pub struct CommandResult {
+	pub command: &'static str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub device: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub serial: Option<String>,
+	/// `user` or `master`, for commands acting on an ATA password
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub slot: Option<&'static str>,
+	/// `success`, `error` or `unsupported`
+	pub result: &'static str,
+	/// Where to append this record once the command has finished
+	#[serde(skip)]
+	pub audit_log: Option<AuditLog>,
+}
+
+impl CommandResult {
+	pub fn new(command: &'static str) -> CommandResult {
+		CommandResult {
+			command,
+			device: None,
+			serial: None,
+			slot: None,
+			result: "error",
+			audit_log: None,
+		}
+	}
+}
+
+/// All drives in the system, for `list`.
+#[derive(Serialize, Debug)]
+pub struct Inventory {
+	pub drive: Vec<DriveReport>,
+}
+
+fn lossy(bytes: &[u8]) -> String {
+	String::from_utf8_lossy(bytes).trim().to_owned()
+}
+
+impl<'a> From<&'a IdentifyController> for Identity {
+	fn from(i: &IdentifyController) -> Identity {
+		Identity {
+			vid: i.vid(),
+			ssvid: i.ssvid(),
+			model: lossy(i.mn()),
+			serial: lossy(i.sn()),
+			firmware: lossy(i.fr()),
+			version: match i.ver() {
+				(0, 0, 0) => "unknown".to_owned(),
+				(major, minor, tertiary) => format!("{}.{}.{}", major, minor, tertiary),
+			},
+			oacs: i.oacs().bits(),
+			oncs: i.oncs().bits(),
+			fna: i.fna().bits(),
+			sanicap: i.sanicap().bits(),
+			frmw: i.frmw().0,
+			npss: i.npss(),
+			subnqn: lossy(i.subnqn()),
+			fabrics: i.is_fabrics(),
+		}
+	}
+}
+
+impl<'a> From<&'a AtaSecurityIdentify> for AtaSecurity {
+	fn from(s: &AtaSecurityIdentify) -> AtaSecurity {
+		AtaSecurity {
+			state: State::from(s).to_string(),
+			security_erase_time: s.security_erase_time(),
+			enhanced_security_erase_time: s.enhanced_security_erase_time(),
+			master_password_identifier: s.master_password_identifier(),
+			master_password_identifier_supported: s.master_password_identifier_supported(),
+			maxset: s.maxset(),
+			s_suprt: s.s_suprt(),
+			s_enabld: s.s_enabld(),
+			locked: s.locked(),
+			frozen: s.frozen(),
+			pwncntex: s.pwncntex(),
+			en_er_sup: s.en_er_sup(),
+		}
+	}
+}
+
+impl<'a> From<&'a tcg::Discovery> for Tcg {
+	fn from(d: &tcg::Discovery) -> Tcg {
+		Tcg {
+			ssc: d
+				.ssc
+				.iter()
+				.map(|&(ssc, _)| ssc.name().to_owned())
+				.collect(),
+			locking: d.locking,
+			block_sid: d.block_sid,
+		}
+	}
+}
+
+impl<'a> From<&'a ComplianceDescriptor> for Compliance {
+	fn from(c: &ComplianceDescriptor) -> Compliance {
+		match *c {
+			ComplianceDescriptor::Fips140 {
+				related_standard,
+				overall_security_level,
+				ref hardware_version,
+				ref version,
+				ref module_name,
+			} => Compliance {
+				descriptor_type: 1,
+				standard: Some(match related_standard {
+					b'2' => "FIPS 140-2".to_owned(),
+					b'3' => "FIPS 140-3".to_owned(),
+					_ => format!("FIPS 140 ({:02x})", related_standard),
+				}),
+				security_level: Some((overall_security_level as char).to_string()),
+				hardware_version: Some(hardware_version.clone()),
+				version: Some(version.clone()),
+				module_name: Some(module_name.clone()),
+			},
+			ComplianceDescriptor::Other(descriptor_type) => Compliance {
+				descriptor_type,
+				standard: None,
+				security_level: None,
+				hardware_version: None,
+				version: None,
+				module_name: None,
+			},
+		}
+	}
+}
+
+impl<'a> From<&'a ieee1667::Probe> for Ieee1667 {
+	fn from(p: &ieee1667::Probe) -> Ieee1667 {
+		Ieee1667 {
+			version: format!("{}.{}", p.version.0, p.version.1),
+			silos: p.silos.iter().map(Silo::from).collect(),
+		}
+	}
+}
+
+impl<'a> From<&'a ieee1667::Silo> for Silo {
+	fn from(s: &ieee1667::Silo) -> Silo {
+		Silo {
+			index: s.index,
+			stid: s.stid,
+			name: s.name().map(str::to_owned),
+			version: format!("{}.{}", s.version.0, s.version.1),
+		}
+	}
+}
+
+impl DriveReport {
+	pub fn new(device: &str, info: &DriveInfo) -> DriveReport {
+		let mut report = DriveReport {
+			device: device.to_owned(),
+			by_id: device::persistent_names(Path::new(device))
+				.into_iter()
+				.map(|p| p.display().to_string())
+				.collect(),
+			wwid: device::wwid(Path::new(device)),
+			transport: device::transport(Path::new(device)),
+			error: None,
+			protocols: None,
+			certificate_length: None,
+			certificate_subject: None,
+			certificate_issuer: None,
+			controller: None,
+			identity: None,
+			ata_security: None,
+			tcg: None,
+			compliance: vec![],
+			ieee1667: None,
+			paths: vec![],
+		};
+		let (i, r_p) = match info.0 {
+			Err(ref e) => {
+				report.error = Some(format!("obtaining NVMe identity information: {:?}", e));
+				return report;
+			}
+			Ok((ref i, ref r_p)) => (i, r_p),
+		};
+		report.identity = Some(Identity::from(i));
+		let r_s = match *r_p {
+			Err(ref e) => {
+				report.error = Some(format!(
+					"enumerating supported NVMe security protocols: {:?}",
+					e
+				));
+				return report;
+			}
+			Ok(None) => return report,
+			Ok(Some((ref p, ref r_s))) => {
+				report.protocols = Some(p.iter().map(|p| p.info().to_string()).collect());
+				r_s
+			}
+		};
+		match *r_s {
+			Err(ref e) => {
+				report.error = Some(format!("obtaining ATA security information: {:?}", e))
+			}
+			Ok(None) => {}
+			Ok(Some(ref s)) => report.ata_security = Some(AtaSecurity::from(s)),
+		}
+		report
+	}
+}
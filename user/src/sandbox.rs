@@ -0,0 +1,436 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Seccomp sandbox of the long-running modes.
+//!
+//! `daemon` runs as root with the passwords of the configured drives, and
+//! `daemon --control` and `exporter` parse requests from other processes.
+//! Once their sockets are set up, and before any thread handles requests,
+//! they install a seccomp filter that only allows the system calls they
+//! need: file access, the NVMe and block device ioctls of `ops`, Unix
+//! sockets and threads. Other system calls fail with EPERM. Running other
+//! programs is only allowed if the configuration uses Clevis, which the
+//! launcher thread of `launcher` starts under the same filter. The filter is
+//! not installed with `--no-sandbox`.
+//!
+//! The system call numbers are those of x86-64. On other architectures
+//! `restrict` fails, so these modes need `--no-sandbox` there.
+
+use std::io;
+
+#[cfg(target_arch = "x86_64")]
+use libc::{self, prctl, EPERM, PR_SET_NO_NEW_PRIVS};
+
+#[cfg(target_arch = "x86_64")]
+use launcher;
+#[cfg(target_arch = "x86_64")]
+use ops;
+#[cfg(target_arch = "x86_64")]
+use rand::{
+	sock_filter, sock_fprog, BPF_ABS, BPF_JEQ, BPF_JGT, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W,
+	OFFSET_OF_SECCOMP_DATA_ARCH, OFFSET_OF_SECCOMP_DATA_ARGS, OFFSET_OF_SECCOMP_DATA_NR,
+	SCMP_ARCH_X86_64, SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS,
+	UPPER_SYSCALL_NR,
+};
+
+/// What the sandboxed process may do besides the basics.
+#[derive(Debug, Clone, Copy)]
+pub struct Profile {
+	/// Run other programs, e.g. Clevis
+	pub exec: bool,
+	/// Accept connections on listening sockets
+	pub accept: bool,
+}
+
+// Memory, threads, signals, time and exiting
+#[cfg(target_arch = "x86_64")]
+const BASE: &'static [u32] = &[
+	9,   // mmap
+	10,  // mprotect
+	11,  // munmap
+	12,  // brk
+	13,  // rt_sigaction
+	14,  // rt_sigprocmask
+	15,  // rt_sigreturn
+	24,  // sched_yield
+	25,  // mremap
+	28,  // madvise
+	35,  // nanosleep
+	39,  // getpid
+	56,  // clone
+	60,  // exit
+	102, // getuid
+	104, // getgid
+	107, // geteuid
+	108, // getegid
+	131, // sigaltstack
+	186, // gettid
+	202, // futex
+	204, // sched_getaffinity
+	218, // set_tid_address
+	219, // restart_syscall
+	228, // clock_gettime
+	230, // clock_nanosleep
+	231, // exit_group
+	234, // tgkill
+	273, // set_robust_list
+	302, // prlimit64
+	318, // getrandom
+	334, // rseq
+	435, // clone3
+];
+
+// Files, sysfs and the state of the throttle
+#[cfg(target_arch = "x86_64")]
+const FILES: &'static [u32] = &[
+	0,   // read
+	1,   // write
+	2,   // open
+	3,   // close
+	4,   // stat
+	5,   // fstat
+	6,   // lstat
+	7,   // poll
+	8,   // lseek
+	17,  // pread64
+	18,  // pwrite64
+	19,  // readv
+	20,  // writev
+	21,  // access
+	72,  // fcntl
+	74,  // fsync
+	75,  // fdatasync
+	77,  // ftruncate
+	78,  // getdents
+	79,  // getcwd
+	82,  // rename
+	83,  // mkdir
+	87,  // unlink
+	89,  // readlink
+	217, // getdents64
+	257, // openat
+	258, // mkdirat
+	262, // newfstatat
+	263, // unlinkat
+	264, // renameat
+	267, // readlinkat
+	269, // faccessat
+	316, // renameat2
+	332, // statx
+	439, // faccessat2
+];
+
+// Sockets that are already open, and connecting to journald
+#[cfg(target_arch = "x86_64")]
+const SOCKETS: &'static [u32] = &[
+	42, // connect
+	44, // sendto
+	45, // recvfrom
+	46, // sendmsg
+	47, // recvmsg
+	48, // shutdown
+	51, // getsockname
+	52, // getpeername
+	55, // getsockopt
+];
+
+#[cfg(target_arch = "x86_64")]
+const ACCEPT: &'static [u32] = &[
+	43,  // accept
+	288, // accept4
+	54,  // setsockopt
+];
+
+#[cfg(target_arch = "x86_64")]
+const EXEC: &'static [u32] = &[
+	22,  // pipe
+	32,  // dup
+	33,  // dup2
+	57,  // fork
+	58,  // vfork
+	59,  // execve
+	61,  // wait4
+	62,  // kill
+	292, // dup3
+	293, // pipe2
+	436, // close_range
+];
+
+#[cfg(target_arch = "x86_64")]
+const URING: &'static [u32] = &[
+	425, // io_uring_setup
+	426, // io_uring_enter
+	427, // io_uring_register
+];
+
+#[cfg(target_arch = "x86_64")]
+const SYS_IOCTL: u32 = 16;
+#[cfg(target_arch = "x86_64")]
+const SYS_SOCKET: u32 = 41;
+// Whether stderr is a terminal
+#[cfg(target_arch = "x86_64")]
+const TCGETS: u32 = 0x5401;
+
+#[cfg(target_arch = "x86_64")]
+fn load(offset: u32) -> sock_filter {
+	sock_filter {
+		code: BPF_LD | BPF_W | BPF_ABS,
+		k: offset,
+		jt: 0,
+		jf: 0,
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> sock_filter {
+	sock_filter {
+		code: BPF_JMP | code | BPF_K,
+		k,
+		jt,
+		jf,
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+fn ret(k: u32) -> sock_filter {
+	sock_filter {
+		code: BPF_RET | BPF_K,
+		k,
+		jt: 0,
+		jf: 0,
+	}
+}
+
+/// Allow the system calls `nrs`. The system call number must be in the
+/// accumulator.
+#[cfg(target_arch = "x86_64")]
+fn allow(filter: &mut Vec<sock_filter>, nrs: &[u32]) {
+	for &nr in nrs {
+		filter.push(jump(BPF_JEQ, nr, 0, 1));
+		filter.push(ret(SECCOMP_RET_ALLOW));
+	}
+}
+
+/// Allow system call `nr` if the lower 32 bits of its argument `arg` are
+/// one of `values`, and fail it otherwise. The accumulator is only replaced
+/// for `nr`.
+#[cfg(target_arch = "x86_64")]
+fn allow_with(filter: &mut Vec<sock_filter>, nr: u32, arg: u32, values: &[u32]) {
+	let n = values.len() as u8;
+	filter.push(jump(BPF_JEQ, nr, 0, n + 3));
+	filter.push(load(OFFSET_OF_SECCOMP_DATA_ARGS + arg * 8));
+	for (i, &value) in values.iter().enumerate() {
+		filter.push(jump(BPF_JEQ, value, n - i as u8, 0));
+	}
+	filter.push(ret(SECCOMP_RET_ERRNO | EPERM as u32));
+	filter.push(ret(SECCOMP_RET_ALLOW));
+}
+
+#[cfg(target_arch = "x86_64")]
+fn filter(profile: Profile) -> Vec<sock_filter> {
+	let mut filter = vec![
+		load(OFFSET_OF_SECCOMP_DATA_ARCH),
+		jump(BPF_JEQ, SCMP_ARCH_X86_64, 1, 0),
+		ret(SECCOMP_RET_KILL_PROCESS),
+		load(OFFSET_OF_SECCOMP_DATA_NR),
+		// x32 system calls
+		jump(BPF_JGT, UPPER_SYSCALL_NR, 0, 1),
+		ret(SECCOMP_RET_KILL_PROCESS),
+	];
+	allow(&mut filter, BASE);
+	allow(&mut filter, FILES);
+	allow(&mut filter, SOCKETS);
+	if profile.accept {
+		allow(&mut filter, ACCEPT);
+	}
+	if profile.exec {
+		allow(&mut filter, EXEC);
+	}
+	if ops::uses_uring() {
+		allow(&mut filter, URING);
+	}
+	let mut ioctls = ops::ioctl_requests().to_vec();
+	ioctls.push(TCGETS);
+	allow_with(&mut filter, SYS_IOCTL, 1, &ioctls);
+	allow_with(&mut filter, SYS_SOCKET, 0, &[libc::AF_UNIX as u32]);
+	filter.push(ret(SECCOMP_RET_ERRNO | EPERM as u32));
+	filter
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn restrict(_profile: Profile) -> io::Result<()> {
+	Err(io::Error::new(
+		io::ErrorKind::Other,
+		"the sandbox is only available on x86-64, run with --no-sandbox",
+	))
+}
+
+/// Install the filter for `profile` for this thread and the launcher thread.
+/// Threads started afterwards inherit it, but not the threads started
+/// before, so call this before starting any other threads.
+#[cfg(target_arch = "x86_64")]
+pub fn restrict(profile: Profile) -> io::Result<()> {
+	try!(install(profile));
+	launcher::run(move || install(profile)).unwrap_or(Ok(()))
+}
+
+/// Install the filter for `profile` for the calling thread.
+#[cfg(target_arch = "x86_64")]
+fn install(profile: Profile) -> io::Result<()> {
+	const SECCOMP_SET_MODE_FILTER: libc::c_ulong = 1;
+	const SYS_SECCOMP: libc::c_long = 317;
+
+	let filter = filter(profile);
+	let prog = sock_fprog {
+		filter: filter.as_ptr(),
+		len: filter.len() as _,
+	};
+	unsafe {
+		if prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		// Without SECCOMP_FILTER_FLAG_TSYNC, which would also put the
+		// launcher thread under the getrandom trap of `rand::init`
+		if libc::syscall(SYS_SECCOMP, SECCOMP_SET_MODE_FILTER, 0, &prog) != 0 {
+			return Err(io::Error::last_os_error());
+		}
+	}
+	Ok(())
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+	use super::*;
+
+	const EXECVE: u32 = 59;
+	const IO_URING_SETUP: u32 = 425;
+	const NO_EXEC: Profile = Profile {
+		exec: false,
+		accept: false,
+	};
+	const DENIED: u32 = SECCOMP_RET_ERRNO | EPERM as u32;
+
+	/// Run the classic BPF program `filter` on a system call.
+	fn run(filter: &[sock_filter], arch: u32, nr: u32, args: [u32; 6]) -> u32 {
+		let mut acc = 0;
+		let mut pc = 0;
+		loop {
+			let insn = &filter[pc];
+			pc += 1;
+			match insn.code & 0x07 {
+				BPF_LD => {
+					acc = match insn.k {
+						OFFSET_OF_SECCOMP_DATA_NR => nr,
+						OFFSET_OF_SECCOMP_DATA_ARCH => arch,
+						k => {
+							// Only the lower 32 bits of arguments are
+							// checked
+							assert_eq!((k - OFFSET_OF_SECCOMP_DATA_ARGS) % 8, 0);
+							args[((k - OFFSET_OF_SECCOMP_DATA_ARGS) / 8) as usize]
+						}
+					}
+				}
+				BPF_JMP => {
+					let taken = match insn.code & 0xf0 {
+						BPF_JEQ => acc == insn.k,
+						BPF_JGT => acc > insn.k,
+						code => panic!("unexpected jump {:#x}", code),
+					};
+					pc += if taken { insn.jt } else { insn.jf } as usize;
+				}
+				BPF_RET => return insn.k,
+				code => panic!("unexpected instruction {:#x}", code),
+			}
+		}
+	}
+
+	fn syscall(profile: Profile, nr: u32, args: [u32; 6]) -> u32 {
+		run(&filter(profile), SCMP_ARCH_X86_64, nr, args)
+	}
+
+	#[test]
+	fn every_syscall_returns() {
+		let filter = filter(Profile {
+			exec: true,
+			accept: true,
+		});
+		for nr in 0..512 {
+			let ret = run(&filter, SCMP_ARCH_X86_64, nr, [0; 6]);
+			assert!(
+				ret == SECCOMP_RET_ALLOW || ret == DENIED,
+				"{}: {:#x}",
+				nr,
+				ret
+			);
+		}
+	}
+
+	#[test]
+	fn foreign_syscalls_kill() {
+		assert_eq!(
+			run(&filter(NO_EXEC), 0x4000_0003, 0, [0; 6]),
+			SECCOMP_RET_KILL_PROCESS
+		);
+		assert_eq!(
+			syscall(NO_EXEC, UPPER_SYSCALL_NR + 1, [0; 6]),
+			SECCOMP_RET_KILL_PROCESS
+		);
+	}
+
+	#[test]
+	fn profiles() {
+		assert_eq!(syscall(NO_EXEC, 0, [0; 6]), SECCOMP_RET_ALLOW);
+		assert_eq!(syscall(NO_EXEC, EXECVE, [0; 6]), DENIED);
+		assert_eq!(syscall(NO_EXEC, ACCEPT[0], [0; 6]), DENIED);
+		assert_eq!(
+			syscall(
+				Profile {
+					exec: true,
+					accept: false,
+				},
+				EXECVE,
+				[0; 6]
+			),
+			SECCOMP_RET_ALLOW
+		);
+		assert_eq!(
+			syscall(
+				Profile {
+					exec: false,
+					accept: true,
+				},
+				ACCEPT[0],
+				[0; 6]
+			),
+			SECCOMP_RET_ALLOW
+		);
+		assert_eq!(syscall(NO_EXEC, IO_URING_SETUP, [0; 6]), DENIED);
+	}
+
+	#[test]
+	fn arguments() {
+		for &request in ops::ioctl_requests().iter().chain(&[TCGETS]) {
+			assert_eq!(
+				syscall(NO_EXEC, SYS_IOCTL, [3, request, 0, 0, 0, 0]),
+				SECCOMP_RET_ALLOW
+			);
+		}
+		// FIONREAD
+		assert_eq!(syscall(NO_EXEC, SYS_IOCTL, [3, 0x541b, 0, 0, 0, 0]), DENIED);
+		assert_eq!(
+			syscall(NO_EXEC, SYS_SOCKET, [libc::AF_UNIX as u32, 0, 0, 0, 0, 0]),
+			SECCOMP_RET_ALLOW
+		);
+		assert_eq!(
+			syscall(NO_EXEC, SYS_SOCKET, [libc::AF_INET as u32, 0, 0, 0, 0, 0]),
+			DENIED
+		);
+	}
+}
@@ -0,0 +1,136 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Ordered ATA security command sequences.
+//!
+//! SECURITY ERASE UNIT is only accepted right after SECURITY ERASE PREPARE,
+//! any other command in between makes the drive abort it. `SecuritySession`
+//! tracks where a sequence is in its type: SECURITY ERASE UNIT can only be
+//! sent from the `ErasePrepared` state, which only SECURITY ERASE PREPARE
+//! returns, and sending it consumes that state. All ATA security commands
+//! go through a session, the raw SECURITY SEND is private to this module.
+
+use std::fs::File;
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+
+use nvme::security::{AtaSecurityPassword, AtaSecurityPasswordBuilder, AtaSecuritySpecific};
+use nvme::Payload;
+use ops::{self, Result};
+use quirks;
+
+/// The builder of the data for `password`, the master password if `master`.
+pub fn password_data(password: [u8; 32], master: bool) -> AtaSecurityPasswordBuilder {
+	let builder = AtaSecurityPassword::builder(password);
+	if master {
+		builder.master()
+	} else {
+		builder
+	}
+}
+
+/// SECURITY SEND of an ATA security command, adjusted for drive quirks.
+fn ata_security_send(f: &File, command: AtaSecuritySpecific, data: Option<&[u8]>) -> Result<()> {
+	let fd = f.as_raw_fd();
+	let quirks = quirks::current();
+	let spsp = quirks.spsp(command as u16);
+	let padded = data.map(|data| {
+		let mut buf = data.to_vec();
+		buf.resize(quirks.transfer_length(data.len()), 0);
+		buf
+	});
+	ops::security_send(
+		fd,
+		quirks::protocol(),
+		spsp,
+		0,
+		padded.as_ref().map(|buf| &buf[..]),
+	)
+}
+
+/// No sequence in progress.
+pub struct Idle;
+
+/// SECURITY ERASE PREPARE was sent, SECURITY ERASE UNIT must follow.
+pub struct ErasePrepared;
+
+pub struct SecuritySession<'a, S> {
+	f: &'a File,
+	state: PhantomData<S>,
+}
+
+impl<'a, S> SecuritySession<'a, S> {
+	fn to<T>(self) -> SecuritySession<'a, T> {
+		SecuritySession {
+			f: self.f,
+			state: PhantomData,
+		}
+	}
+}
+
+impl<'a> SecuritySession<'a, Idle> {
+	pub fn new(f: &'a File) -> SecuritySession<'a, Idle> {
+		SecuritySession {
+			f,
+			state: PhantomData,
+		}
+	}
+
+	/// SECURITY SET PASSWORD with the user or master password in `data`.
+	pub fn set_password(&self, data: &AtaSecurityPassword) -> Result<()> {
+		ata_security_send(
+			self.f,
+			AtaSecuritySpecific::SetPassword,
+			Some(data.to_wire()),
+		)
+	}
+
+	/// SECURITY UNLOCK with the user or master password in `data`.
+	pub fn unlock(&self, data: &AtaSecurityPassword) -> Result<()> {
+		ata_security_send(self.f, AtaSecuritySpecific::Unlock, Some(data.to_wire()))
+	}
+
+	/// SECURITY DISABLE PASSWORD with the user or master password in `data`.
+	pub fn disable_password(&self, data: &AtaSecurityPassword) -> Result<()> {
+		ata_security_send(
+			self.f,
+			AtaSecuritySpecific::DisablePassword,
+			Some(data.to_wire()),
+		)
+	}
+
+	/// SECURITY FREEZE LOCK, until the next power cycle.
+	pub fn freeze_lock(&self) -> Result<()> {
+		ata_security_send(self.f, AtaSecuritySpecific::FreezeLock, None)
+	}
+
+	pub fn erase_prepare(self) -> Result<SecuritySession<'a, ErasePrepared>> {
+		try!(ata_security_send(
+			self.f,
+			AtaSecuritySpecific::ErasePrepare,
+			None
+		));
+		Ok(self.to())
+	}
+}
+
+impl<'a> SecuritySession<'a, ErasePrepared> {
+	/// SECURITY ERASE UNIT with the user or master password in `data`. Build
+	/// `data` before SECURITY ERASE PREPARE, an error in between makes the
+	/// drive abort the erase.
+	pub fn erase_unit(self, data: &AtaSecurityPassword) -> Result<SecuritySession<'a, Idle>> {
+		try!(ata_security_send(
+			self.f,
+			AtaSecuritySpecific::EraseUnit,
+			Some(data.to_wire())
+		));
+		Ok(self.to())
+	}
+}
@@ -0,0 +1,132 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Drive state snapshots for `snapshot save` and `snapshot diff`.
+//!
+//! A snapshot is the `list` inventory of all drives in JSON, with the time it
+//! was taken. Drives are matched by serial number when comparing, so a drive
+//! showing up under a different device name is still recognized. Changes to
+//! the ATA security state that may indicate tampering are called out.
+
+use std::fs::File;
+use std::io::{self, Write};
+
+use serde_json::{self, Value};
+
+use audit;
+use report::Inventory;
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+	time: String,
+	#[serde(flatten)]
+	inventory: &'a Inventory,
+}
+
+/// Noteworthy transitions of ATA security bits: field, old, new, meaning.
+const ALERTS: &'static [(&'static str, bool, bool, &'static str)] = &[
+	("s_enabld", false, true, "a password was enabled"),
+	("s_enabld", true, false, "the password was disabled"),
+	("locked", true, false, "the drive was unlocked"),
+	("frozen", true, false, "the drive is no longer frozen"),
+	(
+		"pwncntex",
+		false,
+		true,
+		"the password attempt counter was exceeded",
+	),
+];
+
+pub fn save(inventory: &Inventory, path: &str) -> io::Result<()> {
+	let snapshot = Snapshot {
+		time: audit::now(),
+		inventory,
+	};
+	let mut json = try!(serde_json::to_string_pretty(&snapshot)
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+	json.push('\n');
+	try!(File::create(path)).write_all(json.as_bytes())
+}
+
+pub fn load(path: &str) -> io::Result<Value> {
+	serde_json::from_reader(try!(File::open(path)))
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn serial(drive: &Value) -> String {
+	match drive.pointer("/identity/serial").and_then(Value::as_str) {
+		Some(serial) => serial.to_owned(),
+		// Without identity information, fall back to the device name
+		None => drive["device"].as_str().unwrap_or("").to_owned(),
+	}
+}
+
+fn compare(path: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+	match (old, new) {
+		(&Value::Object(ref old), &Value::Object(ref new)) => {
+			for (key, value) in old {
+				let path = format!("{}.{}", path, key);
+				compare(&path, value, new.get(key).unwrap_or(&Value::Null), changes);
+			}
+			for (key, value) in new {
+				if !old.contains_key(key) {
+					compare(&format!("{}.{}", path, key), &Value::Null, value, changes);
+				}
+			}
+		}
+		_ if old != new => changes.push(format!(
+			"{}: {} -> {}",
+			path.trim_left_matches('.'),
+			old,
+			new
+		)),
+		_ => {}
+	}
+}
+
+/// Compare the snapshot `old` with the current `inventory`, one line per
+/// change.
+pub fn diff(old: &Value, inventory: &Inventory) -> io::Result<Vec<String>> {
+	let new =
+		try!(serde_json::to_value(inventory)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+	let empty = vec![];
+	let old = old["drive"].as_array().unwrap_or(&empty);
+	let new = new["drive"].as_array().unwrap_or(&empty);
+	let mut lines = vec![];
+	for drive in old {
+		let serial = serial(drive);
+		let current = match new.iter().find(|d| self::serial(d) == serial) {
+			None => {
+				lines.push(format!("{}: removed", serial));
+				continue;
+			}
+			Some(current) => current,
+		};
+		for &(field, from, to, meaning) in ALERTS {
+			let pointer = format!("/ata_security/{}", field);
+			if drive.pointer(&pointer) == Some(&Value::Bool(from))
+				&& current.pointer(&pointer) == Some(&Value::Bool(to))
+			{
+				lines.push(format!("{}: ALERT: {}", serial, meaning));
+			}
+		}
+		let mut changes = vec![];
+		compare("", drive, current, &mut changes);
+		lines.extend(changes.into_iter().map(|c| format!("{}: {}", serial, c)));
+	}
+	for drive in new {
+		let serial = serial(drive);
+		if !old.iter().any(|d| self::serial(d) == serial) {
+			lines.push(format!("{}: added", serial));
+		}
+	}
+	Ok(lines)
+}
@@ -0,0 +1,248 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! `query --stack`: what is layered on top of a namespace.
+//!
+//! The holders of a block device in sysfs are the devices built on it:
+//! device-mapper targets such as dm-crypt and LVM logical volumes, and md
+//! RAID arrays. Following them from the namespace and its partitions, and
+//! looking up file systems and swap, shows everything an erase destroys.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use device::read_attr;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+	Namespace,
+	Partition,
+	DmCrypt,
+	Lvm,
+	DmMultipath,
+	DeviceMapper,
+	Md,
+	Other,
+}
+
+impl Kind {
+	pub fn name(self) -> &'static str {
+		match self {
+			Kind::Namespace => "namespace",
+			Kind::Partition => "partition",
+			Kind::DmCrypt => "dm-crypt",
+			Kind::Lvm => "LVM logical volume",
+			Kind::DmMultipath => "dm-multipath",
+			Kind::DeviceMapper => "device-mapper",
+			Kind::Md => "md RAID",
+			Kind::Other => "block device",
+		}
+	}
+}
+
+#[derive(Serialize, Debug)]
+pub struct Mount {
+	pub path: String,
+	pub fstype: String,
+}
+
+// Plain values must come before nested structures for the TOML serializer.
+#[derive(Serialize, Debug)]
+pub struct Layer {
+	/// The kernel name, e.g. `dm-0`
+	pub name: String,
+	pub kind: Kind,
+	/// The device-mapper name or the RAID level and array state
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub detail: Option<String>,
+	pub swap: bool,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub mounts: Vec<Mount>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub holders: Vec<Layer>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stack {
+	pub device: String,
+	/// What would be destroyed along with the namespace
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub warnings: Vec<String>,
+	pub layers: Layer,
+}
+
+/// What the system uses block devices for, by `major:minor`.
+struct Uses {
+	/// `major:minor`, mount point and file system type
+	mounts: Vec<(String, String, String)>,
+	/// Kernel names of swap devices
+	swaps: Vec<String>,
+}
+
+impl Uses {
+	fn read() -> io::Result<Uses> {
+		let mut mounts = vec![];
+		// Fields are id, parent id, major:minor, root, mount point, ...,
+		// then after a separator file system type and source
+		for line in try!(fs::read_to_string("/proc/self/mountinfo")).lines() {
+			let mut halves = line.splitn(2, " - ");
+			let fields: Vec<_> = halves.next().unwrap_or("").split_whitespace().collect();
+			let fstype = halves
+				.next()
+				.and_then(|rest| rest.split_whitespace().next())
+				.unwrap_or("");
+			if fields.len() >= 5 {
+				mounts.push((
+					fields[2].to_owned(),
+					fields[4].to_owned(),
+					fstype.to_owned(),
+				));
+			}
+		}
+		let swaps = try!(fs::read_to_string("/proc/swaps"))
+			.lines()
+			.skip(1)
+			.filter_map(|line| line.split_whitespace().next())
+			.filter_map(|swap| fs::canonicalize(swap).ok())
+			.filter_map(|swap| swap.file_name().map(|n| n.to_string_lossy().into_owned()))
+			.collect();
+		Ok(Uses { mounts, swaps })
+	}
+}
+
+/// Classify the block device `name` from its sysfs attributes.
+fn classify(sysfs: &Path, name: &str) -> (Kind, Option<String>) {
+	if sysfs.join("partition").exists() {
+		return (Kind::Partition, None);
+	}
+	if let Some(uuid) = read_attr(&sysfs.join("dm/uuid")) {
+		let kind = if uuid.starts_with("CRYPT-") {
+			Kind::DmCrypt
+		} else if uuid.starts_with("LVM-") {
+			Kind::Lvm
+		} else if uuid.starts_with("mpath-") {
+			Kind::DmMultipath
+		} else {
+			Kind::DeviceMapper
+		};
+		return (kind, read_attr(&sysfs.join("dm/name")));
+	}
+	if name.starts_with("md") {
+		if let Some(level) = read_attr(&sysfs.join("md/level")) {
+			let state = read_attr(&sysfs.join("md/array_state")).unwrap_or_default();
+			return (Kind::Md, Some(format!("{}, {}", level, state)));
+		}
+	}
+	(Kind::Other, None)
+}
+
+fn layer(uses: &Uses, name: &str, kind: Option<Kind>, depth: usize) -> io::Result<Layer> {
+	let sysfs = Path::new("/sys/class/block").join(name);
+	let (classified, detail) = classify(&sysfs, name);
+	let dev = read_attr(&sysfs.join("dev"));
+	let mut layer = Layer {
+		name: name.to_owned(),
+		kind: kind.unwrap_or(classified),
+		detail,
+		swap: uses.swaps.iter().any(|s| s == name),
+		mounts: uses
+			.mounts
+			.iter()
+			.filter(|m| Some(&m.0) == dev.as_ref())
+			.map(|m| Mount {
+				path: m.1.clone(),
+				fstype: m.2.clone(),
+			})
+			.collect(),
+		holders: vec![],
+	};
+	// Guard against cycles in a broken sysfs
+	if depth > 16 {
+		return Ok(layer);
+	}
+	let mut names = vec![];
+	if kind == Some(Kind::Namespace) {
+		for entry in try!(fs::read_dir(&sysfs)) {
+			let entry = try!(entry);
+			if entry.path().join("partition").exists() {
+				names.push(entry.file_name().to_string_lossy().into_owned());
+			}
+		}
+	}
+	if let Ok(holders) = fs::read_dir(sysfs.join("holders")) {
+		for holder in holders {
+			names.push(try!(holder).file_name().to_string_lossy().into_owned());
+		}
+	}
+	names.sort();
+	for name in names {
+		layer
+			.holders
+			.push(try!(self::layer(uses, &name, None, depth + 1)));
+	}
+	Ok(layer)
+}
+
+/// Whether the md array `name` is assembled and running.
+fn md_active(name: &str) -> bool {
+	let state = Path::new("/sys/class/block")
+		.join(name)
+		.join("md/array_state");
+	match read_attr(&state) {
+		Some(ref state) => state != "inactive" && state != "clear",
+		None => false,
+	}
+}
+
+/// Warn about active md arrays, mounted file systems and swap in `layer`,
+/// the members of which the erase destroys.
+fn warnings(layer: &Layer, namespace: &str, warnings: &mut Vec<String>) {
+	if layer.kind == Kind::Md && md_active(&layer.name) {
+		warnings.push(format!(
+			"erasing {} destroys a member of the active RAID array {}, remove it from the array \
+			 first",
+			namespace, layer.name
+		));
+	}
+	for mount in &layer.mounts {
+		warnings.push(format!("{} is mounted on {}", layer.name, mount.path));
+	}
+	if layer.swap {
+		warnings.push(format!("{} is used as swap", layer.name));
+	}
+	for holder in &layer.holders {
+		self::warnings(holder, namespace, warnings);
+	}
+}
+
+/// The stack on top of the namespace `dev`.
+pub fn stack(dev: &Path) -> io::Result<Stack> {
+	let dev = try!(fs::canonicalize(dev));
+	let name = try!(dev
+		.file_name()
+		.map(|n| n.to_string_lossy().into_owned())
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid device path")));
+	if !Path::new("/sys/class/block").join(&name).exists() {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("{} is not a block device", name),
+		));
+	}
+	let layers = try!(layer(&try!(Uses::read()), &name, Some(Kind::Namespace), 0));
+	let mut stack = Stack {
+		device: dev.display().to_string(),
+		warnings: vec![],
+		layers,
+	};
+	warnings(&stack.layers, &name, &mut stack.warnings);
+	Ok(stack)
+}
@@ -0,0 +1,131 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Interpret the ATA security state machine.
+//!
+//! The ATA security identify data only exposes a handful of bits. This module
+//! maps them onto the states defined in ACS-3 section 4.18 and works out which
+//! security commands the drive will currently accept.
+
+use std::fmt;
+
+use nvme::security::AtaSecurityIdentify;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+	/// The drive does not support the ATA security feature set
+	Unsupported,
+	/// SEC1: no password set, not frozen
+	Disabled,
+	/// SEC2: no password set, frozen
+	DisabledFrozen,
+	/// SEC4: password set, locked
+	Locked,
+	/// SEC5: password set, unlocked, not frozen
+	Unlocked,
+	/// SEC6: password set, unlocked, frozen
+	UnlockedFrozen,
+}
+
+impl<'a> From<&'a AtaSecurityIdentify> for State {
+	fn from(s: &AtaSecurityIdentify) -> State {
+		match (s.s_suprt(), s.s_enabld(), s.locked(), s.frozen()) {
+			(false, _, _, _) => State::Unsupported,
+			(true, false, _, false) => State::Disabled,
+			(true, false, _, true) => State::DisabledFrozen,
+			(true, true, true, _) => State::Locked,
+			(true, true, false, false) => State::Unlocked,
+			(true, true, false, true) => State::UnlockedFrozen,
+		}
+	}
+}
+
+impl fmt::Display for State {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.write_str(match *self {
+			State::Unsupported => "unsupported",
+			State::Disabled => "SEC1 (security disabled, not frozen)",
+			State::DisabledFrozen => "SEC2 (security disabled, frozen)",
+			State::Locked => "SEC4 (security enabled, locked)",
+			State::Unlocked => "SEC5 (security enabled, unlocked, not frozen)",
+			State::UnlockedFrozen => "SEC6 (security enabled, unlocked, frozen)",
+		})
+	}
+}
+
+/// The subcommands whose outcome depends on the security state.
+pub const OPERATIONS: &'static [&'static str] = &[
+	"set-password",
+	"change-password",
+	"unlock",
+	"disable-password",
+	"erase",
+	"freeze",
+];
+
+/// Returns `None` if `operation` is expected to succeed in the current state,
+/// otherwise the reason it will fail.
+pub fn blocker(s: &AtaSecurityIdentify, operation: &str) -> Option<&'static str> {
+	let state = State::from(s);
+	if state == State::Unsupported {
+		return Some("the drive does not support ATA security");
+	}
+	let frozen = s.frozen();
+	let expired = s.pwncntex();
+	match operation {
+		"set-password" | "change-password" if frozen => {
+			Some("the drive is frozen until the next power cycle")
+		}
+		"set-password" if state == State::Locked => Some("the drive must be unlocked first"),
+		"change-password" if !s.s_enabld() => Some("no password is set"),
+		"change-password" if state == State::Locked => Some("the drive must be unlocked first"),
+		"unlock" | "disable-password" if !s.s_enabld() => Some("no password is set"),
+		"unlock" | "change-password" if expired => {
+			Some("the password attempt counter is exceeded until the next power cycle")
+		}
+		"unlock" if frozen => Some("the drive is frozen until the next power cycle"),
+		"disable-password" if frozen => Some("the drive is frozen until the next power cycle"),
+		"disable-password" if state == State::Locked => Some("the drive must be unlocked first"),
+		"erase" if !s.s_enabld() => Some("a user password must be set first"),
+		"erase" if frozen => Some("the drive is frozen until the next power cycle"),
+		"erase" if expired => {
+			Some("the password attempt counter is exceeded until the next power cycle")
+		}
+		"freeze" if state == State::Locked => Some("the drive must be unlocked first"),
+		"freeze" if frozen => Some("the drive is already frozen"),
+		_ => None,
+	}
+}
+
+/// Suggested next steps for the current state.
+pub fn next_steps(s: &AtaSecurityIdentify) -> Vec<&'static str> {
+	let mut steps = vec![];
+	if s.pwncntex() {
+		steps.push("Power cycle the drive to reset the password attempt counter.");
+	}
+	match State::from(s) {
+		State::Unsupported => {}
+		State::Disabled => {
+			steps.push("Use set-password -u to enable security.");
+			steps.push("Use freeze to prevent others from setting a password until power cycle.");
+		}
+		State::DisabledFrozen | State::UnlockedFrozen => {
+			steps.push("Power cycle the drive (e.g. with unfreeze) to clear the frozen state.");
+		}
+		State::Locked => {
+			steps.push("Use unlock with the user or master password to access data.");
+			steps.push("Use erase with the user or master password if the data is not needed.");
+		}
+		State::Unlocked => {
+			steps.push("Use freeze to prevent password changes until power cycle.");
+		}
+	}
+	steps
+}
@@ -0,0 +1,390 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Human-readable tables of drive information.
+
+use nvme::identify::{Fna, Frmw, Oacs, Oncs, Sanicap};
+use nvme::security::EraseTime;
+use report::DriveReport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+	Red,
+	Yellow,
+	Green,
+}
+
+impl Color {
+	fn code(self) -> &'static str {
+		match self {
+			Color::Red => "\x1b[31m",
+			Color::Yellow => "\x1b[33m",
+			Color::Green => "\x1b[32m",
+		}
+	}
+}
+
+const RESET: &'static str = "\x1b[0m";
+
+struct Cell {
+	text: String,
+	color: Option<Color>,
+}
+
+impl<'a> From<&'a str> for Cell {
+	fn from(text: &str) -> Cell {
+		Cell {
+			text: text.to_owned(),
+			color: None,
+		}
+	}
+}
+
+impl From<String> for Cell {
+	fn from(text: String) -> Cell {
+		Cell { text, color: None }
+	}
+}
+
+/// One-word summary of the drive state, colored by severity.
+fn status(r: &DriveReport) -> Cell {
+	let (text, color) = if r.error.is_some() {
+		("ERROR", Some(Color::Red))
+	} else {
+		match r.ata_security {
+			None => ("UNSUPPORTED", None),
+			Some(ref s) if !s.s_suprt => ("UNSUPPORTED", None),
+			Some(ref s) if s.locked => ("LOCKED", Some(Color::Red)),
+			Some(ref s) if s.frozen => ("FROZEN", Some(Color::Yellow)),
+			Some(_) => ("OK", Some(Color::Green)),
+		}
+	};
+	Cell {
+		text: text.to_owned(),
+		color,
+	}
+}
+
+fn yes_no(b: bool) -> &'static str {
+	if b {
+		"yes"
+	} else {
+		"no"
+	}
+}
+
+fn erase_time(time: u16) -> String {
+	match EraseTime::decode(time) {
+		Some(t) => t.to_string(),
+		None => "not reported".to_string(),
+	}
+}
+
+fn render(rows: &[Vec<Cell>], color: bool) -> String {
+	let mut widths = vec![];
+	for row in rows {
+		for (i, cell) in row.iter().enumerate() {
+			let len = cell.text.chars().count();
+			if i >= widths.len() {
+				widths.push(len);
+			} else if widths[i] < len {
+				widths[i] = len;
+			}
+		}
+	}
+
+	let mut out = String::new();
+	for row in rows {
+		let mut line = String::new();
+		for (i, cell) in row.iter().enumerate() {
+			if i > 0 {
+				line.push_str("  ");
+			}
+			match cell.color {
+				Some(c) if color => {
+					line.push_str(c.code());
+					line.push_str(&cell.text);
+					line.push_str(RESET);
+				}
+				_ => line.push_str(&cell.text),
+			}
+			if i + 1 < row.len() {
+				for _ in cell.text.chars().count()..widths[i] {
+					line.push(' ');
+				}
+			}
+		}
+		out.push_str(line.trim_right());
+		out.push('\n');
+	}
+	out
+}
+
+/// One line per drive, for `list`.
+pub fn list(reports: &[DriveReport], color: bool) -> String {
+	let mut rows: Vec<Vec<Cell>> = vec![vec![
+		"DEVICE".into(),
+		"MODEL".into(),
+		"SERIAL".into(),
+		"FIRMWARE".into(),
+		"WWID".into(),
+		"SECURITY".into(),
+		"LEVEL".into(),
+		"STATUS".into(),
+	]];
+	for r in reports {
+		let (model, serial, firmware) = match r.identity {
+			Some(ref i) => (i.model.clone(), i.serial.clone(), i.firmware.clone()),
+			None => ("-".to_owned(), "-".to_owned(), "-".to_owned()),
+		};
+		let (security, level) = match r.ata_security {
+			Some(ref s) if s.s_suprt => (
+				if s.s_enabld { "enabled" } else { "disabled" },
+				if s.maxset { "maximum" } else { "high" },
+			),
+			_ => ("-", "-"),
+		};
+		rows.push(vec![
+			r.device.as_str().into(),
+			model.into(),
+			serial.into(),
+			firmware.into(),
+			r.wwid.as_ref().map_or("-", String::as_str).into(),
+			security.into(),
+			level.into(),
+			status(r),
+		]);
+	}
+	render(&rows, color)
+}
+
+/// All information about a single drive, for `query`.
+/// Key/value rows for `query`. `verbose` adds the less commonly needed
+/// controller capabilities.
+pub fn detail(r: &DriveReport, color: bool, verbose: bool) -> String {
+	let mut rows: Vec<Vec<Cell>> = vec![vec!["device".into(), r.device.as_str().into()]];
+	for name in &r.by_id {
+		rows.push(vec!["by-id".into(), name.as_str().into()]);
+	}
+	if let Some(ref wwid) = r.wwid {
+		rows.push(vec!["wwid".into(), wwid.as_str().into()]);
+	}
+	if let Some(ref transport) = r.transport {
+		rows.push(vec!["transport".into(), transport.as_str().into()]);
+	}
+	if let Some(ref i) = r.identity {
+		rows.push(vec!["model".into(), i.model.as_str().into()]);
+		rows.push(vec!["serial".into(), i.serial.as_str().into()]);
+		rows.push(vec!["firmware".into(), i.firmware.as_str().into()]);
+		rows.push(vec![
+			"vid:ssvid".into(),
+			format!("{:04x}:{:04x}", i.vid, i.ssvid).into(),
+		]);
+		rows.push(vec![
+			"oacs".into(),
+			format!("{:?}", Oacs::from_bits_truncate(i.oacs)).into(),
+		]);
+		if verbose {
+			let frmw = Frmw(i.frmw);
+			rows.push(vec!["version".into(), i.version.as_str().into()]);
+			rows.push(vec![
+				"oncs".into(),
+				format!("{:?}", Oncs::from_bits_truncate(i.oncs)).into(),
+			]);
+			rows.push(vec![
+				"fna".into(),
+				format!("{:?}", Fna::from_bits_truncate(i.fna)).into(),
+			]);
+			rows.push(vec![
+				"sanicap".into(),
+				format!("{:?}", Sanicap::from_bits_truncate(i.sanicap)).into(),
+			]);
+			rows.push(vec![
+				"firmware slots".into(),
+				format!(
+					"{}{}{}",
+					frmw.slots(),
+					if frmw.slot1_read_only() {
+						", slot 1 read-only"
+					} else {
+						""
+					},
+					if frmw.activation_without_reset() {
+						", activation without reset"
+					} else {
+						""
+					}
+				)
+				.into(),
+			]);
+			rows.push(vec![
+				"power states".into(),
+				(i.npss as u32 + 1).to_string().into(),
+			]);
+			rows.push(vec!["subnqn".into(), i.subnqn.as_str().into()]);
+		}
+	}
+	if let Some(ref c) = r.controller {
+		rows.push(vec!["controller".into(), c.name.as_str().into()]);
+		if let Some(ref state) = c.state {
+			rows.push(vec!["controller state".into(), state.as_str().into()]);
+		}
+		if let Some(queue_count) = c.queue_count {
+			rows.push(vec!["queues".into(), queue_count.to_string().into()]);
+		}
+		if let Some(ref firmware_rev) = c.firmware_rev {
+			rows.push(vec!["kernel firmware".into(), firmware_rev.as_str().into()]);
+		}
+	}
+	for p in &r.paths {
+		rows.push(vec![
+			"path".into(),
+			format!(
+				"{} through {}, {}{}",
+				p.name,
+				p.controller,
+				p.state.as_ref().map_or("unknown", String::as_str),
+				p.ana_state
+					.as_ref()
+					.map_or(String::new(), |a| format!(", {}", a))
+			)
+			.into(),
+		]);
+	}
+	match r.protocols {
+		Some(ref p) => rows.push(vec!["protocols".into(), p.join(", ").into()]),
+		None if r.identity.is_some() && r.error.is_none() => rows.push(vec![
+			"protocols".into(),
+			"NVMe security commands not supported".into(),
+		]),
+		None => {}
+	}
+	if let Some(ref s) = r.ata_security {
+		rows.push(vec!["state".into(), s.state.as_str().into()]);
+		rows.push(vec![
+			"erase time".into(),
+			erase_time(s.security_erase_time).into(),
+		]);
+		rows.push(vec![
+			"enhanced erase time".into(),
+			erase_time(s.enhanced_security_erase_time).into(),
+		]);
+		rows.push(vec![
+			"master password id".into(),
+			if s.master_password_identifier_supported {
+				format!("{:04x}", s.master_password_identifier)
+			} else {
+				"not supported".to_owned()
+			}
+			.into(),
+		]);
+		rows.push(vec![
+			"level".into(),
+			if s.maxset { "maximum" } else { "high" }.into(),
+		]);
+		rows.push(vec!["enhanced erase".into(), yes_no(s.en_er_sup).into()]);
+		rows.push(vec!["attempts exceeded".into(), yes_no(s.pwncntex).into()]);
+	} else if r.protocols.is_some() && r.error.is_none() {
+		rows.push(vec!["state".into(), "ATA security not supported".into()]);
+	}
+	if let Some(ref t) = r.tcg {
+		rows.push(vec![
+			"tcg ssc".into(),
+			if t.ssc.is_empty() {
+				"none".to_owned()
+			} else {
+				t.ssc.join(", ")
+			}
+			.into(),
+		]);
+		if let Some(ref l) = t.locking {
+			rows.push(vec![
+				"tcg locking".into(),
+				if !l.enabled {
+					"disabled"
+				} else if l.locked {
+					"enabled, locked"
+				} else {
+					"enabled, unlocked"
+				}
+				.into(),
+			]);
+			rows.push(vec![
+				"media encryption".into(),
+				yes_no(l.media_encryption).into(),
+			]);
+		}
+		if let Some(ref b) = t.block_sid {
+			rows.push(vec![
+				"block sid".into(),
+				if b.sid_blocked {
+					"blocked"
+				} else {
+					"not blocked"
+				}
+				.into(),
+			]);
+		}
+	}
+	if let Some(len) = r.certificate_length {
+		rows.push(vec!["certificate".into(), format!("{} bytes", len).into()]);
+	}
+	if let Some(ref subject) = r.certificate_subject {
+		rows.push(vec!["certificate subject".into(), subject.clone().into()]);
+	}
+	if let Some(ref issuer) = r.certificate_issuer {
+		rows.push(vec!["certificate issuer".into(), issuer.clone().into()]);
+	}
+	for c in &r.compliance {
+		let text = match (&c.standard, &c.security_level, &c.module_name) {
+			(&Some(ref standard), &Some(ref level), &Some(ref name)) => format!(
+				"{} level {}: {} (hardware {}, version {})",
+				standard,
+				level,
+				name,
+				c.hardware_version.as_ref().map_or("?", String::as_str),
+				c.version.as_ref().map_or("?", String::as_str)
+			),
+			_ => format!("descriptor type {:04x}", c.descriptor_type),
+		};
+		rows.push(vec!["compliance".into(), text.into()]);
+	}
+	if let Some(ref i) = r.ieee1667 {
+		rows.push(vec![
+			"ieee 1667".into(),
+			format!("version {}", i.version).into(),
+		]);
+		for s in &i.silos {
+			rows.push(vec![
+				"ieee 1667 silo".into(),
+				format!(
+					"{}: {} {}",
+					s.index,
+					s.name
+						.clone()
+						.unwrap_or_else(|| format!("type {:08x}", s.stid)),
+					s.version
+				)
+				.into(),
+			]);
+		}
+	}
+	if let Some(ref e) = r.error {
+		rows.push(vec![
+			"error".into(),
+			Cell {
+				text: e.clone(),
+				color: Some(Color::Red),
+			},
+		]);
+	}
+	rows.push(vec!["status".into(), status(r)]);
+	render(&rows, color)
+}
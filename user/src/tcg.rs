@@ -0,0 +1,754 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! TCG Storage security subsystem class (SSC) support.
+//!
+//! This implements Level 0 Discovery and just enough of the TCG Core session
+//! layer to manage the Admin SP of drives implementing one of the Opal family
+//! SSCs (Opal, Opalite, Pyrite and Ruby). Only a single method call per
+//! packet is supported, and no transactions.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::thread;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use ops::{self, Result};
+
+/// Security protocol carrying ComPackets and Level 0 Discovery
+pub const PROTOCOL: u8 = 0x01;
+const LEVEL0_DISCOVERY_COMID: u16 = 0x0001;
+
+const BUFFER_LENGTH: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ssc {
+	Enterprise, // 0x0100
+	OpalV1,     // 0x0200
+	OpalV2,     // 0x0203
+	Opalite,    // 0x0301
+	PyriteV1,   // 0x0302
+	PyriteV2,   // 0x0303
+	Ruby,       // 0x0304
+}
+
+impl Ssc {
+	fn from_feature(code: u16) -> Option<Ssc> {
+		match code {
+			0x0100 => Some(Ssc::Enterprise),
+			0x0200 => Some(Ssc::OpalV1),
+			0x0203 => Some(Ssc::OpalV2),
+			0x0301 => Some(Ssc::Opalite),
+			0x0302 => Some(Ssc::PyriteV1),
+			0x0303 => Some(Ssc::PyriteV2),
+			0x0304 => Some(Ssc::Ruby),
+			_ => None,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Ssc::Enterprise => "Enterprise",
+			Ssc::OpalV1 => "Opal 1",
+			Ssc::OpalV2 => "Opal 2",
+			Ssc::Opalite => "Opalite",
+			Ssc::PyriteV1 => "Pyrite 1",
+			Ssc::PyriteV2 => "Pyrite 2",
+			Ssc::Ruby => "Ruby",
+		}
+	}
+
+	/// Whether the Admin SP of this SSC is managed with the Opal methods.
+	pub fn opal_family(self) -> bool {
+		self != Ssc::Enterprise
+	}
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct Locking {
+	pub supported: bool,
+	pub enabled: bool,
+	pub locked: bool,
+	pub media_encryption: bool,
+	pub mbr_enabled: bool,
+	pub mbr_done: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct BlockSid {
+	/// C_PIN_SID still has its initial value, the MSID
+	pub sid_value_state: bool,
+	/// Block SID Authentication is in effect
+	pub sid_blocked: bool,
+	/// The block is also cleared by a hardware reset
+	pub hardware_reset: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Discovery {
+	/// Implemented SSCs and their base ComID
+	pub ssc: Vec<(Ssc, u16)>,
+	pub locking: Option<Locking>,
+	pub block_sid: Option<BlockSid>,
+}
+
+impl Discovery {
+	/// Parse the Level 0 Discovery response, see TCG Core 3.3.6.
+	pub fn parse(buf: &[u8]) -> Discovery {
+		const HEADER_LENGTH: usize = 48;
+		const FEATURE_LOCKING: u16 = 0x0002;
+		const FEATURE_BLOCK_SID: u16 = 0x0402;
+
+		let mut discovery = Discovery::default();
+		if buf.len() < HEADER_LENGTH {
+			return discovery;
+		}
+		let len = ::std::cmp::min(BigEndian::read_u32(&buf[0..4]) as usize + 4, buf.len());
+		let mut pos = HEADER_LENGTH;
+		while pos + 4 <= len {
+			let code = BigEndian::read_u16(&buf[pos..pos + 2]);
+			let end = ::std::cmp::min(pos + 4 + buf[pos + 3] as usize, len);
+			let data = &buf[pos + 4..end];
+			if code == FEATURE_LOCKING && !data.is_empty() {
+				discovery.locking = Some(Locking {
+					supported: data[0] & 0x01 != 0,
+					enabled: data[0] & 0x02 != 0,
+					locked: data[0] & 0x04 != 0,
+					media_encryption: data[0] & 0x08 != 0,
+					mbr_enabled: data[0] & 0x10 != 0,
+					mbr_done: data[0] & 0x20 != 0,
+				});
+			} else if code == FEATURE_BLOCK_SID && data.len() >= 2 {
+				discovery.block_sid = Some(BlockSid {
+					sid_value_state: data[0] & 0x01 != 0,
+					sid_blocked: data[0] & 0x02 != 0,
+					hardware_reset: data[1] & 0x01 != 0,
+				});
+			} else if let Some(ssc) = Ssc::from_feature(code) {
+				if data.len() >= 2 {
+					discovery.ssc.push((ssc, BigEndian::read_u16(&data[0..2])));
+				}
+			}
+			pos = end;
+		}
+		discovery
+	}
+
+	/// The first Opal family SSC and its base ComID.
+	pub fn opal_family(&self) -> Option<(Ssc, u16)> {
+		self.ssc.iter().cloned().find(|&(ssc, _)| ssc.opal_family())
+	}
+}
+
+pub fn discovery(fd: RawFd) -> Result<Discovery> {
+	// Parsing stops at the end of a cut off response
+	let buf = try!(ops::security_receive_sized(
+		fd,
+		PROTOCOL,
+		LEVEL0_DISCOVERY_COMID,
+		0,
+		BUFFER_LENGTH,
+		|buf| (BigEndian::read_u32(&buf[0..4]) as usize)
+			.checked_add(4)
+			.unwrap_or(usize::max_value())
+	));
+	Ok(Discovery::parse(&buf))
+}
+
+/// Issue the Block SID Authentication command, see the TCG Storage Feature
+/// Set: Block SID Authentication. Authentication as SID fails until the next
+/// power cycle, or hardware reset if `hardware_reset` is set.
+pub fn block_sid(fd: RawFd, hardware_reset: bool) -> Result<()> {
+	const BLOCK_SID_PROTOCOL: u8 = 0x02;
+	const BLOCK_SID_COMID: u16 = 0x0005;
+
+	let mut buf = [0u8; 512];
+	buf[0] = hardware_reset as u8;
+	ops::security_send(fd, BLOCK_SID_PROTOCOL, BLOCK_SID_COMID, 0, Some(&buf))
+}
+
+/// Method status codes, see TCG Core 5.1.5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Status {
+	Success,             // 0x00
+	NotAuthorized,       // 0x01
+	SpBusy,              // 0x03
+	SpFailed,            // 0x04
+	SpDisabled,          // 0x05
+	SpFrozen,            // 0x06
+	NoSessionsAvailable, // 0x07
+	UniquenessConflict,  // 0x08
+	InsufficientSpace,   // 0x09
+	InsufficientRows,    // 0x0a
+	InvalidParameter,    // 0x0c
+	TperMalfunction,     // 0x0f
+	TransactionFailure,  // 0x10
+	ResponseOverflow,    // 0x11
+	AuthorityLockedOut,  // 0x12
+	Fail,                // 0x3f
+	Other(u8),
+}
+
+impl From<u8> for Status {
+	fn from(status: u8) -> Status {
+		use self::Status::*;
+		match status {
+			0x00 => Success,
+			0x01 => NotAuthorized,
+			0x03 => SpBusy,
+			0x04 => SpFailed,
+			0x05 => SpDisabled,
+			0x06 => SpFrozen,
+			0x07 => NoSessionsAvailable,
+			0x08 => UniquenessConflict,
+			0x09 => InsufficientSpace,
+			0x0a => InsufficientRows,
+			0x0c => InvalidParameter,
+			0x0f => TperMalfunction,
+			0x10 => TransactionFailure,
+			0x11 => ResponseOverflow,
+			0x12 => AuthorityLockedOut,
+			0x3f => Fail,
+			_ => Other(status),
+		}
+	}
+}
+
+pub type Uid = [u8; 8];
+
+pub mod uid {
+	use super::Uid;
+
+	pub const SESSION_MANAGER: Uid = [0, 0, 0, 0, 0, 0, 0, 0xff];
+	pub const ADMIN_SP: Uid = [0, 0, 0x02, 0x05, 0, 0, 0, 0x01];
+	pub const AUTHORITY_SID: Uid = [0, 0, 0, 0x09, 0, 0, 0, 0x06];
+	pub const AUTHORITY_PSID: Uid = [0, 0, 0, 0x09, 0, 0x01, 0xff, 0x01];
+	pub const C_PIN_SID: Uid = [0, 0, 0, 0x0b, 0, 0, 0, 0x01];
+	pub const C_PIN_MSID: Uid = [0, 0, 0, 0x0b, 0, 0, 0x84, 0x02];
+}
+
+mod method {
+	use super::Uid;
+
+	pub const START_SESSION: Uid = [0, 0, 0, 0, 0, 0, 0xff, 0x02];
+	pub const SYNC_SESSION: Uid = [0, 0, 0, 0, 0, 0, 0xff, 0x03];
+	pub const GET: Uid = [0, 0, 0, 0x06, 0, 0, 0, 0x16];
+	pub const SET: Uid = [0, 0, 0, 0x06, 0, 0, 0, 0x17];
+	pub const REVERT: Uid = [0, 0, 0, 0x06, 0, 0, 0x02, 0x02];
+}
+
+const START_LIST: u8 = 0xf0;
+const END_LIST: u8 = 0xf1;
+const START_NAME: u8 = 0xf2;
+const END_NAME: u8 = 0xf3;
+const CALL: u8 = 0xf8;
+const END_OF_DATA: u8 = 0xf9;
+const END_OF_SESSION: u8 = 0xfa;
+const EMPTY: u8 = 0xff;
+
+/// Column number of the PIN column of the C_PIN table
+const C_PIN_PIN: u64 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+	Uint(u64),
+	Bytes(Vec<u8>),
+	Control(u8),
+}
+
+fn invalid_response(msg: &str) -> ops::Error {
+	ops::Error::Device(io::Error::new(
+		io::ErrorKind::InvalidData,
+		format!("invalid TCG response: {}", msg),
+	))
+}
+
+/// Token stream encoder, see TCG Core 3.2.2.
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+	fn control(&mut self, token: u8) -> &mut Encoder {
+		self.0.push(token);
+		self
+	}
+
+	fn uint(&mut self, v: u64) -> &mut Encoder {
+		if v < 0x40 {
+			self.0.push(v as u8);
+		} else {
+			let mut buf = [0u8; 8];
+			BigEndian::write_u64(&mut buf, v);
+			let skip = buf.iter().take_while(|&&b| b == 0).count();
+			self.0.push(0x80 | (8 - skip) as u8);
+			self.0.extend_from_slice(&buf[skip..]);
+		}
+		self
+	}
+
+	fn bytes(&mut self, b: &[u8]) -> &mut Encoder {
+		if b.len() < 0x10 {
+			self.0.push(0xa0 | b.len() as u8);
+		} else {
+			assert!(b.len() < 0x800);
+			self.0.push(0xd0 | (b.len() >> 8) as u8);
+			self.0.push(b.len() as u8);
+		}
+		self.0.extend_from_slice(b);
+		self
+	}
+
+	fn named_uint(&mut self, name: u64, v: u64) -> &mut Encoder {
+		self.control(START_NAME)
+			.uint(name)
+			.uint(v)
+			.control(END_NAME)
+	}
+
+	fn named_bytes(&mut self, name: u64, b: &[u8]) -> &mut Encoder {
+		self.control(START_NAME)
+			.uint(name)
+			.bytes(b)
+			.control(END_NAME)
+	}
+
+	/// Start a method call, the parameters follow.
+	fn call(invoking: &Uid, method: &Uid) -> Encoder {
+		let mut e = Encoder(vec![]);
+		e.control(CALL)
+			.bytes(invoking)
+			.bytes(method)
+			.control(START_LIST);
+		e
+	}
+
+	/// Finish a method call started with `call`.
+	fn finish(mut self) -> Vec<u8> {
+		self.control(END_LIST)
+			.control(END_OF_DATA)
+			.control(START_LIST)
+			.uint(0)
+			.uint(0)
+			.uint(0)
+			.control(END_LIST);
+		self.0
+	}
+}
+
+/// Decode the tokens of a method response.
+pub fn decode(buf: &[u8]) -> Result<Vec<Token>> {
+	let mut tokens = vec![];
+	let mut pos = 0;
+	while pos < buf.len() {
+		let b = buf[pos];
+		let (header, len, bytes) = match b {
+			0x00...0x7f => {
+				tokens.push(Token::Uint((b & 0x3f) as u64));
+				pos += 1;
+				continue;
+			}
+			0x80...0xbf => (1, (b & 0x0f) as usize, b & 0x20 != 0),
+			0xc0...0xdf => {
+				if pos + 2 > buf.len() {
+					return Err(invalid_response("truncated atom"));
+				}
+				(
+					2,
+					((b & 0x07) as usize) << 8 | buf[pos + 1] as usize,
+					b & 0x10 != 0,
+				)
+			}
+			0xe0...0xe3 => {
+				if pos + 4 > buf.len() {
+					return Err(invalid_response("truncated atom"));
+				}
+				(
+					4,
+					BigEndian::read_u24(&buf[pos + 1..pos + 4]) as usize,
+					b & 0x02 != 0,
+				)
+			}
+			EMPTY => {
+				pos += 1;
+				continue;
+			}
+			_ => {
+				tokens.push(Token::Control(b));
+				pos += 1;
+				continue;
+			}
+		};
+		let data = match buf.get(pos + header..pos + header + len) {
+			Some(data) => data,
+			None => return Err(invalid_response("truncated atom")),
+		};
+		tokens.push(if bytes {
+			Token::Bytes(data.to_owned())
+		} else {
+			if len > 8 {
+				return Err(invalid_response("integer too large"));
+			}
+			Token::Uint(data.iter().fold(0, |v, &b| v << 8 | b as u64))
+		});
+		pos += header + len;
+	}
+	Ok(tokens)
+}
+
+/// Check the method status list at the end of a response.
+fn check_status(tokens: &[Token]) -> Result<()> {
+	let eod = match tokens
+		.iter()
+		.rposition(|t| *t == Token::Control(END_OF_DATA))
+	{
+		Some(eod) => eod,
+		None => return Err(invalid_response("missing status list")),
+	};
+	match tokens.get(eod + 2) {
+		Some(&Token::Uint(0)) => Ok(()),
+		Some(&Token::Uint(status)) => Err(ops::Error::Tcg(Status::from(status as u8))),
+		_ => Err(invalid_response("missing status list")),
+	}
+}
+
+/// Wrap `payload` in a ComPacket, Packet and Data SubPacket, see TCG Core
+/// 3.2.3.
+fn send(fd: RawFd, comid: u16, tsn: u32, hsn: u32, payload: &[u8]) -> Result<()> {
+	let padded = (payload.len() + 3) & !3;
+	let mut buf = vec![0u8; 20 + 24 + 12 + padded];
+	BigEndian::write_u16(&mut buf[4..6], comid);
+	BigEndian::write_u32(&mut buf[16..20], (24 + 12 + padded) as u32);
+	BigEndian::write_u32(&mut buf[20..24], tsn);
+	BigEndian::write_u32(&mut buf[24..28], hsn);
+	BigEndian::write_u32(&mut buf[40..44], (12 + padded) as u32);
+	BigEndian::write_u32(&mut buf[52..56], payload.len() as u32);
+	buf[56..56 + payload.len()].copy_from_slice(payload);
+	let len = (buf.len() + 511) & !511;
+	buf.resize(len, 0);
+	ops::security_send(fd, PROTOCOL, comid, 0, Some(&buf))
+}
+
+/// Receive the response to a previous `send`, polling while the TPer is
+/// still processing the method.
+fn recv(fd: RawFd, comid: u16) -> Result<Vec<Token>> {
+	let mut size = BUFFER_LENGTH;
+	for _ in 0..500 {
+		let mut buf = vec![0u8; size];
+		try!(ops::security_receive(fd, PROTOCOL, comid, 0, &mut buf));
+		let outstanding = BigEndian::read_u32(&buf[8..12]);
+		let min_transfer = BigEndian::read_u32(&buf[12..16]) as usize;
+		let length = BigEndian::read_u32(&buf[16..20]) as usize;
+		if length == 0 {
+			if outstanding == 0 {
+				return Err(invalid_response("empty response"));
+			}
+			// The response is ready but does not fit the buffer
+			if min_transfer > size {
+				let limit = ::std::cmp::min(
+					try!(ops::max_transfer(fd)).unwrap_or(ops::MAX_SECURITY_RECEIVE),
+					ops::MAX_SECURITY_RECEIVE,
+				);
+				if limit <= size {
+					return Err(invalid_response(
+						"response exceeds the maximum transfer size of the controller",
+					));
+				}
+				size = ::std::cmp::min(min_transfer, limit);
+				continue;
+			}
+			thread::sleep(Duration::from_millis(10));
+			continue;
+		}
+		if length < 24 + 12 || length.checked_add(20).map_or(true, |end| end > buf.len()) {
+			return Err(invalid_response("invalid ComPacket length"));
+		}
+		let payload_length = BigEndian::read_u32(&buf[52..56]) as usize;
+		let end = payload_length.checked_add(56);
+		return match end.and_then(|end| buf.get(56..end)) {
+			Some(payload) => decode(payload),
+			None => Err(invalid_response("invalid SubPacket length")),
+		};
+	}
+	Err(invalid_response("timed out waiting for the TPer"))
+}
+
+/// An open session with a security provider.
+pub struct Session {
+	fd: RawFd,
+	comid: u16,
+	tsn: u32,
+	hsn: u32,
+	open: bool,
+}
+
+impl Session {
+	/// Open a read-write session with the SP `sp`, authenticating as
+	/// `authority` with `challenge` if given.
+	pub fn start(
+		fd: RawFd,
+		comid: u16,
+		sp: &Uid,
+		authority: Option<(&Uid, &[u8])>,
+	) -> Result<Session> {
+		const HOST_SESSION_ID: u32 = 1;
+
+		let mut call = Encoder::call(&uid::SESSION_MANAGER, &method::START_SESSION);
+		call.uint(HOST_SESSION_ID as u64).bytes(sp).uint(1);
+		if let Some((authority, challenge)) = authority {
+			call.named_bytes(0, challenge).named_bytes(3, authority);
+		}
+		try!(send(fd, comid, 0, 0, &call.finish()));
+		let tokens = try!(recv(fd, comid));
+		try!(check_status(&tokens));
+		// CALL SMUID SyncSession [ HostSessionID SPSessionID ... ]
+		match (tokens.get(2), tokens.get(4), tokens.get(5)) {
+			(Some(&Token::Bytes(ref m)), Some(&Token::Uint(hsn)), Some(&Token::Uint(tsn)))
+				if m[..] == method::SYNC_SESSION[..] =>
+			{
+				Ok(Session {
+					fd,
+					comid,
+					tsn: tsn as u32,
+					hsn: hsn as u32,
+					open: true,
+				})
+			}
+			_ => Err(invalid_response("unexpected StartSession response")),
+		}
+	}
+
+	fn call(&mut self, call: Encoder) -> Result<Vec<Token>> {
+		try!(send(
+			self.fd,
+			self.comid,
+			self.tsn,
+			self.hsn,
+			&call.finish()
+		));
+		let tokens = try!(recv(self.fd, self.comid));
+		if tokens.first() == Some(&Token::Control(END_OF_SESSION)) {
+			self.open = false;
+			return Err(invalid_response("session closed by the TPer"));
+		}
+		try!(check_status(&tokens));
+		Ok(tokens)
+	}
+
+	/// Read the PIN column of the C_PIN table row `row`.
+	pub fn get_pin(&mut self, row: &Uid) -> Result<Vec<u8>> {
+		let mut call = Encoder::call(row, &method::GET);
+		call.control(START_LIST)
+			.named_uint(3, C_PIN_PIN)
+			.named_uint(4, C_PIN_PIN)
+			.control(END_LIST);
+		let tokens = try!(self.call(call));
+		// [ [ StartName 3 <pin> EndName ] ]
+		tokens
+			.windows(4)
+			.find(|w| {
+				w[0] == Token::Control(START_NAME)
+					&& w[1] == Token::Uint(C_PIN_PIN)
+					&& w[3] == Token::Control(END_NAME)
+			})
+			.and_then(|w| match w[2] {
+				Token::Bytes(ref pin) => Some(pin.clone()),
+				_ => None,
+			})
+			.ok_or_else(|| invalid_response("PIN missing from Get response"))
+	}
+
+	/// Write the PIN column of the C_PIN table row `row`.
+	pub fn set_pin(&mut self, row: &Uid, pin: &[u8]) -> Result<()> {
+		let mut call = Encoder::call(row, &method::SET);
+		call.control(START_NAME)
+			.uint(1)
+			.control(START_LIST)
+			.named_bytes(C_PIN_PIN, pin)
+			.control(END_LIST)
+			.control(END_NAME);
+		self.call(call).map(|_| ())
+	}
+
+	/// Revert the SP `sp` to its factory state. When reverting the Admin SP
+	/// the TPer closes the session.
+	pub fn revert(mut self, sp: &Uid) -> Result<()> {
+		try!(self.call(Encoder::call(sp, &method::REVERT)));
+		if sp == &uid::ADMIN_SP {
+			self.open = false;
+		}
+		Ok(())
+	}
+
+	pub fn close(mut self) -> Result<()> {
+		self.open = false;
+		try!(send(
+			self.fd,
+			self.comid,
+			self.tsn,
+			self.hsn,
+			&[END_OF_SESSION]
+		));
+		recv(self.fd, self.comid).map(|_| ())
+	}
+}
+
+impl Drop for Session {
+	fn drop(&mut self) {
+		if self.open {
+			let _ = send(self.fd, self.comid, self.tsn, self.hsn, &[END_OF_SESSION])
+				.and_then(|_| recv(self.fd, self.comid));
+		}
+	}
+}
+
+/// Take ownership of the TPer by changing the SID password from the MSID
+/// to `password`.
+pub fn take_ownership(fd: RawFd, comid: u16, password: &[u8]) -> Result<()> {
+	let mut session = try!(Session::start(fd, comid, &uid::ADMIN_SP, None));
+	let msid = try!(session.get_pin(&uid::C_PIN_MSID));
+	try!(session.close());
+
+	let mut session = try!(Session::start(
+		fd,
+		comid,
+		&uid::ADMIN_SP,
+		Some((&uid::AUTHORITY_SID, &msid))
+	));
+	try!(session.set_pin(&uid::C_PIN_SID, password));
+	session.close()
+}
+
+/// Revert the TPer to its factory state, authenticating with the SID
+/// password.
+pub fn revert(fd: RawFd, comid: u16, password: &[u8]) -> Result<()> {
+	let session = try!(Session::start(
+		fd,
+		comid,
+		&uid::ADMIN_SP,
+		Some((&uid::AUTHORITY_SID, password))
+	));
+	session.revert(&uid::ADMIN_SP)
+}
+
+/// Revert the TPer to its factory state, authenticating with the PSID
+/// printed on the drive label. See the TCG Storage Opal SSC Feature Set:
+/// PSID.
+pub fn revert_psid(fd: RawFd, comid: u16, psid: &[u8]) -> Result<()> {
+	let session = try!(Session::start(
+		fd,
+		comid,
+		&uid::ADMIN_SP,
+		Some((&uid::AUTHORITY_PSID, psid))
+	));
+	session.revert(&uid::ADMIN_SP)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A Level 0 Discovery response of a Pyrite 2 drive: TPer, Locking,
+	/// Pyrite SSC V2 and Block SID Authentication features.
+	fn discovery_response() -> Vec<u8> {
+		let mut buf = vec![0u8; 48];
+		buf.extend_from_slice(&[
+			0x00, 0x01, 0x10, 0x0c, 0x11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+		]);
+		buf.extend_from_slice(&[
+			0x00, 0x02, 0x10, 0x0c, 0x07, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+		]);
+		buf.extend_from_slice(&[0x03, 0x03, 0x10, 0x10, 0x07, 0xfe, 0x00, 0x01]);
+		buf.extend_from_slice(&[0; 12]);
+		buf.extend_from_slice(&[
+			0x04, 0x02, 0x10, 0x0c, 0x03, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+		]);
+		let len = buf.len() as u32 - 4;
+		BigEndian::write_u32(&mut buf[0..4], len);
+		buf
+	}
+
+	#[test]
+	fn discovery() {
+		let discovery = Discovery::parse(&discovery_response());
+		assert_eq!(discovery.ssc, vec![(Ssc::PyriteV2, 0x07fe)]);
+		assert_eq!(discovery.opal_family(), Some((Ssc::PyriteV2, 0x07fe)));
+		let locking = discovery.locking.unwrap();
+		assert!(locking.supported && locking.enabled && locking.locked);
+		assert!(!locking.media_encryption && !locking.mbr_enabled);
+		let block_sid = discovery.block_sid.unwrap();
+		assert!(block_sid.sid_value_state && block_sid.sid_blocked && block_sid.hardware_reset);
+	}
+
+	#[test]
+	fn discovery_cut_off() {
+		let mut buf = discovery_response();
+		// The length covers the SSC feature, the Block SID one is ignored
+		BigEndian::write_u32(&mut buf[0..4], 48 + 16 + 16 + 20 - 4);
+		let discovery = Discovery::parse(&buf);
+		assert_eq!(discovery.ssc, vec![(Ssc::PyriteV2, 0x07fe)]);
+		assert!(discovery.block_sid.is_none());
+		assert!(Discovery::parse(&buf[..40]).ssc.is_empty());
+	}
+
+	#[test]
+	fn atoms_round_trip() {
+		let medium = vec![0x5a; 0x7ff];
+		let mut e = Encoder(vec![]);
+		e.uint(0)
+			.uint(0x3f)
+			.uint(0x40)
+			.uint(0x1234_5678_9abc)
+			.uint(::std::u64::MAX)
+			.bytes(&[])
+			.bytes(&[1; 0x0f])
+			.bytes(&[2; 0x10])
+			.bytes(&medium)
+			.named_uint(3, 1)
+			.control(END_OF_DATA);
+		// Tiny, short and medium atoms
+		assert_eq!(&e.0[..6], &[0x00, 0x3f, 0x81, 0x40, 0x86, 0x12]);
+		assert_eq!(&e.0[20..22], &[0xa0, 0xaf]);
+		assert_eq!(&e.0[37..39], &[0xd0, 0x10]);
+		assert_eq!(&e.0[55..57], &[0xd7, 0xff]);
+		assert_eq!(
+			decode(&e.0).unwrap(),
+			vec![
+				Token::Uint(0),
+				Token::Uint(0x3f),
+				Token::Uint(0x40),
+				Token::Uint(0x1234_5678_9abc),
+				Token::Uint(::std::u64::MAX),
+				Token::Bytes(vec![]),
+				Token::Bytes(vec![1; 0x0f]),
+				Token::Bytes(vec![2; 0x10]),
+				Token::Bytes(medium),
+				Token::Control(START_NAME),
+				Token::Uint(3),
+				Token::Uint(1),
+				Token::Control(END_NAME),
+				Token::Control(END_OF_DATA),
+			]
+		);
+	}
+
+	#[test]
+	fn long_atoms() {
+		let mut buf = vec![0xe2, 0x00, 0x08, 0x00];
+		buf.extend_from_slice(&[0x33; 0x800]);
+		buf.push(EMPTY);
+		assert_eq!(decode(&buf).unwrap(), vec![Token::Bytes(vec![0x33; 0x800])]);
+		assert!(decode(&buf[..0x803]).is_err());
+		assert!(decode(&[0xe2, 0x00]).is_err());
+		assert!(decode(&[0xd0]).is_err());
+		// Integers wider than 64 bits
+		assert!(decode(&[0x89, 1, 2, 3, 4, 5, 6, 7, 8, 9]).is_err());
+	}
+}
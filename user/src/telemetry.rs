@@ -0,0 +1,77 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Host-initiated telemetry for `--capture-telemetry`.
+//!
+//! When a command fails, the telemetry data the controller collects on
+//! request is saved to a file that can be sent to the drive vendor. Reading
+//! it creates a new snapshot of the controller state, which is read up to
+//! data area 3 in chunks.
+
+use std::cmp;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+use audit;
+use config;
+use nvme::identify::{IdentifyController, Lpa};
+use nvme::log::{Page, TelemetryHeader};
+use nvme::Payload;
+use ops::{self, Result};
+
+/// Bytes to transfer at once
+const CHUNK: usize = 0x1_0000;
+/// Create Telemetry Host-Initiated Data, in the Log Specific Field
+const CREATE: u8 = 1;
+
+/// Save the host-initiated telemetry of the controller open as `fd` to a
+/// new file in `dir`.
+pub fn capture(fd: RawFd, identity: &IdentifyController, dir: &str) -> Result<PathBuf> {
+	if !identity.lpa().contains(Lpa::TELEMETRY | Lpa::EXTENDED_DATA) {
+		return Err(io::Error::new(
+			io::ErrorKind::Other,
+			"the controller does not support telemetry",
+		)
+		.into());
+	}
+	let mut header = [0u8; TelemetryHeader::SIZE];
+	try!(ops::get_log_page_at(
+		fd,
+		Page::TelemetryHostInitiated,
+		CREATE,
+		0,
+		&mut header
+	));
+	let blocks = try!(TelemetryHeader::from_wire(&header)).last_block(3) as usize + 1;
+	let mut data = vec![0u8; blocks * 512];
+	data[..512].copy_from_slice(&header);
+	let mut offset = 512;
+	while offset < data.len() {
+		let end = cmp::min(offset + CHUNK, data.len());
+		try!(ops::get_log_page_at(
+			fd,
+			Page::TelemetryHostInitiated,
+			0,
+			offset as u64,
+			&mut data[offset..end]
+		));
+		offset = end;
+	}
+	let path = Path::new(dir).join(format!(
+		"telemetry-{}-{}.bin",
+		config::serial(identity),
+		audit::now()
+	));
+	let mut f = try!(OpenOptions::new().write(true).create_new(true).open(&path));
+	try!(f.write_all(&data));
+	Ok(path)
+}
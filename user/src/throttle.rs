@@ -0,0 +1,110 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Anti-hammering for automated unlock attempts.
+//!
+//! A drive rejects all passwords after a few failed unlock attempts (5 in
+//! ACS-3) until it is power cycled. With a stale password file, `daemon` and
+//! `unlock-all` would spend those attempts on every start and hotplug, leaving
+//! none for the administrator. Instead, the passwords a drive rejected are
+//! remembered in `DIR` and never tried again automatically, and after
+//! `MAX_REJECTED` different ones automated attempts stop altogether. A
+//! successful unlock, automated or not, clears the record.
+//!
+//! Only a hash of the password sent to the drive is stored. With
+//! `daemon_user`, the broker checks and updates the records, which stay
+//! owned by root.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+
+use ops;
+
+const DIR: &'static str = "/var/lib/nvme-ata-security/rejected";
+
+/// Automated attempts with different passwords before giving up, leaving
+/// the rest of the attempt counter for unlocking by hand
+const MAX_REJECTED: usize = 2;
+
+/// The record of the drive with serial number `serial`. The serial number
+/// comes from the drive and may contain `/` or be `..`, so the file name is
+/// its hex encoding.
+fn path(serial: &str) -> PathBuf {
+	let name: String = serial.bytes().map(|b| format!("{:02x}", b)).collect();
+	PathBuf::from(DIR).join(name)
+}
+
+fn fingerprint(password: &[u8; 32]) -> String {
+	let mut sha256 = Sha256::new();
+	sha256.input(b"nvme-ata-security rejected password\0");
+	sha256.input(password);
+	sha256
+		.result()
+		.iter()
+		.map(|b| format!("{:02x}", b))
+		.collect()
+}
+
+fn rejected_passwords(serial: &str) -> io::Result<Vec<String>> {
+	match File::open(path(serial)) {
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+		Err(e) => Err(e),
+		Ok(f) => BufReader::new(f).lines().collect(),
+	}
+}
+
+/// Whether an automated unlock of the drive with serial number `serial`
+/// using `password` may be attempted, and why not.
+pub fn check(serial: &str, password: &[u8; 32]) -> Result<(), String> {
+	let rejected = try!(rejected_passwords(serial).map_err(|e| format!(
+		"Unable to read {}: {}",
+		path(serial).display(),
+		e
+	)));
+	if rejected.contains(&fingerprint(password)) {
+		Err(
+			"The drive rejected this password before, not trying it again. Unlock the \
+		     drive by hand once the password is fixed."
+				.to_owned(),
+		)
+	} else if rejected.len() >= MAX_REJECTED {
+		Err(format!(
+			"The drive rejected {} different passwords, not trying again. Unlock the drive \
+			 by hand.",
+			rejected.len()
+		))
+	} else {
+		Ok(())
+	}
+}
+
+/// Remember that the drive rejected `password`, if the unlock failed with
+/// `error` because of the password. Other errors say nothing about it.
+pub fn rejected(serial: &str, password: &[u8; 32], error: &ops::Error) -> io::Result<()> {
+	if !error.is_authentication_failure() {
+		return Ok(());
+	}
+	try!(fs::create_dir_all(DIR));
+	let mut f = try!(OpenOptions::new()
+		.append(true)
+		.create(true)
+		.mode(0o600)
+		.open(path(serial)));
+	writeln!(f, "{}", fingerprint(password))
+}
+
+/// Forget the rejected passwords after a successful unlock.
+pub fn accepted(serial: &str) {
+	let _ = fs::remove_file(path(serial));
+}
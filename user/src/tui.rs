@@ -0,0 +1,269 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Full-screen terminal dashboard for `tui`.
+//!
+//! Lists all drives like `list` does, with keys to move the selection, show
+//! details, unlock and freeze. The terminal is in raw mode while the list is
+//! shown and back in normal mode while a password is entered.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use libc;
+use nix;
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::sys::termios::{self, SetArg, Termios};
+
+use audit;
+use config;
+use device;
+use report::DriveReport;
+use table;
+use {read_password_err, security_freeze, security_unlock, DriveInfo, PasswordSource};
+use {EXIT_FAILURE, EXIT_UNSUPPORTED};
+
+const CLEAR: &'static str = "\x1b[H\x1b[2J";
+const REVERSE: &'static str = "\x1b[7m";
+const RESET: &'static str = "\x1b[0m";
+const HELP: &'static str =
+	"up/down: select  enter: details  u: unlock  f: freeze  r: refresh  q/esc: quit";
+
+/// Raw terminal mode, the original mode is restored on drop.
+struct RawMode(Termios);
+
+impl RawMode {
+	fn enter() -> nix::Result<RawMode> {
+		let original = try!(termios::tcgetattr(0));
+		let mut raw = original.clone();
+		termios::cfmakeraw(&mut raw);
+		try!(termios::tcsetattr(0, SetArg::TCSANOW, &raw));
+		Ok(RawMode(original))
+	}
+}
+
+impl Drop for RawMode {
+	fn drop(&mut self) {
+		let _ = termios::tcsetattr(0, SetArg::TCSANOW, &self.0);
+	}
+}
+
+enum Key {
+	Up,
+	Down,
+	Enter,
+	Char(u8),
+}
+
+/// Milliseconds to wait for the rest of an escape sequence, a lone ESC is
+/// the escape key itself.
+const ESCAPE_TIMEOUT: libc::c_int = 50;
+
+/// The next byte of an escape sequence, `None` when none follows in time.
+fn read_escape() -> io::Result<Option<u8>> {
+	let ready = try!(
+		poll(&mut [PollFd::new(0, EventFlags::POLLIN)], ESCAPE_TIMEOUT)
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+	);
+	if ready == 0 {
+		return Ok(None);
+	}
+	let mut buf = [0u8; 1];
+	try!(io::stdin().read_exact(&mut buf));
+	Ok(Some(buf[0]))
+}
+
+fn read_key() -> io::Result<Key> {
+	let mut buf = [0u8; 1];
+	try!(io::stdin().read_exact(&mut buf));
+	Ok(match buf[0] {
+		b'\r' | b'\n' => Key::Enter,
+		b'k' => Key::Up,
+		b'j' => Key::Down,
+		0x1b => {
+			// Arrow keys are ESC [ A and ESC [ B
+			match try!(read_escape()) {
+				Some(b'[') => match try!(read_escape()) {
+					Some(b'A') => Key::Up,
+					Some(b'B') => Key::Down,
+					_ => Key::Char(0x1b),
+				},
+				_ => Key::Char(0x1b),
+			}
+		}
+		c => Key::Char(c),
+	})
+}
+
+/// Write `text` in raw mode, where a newline doesn't return the cursor.
+fn write_raw(text: &str) {
+	let mut stdout = io::stdout();
+	let _ = stdout.write_all(text.replace('\n', "\r\n").as_bytes());
+	let _ = stdout.flush();
+}
+
+struct Drives {
+	devices: Vec<PathBuf>,
+	reports: Vec<DriveReport>,
+}
+
+fn scan() -> io::Result<Drives> {
+	let mut drives = Drives {
+		devices: vec![],
+		reports: vec![],
+	};
+	for dev in try!(device::namespaces()) {
+		if let Ok(f) = File::open(&dev) {
+			let report = DriveReport::new(&dev.display().to_string(), &DriveInfo::query(&f));
+			drives.devices.push(dev);
+			drives.reports.push(report);
+		}
+	}
+	Ok(drives)
+}
+
+fn draw(drives: &Drives, selected: usize, message: &str) {
+	let mut screen = String::from(CLEAR);
+	for (i, line) in table::list(&drives.reports, true).lines().enumerate() {
+		if i == selected + 1 {
+			screen.push_str(REVERSE);
+			screen.push_str(line);
+			screen.push_str(RESET);
+		} else {
+			screen.push_str(line);
+		}
+		screen.push('\n');
+	}
+	screen.push('\n');
+	screen.push_str(HELP);
+	screen.push('\n');
+	screen.push_str(message);
+	write_raw(&screen);
+}
+
+/// Unlock `dev` with a password entered on the terminal, in normal mode.
+fn unlock(config: &config::Config, dev: &PathBuf) -> String {
+	let f = match File::open(dev) {
+		Err(e) => return format!("Unable to open {}: {}", dev.display(), e),
+		Ok(f) => f,
+	};
+	let (identity, security) = match DriveInfo::query(&f).check_support() {
+		Err(_) => return format!("{} does not support ATA security", dev.display()),
+		Ok(v) => v,
+	};
+	if !security.locked() {
+		return format!("{} is not locked", dev.display());
+	}
+	let normalize = config.device(&identity).and_then(|c| c.normalize);
+	let password = match read_password_err(
+		PasswordSource::Stdin,
+		&identity,
+		"password",
+		false,
+		None,
+		normalize,
+	) {
+		Err(e) => return format!("Error trying to read password: {}", e),
+		Ok(password) => password,
+	};
+	let result = security_unlock(&f, password, false);
+	audit::record("unlock", dev, &f, &result);
+	match result {
+		Err(e) => format!("Unable to unlock {}: {:?}", dev.display(), e),
+		Ok(()) => format!("Unlocked {}", dev.display()),
+	}
+}
+
+fn freeze(dev: &PathBuf) -> String {
+	let f = match File::open(dev) {
+		Err(e) => return format!("Unable to open {}: {}", dev.display(), e),
+		Ok(f) => f,
+	};
+	let result = security_freeze(&f);
+	audit::record("freeze", dev, &f, &result);
+	match result {
+		Err(e) => format!("Unable to freeze {}: {:?}", dev.display(), e),
+		Ok(()) => format!("Froze {}", dev.display()),
+	}
+}
+
+pub fn run(config: &config::Config) -> i32 {
+	if !nix::unistd::isatty(0).unwrap_or(false) {
+		eprintln!("tui is interactive and needs a terminal");
+		return EXIT_UNSUPPORTED;
+	}
+	if let Some(ref path) = config.audit_log {
+		if let Err(e) = audit::open_for_process(path) {
+			eprintln!("Unable to open audit log {}: {}", path, e);
+			return EXIT_FAILURE;
+		}
+	}
+	let mut drives = match scan() {
+		Err(e) => {
+			eprintln!("Unable to enumerate NVMe devices: {}", e);
+			return EXIT_FAILURE;
+		}
+		Ok(drives) => drives,
+	};
+	let mut selected = 0;
+	let mut message = String::new();
+	loop {
+		let key = {
+			let _raw = match RawMode::enter() {
+				Err(e) => {
+					eprintln!("Unable to configure the terminal: {}", e);
+					return EXIT_FAILURE;
+				}
+				Ok(raw) => raw,
+			};
+			draw(&drives, selected, &message);
+			match read_key() {
+				Err(_) => return EXIT_FAILURE,
+				Ok(key) => key,
+			}
+		};
+		message.clear();
+		let dev = drives.devices.get(selected).cloned();
+		match (key, dev) {
+			(Key::Char(b'q'), _) | (Key::Char(3), _) | (Key::Char(0x1b), _) => {
+				write_raw(CLEAR);
+				return 0;
+			}
+			(Key::Up, _) => selected = selected.saturating_sub(1),
+			(Key::Down, _) => {
+				if selected + 1 < drives.devices.len() {
+					selected += 1
+				}
+			}
+			(Key::Char(b'r'), _) => {}
+			(Key::Enter, Some(_)) => {
+				write_raw(CLEAR);
+				print!("{}", table::detail(&drives.reports[selected], true, false));
+				println!("\nPress enter to return");
+				let _ = io::stdin().read_line(&mut String::new());
+			}
+			(Key::Char(b'u'), Some(dev)) => {
+				write_raw(CLEAR);
+				message = unlock(config, &dev);
+			}
+			(Key::Char(b'f'), Some(dev)) => message = freeze(&dev),
+			_ => continue,
+		}
+		// Refresh after every action, the state may have changed
+		match scan() {
+			Err(e) => message = format!("Unable to enumerate NVMe devices: {}", e),
+			Ok(new) => drives = new,
+		}
+		if selected >= drives.devices.len() {
+			selected = drives.devices.len().saturating_sub(1);
+		}
+	}
+}
@@ -0,0 +1,111 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Kernel uevents.
+//!
+//! This listens to the kernel multicast group of the `NETLINK_KOBJECT_UEVENT`
+//! socket directly, so events arrive before udev has processed them. The
+//! device node itself is created by devtmpfs.
+
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use libc::*;
+
+/// Kernel event multicast group, udev uses group 2 for processed events
+const UEVENT_GROUP_KERNEL: u32 = 1;
+
+pub struct Uevent {
+	pub action: String,
+	pub devpath: String,
+	pub env: HashMap<String, String>,
+}
+
+impl Uevent {
+	fn parse(buf: &[u8]) -> Option<Uevent> {
+		let mut fields = buf
+			.split(|&b| b == 0)
+			.filter(|f| !f.is_empty())
+			.map(String::from_utf8_lossy);
+		let header = fields.next()?;
+		let at = header.find('@')?;
+		let mut env = HashMap::new();
+		for field in fields {
+			if let Some(eq) = field.find('=') {
+				env.insert(field[..eq].to_owned(), field[eq + 1..].to_owned());
+			}
+		}
+		Some(Uevent {
+			action: header[..at].to_owned(),
+			devpath: header[at + 1..].to_owned(),
+			env,
+		})
+	}
+
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.env.get(key).map(String::as_str)
+	}
+}
+
+pub struct Socket(RawFd);
+
+impl Socket {
+	pub fn open() -> io::Result<Socket> {
+		unsafe {
+			let fd = socket(
+				AF_NETLINK,
+				SOCK_DGRAM | SOCK_CLOEXEC,
+				NETLINK_KOBJECT_UEVENT,
+			);
+			if fd < 0 {
+				return Err(io::Error::last_os_error());
+			}
+			let socket = Socket(fd);
+			let mut addr: sockaddr_nl = mem::zeroed();
+			addr.nl_family = AF_NETLINK as sa_family_t;
+			addr.nl_groups = UEVENT_GROUP_KERNEL;
+			if bind(
+				fd,
+				&addr as *const sockaddr_nl as *const sockaddr,
+				mem::size_of::<sockaddr_nl>() as socklen_t,
+			) != 0
+			{
+				return Err(io::Error::last_os_error());
+			}
+			Ok(socket)
+		}
+	}
+
+	/// Wait for the next event. Malformed messages are skipped.
+	pub fn recv(&self) -> io::Result<Uevent> {
+		let mut buf = [0u8; 8192];
+		loop {
+			let len = unsafe { recv(self.0, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+			if len < 0 {
+				let err = io::Error::last_os_error();
+				if err.kind() == io::ErrorKind::Interrupted {
+					continue;
+				}
+				return Err(err);
+			}
+			if let Some(event) = Uevent::parse(&buf[..len as usize]) {
+				return Ok(event);
+			}
+		}
+	}
+}
+
+impl Drop for Socket {
+	fn drop(&mut self) {
+		unsafe { close(self.0) };
+	}
+}
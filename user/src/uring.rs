@@ -0,0 +1,277 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! NVMe admin commands through io_uring passthrough, for `--transport=uring`.
+//!
+//! Needs Linux 6.0 or later. Commands are sent as `IORING_OP_URING_CMD`
+//! with `NVME_URING_CMD_ADMIN` to a controller character device such as
+//! `/dev/nvme0`, the only device the kernel accepts admin commands on
+//! (`nvme_dev_uring_cmd`). Each call sets up its own ring with 128-byte
+//! submission entries and waits for its completions, so nothing is kept
+//! between calls. `admin_cmds` submits the commands for many drives on one
+//! ring, so they run at the same time without a thread for each drive.
+
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{fence, Ordering};
+
+use libc;
+use nix::errno::Errno;
+use nix::Error as NixError;
+
+// The io_uring system calls have the same numbers on all architectures
+const SYS_IO_URING_SETUP: libc::c_long = 425;
+const SYS_IO_URING_ENTER: libc::c_long = 426;
+
+const IORING_SETUP_SQE128: u32 = 1 << 10;
+const IORING_SETUP_CQE32: u32 = 1 << 11;
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x800_0000;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_OP_URING_CMD: u8 = 46;
+
+/// `_IOWR('N', 0x82, struct nvme_uring_cmd)`
+const NVME_URING_CMD_ADMIN: u32 = 0xc048_4e82;
+
+const SQE_SIZE: usize = 128;
+const CQE_SIZE: usize = 32;
+/// Offset of the command in a submission entry
+const SQE_CMD: isize = 48;
+/// Commands submitted on one ring at most
+const MAX_ENTRIES: usize = 256;
+
+#[derive(Default)]
+#[repr(C)]
+struct SqringOffsets {
+	pub head: u32,
+	pub tail: u32,
+	pub ring_mask: u32,
+	pub ring_entries: u32,
+	pub flags: u32,
+	pub dropped: u32,
+	pub array: u32,
+	pub resv1: u32,
+	pub user_addr: u64,
+}
+
+#[derive(Default)]
+#[repr(C)]
+struct CqringOffsets {
+	pub head: u32,
+	pub tail: u32,
+	pub ring_mask: u32,
+	pub ring_entries: u32,
+	pub overflow: u32,
+	pub cqes: u32,
+	pub flags: u32,
+	pub resv1: u32,
+	pub user_addr: u64,
+}
+
+#[derive(Default)]
+#[repr(C)]
+struct Params {
+	pub sq_entries: u32,
+	pub cq_entries: u32,
+	pub flags: u32,
+	pub sq_thread_cpu: u32,
+	pub sq_thread_idle: u32,
+	pub features: u32,
+	pub wq_fd: u32,
+	pub resv: [u32; 3],
+	pub sq_off: SqringOffsets,
+	pub cq_off: CqringOffsets,
+}
+
+/// `struct nvme_uring_cmd`
+#[derive(Default)]
+#[repr(C)]
+pub struct UringCmd {
+	pub opcode: u8,
+	pub flags: u8,
+	pub rsvd1: u16,
+	pub nsid: u32,
+	pub cdw2: u32,
+	pub cdw3: u32,
+	pub metadata: u64,
+	pub addr: u64,
+	pub metadata_len: u32,
+	pub data_len: u32,
+	pub cdw10: u32,
+	pub cdw11: u32,
+	pub cdw12: u32,
+	pub cdw13: u32,
+	pub cdw14: u32,
+	pub cdw15: u32,
+	pub timeout_ms: u32,
+	pub rsvd2: u32,
+}
+
+struct Ring(RawFd);
+
+impl Drop for Ring {
+	fn drop(&mut self) {
+		unsafe { libc::close(self.0) };
+	}
+}
+
+struct Mapping(*mut u8, usize);
+
+impl Mapping {
+	fn new(ring: &Ring, len: usize, offset: libc::off_t) -> Result<Mapping, NixError> {
+		let ptr = unsafe {
+			libc::mmap(
+				ptr::null_mut(),
+				len,
+				libc::PROT_READ | libc::PROT_WRITE,
+				libc::MAP_SHARED | libc::MAP_POPULATE,
+				ring.0,
+				offset,
+			)
+		};
+		if ptr == libc::MAP_FAILED {
+			return Err(NixError::Sys(Errno::last()));
+		}
+		Ok(Mapping(ptr as *mut u8, len))
+	}
+
+	unsafe fn at<T>(&self, offset: u32) -> *mut T {
+		self.0.offset(offset as isize) as *mut T
+	}
+}
+
+impl Drop for Mapping {
+	fn drop(&mut self) {
+		unsafe { libc::munmap(self.0 as *mut libc::c_void, self.1) };
+	}
+}
+
+/// Send `cmd` to the controller device open as `fd` and wait for it to
+/// complete. Returns the NVMe status and Dword 0 of the completion, like the
+/// admin command ioctl.
+pub fn admin_cmd(fd: RawFd, cmd: &UringCmd) -> Result<(i32, u32), NixError> {
+	try!(submit(&[(fd, cmd)])).pop().unwrap()
+}
+
+/// Send each command to the controller device open as its `RawFd` and wait
+/// for all of them to complete. The results are in the order of `cmds`.
+pub fn admin_cmds(
+	cmds: &[(RawFd, &UringCmd)],
+) -> Result<Vec<Result<(i32, u32), NixError>>, NixError> {
+	let mut results = Vec::with_capacity(cmds.len());
+	for chunk in cmds.chunks(MAX_ENTRIES) {
+		results.extend(try!(submit(chunk)));
+	}
+	Ok(results)
+}
+
+fn submit(cmds: &[(RawFd, &UringCmd)]) -> Result<Vec<Result<(i32, u32), NixError>>, NixError> {
+	let mut params = Params {
+		flags: IORING_SETUP_SQE128 | IORING_SETUP_CQE32,
+		..Default::default()
+	};
+	let ring = unsafe {
+		libc::syscall(
+			SYS_IO_URING_SETUP,
+			cmds.len() as u32,
+			&mut params as *mut Params,
+		)
+	};
+	if ring < 0 {
+		return Err(NixError::Sys(Errno::last()));
+	}
+	let ring = Ring(ring as RawFd);
+	let sq = try!(Mapping::new(
+		&ring,
+		params.sq_off.array as usize + params.sq_entries as usize * 4,
+		IORING_OFF_SQ_RING
+	));
+	let cq = try!(Mapping::new(
+		&ring,
+		params.cq_off.cqes as usize + params.cq_entries as usize * CQE_SIZE,
+		IORING_OFF_CQ_RING
+	));
+	let sqes = try!(Mapping::new(
+		&ring,
+		params.sq_entries as usize * SQE_SIZE,
+		IORING_OFF_SQES
+	));
+	let mut results: Vec<Option<Result<(i32, u32), NixError>>> =
+		cmds.iter().map(|_| None).collect();
+	unsafe {
+		let tail = sq.at::<u32>(params.sq_off.tail);
+		let mask = ptr::read_volatile(sq.at::<u32>(params.sq_off.ring_mask));
+		let mut index = ptr::read_volatile(tail);
+		for (i, &(fd, cmd)) in cmds.iter().enumerate() {
+			let sqe = sqes.0.offset((i * SQE_SIZE) as isize);
+			ptr::write_bytes(sqe, 0, SQE_SIZE);
+			*sqe = IORING_OP_URING_CMD;
+			*(sqe.offset(4) as *mut i32) = fd;
+			*(sqe.offset(8) as *mut u32) = NVME_URING_CMD_ADMIN;
+			// user_data, to match the completion to the command
+			*(sqe.offset(32) as *mut u64) = i as u64;
+			ptr::copy_nonoverlapping(
+				cmd as *const UringCmd as *const u8,
+				sqe.offset(SQE_CMD),
+				mem::size_of::<UringCmd>(),
+			);
+			*sq.at::<u32>(params.sq_off.array + (index & mask) * 4) = i as u32;
+			index = index.wrapping_add(1);
+		}
+		fence(Ordering::Release);
+		ptr::write_volatile(tail, index);
+
+		let mut unsubmitted = cmds.len() as u32;
+		let mut completed = 0;
+		while completed < cmds.len() {
+			let ret = libc::syscall(
+				SYS_IO_URING_ENTER,
+				ring.0,
+				unsubmitted,
+				1u32,
+				IORING_ENTER_GETEVENTS,
+				ptr::null::<libc::c_void>(),
+				0usize,
+			);
+			if ret < 0 {
+				match Errno::last() {
+					Errno::EINTR => continue,
+					e => return Err(NixError::Sys(e)),
+				}
+			}
+			unsubmitted -= ret as u32;
+			fence(Ordering::Acquire);
+
+			let head = cq.at::<u32>(params.cq_off.head);
+			let mask = ptr::read_volatile(cq.at::<u32>(params.cq_off.ring_mask));
+			let end = ptr::read_volatile(cq.at::<u32>(params.cq_off.tail));
+			let mut index = ptr::read_volatile(head);
+			while index != end {
+				let cqe = cq.at::<u8>(params.cq_off.cqes + (index & mask) * CQE_SIZE as u32);
+				let i = ptr::read_volatile(cqe as *const u64) as usize;
+				let res = ptr::read_volatile(cqe.offset(8) as *const i32);
+				// The first extra field of the big CQE
+				let result = ptr::read_volatile(cqe.offset(16) as *const u64);
+				results[i] = Some(if res < 0 {
+					Err(NixError::Sys(Errno::from_i32(-res)))
+				} else {
+					Ok((res, result as u32))
+				});
+				index = index.wrapping_add(1);
+				completed += 1;
+			}
+			fence(Ordering::Release);
+			ptr::write_volatile(head, index);
+		}
+	}
+	Ok(results.into_iter().map(|r| r.unwrap()).collect())
+}
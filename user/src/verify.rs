@@ -0,0 +1,208 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Check that an erase actually changed the contents of a namespace.
+//!
+//! Before the erase, a sentinel pattern is written to a sample of logical
+//! blocks spread across the namespace. Their contents are kept and written
+//! back if the erase fails, so that only a successful erase destroys data. Afterwards the same blocks are read
+//! back. None of them should contain the sentinel any more. Drives may
+//! return zeroes, a vendor pattern or (after a cryptographic erase) random
+//! data, so blocks that aren't zero are counted but are not an error.
+//!
+//! All I/O uses `O_DIRECT` so the page cache can't hide what is on the
+//! medium.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+use libc;
+
+use device;
+
+const SENTINEL: &'static [u8; 16] = b"nvme-ata-verify\0";
+/// Number of blocks sampled, including the first and last block
+const SAMPLES: u64 = 256;
+/// Larger than any logical block size, and a multiple of the alignment
+/// `O_DIRECT` needs
+const MAX_BLOCK_SIZE: usize = 4096;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Summary {
+	pub sampled: u64,
+	pub zeroed: u64,
+	/// Blocks that still contain the sentinel, the erase missed these
+	pub sentinel: u64,
+}
+
+/// A block-sized, suitably aligned buffer for `O_DIRECT`.
+struct Buffer {
+	storage: Vec<u8>,
+	offset: usize,
+	len: usize,
+}
+
+impl Buffer {
+	fn new(len: usize) -> Buffer {
+		let storage = vec![0u8; len + MAX_BLOCK_SIZE];
+		let offset = MAX_BLOCK_SIZE - (storage.as_ptr() as usize % MAX_BLOCK_SIZE);
+		Buffer {
+			storage,
+			offset: offset % MAX_BLOCK_SIZE,
+			len,
+		}
+	}
+
+	fn get(&mut self) -> &mut [u8] {
+		&mut self.storage[self.offset..self.offset + self.len]
+	}
+}
+
+struct Namespace {
+	file: File,
+	block_size: u64,
+	blocks: u64,
+}
+
+impl Namespace {
+	fn open(dev: &Path, write: bool) -> io::Result<Namespace> {
+		let name = try!(device::kernel_name(dev));
+		if !device::is_namespace_name(&name) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"verification needs a namespace block device",
+			));
+		}
+		let queue = Path::new("/sys/block").join(&name);
+		let attr = |path: &str| -> io::Result<u64> {
+			::std::fs::read_to_string(queue.join(path)).and_then(|s| {
+				s.trim().parse().map_err(|_| {
+					io::Error::new(io::ErrorKind::InvalidData, "invalid sysfs attribute")
+				})
+			})
+		};
+		let block_size = try!(attr("queue/logical_block_size"));
+		// Always in 512-byte sectors
+		let size = try!(attr("size")) * 512;
+		if block_size == 0 || block_size as usize > MAX_BLOCK_SIZE {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"unsupported logical block size",
+			));
+		}
+		let file = try!(OpenOptions::new()
+			.read(true)
+			.write(write)
+			.custom_flags(libc::O_DIRECT)
+			.open(dev));
+		Ok(Namespace {
+			file,
+			block_size,
+			blocks: size / block_size,
+		})
+	}
+
+	/// The sampled blocks, evenly spread from the first to the last block.
+	fn samples(&self) -> Vec<u64> {
+		if self.blocks <= SAMPLES {
+			return (0..self.blocks).collect();
+		}
+		(0..SAMPLES)
+			.map(|i| i * (self.blocks - 1) / (SAMPLES - 1))
+			.collect()
+	}
+}
+
+/// The original contents of the blocks `prepare` overwrote, to put back if
+/// the erase fails.
+pub struct Prepared {
+	ns: Namespace,
+	blocks: Vec<(u64, Buffer)>,
+}
+
+impl Prepared {
+	/// Write the original contents back.
+	pub fn restore(mut self) -> io::Result<()> {
+		for &mut (lba, ref mut buf) in &mut self.blocks {
+			let len = try!(self.ns.file.write_at(buf.get(), lba * self.ns.block_size));
+			if len != self.ns.block_size as usize {
+				return Err(io::Error::new(io::ErrorKind::WriteZero, "short write"));
+			}
+		}
+		self.ns.file.sync_all()
+	}
+}
+
+/// Write the sentinel to the sampled blocks of `dev`, after reading their
+/// contents so that `Prepared::restore` can put them back.
+pub fn prepare(dev: &Path) -> io::Result<Prepared> {
+	let ns = try!(Namespace::open(dev, true));
+	let block_size = ns.block_size as usize;
+	let mut blocks = vec![];
+	for lba in ns.samples() {
+		let mut buf = Buffer::new(block_size);
+		if try!(ns.file.read_at(buf.get(), lba * ns.block_size)) != block_size {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short read"));
+		}
+		blocks.push((lba, buf));
+	}
+	let mut sentinel = Buffer::new(block_size);
+	for (i, b) in sentinel.get().iter_mut().enumerate() {
+		*b = SENTINEL[i % SENTINEL.len()];
+	}
+	let mut prepared = Prepared { ns, blocks: vec![] };
+	for (lba, buf) in blocks {
+		let written = prepared
+			.ns
+			.file
+			.write_at(sentinel.get(), lba * prepared.ns.block_size);
+		// A failed write may still have changed the block
+		prepared.blocks.push((lba, buf));
+		let result = match written {
+			Ok(len) if len == block_size => Ok(()),
+			Ok(_) => Err(io::Error::new(io::ErrorKind::WriteZero, "short write")),
+			Err(e) => Err(e),
+		};
+		if let Err(e) = result {
+			let _ = prepared.restore();
+			return Err(e);
+		}
+	}
+	if let Err(e) = prepared.ns.file.sync_all() {
+		let _ = prepared.restore();
+		return Err(e);
+	}
+	Ok(prepared)
+}
+
+/// Read back the sampled blocks of `dev` after an erase.
+pub fn check(dev: &Path) -> io::Result<Summary> {
+	let ns = try!(Namespace::open(dev, false));
+	let mut buf = Buffer::new(ns.block_size as usize);
+	let mut summary = Summary::default();
+	for lba in ns.samples() {
+		if try!(ns.file.read_at(buf.get(), lba * ns.block_size)) != ns.block_size as usize {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short read"));
+		}
+		let block = buf.get();
+		summary.sampled += 1;
+		if block.iter().all(|&b| b == 0) {
+			summary.zeroed += 1;
+		} else if block
+			.chunks(SENTINEL.len())
+			.any(|c| c == &SENTINEL[..c.len()])
+		{
+			summary.sentinel += 1;
+		}
+	}
+	Ok(summary)
+}
@@ -0,0 +1,191 @@
+/*
+ * Linux userspace tool to configure ATA security on NVMe drives
+ *
+ * (C) Copyright 2016 Jethro G. Beekman
+ *
+ * This program is free software; you can redistribute it and/or modify it
+ * under the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ */
+//! Just enough DER to name the certificate of the certificate data page.
+//!
+//! The page holds an X.509 certificate, see SPC-5 7.7.1.4. Only the issuer
+//! and subject of the TBSCertificate are decoded, see RFC 5280 4.1.
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_OID: u8 = 0x06;
+const TAG_VERSION: u8 = 0xa0;
+
+/// Split the DER value at the start of `buf` into its tag, its contents and
+/// the rest of `buf`.
+fn tlv(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+	if buf.len() < 2 {
+		return None;
+	}
+	let (len, start) = match buf[1] {
+		n @ 0...0x7f => (n as usize, 2),
+		// Long form with up to four length bytes
+		n @ 0x81...0x84 => {
+			let count = (n & 0x7f) as usize;
+			if buf.len() < 2 + count {
+				return None;
+			}
+			let len = buf[2..2 + count]
+				.iter()
+				.fold(0usize, |len, &b| len << 8 | b as usize);
+			(len, 2 + count)
+		}
+		_ => return None,
+	};
+	let end = match start.checked_add(len) {
+		Some(end) if end <= buf.len() => end,
+		_ => return None,
+	};
+	Some((buf[0], &buf[start..end], &buf[end..]))
+}
+
+/// The contents of the DER value of type `tag` at the start of `buf`, and
+/// the rest of `buf`.
+fn expect(buf: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+	match tlv(buf) {
+		Some((t, contents, rest)) if t == tag => Some((contents, rest)),
+		_ => None,
+	}
+}
+
+fn oid(buf: &[u8]) -> String {
+	match buf {
+		[0x55, 0x04, 0x03] => "CN".to_owned(),
+		[0x55, 0x04, 0x05] => "serialNumber".to_owned(),
+		[0x55, 0x04, 0x06] => "C".to_owned(),
+		[0x55, 0x04, 0x07] => "L".to_owned(),
+		[0x55, 0x04, 0x08] => "ST".to_owned(),
+		[0x55, 0x04, 0x0a] => "O".to_owned(),
+		[0x55, 0x04, 0x0b] => "OU".to_owned(),
+		_ => {
+			let mut arcs = vec![];
+			if let Some(&first) = buf.first() {
+				arcs.push(u64::from(first / 40));
+				arcs.push(u64::from(first % 40));
+				let mut arc = 0u64;
+				for &b in &buf[1..] {
+					arc = arc << 7 | u64::from(b & 0x7f);
+					if b & 0x80 == 0 {
+						arcs.push(arc);
+						arc = 0;
+					}
+				}
+			}
+			arcs.iter()
+				.map(u64::to_string)
+				.collect::<Vec<_>>()
+				.join(".")
+		}
+	}
+}
+
+/// A Name as `CN=..., O=...`, see RFC 5280 4.1.2.4.
+fn name(mut rdns: &[u8]) -> Option<String> {
+	let mut parts = vec![];
+	while !rdns.is_empty() {
+		let (mut set, rest) = expect(rdns, TAG_SET)?;
+		rdns = rest;
+		while !set.is_empty() {
+			let (attribute, rest) = expect(set, TAG_SEQUENCE)?;
+			set = rest;
+			let (oid_bytes, value) = expect(attribute, TAG_OID)?;
+			let (_, value, _) = tlv(value)?;
+			parts.push(format!(
+				"{}={}",
+				oid(oid_bytes),
+				String::from_utf8_lossy(value)
+			));
+		}
+	}
+	Some(parts.join(", "))
+}
+
+/// The subject and issuer of the DER encoded X.509 certificate `der`.
+pub fn subject_issuer(der: &[u8]) -> Option<(String, String)> {
+	let (certificate, _) = expect(der, TAG_SEQUENCE)?;
+	let (tbs, _) = expect(certificate, TAG_SEQUENCE)?;
+	let tbs = match tlv(tbs)? {
+		(TAG_VERSION, _, rest) => rest,
+		_ => tbs,
+	};
+	// The serial number and the signature algorithm
+	let (_, _, rest) = tlv(tbs)?;
+	let (_, rest) = expect(rest, TAG_SEQUENCE)?;
+	let (issuer, rest) = expect(rest, TAG_SEQUENCE)?;
+	// The validity
+	let (_, rest) = expect(rest, TAG_SEQUENCE)?;
+	let (subject, _) = expect(rest, TAG_SEQUENCE)?;
+	Some((name(subject)?, name(issuer)?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A drive certificate issued by a separate CA, made with `openssl x509
+	/// -req`, in hex.
+	const CERTIFICATE: &'static str =
+		"308201af30820155a003020102020102300a06082a8648ce3d04030230353118\
+		 3016060355040a0c0f4578616d706c652053746f726167653119301706035504\
+		 030c104578616d706c65204472697665204341301e170d323631303136303332\
+		 3233335a170d3336313031333033323233335a304931183016060355040a0c0f\
+		 4578616d706c652053746f72616765311730150603550405130e533345574e58\
+		 304e3132333435363114301206035504030c0b4578616d706c65205353443059\
+		 301306072a8648ce3d020106082a8648ce3d03010703420004a1d3cc32e68ed1\
+		 ffcfbca9aea7c5e374913c639d59864c04fad77ff4353eaa80b2fd496377e848\
+		 251f736ae739ff3960363e93365b0366e2791b69afb10c46bfa3423040301d06\
+		 03551d0e041604144a298b104e5bdab4a1c067c5c4717849e29567f1301f0603\
+		 551d2304183016801459d205f7e2544da318a9460b393ba48b747f8158300a06\
+		 082a8648ce3d04030203480030450220195630972fbccd06f8ce2d47dee5412b\
+		 3155ce1575bc50df72aea97f09d45bf3022100ab09e8994c1c0100b473cdb2ec\
+		 b07b3769c0cf5f318a53b533cc95de7e73fbbd";
+
+	fn certificate() -> Vec<u8> {
+		(0..CERTIFICATE.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&CERTIFICATE[i..i + 2], 16).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn certificate_names() {
+		let certificate = certificate();
+		assert_eq!(
+			subject_issuer(&certificate),
+			Some((
+				"O=Example Storage, serialNumber=S3EWNX0N123456, CN=Example SSD".to_owned(),
+				"O=Example Storage, CN=Example Drive CA".to_owned(),
+			))
+		);
+		assert_eq!(subject_issuer(&certificate[..100]), None);
+		assert_eq!(subject_issuer(&[]), None);
+	}
+
+	#[test]
+	fn lengths() {
+		assert_eq!(
+			tlv(&[0x04, 0x02, 1, 2, 3]),
+			Some((0x04, &[1, 2][..], &[3][..]))
+		);
+		let mut long = vec![0x04, 0x82, 0x01, 0x00];
+		long.resize(4 + 0x100, 0xaa);
+		assert_eq!(tlv(&long).map(|(_, value, _)| value.len()), Some(0x100));
+		// Truncated contents and length bytes, indefinite length
+		assert_eq!(tlv(&[0x04, 0x03, 1, 2]), None);
+		assert_eq!(tlv(&[0x04, 0x82, 0x01]), None);
+		assert_eq!(tlv(&[0x30, 0x80, 0, 0]), None);
+	}
+
+	#[test]
+	fn oids() {
+		assert_eq!(oid(&[0x55, 0x04, 0x03]), "CN");
+		assert_eq!(oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]), "1.2.840.113549");
+	}
+}